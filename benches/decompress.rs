@@ -0,0 +1,93 @@
+#[macro_use]
+extern crate bencher;
+
+extern crate exr;
+use std::{fs, io::Cursor};
+
+use bencher::Bencher;
+use exr::{
+    block::{chunk::Chunk, UncompressedBlock},
+    compression::DecompressionScratch,
+    meta::MetaData,
+};
+
+fn zip_allocating(bench: &mut Bencher) {
+    bench_decompress_allocating(bench, "tests/images/valid/custom/crowskull/crow_zip_half.exr");
+}
+
+fn zip_into_scratch(bench: &mut Bencher) {
+    bench_decompress_into_scratch(bench, "tests/images/valid/custom/crowskull/crow_zip_half.exr");
+}
+
+fn pxr24_allocating(bench: &mut Bencher) {
+    bench_decompress_allocating(bench, "tests/images/valid/custom/crowskull/crow_pxr24.exr");
+}
+
+fn pxr24_into_scratch(bench: &mut Bencher) {
+    bench_decompress_into_scratch(bench, "tests/images/valid/custom/crowskull/crow_pxr24.exr");
+}
+
+fn dwa_allocating(bench: &mut Bencher) {
+    bench_decompress_allocating(bench, "tests/images/valid/custom/crowskull/crow_dwa.exr");
+}
+
+fn dwa_into_scratch(bench: &mut Bencher) {
+    bench_decompress_into_scratch(bench, "tests/images/valid/custom/crowskull/crow_dwa.exr");
+}
+
+/// Decompress each block into a new vector.
+fn bench_decompress_allocating(bench: &mut Bencher, path: &str) {
+    let (meta_data, chunks) = read_chunks(path);
+
+    bench.iter(|| {
+        for chunk in &chunks {
+            // both paths clone the chunk, as this path consumes it
+            let chunk = bencher::black_box(chunk.clone());
+            let block = UncompressedBlock::decompress_chunk(chunk, &meta_data, false).unwrap();
+            bencher::black_box(block);
+        }
+    })
+}
+
+/// Decompress each block into the same buffer, reusing the scratch space.
+fn bench_decompress_into_scratch(bench: &mut Bencher, path: &str) {
+    let (meta_data, chunks) = read_chunks(path);
+    let mut scratch = DecompressionScratch::default();
+    let mut pixels = Vec::new();
+
+    bench.iter(|| {
+        for chunk in &chunks {
+            let chunk = bencher::black_box(chunk.clone());
+            let index = UncompressedBlock::decompress_chunk_into(
+                &chunk,
+                &meta_data,
+                false,
+                &mut scratch,
+                &mut pixels,
+            )
+            .unwrap();
+
+            bencher::black_box((index, &pixels));
+        }
+    })
+}
+
+fn read_chunks(path: &str) -> (MetaData, Vec<Chunk>) {
+    let file = fs::read(path).unwrap();
+    let reader = exr::block::read(Cursor::new(file), false).unwrap();
+    let meta_data = reader.meta_data().clone();
+    let chunks = reader.all_chunks(false).unwrap().collect::<Result<_, _>>().unwrap();
+    (meta_data, chunks)
+}
+
+benchmark_group!(
+    decompress,
+    zip_allocating,
+    zip_into_scratch,
+    pxr24_allocating,
+    pxr24_into_scratch,
+    dwa_allocating,
+    dwa_into_scratch,
+);
+
+benchmark_main!(decompress);
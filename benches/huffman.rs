@@ -0,0 +1,78 @@
+#[macro_use]
+extern crate bencher;
+
+extern crate exr;
+
+use bencher::Bencher;
+use exr::compression::piz_huffman::*;
+
+/// Noisy samples, most of them close to zero, like the wavelet
+/// coefficients of a grainy image.
+fn grainy_samples() -> Vec<u16> {
+    let mut state: u32 = 0x9e3779b9;
+
+    (0..512 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+
+            // sum of two uniform values is more likely to be small
+            let offset = (state & 0xff) as i32 + ((state >> 8) & 0xff) as i32 - 255;
+            offset as i16 as u16
+        })
+        .collect()
+}
+
+/// Mostly zeroes with some noise, which contains many run length codes.
+fn sparse_samples() -> Vec<u16> {
+    grainy_samples()
+        .into_iter()
+        .map(|sample| {
+            if sample % 7 == 0 {
+                sample
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+fn bench_decompress(
+    bench: &mut Bencher,
+    samples: Vec<u16>,
+    decompress: fn(&[u8], usize) -> exr::error::Result<Vec<u16>>,
+) {
+    let compressed = compress(&samples).unwrap();
+
+    bench.iter(|| {
+        let decompressed = decompress(bencher::black_box(&compressed), samples.len()).unwrap();
+        bencher::black_box(decompressed);
+    })
+}
+
+fn decompress_grainy_with_table(bench: &mut Bencher) {
+    bench_decompress(bench, grainy_samples(), decompress)
+}
+
+fn decompress_grainy_symbol_by_symbol(bench: &mut Bencher) {
+    bench_decompress(bench, grainy_samples(), decompress_symbol_by_symbol)
+}
+
+fn decompress_sparse_with_table(bench: &mut Bencher) {
+    bench_decompress(bench, sparse_samples(), decompress)
+}
+
+fn decompress_sparse_symbol_by_symbol(bench: &mut Bencher) {
+    bench_decompress(bench, sparse_samples(), decompress_symbol_by_symbol)
+}
+
+benchmark_group!(
+    huffman,
+    decompress_grainy_with_table,
+    decompress_grainy_symbol_by_symbol,
+    decompress_sparse_with_table,
+    decompress_sparse_symbol_by_symbol
+);
+
+benchmark_main!(huffman);
@@ -0,0 +1,354 @@
+//! Random access to single blocks of a file, for example the tiles of a
+//! mip-mapped texture. Keeps the file open, and keeps the most recently used
+//! decompressed blocks in memory.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use smallvec::SmallVec;
+
+use crate::{
+    block::{
+        chunk::{Chunk, TileCoordinates},
+        reader::validate_offset_tables,
+        UncompressedBlock,
+    },
+    error::{Error, Result},
+    io::{PeekRead, Tracking},
+    meta::{header::Header, MetaData, OffsetTables},
+};
+
+/// Loads single blocks of a file on demand, and keeps the most recently used
+/// blocks in memory, up to a maximum number of bytes.
+///
+/// The blocks of a tiled image are its tiles, including the tiles of all
+/// mip map or rip map levels. The blocks of a scan line image are groups of
+/// lines, with the tile index `(0, y / lines_per_block)`. Deep data is not
+/// supported.
+///
+/// All methods take `&self`, so that multiple threads can look up blocks at the
+/// same time. The file is locked only while reading the compressed bytes of a
+/// block, and the blocks are decompressed without holding any lock.
+/// If multiple threads request the same missing block at the same time,
+/// each of them may decompress it.
+#[derive(Debug)]
+pub struct TileCache<R> {
+    meta_data: MetaData,
+    offset_tables: OffsetTables,
+    pedantic: bool,
+
+    /// The index of each block in the offset table of its header.
+    block_indices: SmallVec<[HashMap<TileCoordinates, usize>; 3]>,
+
+    /// The maximum number of bytes of all cached blocks.
+    max_byte_size: usize,
+
+    file: Mutex<PeekRead<Tracking<R>>>,
+    blocks: Mutex<LeastRecentlyUsed>,
+}
+
+/// The layer index and the tile coordinates of a block.
+type BlockKey = (usize, TileCoordinates);
+
+/// The cached blocks, and the order in which they were used.
+#[derive(Debug, Default)]
+struct LeastRecentlyUsed {
+    /// The cached blocks, and the time at which each block was last used.
+    blocks: HashMap<BlockKey, (Arc<UncompressedBlock>, u64)>,
+
+    /// The cached blocks by the time they were last used, beginning with the
+    /// oldest.
+    usage_order: BTreeMap<u64, BlockKey>,
+
+    /// Increases with each use of a block.
+    current_time: u64,
+
+    /// The number of pixel bytes of all cached blocks.
+    byte_size: usize,
+}
+
+impl TileCache<BufReader<File>> {
+    /// Open the file and read its meta data, keeping the file open.
+    /// Caches at most the specified number of decompressed bytes.
+    /// Uses relaxed error handling. See `TileCache::new`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or its meta data is
+    /// invalid.
+    pub fn open(path: impl AsRef<Path>, max_byte_size: usize) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?), max_byte_size, false)
+    }
+}
+
+impl<R: Read + Seek> TileCache<R> {
+    /// Read the meta data and the offset tables from the reader,
+    /// which must be buffered and positioned at the start of the file.
+    /// Caches at most the specified number of decompressed bytes.
+    /// If not pedantic and the offset tables are invalid, they are
+    /// reconstructed by reading the whole file. See
+    /// `MetaData::reconstruct_offset_tables`.
+    ///
+    /// # Errors
+    /// Returns an error if the meta data is invalid,
+    /// or if pedantic and the offset tables are invalid.
+    pub fn new(buffered: R, max_byte_size: usize, pedantic: bool) -> Result<Self> {
+        let mut read = PeekRead::new(Tracking::new(buffered));
+        let meta_data = MetaData::read_validated_from_buffered_peekable(&mut read, pedantic)?;
+        let mut offset_tables = MetaData::read_offset_tables(&mut read, &meta_data.headers)?;
+
+        let offset_tables_validation =
+            validate_offset_tables(&meta_data.headers, &offset_tables, read.byte_position());
+
+        if let Err(error) = offset_tables_validation {
+            if pedantic {
+                return Err(error);
+            }
+
+            offset_tables = MetaData::reconstruct_offset_tables(&mut read, &meta_data);
+        }
+
+        let block_indices = meta_data
+            .headers
+            .iter()
+            .map(|header| {
+                header
+                    .blocks_increasing_y_order()
+                    .enumerate()
+                    .map(|(index_in_header, tile)| (tile.location, index_in_header))
+                    .collect()
+            })
+            .collect();
+
+        Ok(Self {
+            meta_data,
+            offset_tables,
+            pedantic,
+            block_indices,
+            max_byte_size,
+            file: Mutex::new(read),
+            blocks: Mutex::new(LeastRecentlyUsed::default()),
+        })
+    }
+
+    /// The meta data of the file.
+    pub const fn meta_data(&self) -> &MetaData {
+        &self.meta_data
+    }
+
+    /// The headers of the file.
+    pub fn headers(&self) -> &[Header] {
+        &self.meta_data.headers
+    }
+
+    /// The decompressed block of the layer at the tile coordinates,
+    /// which contain the tile index and the resolution level index.
+    /// Reads and decompresses the block if it is not cached,
+    /// and removes the least recently used blocks if the cache is full.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such block,
+    /// or if it cannot be read or decompressed.
+    pub fn tile(
+        &self,
+        layer_index: usize,
+        tile: TileCoordinates,
+    ) -> Result<Arc<UncompressedBlock>> {
+        let key = (layer_index, tile);
+
+        let cached_block = self.lock_blocks().get(key);
+        if let Some(block) = cached_block {
+            return Ok(block);
+        }
+
+        let chunk = self.read_chunk(layer_index, tile)?;
+        let block =
+            Arc::new(UncompressedBlock::decompress_chunk(chunk, &self.meta_data, self.pedantic)?);
+
+        self.lock_blocks().insert(key, Arc::clone(&block), self.max_byte_size);
+        Ok(block)
+    }
+
+    /// The number of decompressed bytes of all cached blocks.
+    pub fn cached_byte_size(&self) -> usize {
+        self.lock_blocks().byte_size
+    }
+
+    /// Remove all blocks from the cache.
+    pub fn clear(&self) {
+        *self.lock_blocks() = LeastRecentlyUsed::default();
+    }
+
+    /// Read the compressed block from the file.
+    fn read_chunk(&self, layer_index: usize, tile: TileCoordinates) -> Result<Chunk> {
+        let header =
+            self.headers().get(layer_index).ok_or_else(|| Error::invalid("layer index"))?;
+
+        if header.deep {
+            return Err(Error::unsupported("deep data in a tile cache"));
+        }
+
+        let &index_in_header = self.block_indices[layer_index]
+            .get(&tile)
+            .ok_or_else(|| Error::invalid("tile coordinates"))?;
+
+        // reconstructed offset tables contain zero for missing chunks
+        let offset = self.offset_tables[layer_index][index_in_header];
+        if offset == 0 {
+            return Err(Error::invalid("missing chunk"));
+        }
+
+        let chunk = {
+            let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+            file.skip_to(usize::try_from(offset)?)?;
+            Chunk::read(&mut *file, &self.meta_data)?
+        };
+
+        if chunk.layer_index != layer_index
+            || header.get_block_data_indices(&chunk.compressed_block)? != tile
+        {
+            return Err(Error::invalid("chunk offset table"));
+        }
+
+        Ok(chunk)
+    }
+
+    fn lock_blocks(&self) -> MutexGuard<'_, LeastRecentlyUsed> {
+        // the cache is never left in an inconsistent state, even if a thread panicked
+        self.blocks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl LeastRecentlyUsed {
+    /// The cached block, which is marked as the most recently used block.
+    fn get(&mut self, key: BlockKey) -> Option<Arc<UncompressedBlock>> {
+        let (block, last_used) = self.blocks.get_mut(&key)?;
+
+        self.usage_order.remove(last_used);
+        self.current_time += 1;
+        *last_used = self.current_time;
+        self.usage_order.insert(self.current_time, key);
+
+        Some(Arc::clone(block))
+    }
+
+    /// Add the block as the most recently used block,
+    /// and remove the least recently used blocks until all blocks fit into the
+    /// maximum size.
+    fn insert(&mut self, key: BlockKey, block: Arc<UncompressedBlock>, max_byte_size: usize) {
+        if self.get(key).is_some() {
+            return; // another thread inserted the block in the meantime
+        }
+
+        self.current_time += 1;
+        self.byte_size += block.data.len();
+        self.usage_order.insert(self.current_time, key);
+        self.blocks.insert(key, (block, self.current_time));
+
+        while self.byte_size > max_byte_size {
+            let Some((_, oldest_key)) = self.usage_order.pop_first() else {
+                break;
+            };
+            let (oldest_block, _) = self.blocks.remove(&oldest_key).expect("cache order bug");
+            self.byte_size -= oldest_block.data.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::Vec2;
+
+    const PATH: &str = "tests/images/valid/openexr/MultiResolution/Bonita.exr";
+
+    #[test]
+    fn tiles_match_all_chunks() {
+        let cache = TileCache::open(PATH, usize::MAX).unwrap();
+        let header = cache.headers()[0].clone();
+
+        let reader = crate::block::read(BufReader::new(File::open(PATH).unwrap()), false).unwrap();
+        let meta_data = reader.meta_data().clone();
+
+        for chunk in reader.all_chunks(false).unwrap() {
+            let chunk = chunk.unwrap();
+            let tile = header.get_block_data_indices(&chunk.compressed_block).unwrap();
+            let expected = UncompressedBlock::decompress_chunk(chunk, &meta_data, false).unwrap();
+
+            // the second lookup is served from the cache
+            assert_eq!(*cache.tile(0, tile).unwrap(), expected);
+            assert_eq!(*cache.tile(0, tile).unwrap(), expected);
+        }
+
+        assert!(cache.cached_byte_size() > 0);
+        cache.clear();
+        assert_eq!(cache.cached_byte_size(), 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_tiles() {
+        let first_tile = TileCoordinates {
+            tile_index: Vec2(0, 0),
+            level_index: Vec2(0, 0),
+        };
+
+        let second_tile = TileCoordinates {
+            tile_index: Vec2(1, 0),
+            ..first_tile
+        };
+
+        let tile_byte_size =
+            TileCache::open(PATH, usize::MAX).unwrap().tile(0, first_tile).unwrap().data.len();
+
+        // only two tiles fit into the cache
+        let cache = TileCache::open(PATH, tile_byte_size * 2).unwrap();
+        let first = cache.tile(0, first_tile).unwrap();
+        cache.tile(0, second_tile).unwrap();
+        cache.tile(0, first_tile).unwrap();
+
+        // removes the second tile, which is the least recently used tile
+        cache
+            .tile(
+                0,
+                TileCoordinates {
+                    tile_index: Vec2(2, 0),
+                    ..first_tile
+                },
+            )
+            .unwrap();
+        assert_eq!(cache.cached_byte_size(), tile_byte_size * 2);
+
+        let blocks = cache.lock_blocks();
+        assert!(blocks.blocks.contains_key(&(0, first_tile)));
+        assert!(!blocks.blocks.contains_key(&(0, second_tile)));
+        assert!(Arc::ptr_eq(&blocks.blocks[&(0, first_tile)].0, &first));
+    }
+
+    #[test]
+    fn parallel_lookups() {
+        let cache = TileCache::open(PATH, 1024 * 1024).unwrap();
+        let tiles: Vec<TileCoordinates> =
+            cache.headers()[0].blocks_increasing_y_order().map(|tile| tile.location).collect();
+
+        std::thread::scope(|scope| {
+            for thread_index in 0..4 {
+                let cache = &cache;
+                let tiles = &tiles;
+
+                scope.spawn(move || {
+                    for &tile in tiles.iter().skip(thread_index) {
+                        let block = cache.tile(0, tile).unwrap();
+                        assert_eq!(block.index.level, tile.level_index);
+                    }
+                });
+            }
+        });
+
+        assert!(cache.cached_byte_size() <= 1024 * 1024);
+    }
+}
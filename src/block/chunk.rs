@@ -283,16 +283,14 @@ impl CompressedTileBlock {
 impl CompressedDeepScanLineBlock {
     /// Without validation, write this instance to the byte stream.
     pub fn write<W: Write>(&self, write: &mut W) -> UnitResult {
-        debug_assert_ne!(
-            self.compressed_sample_data_le.len(),
-            0,
-            "empty blocks should not be put in the file bug"
-        );
-
+        // the sample data is empty if no pixel in this block contains any samples
         i32::write_le(self.y_coordinate, write)?;
-        u64::write_le(self.compressed_pixel_offset_table.len() as u64, write)?;
-        u64::write_le(self.compressed_sample_data_le.len() as u64, write)?; // TODO just guessed
-        u64::write_le(self.decompressed_sample_data_size as u64, write)?;
+        u64::write_le(
+            usize_to_u64(self.compressed_pixel_offset_table.len(), "deep table size")?,
+            write,
+        )?;
+        u64::write_le(usize_to_u64(self.compressed_sample_data_le.len(), "deep size")?, write)?;
+        u64::write_le(usize_to_u64(self.decompressed_sample_data_size, "raw deep size")?, write)?;
         i8::write_slice_le(write, &self.compressed_pixel_offset_table)?;
         u8::write_slice_le(write, &self.compressed_sample_data_le)?;
         Ok(())
@@ -335,16 +333,14 @@ impl CompressedDeepScanLineBlock {
 impl CompressedDeepTileBlock {
     /// Without validation, write this instance to the byte stream.
     pub fn write<W: Write>(&self, write: &mut W) -> UnitResult {
-        debug_assert_ne!(
-            self.compressed_sample_data_le.len(),
-            0,
-            "empty blocks should not be put in the file bug"
-        );
-
+        // the sample data is empty if no pixel in this block contains any samples
         self.coordinates.write(write)?;
-        u64::write_le(self.compressed_pixel_offset_table.len() as u64, write)?;
-        u64::write_le(self.compressed_sample_data_le.len() as u64, write)?; // TODO just guessed
-        u64::write_le(self.decompressed_sample_data_size as u64, write)?;
+        u64::write_le(
+            usize_to_u64(self.compressed_pixel_offset_table.len(), "deep table size")?,
+            write,
+        )?;
+        u64::write_le(usize_to_u64(self.compressed_sample_data_le.len(), "deep size")?, write)?;
+        u64::write_le(usize_to_u64(self.decompressed_sample_data_size, "raw deep size")?, write)?;
         i8::write_slice_le(write, &self.compressed_pixel_offset_table)?;
         u8::write_slice_le(write, &self.compressed_sample_data_le)?;
         Ok(())
@@ -355,7 +351,7 @@ impl CompressedDeepTileBlock {
         let coordinates = TileCoordinates::read(read)?;
         let compressed_pixel_offset_table_size =
             u64_to_usize(u64::read_le(read)?, "deep table size")?;
-        let compressed_sample_data_size = u64_to_usize(u64::read_le(read)?, "deep size")?; // TODO u64 just guessed
+        let compressed_sample_data_size = u64_to_usize(u64::read_le(read)?, "deep size")?;
         let decompressed_sample_data_size = u64_to_usize(u64::read_le(read)?, "raw deep size")?;
 
         let compressed_pixel_offset_table = i8::read_vec_le(
@@ -384,7 +380,7 @@ impl CompressedDeepTileBlock {
 }
 
 use crate::{
-    error::{i32_to_usize, u64_to_usize, usize_to_i32, Error, Result, UnitResult},
+    error::{i32_to_usize, u64_to_usize, usize_to_i32, usize_to_u64, Error, Result, UnitResult},
     math::Vec2,
 };
 
@@ -411,6 +407,17 @@ impl Chunk {
 
     /// Read the value without validating.
     pub fn read(read: &mut impl Read, meta_data: &MetaData) -> Result<Self> {
+        Self::read_limited(read, meta_data, usize::MAX)
+    }
+
+    /// Read the value without validating.
+    /// Returns an error instead of reading a block with more bytes than the
+    /// limit, for example more bytes than the file contains.
+    pub(crate) fn read_limited(
+        read: &mut impl Read,
+        meta_data: &MetaData,
+        max_byte_size: usize,
+    ) -> Result<Self> {
         let layer_number = i32_to_usize(
             if meta_data.requirements.is_multilayer() {
                 i32::read_le(read)?
@@ -427,7 +434,7 @@ impl Chunk {
         }
 
         let header = &meta_data.headers[layer_number];
-        let max_block_byte_size = header.max_block_byte_size();
+        let max_block_byte_size = header.max_block_byte_size().min(max_byte_size);
 
         let chunk = Self {
             layer_index: layer_number,
@@ -0,0 +1,756 @@
+//! Tidy deep data: sort, split and merge the samples of each deep pixel.
+//!
+//! Follows the rules of the OpenEXR document "Interpreting Deep Pixels":
+//! The samples of each pixel are sorted by depth. Volumetric samples,
+//! which extend from `Z` to `ZBack`, are split where they overlap
+//! other samples. Samples that cover the same depth range are merged.
+//! The resulting samples are sorted and do not overlap, which is called "tidy".
+//! Tidy samples can be composited front to back with the `over` operation.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, Write},
+};
+
+use half::f16;
+
+use crate::{
+    block::{
+        lines::DeepLineRef, reader::ChunksReader, samples::Sample, writer::ChunksWriter,
+        UncompressedDeepBlock,
+    },
+    compression::ByteVec,
+    error::{Error, Result, UnitResult},
+    io::Data,
+    meta::attribute::{ChannelList, DeepImageState, SampleType, Text},
+};
+
+/// Read a file, tidy the samples of all deep layers, and write the result.
+///
+/// Flat layers are copied without decompressing them.
+/// Sets the `deep_image_state` of all deep layers to `DeepImageState::Tidy`,
+/// and updates their `max_samples_per_pixel`, as splitting may add samples.
+/// Holds all chunks of the file in memory.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or written,
+/// or if a deep layer has no `Z` channel.
+pub fn tidy_deep_data(
+    read: impl Read + Seek,
+    write: impl Write + Seek,
+    pedantic: bool,
+) -> UnitResult {
+    let chunks = super::read(read, pedantic)?.all_chunks(pedantic)?;
+    let meta_data = chunks.meta_data().clone();
+    let mut headers = meta_data.headers.clone();
+
+    let mut tidy_chunks = HashMap::with_capacity(chunks.expected_chunk_count());
+    let mut max_samples_per_pixel = vec![0; headers.len()];
+
+    for chunk in chunks {
+        let chunk = chunk?;
+        let header = meta_data
+            .headers
+            .get(chunk.layer_index)
+            .ok_or_else(|| Error::invalid("chunk layer index"))?;
+
+        let coordinates = header.get_block_data_indices(&chunk.compressed_block)?;
+
+        let chunk = if header.deep {
+            let block = UncompressedDeepBlock::decompress_chunk(chunk, &meta_data, pedantic)?
+                .tidy(&header.channels)?;
+
+            let max_samples = &mut max_samples_per_pixel[block.index.layer];
+            *max_samples = block.pixel_sample_counts().fold(*max_samples, usize::max);
+
+            block.compress_to_chunk(&meta_data.headers)?
+        } else {
+            chunk
+        };
+
+        if tidy_chunks.insert((chunk.layer_index, coordinates), chunk).is_some() {
+            return Err(Error::invalid("duplicate chunk"));
+        }
+    }
+
+    for (header, max_samples) in headers.iter_mut().zip(max_samples_per_pixel) {
+        if header.deep {
+            header.max_samples_per_pixel = Some(max_samples);
+            header.own_attributes.deep_image_state = Some(DeepImageState::Tidy);
+        }
+    }
+
+    super::write(write, headers, pedantic, move |meta_data, chunk_writer| {
+        for (layer_index, header) in meta_data.headers.iter().enumerate() {
+            for (index_in_header, tile) in header.enumerate_ordered_blocks() {
+                let chunk = tidy_chunks
+                    .remove(&(layer_index, tile.location))
+                    .ok_or_else(|| Error::invalid("missing chunk"))?;
+
+                chunk_writer.write_chunk(index_in_header, chunk)?;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+impl UncompressedDeepBlock {
+    /// Sort the samples of each pixel by depth, split overlapping volumetric
+    /// samples, and merge samples that cover the same depth range.
+    /// The channels must contain a `Z` channel and may contain a `ZBack`
+    /// channel. Channels with unsigned integer samples are not blended,
+    /// and contain the value of the front-most sample when merging.
+    /// The number of samples in a pixel may change.
+    ///
+    /// # Errors
+    /// Returns an error if there is no `Z` channel,
+    /// or if the channels do not match this block.
+    pub fn tidy(&self, channels: &ChannelList) -> Result<Self> {
+        let sample_types: Vec<SampleType> =
+            channels.list.iter().map(|channel| channel.sample_type).collect();
+
+        let roles = DeepChannelRoles::new(
+            channels.list.iter().map(|channel| (&channel.name, channel.sample_type)),
+        )?;
+
+        let lines: Vec<DeepLineRef<'_>> = self.lines(channels).collect();
+        let mut lines = lines.chunks_exact(channels.list.len());
+
+        let mut pixel = DeepPixel::default();
+        let mut tidy_sample_counts = Vec::new();
+        let mut tidy_bytes: Vec<ByteVec> = channels.list.iter().map(|_| ByteVec::new()).collect();
+
+        Self::from_lines(channels, self.index, |line, sample_counts, bytes| {
+            // tidy all channels of a line when its first channel is requested
+            if line.channel == 0 {
+                let channel_lines =
+                    lines.next().ok_or_else(|| Error::invalid("deep block line count"))?;
+
+                tidy_sample_counts.clear();
+                tidy_bytes.iter_mut().for_each(Vec::clear);
+
+                tidy_line(
+                    channel_lines,
+                    &sample_types,
+                    &roles,
+                    &mut pixel,
+                    &mut tidy_sample_counts,
+                    &mut tidy_bytes,
+                );
+            }
+
+            sample_counts.extend_from_slice(&tidy_sample_counts);
+            bytes.extend_from_slice(&tidy_bytes[line.channel]);
+            Ok(())
+        })
+    }
+}
+
+/// Tidy the samples of all channels of a single line.
+/// Appends the new number of samples of each pixel,
+/// and the native-endian bytes of each channel.
+fn tidy_line(
+    channel_lines: &[DeepLineRef<'_>],
+    sample_types: &[SampleType],
+    roles: &DeepChannelRoles,
+    pixel: &mut DeepPixel,
+    sample_counts: &mut Vec<u32>,
+    channel_bytes: &mut [ByteVec],
+) {
+    let first_line = channel_lines[0];
+
+    for x in 0..first_line.location.sample_count {
+        let sample_range = first_line.pixel_sample_range(x);
+
+        pixel.clear(channel_lines.len());
+        for sample_index in sample_range.clone() {
+            let values = channel_lines
+                .iter()
+                .zip(sample_types)
+                .map(|(line, &sample_type)| read_sample(line, sample_type, sample_index).to_f32());
+
+            pixel.push_sample(values, roles);
+        }
+
+        let mut sample_count: u32 = 0;
+        pixel.tidy(&roles.roles, |front, back, values, source| {
+            sample_count += 1;
+
+            let channels = channel_lines.iter().zip(sample_types).zip(channel_bytes.iter_mut());
+            for (channel_index, ((line, &sample_type), bytes)) in channels.enumerate() {
+                let value = match roles.roles[channel_index] {
+                    Role::Depth => front,
+                    Role::DepthBack => back,
+                    Role::Alpha
+                    | Role::Color {
+                        ..
+                    } => values[channel_index],
+
+                    Role::Front => {
+                        let byte_size = sample_type.bytes_per_sample();
+                        let start = (sample_range.start + source) * byte_size;
+                        bytes.extend_from_slice(&line.value[start..start + byte_size]);
+                        continue;
+                    }
+                };
+
+                write_sample(sample_type, value, bytes);
+            }
+        });
+
+        sample_counts.push(sample_count);
+    }
+}
+
+/// Read a single native-endian sample from a deep line.
+fn read_sample(line: &DeepLineRef<'_>, sample_type: SampleType, sample_index: usize) -> Sample {
+    let start = sample_index * sample_type.bytes_per_sample();
+    let bytes = &mut &line.value[start..start + sample_type.bytes_per_sample()];
+
+    // the deep block guarantees that each line contains all of its samples
+    let sample = match sample_type {
+        SampleType::F16 => f16::read_ne(bytes).map(Sample::F16),
+        SampleType::F32 => f32::read_ne(bytes).map(Sample::F32),
+        SampleType::U32 => u32::read_ne(bytes).map(Sample::U32),
+    };
+
+    sample.expect("deep line sample bug")
+}
+
+/// Append a single native-endian sample to the bytes of a deep line.
+fn write_sample(sample_type: SampleType, value: f32, bytes: &mut ByteVec) {
+    match sample_type {
+        SampleType::F16 => bytes.extend_from_slice(&f16::from_f32(value).to_ne_bytes()),
+        SampleType::F32 => bytes.extend_from_slice(&value.to_ne_bytes()),
+        SampleType::U32 => bytes.extend_from_slice(&(value as u32).to_ne_bytes()),
+    }
+}
+
+/// How a deep channel takes part in sorting, splitting, merging and
+/// compositing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    /// The front depth of each sample.
+    Depth,
+
+    /// The back depth of each sample.
+    DepthBack,
+
+    /// The opacity of each sample, composited with itself.
+    Alpha,
+
+    /// A premultiplied value, composited with the alpha at this index.
+    /// The index points to the implicit opaque alpha if there is no alpha
+    /// channel.
+    Color {
+        alpha: usize,
+    },
+
+    /// An integer value that cannot be blended, such as an object id.
+    /// Contains the value of the first sample when merging.
+    Front,
+}
+
+/// How each channel of a deep layer takes part in sorting, splitting, merging
+/// and compositing. The `Z` channel contains the front depth of each sample.
+/// The optional `ZBack` channel contains the back depth of volumetric samples.
+/// Channels with unsigned integer samples are not blended.
+/// Alpha channels are named `A`, or `AR`, `AG` and `AB` for the opacity of
+/// each color. The `R`, `G` and `B` channels are blended using their own alpha
+/// channel if it exists, and all other color channels use the `A` channel.
+/// Alpha channels with the same prefix are preferred (for example, `diffuse.R`
+/// uses `diffuse.AR` or `diffuse.A` before `AR` or `A`).
+/// Samples without any alpha channel are assumed to be opaque.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DeepChannelRoles {
+    /// The role of each channel.
+    pub roles: Vec<Role>,
+
+    /// The index of the `Z` channel.
+    pub depth: usize,
+
+    /// The index of the `ZBack` channel, if any.
+    pub depth_back: Option<usize>,
+}
+
+impl DeepChannelRoles {
+    /// Find the role of each channel.
+    /// Returns an error if there is no `Z` channel.
+    pub fn new<'t>(channels: impl Iterator<Item = (&'t Text, SampleType)>) -> Result<Self> {
+        let channels: Vec<(&Text, SampleType)> = channels.collect();
+
+        let depth = channels
+            .iter()
+            .position(|(name, _)| name.bytes() == b"Z")
+            .ok_or_else(|| Error::invalid("deep data without a `Z` channel"))?;
+
+        let depth_back = channels.iter().position(|(name, _)| name.bytes() == b"ZBack");
+
+        // the implicit opaque alpha is stored right after the last channel
+        let opaque = channels.len();
+        let roles = channels
+            .iter()
+            .enumerate()
+            .map(|(index, &(name, sample_type))| {
+                if index == depth {
+                    Role::Depth
+                } else if Some(index) == depth_back {
+                    Role::DepthBack
+                } else if sample_type == SampleType::U32 {
+                    Role::Front
+                } else if is_alpha(name) {
+                    Role::Alpha
+                } else {
+                    Role::Color {
+                        alpha: alpha_channel_index(name, &channels).unwrap_or(opaque),
+                    }
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            roles,
+            depth,
+            depth_back,
+        })
+    }
+}
+
+/// Whether this channel is named `A`, `AR`, `AG` or `AB`, with an optional
+/// prefix like `diffuse.A`.
+fn is_alpha(name: &Text) -> bool {
+    let (_, suffix) = split_prefix(name.bytes());
+    matches!(suffix, b"A" | b"AR" | b"AG" | b"AB")
+}
+
+/// Find the alpha channel of the specified color channel.
+/// `R`, `G` and `B` use `AR`, `AG` and `AB` before `A`.
+/// Alpha channels with the same prefix come before the ones without prefix.
+fn alpha_channel_index(name: &Text, channels: &[(&Text, SampleType)]) -> Option<usize> {
+    let (prefix, suffix) = split_prefix(name.bytes());
+    let color_alpha: Option<&[u8]> = match suffix {
+        b"R" => Some(b"AR"),
+        b"G" => Some(b"AG"),
+        b"B" => Some(b"AB"),
+        _ => None,
+    };
+
+    let find = |prefix: &[u8], alpha: &[u8]| {
+        channels.iter().position(|&(other, sample_type)| {
+            sample_type != SampleType::U32 && split_prefix(other.bytes()) == (prefix, alpha)
+        })
+    };
+
+    let find_with_prefix = |prefix: &[u8]| {
+        color_alpha.and_then(|alpha| find(prefix, alpha)).or_else(|| find(prefix, b"A"))
+    };
+
+    find_with_prefix(prefix).or_else(|| find_with_prefix(&[]))
+}
+
+/// Split a channel name after its last dot, into the prefix and the suffix.
+/// The prefix is empty if the name contains no dot.
+fn split_prefix(name: &[u8]) -> (&[u8], &[u8]) {
+    let prefix_len = name.iter().rposition(|&byte| byte == b'.').map_or(0, |dot| dot + 1);
+    name.split_at(prefix_len)
+}
+
+/// The samples of a single deep pixel. Can be reused for multiple pixels.
+#[derive(Debug, Default)]
+pub(crate) struct DeepPixel {
+    /// The front and back depth of each sample.
+    depths: Vec<(f32, f32)>,
+
+    /// The values of all channels of each sample, sample after sample.
+    /// Contains an additional opaque alpha value at the end of each sample.
+    values: Vec<f32>,
+
+    /// The value count of a single sample.
+    stride: usize,
+
+    /// All distinct depths of the pixel, sorted from front to back.
+    boundaries: Vec<f32>,
+
+    /// Temporary values while merging samples.
+    merged: Vec<f32>,
+
+    /// Temporary values while splitting samples.
+    piece: Vec<f32>,
+}
+
+impl DeepPixel {
+    /// Remove all samples, preparing for a pixel with this number of channels.
+    pub fn clear(&mut self, channel_count: usize) {
+        self.stride = channel_count + 1;
+        self.depths.clear();
+        self.values.clear();
+    }
+
+    /// Add a sample to this pixel, containing one value for each channel.
+    pub fn push_sample(&mut self, values: impl Iterator<Item = f32>, roles: &DeepChannelRoles) {
+        let start = self.values.len();
+        self.values.extend(values);
+        self.values.push(1.0);
+        debug_assert_eq!(self.values.len() - start, self.stride, "deep channel count bug");
+
+        let front = self.values[start + roles.depth];
+        let back = roles.depth_back.map_or(front, |back| self.values[start + back]);
+
+        // a back depth in front of the front depth is treated as a point sample
+        self.depths.push((front, back.max(front)));
+    }
+
+    /// The front depth of the sample with the smallest front depth, and its
+    /// index.
+    pub fn front_sample(&self) -> Option<(usize, f32)> {
+        self.depths
+            .iter()
+            .map(|&(front, _)| front)
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    /// Split and merge all samples of this pixel. Calls the closure for each
+    /// tidy sample, from front to back, with the front depth, the back
+    /// depth, the value of each channel, and the index of the first
+    /// original sample that contributed to it.
+    pub fn tidy(&mut self, roles: &[Role], mut tidy_sample: impl FnMut(f32, f32, &[f32], usize)) {
+        self.boundaries.clear();
+        self.boundaries.extend(self.depths.iter().flat_map(|&(front, back)| [front, back]));
+        self.boundaries.sort_unstable_by(f32::total_cmp);
+        self.boundaries.dedup_by(|a, b| a.total_cmp(b).is_eq());
+
+        self.merged.resize(self.stride, 0.0);
+        self.piece.resize(self.stride, 0.0);
+
+        for (boundary_index, &depth) in self.boundaries.iter().enumerate() {
+            // point samples at this depth come before volumes starting at this depth.
+            // the boundaries are copies of the sample depths, so they are compared exactly
+            let mut source = None;
+            for (sample, &(front, back)) in self.depths.iter().enumerate() {
+                if front.total_cmp(&depth).is_eq() && back.total_cmp(&depth).is_eq() {
+                    let values = &self.values[sample * self.stride..(sample + 1) * self.stride];
+                    merge_into(&mut self.merged, &mut source, sample, values, roles);
+                }
+            }
+
+            if let Some(source) = source {
+                tidy_sample(depth, depth, &self.merged, source);
+            }
+
+            let next_depth = match self.boundaries.get(boundary_index + 1) {
+                Some(&next_depth) => next_depth,
+                None => break,
+            };
+
+            source = None;
+            for (sample, &(front, back)) in self.depths.iter().enumerate() {
+                if front < back && front <= depth && next_depth <= back {
+                    let values = &self.values[sample * self.stride..(sample + 1) * self.stride];
+                    let fraction = (next_depth - depth) / (back - front);
+                    split(values, fraction, &mut self.piece, roles);
+                    merge_into(&mut self.merged, &mut source, sample, &self.piece, roles);
+                }
+            }
+
+            if let Some(source) = source {
+                tidy_sample(depth, next_depth, &self.merged, source);
+            }
+        }
+    }
+
+    /// Split, merge and composite all samples of this pixel.
+    /// Returns the composited value of each channel.
+    pub fn composite(&mut self, roles: &[Role]) -> Vec<f32> {
+        let mut accumulated = vec![0.0; self.stride];
+        self.tidy(roles, |_, _, values, _| composite_over(&mut accumulated, values, roles));
+        accumulated
+    }
+}
+
+/// The alpha of a sample, given the index of its value.
+fn alpha_index(index: usize, role: Option<&Role>) -> usize {
+    match role {
+        Some(Role::Color {
+            alpha,
+        }) => *alpha,
+        _ => index, // alpha channels and the implicit opaque alpha
+    }
+}
+
+/// Whether the value at this index is blended when compositing.
+fn is_blended(role: Option<&Role>) -> bool {
+    matches!(role, None | Some(Role::Alpha) | Some(Role::Color { .. }))
+}
+
+/// Split a volumetric sample, keeping the specified fraction of its thickness.
+fn split(values: &[f32], fraction: f32, result: &mut [f32], roles: &[Role]) {
+    for (index, value) in values.iter().enumerate() {
+        let role = roles.get(index);
+        if !is_blended(role) {
+            result[index] = *value;
+            continue;
+        }
+
+        let alpha = values[alpha_index(index, role)];
+        let split_alpha = split_alpha(alpha, fraction);
+
+        result[index] = if alpha_index(index, role) == index {
+            split_alpha
+        } else if alpha <= 0.0 {
+            value * fraction
+        } else {
+            value * split_alpha / alpha
+        };
+    }
+}
+
+fn split_alpha(alpha: f32, fraction: f32) -> f32 {
+    if alpha >= 1.0 {
+        1.0
+    } else {
+        -(fraction * (-alpha).ln_1p()).exp_m1()
+    }
+}
+
+/// Merge a sample into the merged samples, which cover the same depth range.
+/// The source is the index of the first merged sample.
+fn merge_into(
+    merged: &mut [f32],
+    source: &mut Option<usize>,
+    sample: usize,
+    values: &[f32],
+    roles: &[Role],
+) {
+    if source.is_none() {
+        merged.copy_from_slice(values);
+        *source = Some(sample);
+        return;
+    }
+
+    // colors first, as they depend on the alpha values before merging
+    for index in 0..values.len() {
+        let role = roles.get(index);
+        let alpha = alpha_index(index, role);
+        if is_blended(role) && alpha != index {
+            merged[index] = merge_color(merged[alpha], merged[index], values[alpha], values[index]);
+        }
+    }
+
+    for index in 0..values.len() {
+        let role = roles.get(index);
+        if is_blended(role) && alpha_index(index, role) == index {
+            merged[index] = values[index].mul_add(1.0 - merged[index], merged[index]);
+        }
+    }
+}
+
+fn merge_color(alpha1: f32, color1: f32, alpha2: f32, color2: f32) -> f32 {
+    if alpha1 >= 1.0 && alpha2 >= 1.0 {
+        (color1 + color2) / 2.0
+    } else if alpha1 >= 1.0 {
+        color1
+    } else if alpha2 >= 1.0 {
+        color2
+    } else {
+        let alpha = alpha2.mul_add(1.0 - alpha1, alpha1);
+
+        let u1 = -(-alpha1).ln_1p();
+        let v1 = if u1 < alpha1 * f32::MAX {
+            u1 / alpha1
+        } else {
+            1.0
+        };
+
+        let u2 = -(-alpha2).ln_1p();
+        let v2 = if u2 < alpha2 * f32::MAX {
+            u2 / alpha2
+        } else {
+            1.0
+        };
+
+        let u = u1 + u2;
+        let w = if u > 1.0 || alpha < u * f32::MAX {
+            alpha / u
+        } else {
+            1.0
+        };
+
+        color1.mul_add(v1, color2 * v2) * w
+    }
+}
+
+/// Composite a sample behind the accumulated samples.
+fn composite_over(accumulated: &mut [f32], values: &[f32], roles: &[Role]) {
+    // colors first, as they depend on the accumulated alpha before compositing
+    for index in 0..values.len() {
+        let role = roles.get(index);
+        let alpha = alpha_index(index, role);
+        if is_blended(role) && alpha != index {
+            accumulated[index] += (1.0 - accumulated[alpha]) * values[index];
+        }
+    }
+
+    for index in 0..values.len() {
+        let role = roles.get(index);
+        if is_blended(role) && alpha_index(index, role) == index {
+            accumulated[index] += (1.0 - accumulated[index]) * values[index];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{block::BlockIndex, math::Vec2, meta::attribute::ChannelDescription};
+
+    fn channels() -> ChannelList {
+        ChannelList::new(smallvec![
+            ChannelDescription::new("A", SampleType::F32, true),
+            ChannelDescription::new("Z", SampleType::F32, false),
+            ChannelDescription::new("ZBack", SampleType::F32, false),
+            ChannelDescription::new("id", SampleType::U32, false),
+        ])
+    }
+
+    fn block(
+        channels: &ChannelList,
+        sample_counts: &[u32],
+        values: &[Vec<f32>],
+    ) -> UncompressedDeepBlock {
+        let index = BlockIndex {
+            layer: 0,
+            pixel_position: Vec2(0, 0),
+            pixel_size: Vec2(sample_counts.len(), 1),
+            level: Vec2(0, 0),
+        };
+
+        UncompressedDeepBlock::from_lines(channels, index, |line, counts, bytes| {
+            counts.extend_from_slice(sample_counts);
+
+            let sample_type = channels.list[line.channel].sample_type;
+            for &value in &values[line.channel] {
+                write_sample(sample_type, value, bytes);
+            }
+
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    fn values(block: &UncompressedDeepBlock, channels: &ChannelList) -> Vec<Vec<f32>> {
+        block
+            .lines(channels)
+            .map(|line| {
+                let sample_type = channels.list[line.location.channel].sample_type;
+                let count = line.value.len() / sample_type.bytes_per_sample();
+                (0..count).map(|index| read_sample(&line, sample_type, index).to_f32()).collect()
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[Vec<f32>], expected: &[Vec<f32>]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert_eq!(actual.len(), expected.len(), "expected {:?}, found {:?}", expected, actual);
+
+            for (actual, expected) in actual.iter().zip(expected) {
+                assert!(
+                    (actual - expected).abs() < 1e-5,
+                    "expected {}, found {}",
+                    expected,
+                    actual
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tidy_block_sorts_splits_and_merges() {
+        let channels = channels();
+
+        // first pixel: an opaque point sample inside a volume from 0 to 2,
+        // second pixel: unsorted point samples, two of them at the same depth
+        let messy = block(
+            &channels,
+            &[2, 3],
+            &[
+                vec![1.0, 0.75, 0.5, 0.25, 0.5],
+                vec![1.0, 0.0, 5.0, 3.0, 5.0],
+                vec![1.0, 2.0, 0.0, 0.0, 0.0],
+                vec![3.0, 7.0, 1.0, 2.0, 9.0],
+            ],
+        );
+
+        let tidy = messy.tidy(&channels).unwrap();
+        assert_eq!(tidy.pixel_sample_counts().collect::<Vec<_>>(), [3, 2]);
+
+        let expected = [
+            vec![0.5, 1.0, 0.5, 0.25, 0.75],
+            vec![0.0, 1.0, 1.0, 3.0, 5.0],
+            vec![1.0, 1.0, 2.0, 3.0, 5.0],
+            vec![7.0, 3.0, 7.0, 2.0, 1.0],
+        ];
+
+        assert_close(&values(&tidy, &channels), &expected);
+
+        // tidy samples stay the same
+        let tidy_again = tidy.tidy(&channels).unwrap();
+        assert_eq!(tidy_again.pixel_offset_table, tidy.pixel_offset_table);
+        assert_close(&values(&tidy_again, &channels), &expected);
+    }
+
+    #[test]
+    fn color_channels_use_their_own_alpha() {
+        let names = [
+            "A",
+            "AR",
+            "B",
+            "G",
+            "R",
+            "Z",
+            "diffuse.A",
+            "diffuse.AG",
+            "diffuse.G",
+            "diffuse.R",
+            "specular.B",
+        ];
+
+        let names: Vec<Text> = names.iter().map(|&name| Text::from(name)).collect();
+        let roles =
+            DeepChannelRoles::new(names.iter().map(|name| (name, SampleType::F16))).unwrap();
+
+        let color = |alpha| Role::Color {
+            alpha,
+        };
+
+        assert_eq!(
+            roles.roles,
+            [
+                Role::Alpha,
+                Role::Alpha,
+                color(0),
+                color(0),
+                color(1),
+                Role::Depth,
+                Role::Alpha,
+                Role::Alpha,
+                color(7),
+                color(6),
+                color(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn tidy_block_requires_depth() {
+        let channels =
+            ChannelList::new(smallvec![ChannelDescription::new("A", SampleType::F32, true)]);
+
+        let block = block(&channels, &[1], &[vec![1.0]]);
+        assert!(block.tidy(&channels).is_err());
+    }
+}
@@ -225,3 +225,43 @@ impl LineRef<'_> {
         (0..self.location.sample_count).map(move |_| T::read_ne(&mut read))
     }
 }
+
+/// A single line of deep samples of one channel.
+/// Each pixel in this line may contain any number of samples.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct DeepLineRef<'s> {
+    /// Where this line is located inside the image.
+    /// The `sample_count` of the location is the number of pixels in this
+    /// line.
+    pub location: LineIndex,
+
+    /// For each pixel in this line, the number of samples in that pixel
+    /// plus all pixels to the left of it.
+    pub pixel_offset_table: &'s [u32],
+
+    /// The raw bytes of all samples in this line, in native-endian format.
+    /// Must be re-interpreted as slice of f16, f32, or u32,
+    /// according to the channel data type.
+    pub value: &'s [u8],
+}
+
+impl DeepLineRef<'_> {
+    /// The range of sample indices that belong to the pixel
+    /// with the specified horizontal index inside this line.
+    pub fn pixel_sample_range(&self, x: usize) -> Range<usize> {
+        let start = if x == 0 {
+            0
+        } else {
+            self.pixel_offset_table[x - 1] as usize
+        };
+        start..self.pixel_offset_table[x] as usize
+    }
+
+    /// Iterate over all samples in this line, from left to right.
+    /// Use `pixel_sample_range` to find the samples of a pixel.
+    pub fn read_samples<T: crate::io::Data>(&self) -> impl Iterator<Item = Result<T>> + '_ {
+        let mut read = self.value;
+        let sample_count = self.value.len() / T::BYTE_SIZE;
+        (0..sample_count).map(move |_| T::read_ne(&mut read))
+    }
+}
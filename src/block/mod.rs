@@ -7,26 +7,39 @@
 //! Start with the `block::read(...)`
 //! and `block::write(...)` functions.
 
+pub mod cache;
 pub mod reader;
 pub mod writer;
 
 pub mod chunk;
+pub mod deep;
 pub mod lines;
 pub mod samples;
 
-use std::io::{Read, Seek, Write};
+use std::{
+    convert::TryFrom,
+    io::{Read, Seek, Write},
+};
 
 use crate::{
     block::{
         chunk::{
-            Chunk, CompressedBlock, CompressedScanLineBlock, CompressedTileBlock, TileCoordinates,
+            Chunk, CompressedBlock, CompressedDeepScanLineBlock, CompressedDeepTileBlock,
+            CompressedScanLineBlock, CompressedTileBlock, TileCoordinates,
         },
-        lines::{LineIndex, LineRef, LineRefMut, LineSlice},
+        lines::{DeepLineRef, LineIndex, LineRef, LineRefMut, LineSlice},
+    },
+    compression::{
+        convert_deep_current_to_little_endian, convert_deep_little_endian_to_current, ByteVec,
+        DecompressionScratch,
     },
-    compression::ByteVec,
     error::{usize_to_i32, Error, Result, UnitResult},
     math::Vec2,
-    meta::{attribute::ChannelList, header::Header, BlockDescription, Headers, MetaData},
+    meta::{
+        attribute::{ChannelList, IntegerBounds},
+        header::Header,
+        BlockDescription, Headers, MetaData,
+    },
 };
 
 /// Specifies where a block of pixel data should be placed in the actual image.
@@ -69,6 +82,30 @@ pub struct UncompressedBlock {
     pub data: ByteVec,
 }
 
+/// Contains a block of deep pixel data and where that data should be placed in
+/// the actual image. Each pixel may contain any number of samples, including
+/// none.
+///
+/// The sample bytes must be encoded in native-endian format.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UncompressedDeepBlock {
+    /// Location of the data inside the image.
+    pub index: BlockIndex,
+
+    /// The pixel offset table, containing one entry for each pixel in the
+    /// block, row by row. Each entry is the number of samples in that pixel
+    /// plus all pixels to the left of it in the same row.
+    /// The last entry of a row is the sample count of the whole row.
+    pub pixel_offset_table: Vec<u32>,
+
+    /// The samples of all pixels in this block.
+    /// For each line in the block, for each channel, the samples of all pixels
+    /// in that line are contiguous. Stores all samples of the first channel,
+    /// then all samples of the second channel, and so on. This data is in
+    /// native-endian format.
+    pub sample_data: ByteVec,
+}
+
 /// Immediately reads the meta data from the file.
 ///
 /// Then, returns a reader that can be used to read all pixel blocks.
@@ -127,6 +164,65 @@ pub fn enumerate_ordered_header_block_indices(
     })
 }
 
+/// Find the header, the absolute pixel section, and the block index of a flat
+/// chunk. Returns an error for deep chunks.
+fn locate_flat_chunk<'m>(
+    chunk: &Chunk,
+    meta_data: &'m MetaData,
+) -> Result<(&'m Header, IntegerBounds, BlockIndex)> {
+    let header: &Header = meta_data
+        .headers
+        .get(chunk.layer_index)
+        .ok_or_else(|| Error::invalid("chunk layer index"))?;
+
+    if let CompressedBlock::DeepScanLine(_) | CompressedBlock::DeepTile(_) = chunk.compressed_block
+    {
+        return Err(Error::invalid("deep data chunk cannot be decompressed into a flat block"));
+    }
+
+    let tile_data_indices = header.get_block_data_indices(&chunk.compressed_block)?;
+    let absolute_indices = header.get_absolute_block_pixel_coordinates(tile_data_indices)?;
+
+    absolute_indices.validate(Some(header.layer_size))?;
+
+    let index = BlockIndex {
+        layer: chunk.layer_index,
+        pixel_position: absolute_indices.position.to_usize("data indices start")?,
+        level: tile_data_indices.level_index,
+        pixel_size: absolute_indices.size,
+    };
+
+    Ok((header, absolute_indices, index))
+}
+
+/// Decompress a located flat chunk into a buffer of exactly the block byte
+/// size.
+fn decompress_flat_chunk(
+    chunk: &Chunk,
+    header: &Header,
+    absolute_indices: IntegerBounds,
+    pedantic: bool,
+    scratch: &mut DecompressionScratch,
+    pixels_ne: &mut [u8],
+) -> UnitResult {
+    let compressed_pixels_le = match &chunk.compressed_block {
+        CompressedBlock::Tile(tile) => &tile.compressed_pixels_le,
+        CompressedBlock::ScanLine(line) => &line.compressed_pixels_le,
+        CompressedBlock::DeepScanLine(_) | CompressedBlock::DeepTile(_) => {
+            unreachable!("deep chunk location bug")
+        }
+    };
+
+    header.compression.decompress_image_section_from_le_into(
+        header,
+        compressed_pixels_le,
+        absolute_indices,
+        pedantic,
+        scratch,
+        pixels_ne,
+    )
+}
+
 impl UncompressedBlock {
     /// Decompress the possibly compressed chunk and returns an
     /// `UncompressedBlock`.
@@ -134,15 +230,7 @@ impl UncompressedBlock {
     #[inline]
     #[must_use]
     pub fn decompress_chunk(chunk: Chunk, meta_data: &MetaData, pedantic: bool) -> Result<Self> {
-        let header: &Header = meta_data
-            .headers
-            .get(chunk.layer_index)
-            .ok_or_else(|| Error::invalid("chunk layer index"))?;
-
-        let tile_data_indices = header.get_block_data_indices(&chunk.compressed_block)?;
-        let absolute_indices = header.get_absolute_block_pixel_coordinates(tile_data_indices)?;
-
-        absolute_indices.validate(Some(header.layer_size))?;
+        let (header, absolute_indices, index) = locate_flat_chunk(&chunk, meta_data)?;
 
         match chunk.compressed_block {
             CompressedBlock::Tile(CompressedTileBlock {
@@ -159,18 +247,65 @@ impl UncompressedBlock {
                     absolute_indices,
                     pedantic,
                 )?,
-                index: BlockIndex {
-                    layer: chunk.layer_index,
-                    pixel_position: absolute_indices.position.to_usize("data indices start")?,
-                    level: tile_data_indices.level_index,
-                    pixel_size: absolute_indices.size,
-                },
+                index,
             }),
 
-            _ => Err(Error::unsupported("deep data not supported yet")),
+            CompressedBlock::DeepScanLine(_) | CompressedBlock::DeepTile(_) => {
+                unreachable!("deep chunk location bug")
+            }
         }
     }
 
+    /// Decompress the possibly compressed chunk into the buffer, which is
+    /// resized to the byte size of the block. Returns where the block is
+    /// placed in the image. Reusing the same buffer and scratch space for
+    /// all chunks of an image avoids allocating memory for each block.
+    ///
+    /// # Errors
+    /// Returns an error if the chunk is not a flat block of this image,
+    /// or if its data cannot be decompressed.
+    pub fn decompress_chunk_into(
+        chunk: &Chunk,
+        meta_data: &MetaData,
+        pedantic: bool,
+        scratch: &mut DecompressionScratch,
+        pixels_ne: &mut ByteVec,
+    ) -> Result<BlockIndex> {
+        let (header, absolute_indices, index) = locate_flat_chunk(chunk, meta_data)?;
+        pixels_ne.resize(absolute_indices.size.area() * header.channels.bytes_per_pixel, 0);
+
+        decompress_flat_chunk(chunk, header, absolute_indices, pedantic, scratch, pixels_ne)?;
+        Ok(index)
+    }
+
+    /// Decompress the possibly compressed chunk into the start of the slice,
+    /// which must be at least as large as the block, for example
+    /// `Header::max_block_byte_size()` bytes. Returns where the block is placed
+    /// in the image, which also determines how many bytes have been
+    /// written. Reusing the same scratch space for all chunks of an image
+    /// avoids allocating memory for each block.
+    ///
+    /// # Errors
+    /// Returns an error if the chunk is not a flat block of this image,
+    /// if the slice is too small, or if the data cannot be decompressed.
+    pub fn decompress_chunk_into_slice(
+        chunk: &Chunk,
+        meta_data: &MetaData,
+        pedantic: bool,
+        scratch: &mut DecompressionScratch,
+        pixels_ne: &mut [u8],
+    ) -> Result<BlockIndex> {
+        let (header, absolute_indices, index) = locate_flat_chunk(chunk, meta_data)?;
+
+        let byte_size = absolute_indices.size.area() * header.channels.bytes_per_pixel;
+        let pixels_ne = pixels_ne
+            .get_mut(..byte_size)
+            .ok_or_else(|| Error::invalid("buffer too small for decompressed block"))?;
+
+        decompress_flat_chunk(chunk, header, absolute_indices, pedantic, scratch, pixels_ne)?;
+        Ok(index)
+    }
+
     /// Consume this block by compressing it, returning a `Chunk`.
     // for uncompressed data, the ByteVec in the chunk is moved all the way
     #[inline]
@@ -308,3 +443,633 @@ impl UncompressedBlock {
         }
     }
 }
+
+impl UncompressedDeepBlock {
+    /// Decompress the possibly compressed deep chunk and returns an
+    /// `UncompressedDeepBlock`.
+    ///
+    /// # Errors
+    /// Returns an error if the chunk is not a deep block of this image,
+    /// or if its data cannot be decompressed.
+    pub fn decompress_chunk(chunk: Chunk, meta_data: &MetaData, pedantic: bool) -> Result<Self> {
+        let header: &Header = meta_data
+            .headers
+            .get(chunk.layer_index)
+            .ok_or_else(|| Error::invalid("chunk layer index"))?;
+
+        let tile_data_indices = header.get_block_data_indices(&chunk.compressed_block)?;
+        let absolute_indices = header.get_absolute_block_pixel_coordinates(tile_data_indices)?;
+
+        absolute_indices.validate(Some(header.layer_size))?;
+
+        let (compressed_table, compressed_sample_data_le, sample_data_size) =
+            match chunk.compressed_block {
+                CompressedBlock::DeepScanLine(CompressedDeepScanLineBlock {
+                    compressed_pixel_offset_table,
+                    compressed_sample_data_le,
+                    decompressed_sample_data_size,
+                    ..
+                })
+                | CompressedBlock::DeepTile(CompressedDeepTileBlock {
+                    compressed_pixel_offset_table,
+                    compressed_sample_data_le,
+                    decompressed_sample_data_size,
+                    ..
+                }) => (
+                    compressed_pixel_offset_table,
+                    compressed_sample_data_le,
+                    decompressed_sample_data_size,
+                ),
+
+                CompressedBlock::ScanLine(_) | CompressedBlock::Tile(_) => {
+                    return Err(Error::invalid(
+                        "flat data chunk cannot be decompressed into a deep block",
+                    ))
+                }
+            };
+
+        let width = absolute_indices.size.width();
+        let compressed_table_le =
+            compressed_table.into_iter().map(|byte| byte.to_le_bytes()[0]).collect();
+
+        let table_le = header.compression.decompress_deep_bytes_le(
+            compressed_table_le,
+            absolute_indices.size.area() * 4,
+            pedantic,
+        )?;
+
+        let pixel_offset_table = table_le
+            .chunks_exact(4)
+            .map(|bytes| i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .map(|offset| u32::try_from(offset).map_err(|_| Error::invalid("deep pixel offset")))
+            .collect::<Result<Vec<u32>>>()?;
+
+        // offsets accumulate the samples from left to right, starting anew in each line
+        let mut total_sample_count: usize = 0;
+        for line in pixel_offset_table.chunks_exact(width) {
+            if line.windows(2).any(|pair| pair[0] > pair[1]) {
+                return Err(Error::invalid("deep pixel offset table order"));
+            }
+
+            total_sample_count += line.last().map_or(0, |&count| count as usize);
+        }
+
+        if let Some(max_samples) = header.max_samples_per_pixel {
+            if pedantic && total_sample_count > max_samples * absolute_indices.size.area() {
+                return Err(Error::invalid("deep sample count exceeds max samples attribute"));
+            }
+        }
+
+        let expected_byte_size = total_sample_count * header.channels.bytes_per_pixel;
+        if sample_data_size != expected_byte_size {
+            return Err(Error::invalid("deep sample data size"));
+        }
+
+        let sample_data_le = header.compression.decompress_deep_bytes_le(
+            compressed_sample_data_le,
+            expected_byte_size,
+            pedantic,
+        )?;
+
+        let line_sample_counts = pixel_offset_table
+            .chunks_exact(width)
+            .map(|line| line.last().map_or(0, |&count| count as usize));
+
+        Ok(Self {
+            sample_data: convert_deep_little_endian_to_current(
+                sample_data_le,
+                &header.channels,
+                line_sample_counts,
+            )?,
+            index: BlockIndex {
+                layer: chunk.layer_index,
+                pixel_position: absolute_indices.position.to_usize("data indices start")?,
+                level: tile_data_indices.level_index,
+                pixel_size: absolute_indices.size,
+            },
+            pixel_offset_table,
+        })
+    }
+
+    /// Consume this block by compressing it, returning a `Chunk`.
+    ///
+    /// # Errors
+    /// Returns an error if the compression method of the layer
+    /// cannot compress deep data.
+    pub fn compress_to_chunk(self, headers: &[Header]) -> Result<Chunk> {
+        let header: &Header = headers.get(self.index.layer).expect("block layer index bug");
+
+        if !header.deep {
+            return Err(Error::invalid("deep block cannot be compressed into a flat chunk"));
+        }
+
+        if self.pixel_offset_table.len() != self.index.pixel_size.area() {
+            return Err(Error::invalid("deep pixel offset table size"));
+        }
+
+        let decompressed_sample_data_size = self.sample_data.len();
+        if decompressed_sample_data_size
+            != self.total_sample_count() * header.channels.bytes_per_pixel
+        {
+            return Err(Error::invalid("deep sample data size"));
+        }
+
+        let tile_coordinates = TileCoordinates {
+            tile_index: self.index.pixel_position / header.max_block_pixel_size(),
+            level_index: self.index.level,
+        };
+
+        let absolute_indices = header.get_absolute_block_pixel_coordinates(tile_coordinates)?;
+        absolute_indices.validate(Some(header.layer_size))?;
+
+        if absolute_indices.size != self.index.pixel_size {
+            return Err(Error::invalid("deep block size"));
+        }
+
+        let mut table_le = ByteVec::with_capacity(self.pixel_offset_table.len() * 4);
+        for &offset in &self.pixel_offset_table {
+            let offset = i32::try_from(offset).map_err(|_| Error::invalid("deep pixel offset"))?;
+            table_le.extend_from_slice(&offset.to_le_bytes());
+        }
+
+        let compressed_pixel_offset_table = header
+            .compression
+            .compress_deep_bytes_le(&header.compression_options, table_le)?
+            .into_iter()
+            .map(|byte| i8::from_le_bytes([byte]))
+            .collect();
+
+        let line_sample_counts: Vec<usize> = self.line_sample_counts().collect();
+        let sample_data_le = convert_deep_current_to_little_endian(
+            self.sample_data,
+            &header.channels,
+            line_sample_counts.into_iter(),
+        )?;
+
+        let compressed_sample_data_le = header
+            .compression
+            .compress_deep_bytes_le(&header.compression_options, sample_data_le)?;
+
+        Ok(Chunk {
+            layer_index: self.index.layer,
+            compressed_block: match header.blocks {
+                BlockDescription::ScanLines => {
+                    CompressedBlock::DeepScanLine(CompressedDeepScanLineBlock {
+                        y_coordinate: usize_to_i32(self.index.pixel_position.y(), "pixel index")?
+                            + header.own_attributes.layer_position.y(),
+
+                        decompressed_sample_data_size,
+                        compressed_pixel_offset_table,
+                        compressed_sample_data_le,
+                    })
+                }
+
+                BlockDescription::Tiles(_) => CompressedBlock::DeepTile(CompressedDeepTileBlock {
+                    coordinates: tile_coordinates,
+                    decompressed_sample_data_size,
+                    compressed_pixel_offset_table,
+                    compressed_sample_data_le,
+                }),
+            },
+        })
+    }
+
+    /// Create an uncompressed deep block by requesting one line of samples
+    /// after another. For each line, the closure appends the number of
+    /// samples of each pixel to the vector of sample counts, and appends the
+    /// native-endian bytes of all samples in the line to the byte vector.
+    /// All channels must have the same number of samples in each pixel.
+    ///
+    /// # Errors
+    /// Returns an error if the closure fails, or if the channels
+    /// have different sample counts.
+    pub fn from_lines(
+        channels: &ChannelList,
+        block_index: BlockIndex,
+        mut extract_line: impl FnMut(LineIndex, &mut Vec<u32>, &mut ByteVec) -> UnitResult,
+    ) -> Result<Self> {
+        let width = block_index.pixel_size.width();
+        let mut pixel_offset_table = Vec::with_capacity(block_index.pixel_size.area());
+        let mut sample_data = ByteVec::new();
+
+        let mut line_sample_counts = Vec::with_capacity(width);
+        let mut channel_sample_counts = Vec::with_capacity(width);
+
+        for (_, line) in LineIndex::lines_in_block(block_index, channels) {
+            let byte_count_before = sample_data.len();
+            channel_sample_counts.clear();
+            extract_line(line, &mut channel_sample_counts, &mut sample_data)?;
+
+            if channel_sample_counts.len() != width {
+                return Err(Error::invalid("deep line sample count"));
+            }
+
+            if line.channel == 0 {
+                std::mem::swap(&mut line_sample_counts, &mut channel_sample_counts);
+
+                let mut line_offset: u32 = 0;
+                for &count in &line_sample_counts {
+                    line_offset = line_offset
+                        .checked_add(count)
+                        .ok_or_else(|| Error::invalid("deep line sample count"))?;
+
+                    pixel_offset_table.push(line_offset);
+                }
+            } else if channel_sample_counts != line_sample_counts {
+                return Err(Error::invalid("deep channels with different sample counts"));
+            }
+
+            let line_sample_count: usize =
+                line_sample_counts.iter().map(|&count| count as usize).sum();
+
+            let sample_type = channels.list[line.channel].sample_type;
+            if sample_data.len() - byte_count_before
+                != line_sample_count * sample_type.bytes_per_sample()
+            {
+                return Err(Error::invalid("deep line byte size"));
+            }
+        }
+
+        Ok(Self {
+            index: block_index,
+            pixel_offset_table,
+            sample_data,
+        })
+    }
+
+    /// The number of samples in each line of this block, from top to bottom.
+    pub fn line_sample_counts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pixel_offset_table
+            .chunks_exact(self.index.pixel_size.width())
+            .map(|line| line.last().map_or(0, |&count| count as usize))
+    }
+
+    /// The number of samples in each pixel of this block, row by row.
+    pub fn pixel_sample_counts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pixel_offset_table.chunks_exact(self.index.pixel_size.width()).flat_map(|line| {
+            let mut previous = 0;
+            line.iter().map(move |&offset| {
+                let count = offset - previous;
+                previous = offset;
+                count as usize
+            })
+        })
+    }
+
+    /// The number of samples in all pixels of this block.
+    pub fn total_sample_count(&self) -> usize {
+        self.line_sample_counts().sum()
+    }
+
+    /// Iterate all the lines in this block.
+    /// Each line contains the samples of all pixels in the line, for one of the
+    /// channels.
+    pub fn lines<'s>(
+        &'s self,
+        channels: &'s ChannelList,
+    ) -> impl Iterator<Item = DeepLineRef<'s>> + 's {
+        let width = self.index.pixel_size.width();
+        let mut byte_index = 0;
+
+        self.pixel_offset_table.chunks_exact(width).enumerate().flat_map(
+            move |(line_y, pixel_offset_table)| {
+                let line_sample_count =
+                    pixel_offset_table.last().map_or(0, |&count| count as usize);
+
+                let mut channel_byte_index = byte_index;
+                byte_index += line_sample_count * channels.bytes_per_pixel;
+
+                channels.list.iter().enumerate().map(move |(channel_index, channel)| {
+                    let byte_count = line_sample_count * channel.sample_type.bytes_per_sample();
+                    let start = channel_byte_index;
+                    channel_byte_index += byte_count;
+
+                    DeepLineRef {
+                        location: LineIndex {
+                            layer: self.index.layer,
+                            channel: channel_index,
+                            level: self.index.level,
+                            position: self.index.pixel_position + Vec2(0, line_y),
+                            sample_count: width,
+                        },
+                        pixel_offset_table,
+                        value: &self.sample_data[start..start + byte_count],
+                    }
+                })
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use half::f16;
+
+    use super::*;
+    use crate::{
+        compression::Compression,
+        math::RoundingMode,
+        meta::{
+            attribute::{ChannelDescription, LevelMode, LineOrder, SampleType, TileDescription},
+            compute_chunk_count,
+        },
+    };
+
+    const SIZE: Vec2<usize> = Vec2(37, 21);
+
+    fn sample_count(level: Vec2<usize>, x: usize, y: usize) -> usize {
+        (x / 3 + y + level.x() + 2 * level.y()) % 4
+    }
+
+    fn deep_header(compression: Compression, blocks: BlockDescription) -> Header {
+        let channels = smallvec![
+            ChannelDescription::new("A", SampleType::F16, true),
+            ChannelDescription::new("Z", SampleType::F32, false),
+        ];
+
+        let mut header = Header::new("deep".into(), SIZE, channels);
+        header.blocks = blocks;
+        header.line_order = LineOrder::Increasing;
+        header.compression = compression;
+        header.chunk_count = compute_chunk_count(compression, SIZE, blocks);
+        header.deep = true;
+        header.deep_data_version = Some(1);
+        header.max_samples_per_pixel = Some(3);
+        header
+    }
+
+    fn deep_meta_data(header: Header) -> MetaData {
+        let headers: Headers = smallvec![header];
+        let requirements = MetaData::validate(&headers, true).unwrap();
+        assert!(requirements.has_deep_data);
+
+        MetaData {
+            requirements,
+            headers,
+        }
+    }
+
+    /// Create a deep chunk by hand, the way a file would contain it.
+    fn deep_chunk(header: &Header, block: BlockIndex) -> Chunk {
+        let mut table_le = Vec::new();
+        let mut samples_le = Vec::new();
+
+        let xs = block.pixel_position.x()..block.pixel_position.x() + block.pixel_size.width();
+        let ys = block.pixel_position.y()..block.pixel_position.y() + block.pixel_size.height();
+
+        for y in ys {
+            let mut offset = 0_i32;
+            for x in xs.clone() {
+                offset += sample_count(block.level, x, y) as i32;
+                table_le.extend_from_slice(&offset.to_le_bytes());
+            }
+
+            for x in xs.clone() {
+                for _ in 0..sample_count(block.level, x, y) {
+                    samples_le.extend_from_slice(&f16::from_f32(x as f32).to_le_bytes());
+                }
+            }
+
+            for x in xs.clone() {
+                for sample in 0..sample_count(block.level, x, y) {
+                    samples_le.extend_from_slice(&((y * 10 + sample) as f32).to_le_bytes());
+                }
+            }
+        }
+
+        let compression = header.compression;
+        let decompressed_sample_data_size = samples_le.len();
+        let compressed_pixel_offset_table = compression
+            .compress_deep_bytes_le(&header.compression_options, table_le)
+            .unwrap()
+            .into_iter()
+            .map(|byte| byte as i8)
+            .collect();
+
+        let compressed_sample_data_le =
+            compression.compress_deep_bytes_le(&header.compression_options, samples_le).unwrap();
+
+        let compressed_block = match header.blocks {
+            BlockDescription::ScanLines => {
+                CompressedBlock::DeepScanLine(CompressedDeepScanLineBlock {
+                    y_coordinate: block.pixel_position.y() as i32,
+                    decompressed_sample_data_size,
+                    compressed_pixel_offset_table,
+                    compressed_sample_data_le,
+                })
+            }
+
+            BlockDescription::Tiles(tiles) => CompressedBlock::DeepTile(CompressedDeepTileBlock {
+                coordinates: TileCoordinates {
+                    tile_index: block.pixel_position / tiles.tile_size,
+                    level_index: block.level,
+                },
+                decompressed_sample_data_size,
+                compressed_pixel_offset_table,
+                compressed_sample_data_le,
+            }),
+        };
+
+        Chunk {
+            layer_index: block.layer,
+            compressed_block,
+        }
+    }
+
+    fn assert_deep_block_contents(block: &UncompressedDeepBlock, channels: &ChannelList) {
+        let BlockIndex {
+            pixel_position: position,
+            pixel_size: size,
+            level,
+            ..
+        } = block.index;
+
+        let expected_counts: Vec<usize> = (position.y()..position.y() + size.height())
+            .flat_map(|y| {
+                (position.x()..position.x() + size.width()).map(move |x| sample_count(level, x, y))
+            })
+            .collect();
+
+        let counts: Vec<usize> = block.pixel_sample_counts().collect();
+        assert_eq!(counts, expected_counts);
+        assert_eq!(block.total_sample_count(), expected_counts.iter().sum::<usize>());
+
+        for line in block.lines(channels) {
+            let y = line.location.position.y();
+
+            for local_x in 0..size.width() {
+                let x = position.x() + local_x;
+                let range = line.pixel_sample_range(local_x);
+                assert_eq!(range.len(), sample_count(level, x, y));
+
+                if line.location.channel == 0 {
+                    let samples: Vec<f16> = line.read_samples().collect::<Result<_>>().unwrap();
+                    assert!(samples[range].iter().all(|&a| a.to_f32() == x as f32));
+                } else {
+                    let samples: Vec<f32> = line.read_samples().collect::<Result<_>>().unwrap();
+                    let expected: Vec<f32> =
+                        (0..range.len()).map(|s| (y * 10 + s) as f32).collect();
+                    assert_eq!(samples[range], expected[..]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decompress_deep_scan_lines() {
+        for compression in
+            [Compression::Uncompressed, Compression::RLE, Compression::ZIP1, Compression::ZIP16]
+        {
+            let meta_data = deep_meta_data(deep_header(compression, BlockDescription::ScanLines));
+            let header = &meta_data.headers[0];
+
+            for (_, index) in enumerate_ordered_header_block_indices(&meta_data.headers) {
+                let mut bytes = Vec::new();
+                deep_chunk(header, index).write(&mut bytes, 1).unwrap();
+                let chunk = Chunk::read(&mut bytes.as_slice(), &meta_data).unwrap();
+
+                let block =
+                    UncompressedDeepBlock::decompress_chunk(chunk, &meta_data, true).unwrap();
+
+                assert_eq!(block.index, index, "{compression}");
+                assert_deep_block_contents(&block, &header.channels);
+            }
+        }
+    }
+
+    #[test]
+    fn decompress_deep_mip_map_tiles_from_file() {
+        let blocks = BlockDescription::Tiles(TileDescription {
+            tile_size: Vec2(8, 8),
+            level_mode: LevelMode::MipMap,
+            rounding_mode: RoundingMode::Up,
+        });
+
+        let header = deep_header(Compression::ZIP1, blocks);
+        let headers: Headers = smallvec![header.clone()];
+        let mut file = Cursor::new(Vec::new());
+
+        write(&mut file, headers, true, |meta_data, chunk_writer| {
+            use self::writer::ChunksWriter;
+
+            for (index_in_header, index) in
+                enumerate_ordered_header_block_indices(&meta_data.headers)
+            {
+                chunk_writer.write_chunk(index_in_header, deep_chunk(&header, index))?;
+            }
+
+            Ok(())
+        })
+        .unwrap();
+
+        use self::reader::ChunksReader;
+
+        file.set_position(0);
+        let mut decompressor =
+            read(file, true).unwrap().all_chunks(true).unwrap().sequential_decompressor(true);
+
+        assert!(decompressor.meta_data().headers[0].deep);
+
+        let mut levels = std::collections::HashSet::new();
+        while let Some(block) = decompressor.decompress_next_deep_block() {
+            let block = block.unwrap();
+            levels.insert(block.index.level);
+            assert_deep_block_contents(&block, &header.channels);
+        }
+
+        assert_eq!(levels.len(), 7, "all mip map levels should be decompressed");
+    }
+
+    #[test]
+    fn decompress_deep_rip_map_tiles_from_file() {
+        use self::reader::ChunksReader;
+
+        let tile_size = Vec2(8, 8);
+        let blocks = BlockDescription::Tiles(TileDescription {
+            tile_size,
+            level_mode: LevelMode::RipMap,
+            rounding_mode: RoundingMode::Down,
+        });
+
+        let header = deep_header(Compression::RLE, blocks);
+        let headers: Headers = smallvec![header.clone()];
+        let mut file = Cursor::new(Vec::new());
+
+        write(&mut file, headers, true, |meta_data, chunk_writer| {
+            use self::writer::ChunksWriter;
+
+            for (index_in_header, index) in
+                enumerate_ordered_header_block_indices(&meta_data.headers)
+            {
+                chunk_writer.write_chunk(index_in_header, deep_chunk(&header, index))?;
+            }
+
+            Ok(())
+        })
+        .unwrap();
+
+        // the level sizes of 37x21 pixels, rounded down: 37, 18, 9, 4, 2, 1 and 21, 10,
+        // 5, 2, 1
+        let level_size = |level: usize, size: usize| (size >> level).max(1);
+        let mut expected_blocks = Vec::new();
+        for level_y in 0..5 {
+            for level_x in 0..6 {
+                let size = Vec2(level_size(level_x, SIZE.x()), level_size(level_y, SIZE.y()));
+
+                for y in (0..size.y()).step_by(tile_size.y()) {
+                    for x in (0..size.x()).step_by(tile_size.x()) {
+                        let position = Vec2(x, y);
+                        let block_size =
+                            Vec2(tile_size.x().min(size.x() - x), tile_size.y().min(size.y() - y));
+
+                        expected_blocks.push((Vec2(level_x, level_y), position, block_size));
+                    }
+                }
+            }
+        }
+
+        file.set_position(0);
+        let mut decompressor =
+            read(file, true).unwrap().all_chunks(true).unwrap().sequential_decompressor(true);
+
+        let mut decompressed_blocks = Vec::new();
+        while let Some(block) = decompressor.decompress_next_deep_block() {
+            let block = block.unwrap();
+            assert_deep_block_contents(&block, &header.channels);
+
+            let BlockIndex {
+                level,
+                pixel_position,
+                pixel_size,
+                ..
+            } = block.index;
+
+            decompressed_blocks.push((level, pixel_position, pixel_size));
+        }
+
+        let sort_key = |&(level, position, _): &(Vec2<usize>, Vec2<usize>, Vec2<usize>)| {
+            (level.y(), level.x(), position.y(), position.x())
+        };
+
+        expected_blocks.sort_by_key(sort_key);
+        decompressed_blocks.sort_by_key(sort_key);
+        assert_eq!(decompressed_blocks, expected_blocks);
+    }
+
+    #[test]
+    fn reject_unordered_deep_offset_table() {
+        let meta_data =
+            deep_meta_data(deep_header(Compression::Uncompressed, BlockDescription::ScanLines));
+        let (_, index) = enumerate_ordered_header_block_indices(&meta_data.headers).next().unwrap();
+        let mut chunk = deep_chunk(&meta_data.headers[0], index);
+
+        if let CompressedBlock::DeepScanLine(ref mut block) = chunk.compressed_block {
+            // make the first offset larger than the following offsets
+            block.compressed_pixel_offset_table[0..4].copy_from_slice(&[0x7f, 0, 0, 0]);
+        }
+
+        assert!(UncompressedDeepBlock::decompress_chunk(chunk, &meta_data, true).is_err());
+    }
+}
@@ -9,8 +9,9 @@ use std::{
 use crate::{
     block::{
         chunk::{Chunk, TileCoordinates},
-        BlockIndex, UncompressedBlock,
+        BlockIndex, UncompressedBlock, UncompressedDeepBlock,
     },
+    compression::{ByteVec, DecompressionScratch},
     error::{u64_to_usize, Error, Result, UnitResult},
     io::{PeekRead, Tracking},
     meta::{header::Header, MetaData, OffsetTables},
@@ -89,27 +90,76 @@ impl<R: Read + Seek> Reader<R> {
     /// Does not decode the chunks now, but returns a decoder.
     /// Reading only some chunks may seeking the file, potentially skipping many
     /// bytes.
+    /// If not pedantic and the offset tables are invalid, the chunks are
+    /// located by reading the whole file, and an error is returned if any of
+    /// the filtered chunks cannot be found.
+    /// See `MetaData::reconstruct_offset_tables`.
     // TODO tile indices add no new information to block index??
     pub fn filter_chunks(
-        mut self,
+        self,
         pedantic: bool,
         mut filter: impl FnMut(&MetaData, TileCoordinates, BlockIndex) -> bool,
     ) -> Result<FilteredChunksReader<R>> {
-        let offset_tables =
+        self.select_chunks(pedantic, false, |meta, tile, block| {
+            Ok(filter(meta, tile, block).then_some(()))
+        })
+    }
+
+    /// Like `filter_chunks` without being pedantic,
+    /// but skips the filtered chunks that cannot be found
+    /// where the offset tables are invalid.
+    pub(crate) fn filter_located_chunks(
+        self,
+        mut filter: impl FnMut(&MetaData, TileCoordinates, BlockIndex) -> bool,
+    ) -> Result<FilteredChunksReader<R>> {
+        self.select_chunks(false, true, |meta, tile, block| {
+            Ok(filter(meta, tile, block).then_some(()))
+        })
+    }
+
+    /// Prepare to read all chunks from the file, in the order of the key that
+    /// the closure returns for each block. Chunks with the same key are read in
+    /// the order of the file. Seeks the file where the two orders differ.
+    pub(crate) fn sorted_chunks(
+        self,
+        pedantic: bool,
+        mut key: impl FnMut(BlockIndex) -> Result<usize>,
+    ) -> Result<FilteredChunksReader<R>> {
+        self.select_chunks(pedantic, false, |_, _, block| key(block).map(Some))
+    }
+
+    /// Read the chunks for which the closure returns a key, sorted by that key.
+    /// Chunks that are missing from reconstructed offset tables are either
+    /// skipped or an error.
+    fn select_chunks<K: Ord>(
+        mut self,
+        pedantic: bool,
+        skip_missing_chunks: bool,
+        mut select: impl FnMut(&MetaData, TileCoordinates, BlockIndex) -> Result<Option<K>>,
+    ) -> Result<FilteredChunksReader<R>> {
+        let mut offset_tables =
             MetaData::read_offset_tables(&mut self.remaining_reader, &self.meta_data.headers)?;
 
-        // TODO regardless of pedantic, if invalid, read all chunks instead, and filter
-        // after reading each chunk?
-        if pedantic {
-            validate_offset_tables(
-                self.meta_data.headers.as_slice(),
-                &offset_tables,
-                self.remaining_reader.byte_position(),
-            )?;
+        let offset_tables_validation = validate_offset_tables(
+            self.meta_data.headers.as_slice(),
+            &offset_tables,
+            self.remaining_reader.byte_position(),
+        );
+
+        // files from crashed writers often contain zeroed offset tables,
+        // but the chunks can still be found by reading them one after another
+        if let Err(error) = offset_tables_validation {
+            if pedantic {
+                return Err(error);
+            }
+
+            offset_tables =
+                MetaData::reconstruct_offset_tables(&mut self.remaining_reader, &self.meta_data);
         }
 
         let mut filtered_offsets =
             Vec::with_capacity((self.meta_data.headers.len() * 32).min(2 * 2048));
+        let mut filtered_flat_chunk_count = 0;
 
         // TODO detect whether the filter actually would skip chunks, and aviod sorting
         // etc when not filtering is applied
@@ -127,34 +177,50 @@ impl<R: Read + Seek> Reader<R> {
                     pixel_size: data_indices.size,
                 };
 
-                if filter(&self.meta_data, tile.location, block) {
-                    filtered_offsets.push(offset_tables[header_index][block_index]);
+                if let Some(key) = select(&self.meta_data, tile.location, block)? {
                     // safe indexing from `enumerate()`
+                    let offset = offset_tables[header_index][block_index];
+
+                    // reconstructed tables contain zero for missing chunks
+                    if offset != 0 {
+                        filtered_offsets.push((key, offset));
+                        filtered_flat_chunk_count += usize::from(!header.deep);
+                    } else if !skip_missing_chunks {
+                        return Err(Error::invalid("missing chunk"));
+                    }
                 }
             }
         }
 
-        filtered_offsets.sort_unstable(); // enables reading continuously if possible (already sorted where line order
-                                          // increasing)
+        // enables reading continuously if possible (already sorted where line order
+        // increasing)
+        filtered_offsets.sort_unstable_by_key(|&(_, offset)| offset);
 
         if pedantic {
             // table is sorted. if any two neighbours are equal, we have duplicates. this is
             // invalid.
-            if filtered_offsets.windows(2).any(|pair| pair[0] == pair[1]) {
+            if filtered_offsets.windows(2).any(|pair| pair[0].1 == pair[1].1) {
                 return Err(Error::invalid("chunk offset table"));
             }
         }
 
+        // stable, keeps the offset order of chunks with the same key
+        filtered_offsets.sort_by(|(key, _), (other_key, _)| key.cmp(other_key));
+
+        let filtered_offsets: Vec<u64> =
+            filtered_offsets.into_iter().map(|(_, offset)| offset).collect();
+
         Ok(FilteredChunksReader {
             meta_data: self.meta_data,
             expected_filtered_chunk_count: filtered_offsets.len(),
+            expected_filtered_flat_chunk_count: filtered_flat_chunk_count,
             remaining_filtered_chunk_indices: filtered_offsets.into_iter(),
             remaining_bytes: self.remaining_reader,
         })
     }
 }
 
-fn validate_offset_tables(
+pub(crate) fn validate_offset_tables(
     headers: &[Header],
     offset_tables: &OffsetTables,
     chunks_start_byte: usize,
@@ -162,10 +228,10 @@ fn validate_offset_tables(
     let max_pixel_bytes: usize = headers
         .iter() // when compressed, chunks are smaller, but never larger than max
         .map(|header| header.max_pixel_file_bytes())
-        .sum();
+        .fold(0, usize::saturating_add); // deep data may not be limited
 
     // check that each offset is within the bounds
-    let end_byte = chunks_start_byte + max_pixel_bytes;
+    let end_byte = chunks_start_byte.saturating_add(max_pixel_bytes);
     let is_invalid =
         offset_tables.iter().flatten().map(|&u64| u64_to_usize(u64, "chunk start")).any(
             |maybe_chunk_start| match maybe_chunk_start {
@@ -191,6 +257,7 @@ fn validate_offset_tables(
 pub struct FilteredChunksReader<R> {
     meta_data: MetaData,
     expected_filtered_chunk_count: usize,
+    expected_filtered_flat_chunk_count: usize,
     remaining_filtered_chunk_indices: std::vec::IntoIter<u64>,
     remaining_bytes: PeekRead<Tracking<R>>,
 }
@@ -335,6 +402,7 @@ pub trait ChunksReader: Sized + Iterator<Item = Result<Chunk>> + ExactSizeIterat
         SequentialBlockDecompressor {
             remaining_chunks_reader: self,
             pedantic,
+            scratch: DecompressionScratch::default(),
         }
     }
 }
@@ -431,6 +499,14 @@ impl<R: Read + Seek> Iterator for AllChunksReader<R> {
     }
 }
 
+impl<R> FilteredChunksReader<R> {
+    /// The number of filtered chunks that belong to flat layers,
+    /// excluding the chunks of deep layers.
+    pub fn expected_flat_chunk_count(&self) -> usize {
+        self.expected_filtered_flat_chunk_count
+    }
+}
+
 impl<R: Read + Seek> ChunksReader for FilteredChunksReader<R> {
     fn meta_data(&self) -> &MetaData {
         &self.meta_data
@@ -475,6 +551,7 @@ impl<R: Read + Seek> Iterator for FilteredChunksReader<R> {
 pub struct SequentialBlockDecompressor<R: ChunksReader> {
     remaining_chunks_reader: R,
     pedantic: bool,
+    scratch: DecompressionScratch,
 }
 
 impl<R: ChunksReader> SequentialBlockDecompressor<R> {
@@ -493,6 +570,38 @@ impl<R: ChunksReader> SequentialBlockDecompressor<R> {
             )
         })
     }
+
+    /// Read and then decompress a single block of pixels from the byte source,
+    /// into the buffer, which is resized to the byte size of the block.
+    /// Returns where the block is placed in the image.
+    /// Reusing the same buffer for all blocks avoids allocating memory for each
+    /// block, as the intermediate buffers are reused by this decompressor.
+    pub fn decompress_next_block_into(
+        &mut self,
+        pixels_ne: &mut ByteVec,
+    ) -> Option<Result<BlockIndex>> {
+        self.remaining_chunks_reader.read_next_chunk().map(|compressed_chunk| {
+            UncompressedBlock::decompress_chunk_into(
+                &compressed_chunk?,
+                self.remaining_chunks_reader.meta_data(),
+                self.pedantic,
+                &mut self.scratch,
+                pixels_ne,
+            )
+        })
+    }
+
+    /// Read and then decompress a single block of deep pixels from the byte
+    /// source. Returns an error if the block belongs to a flat layer.
+    pub fn decompress_next_deep_block(&mut self) -> Option<Result<UncompressedDeepBlock>> {
+        self.remaining_chunks_reader.read_next_chunk().map(|compressed_chunk| {
+            UncompressedDeepBlock::decompress_chunk(
+                compressed_chunk?,
+                self.remaining_chunks_reader.meta_data(),
+                self.pedantic,
+            )
+        })
+    }
 }
 
 #[cfg(feature = "rayon")]
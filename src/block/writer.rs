@@ -140,6 +140,10 @@ pub trait ChunksWriter: Sized {
             parallel_writer.add_block_to_compression_queue(index_in_header_increasing_y, block)?;
         }
 
+        // the blocks may be only some of the blocks in the file,
+        // so the last block does not necessarily wait for the others
+        parallel_writer.write_all_queued_chunks()?;
+
         // TODO debug_assert_eq!(self.is_complete());
         Ok(())
     }
@@ -7,8 +7,8 @@ use lebe::io::{ReadPrimitive, WriteEndian};
 use table::{EXP_TABLE, LOG_TABLE};
 
 use crate::{
-    compression::{mod_p, ByteVec},
-    error::usize_to_i32,
+    compression::{mod_p, ByteVec, DecompressionScratch},
+    error::{usize_to_i32, UnitResult},
     io::Data,
     meta::attribute::ChannelList,
     prelude::*,
@@ -271,21 +271,27 @@ fn cpy_u8(src: &[u16], src_i: usize, dst: &mut [u8], dst_i: usize, n: usize) {
 
 pub fn decompress(
     channels: &ChannelList,
-    compressed_le: ByteVec,
+    compressed_le: &[u8],
     rectangle: IntegerBounds,
-    expected_byte_size: usize,
     _pedantic: bool,
-) -> Result<ByteVec> {
+    scratch: &mut DecompressionScratch,
+    decompressed_ne: &mut [u8],
+) -> UnitResult {
+    let expected_byte_size = decompressed_ne.len();
     debug_assert_eq!(
         expected_byte_size,
         rectangle.size.area() * channels.bytes_per_pixel,
-        "expected byte size does not match header" // TODO compute instead of passing argument?
+        "expected byte size does not match header"
     );
 
     debug_assert!(!channels.list.is_empty(), "no channels found");
 
     if compressed_le.is_empty() {
-        return Ok(Vec::new());
+        return if expected_byte_size == 0 {
+            Ok(())
+        } else {
+            Err(Error::invalid("decompressed data"))
+        };
     }
 
     // Extract channel information needed for decompression.
@@ -313,7 +319,8 @@ pub fn decompress(
     // Temporary buffer is used to decompress B44 datas the way they are stored in
     // the compressed buffer (channel by channel). We interleave the final
     // result later.
-    let mut tmp = Vec::with_capacity(expected_byte_size);
+    let tmp = &mut scratch.bytes;
+    tmp.clear();
 
     // Index in the compressed buffer.
     let mut in_i = 0usize;
@@ -416,21 +423,21 @@ pub fn decompress(
 
                 // Copy rows (without going outside channel).
                 if y + 3 < y_sample_count {
-                    cpy_u8(&s, 0, &mut tmp, row0, x_resting_sample_count);
-                    cpy_u8(&s, 4, &mut tmp, row1, x_resting_sample_count);
-                    cpy_u8(&s, 8, &mut tmp, row2, x_resting_sample_count);
-                    cpy_u8(&s, 12, &mut tmp, row3, x_resting_sample_count);
+                    cpy_u8(&s, 0, tmp, row0, x_resting_sample_count);
+                    cpy_u8(&s, 4, tmp, row1, x_resting_sample_count);
+                    cpy_u8(&s, 8, tmp, row2, x_resting_sample_count);
+                    cpy_u8(&s, 12, tmp, row3, x_resting_sample_count);
                 } else {
                     debug_assert!(y < y_sample_count);
 
-                    cpy_u8(&s, 0, &mut tmp, row0, x_resting_sample_count);
+                    cpy_u8(&s, 0, tmp, row0, x_resting_sample_count);
 
                     if y + 1 < y_sample_count {
-                        cpy_u8(&s, 4, &mut tmp, row1, x_resting_sample_count);
+                        cpy_u8(&s, 4, tmp, row1, x_resting_sample_count);
                     }
 
                     if y + 2 < y_sample_count {
-                        cpy_u8(&s, 8, &mut tmp, row2, x_resting_sample_count);
+                        cpy_u8(&s, 8, tmp, row2, x_resting_sample_count);
                     }
                 }
 
@@ -446,7 +453,7 @@ pub fn decompress(
     debug_assert_eq!(tmp.len(), expected_byte_size);
 
     // Interleave uncompressed channel data.
-    let mut out = Vec::with_capacity(expected_byte_size);
+    let mut out: &mut [u8] = decompressed_ne;
 
     for y in rectangle.position.y()..rectangle.end().y() {
         for channel in &mut channel_data {
@@ -474,11 +481,10 @@ pub fn decompress(
                 for mut f16_bytes in channel_bytes.chunks(std::mem::size_of::<f16>()) {
                     let native_endian_f16_bits =
                         u16::read_from_little_endian(&mut f16_bytes).expect("memory read failed");
-                    out.write_as_native_endian(&native_endian_f16_bits)
-                        .expect("memory write failed");
+                    out.write_as_native_endian(&native_endian_f16_bits)?;
                 }
             } else {
-                u8::write_slice_ne(&mut out, channel_bytes).expect("write to in-memory failed");
+                u8::write_slice_ne(&mut out, channel_bytes)?;
             }
         }
     }
@@ -490,11 +496,11 @@ pub fn decompress(
         );
     }
 
-    debug_assert_eq!(out.len(), expected_byte_size);
+    debug_assert!(out.is_empty());
 
     // TODO do not convert endianness for f16-only images
     //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    super::convert_little_endian_to_current(out, channels, rectangle)
+    super::convert_little_endian_to_current_in_place(decompressed_ne, channels, rectangle)
 }
 
 pub fn compress(
@@ -691,7 +697,7 @@ mod test {
         compression::{
             b44,
             b44::{convert_from_linear, convert_to_linear},
-            ByteVec,
+            ByteVec, DecompressionScratch,
         },
         image::validate_results::ValidateResult,
         meta::attribute::ChannelList,
@@ -741,9 +747,16 @@ mod test {
 
         let compressed = b44::compress(&channels, pixel_bytes.clone(), rectangle, true).unwrap();
 
-        let decompressed =
-            b44::decompress(&channels, compressed.clone(), rectangle, pixel_bytes.len(), true)
-                .unwrap();
+        let mut decompressed = vec![0; pixel_bytes.len()];
+        b44::decompress(
+            &channels,
+            &compressed,
+            rectangle,
+            true,
+            &mut DecompressionScratch::default(),
+            &mut decompressed,
+        )
+        .unwrap();
 
         assert_eq!(decompressed.len(), pixel_bytes.len());
 
@@ -2,12 +2,13 @@
 // scanline buffer the rest of the crate uses, the per-channel byte runs, the
 // planar byte planes of the UNKNOWN/RLE sections, and back again.
 
+use std::ops::Range;
+
 use half::f16;
 
 use super::{ChannelInfo, CompressorScheme};
 use crate::{
-    compression::ByteVec,
-    error::{Error, Result},
+    error::{Error, Result, UnitResult},
     meta::attribute::{ChannelList, IntegerBounds, SampleType},
 };
 
@@ -113,58 +114,69 @@ pub(super) fn u16s_to_le_bytes(values: &[u16]) -> Vec<u8> {
     out
 }
 
-/// Split a planar buffer into one byte run per channel of the given scheme,
+/// Find the byte run of each channel of the given scheme in a planar buffer,
 /// in channel order (mirrors "DwaCompressor_setupChannelData"s running
-/// per-scheme cursor). Other schemes get an empty vec.
-pub(super) fn split_planar_channels(
+/// per-scheme cursor). Other schemes get an empty range.
+fn planar_channel_ranges(
     infos: &[ChannelInfo],
     scheme: CompressorScheme,
-    planar: &[u8],
-) -> Result<Vec<Vec<u8>>> {
-    let mut per_channel = vec![vec![]; infos.len()];
+    planar_byte_size: usize,
+) -> Result<Vec<Range<usize>>> {
     let mut cursor = 0;
 
-    for (channel_bytes, info) in per_channel.iter_mut().zip(infos) {
-        if info.scheme != scheme {
-            continue;
-        }
-        let length = info.width * info.height * info.bytes_per_sample;
-        *channel_bytes = planar
-            .get(cursor..cursor + length)
-            .ok_or_else(|| Error::invalid("truncated DWA channel data"))?
-            .to_vec();
-        cursor += length;
-    }
-    Ok(per_channel)
+    infos
+        .iter()
+        .map(|info| {
+            if info.scheme != scheme {
+                return Ok(0..0);
+            }
+
+            let range = cursor..cursor + info.width * info.height * info.bytes_per_sample;
+            if range.end > planar_byte_size {
+                return Err(Error::invalid("truncated DWA channel data"));
+            }
+
+            cursor = range.end;
+            Ok(range)
+        })
+        .collect()
 }
 
-/// Restore per-sample byte order from byte planes
-pub(super) fn interleave_byte_planes(planar: &[u8], bytes_per_sample: usize) -> Vec<u8> {
+/// Restore per-sample byte order from the byte planes of one channel,
+/// for the samples starting at `first_sample` that fit into `interleaved`.
+fn interleave_byte_planes(
+    planar: &[u8],
+    bytes_per_sample: usize,
+    first_sample: usize,
+    interleaved: &mut [u8],
+) {
     let sample_count = planar.len() / bytes_per_sample;
-    let mut interleaved = vec![0u8; planar.len()];
-    for sample in 0..sample_count {
-        for byte in 0..bytes_per_sample {
-            interleaved[sample * bytes_per_sample + byte] = planar[byte * sample_count + sample];
+
+    for (sample, sample_bytes) in interleaved.chunks_exact_mut(bytes_per_sample).enumerate() {
+        for (byte, target) in sample_bytes.iter_mut().enumerate() {
+            *target = planar[byte * sample_count + first_sample + sample];
         }
     }
-    interleaved
 }
 
 /// Interleave the per-channel decoded data into the scanline layout the
 /// rest of exrs expects: rows of "y" ascending, channels in list order
 /// within each row, samples little-endian.
+/// The unknown and RLE channels are read from their planar sections.
 pub(super) fn write_scanlines(
     channels: &ChannelList,
     infos: &[ChannelInfo],
     rectangle: IntegerBounds,
     lossy_samples: &[Vec<f16>],
-    unknown_bytes: &[Vec<u8>],
-    rle_bytes: &[Vec<u8>],
-    expected_byte_size: usize,
-) -> Result<ByteVec> {
-    // Reassemble the per-channel decoded data into the scanline layout the
-    // rest of the crate expects: rows in ascending y, channels in list order.
-    let mut out = Vec::with_capacity(expected_byte_size);
+    unknown_planar: &[u8],
+    rle_planar: &[u8],
+    out: &mut [u8],
+) -> UnitResult {
+    let unknown_ranges =
+        planar_channel_ranges(infos, CompressorScheme::Unknown, unknown_planar.len())?;
+    let rle_ranges = planar_channel_ranges(infos, CompressorScheme::Rle, rle_planar.len())?;
+
+    let mut remaining = out;
 
     for y in rectangle.position.y()..rectangle.end().y() {
         for (index, channel) in channels.list.iter().enumerate() {
@@ -175,19 +187,27 @@ pub(super) fn write_scanlines(
 
             let info = &infos[index];
             let row = ((y - rectangle.position.y()) / sampling_y) as usize;
+            let row_length = info.width * info.bytes_per_sample;
+
+            if row_length > remaining.len() {
+                return Err(Error::invalid("DWA decoded size mismatch"));
+            }
+
+            let (target, rest) = std::mem::take(&mut remaining).split_at_mut(row_length);
+            remaining = rest;
 
             match info.scheme {
                 CompressorScheme::LossyDct => {
                     let row_samples = &lossy_samples[index][row * info.width..][..info.width];
                     match info.sample_type {
                         SampleType::F16 => {
-                            for sample in row_samples {
-                                out.extend_from_slice(&sample.to_bits().to_le_bytes());
+                            for (bytes, sample) in target.chunks_exact_mut(2).zip(row_samples) {
+                                bytes.copy_from_slice(&sample.to_bits().to_le_bytes());
                             }
                         }
                         SampleType::F32 => {
-                            for sample in row_samples {
-                                out.extend_from_slice(&sample.to_f32().to_le_bytes());
+                            for (bytes, sample) in target.chunks_exact_mut(4).zip(row_samples) {
+                                bytes.copy_from_slice(&sample.to_f32().to_le_bytes());
                             }
                         }
                         // rejected before decoding
@@ -199,23 +219,24 @@ pub(super) fn write_scanlines(
                     }
                 }
 
-                CompressorScheme::Unknown | CompressorScheme::Rle => {
-                    let bytes = if info.scheme == CompressorScheme::Unknown {
-                        &unknown_bytes[index]
-                    } else {
-                        &rle_bytes[index]
-                    };
-                    let row_length = info.width * info.bytes_per_sample;
-                    out.extend_from_slice(&bytes[row * row_length..][..row_length]);
+                CompressorScheme::Unknown => {
+                    let bytes = &unknown_planar[unknown_ranges[index].clone()];
+                    target.copy_from_slice(&bytes[row * row_length..][..row_length]);
+                }
+
+                CompressorScheme::Rle => {
+                    let planar = &rle_planar[rle_ranges[index].clone()];
+                    interleave_byte_planes(planar, info.bytes_per_sample, row * info.width, target);
                 }
             }
         }
     }
 
-    if out.len() != expected_byte_size {
+    if !remaining.is_empty() {
         return Err(Error::invalid("DWA decoded size mismatch"));
     }
-    Ok(out)
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -256,7 +277,9 @@ mod test {
             for sample_count in [0usize, 1, 5, 37] {
                 let original = random_bytes(&mut random, sample_count * bytes_per_sample);
                 let planar = separate_byte_planes(&original, bytes_per_sample);
-                let interleaved = interleave_byte_planes(&planar, bytes_per_sample);
+
+                let mut interleaved = vec![0; planar.len()];
+                interleave_byte_planes(&planar, bytes_per_sample, 0, &mut interleaved);
                 assert_eq!(interleaved, original);
             }
         }
@@ -278,7 +301,12 @@ mod test {
             .collect();
 
         let packed = pack_unknown_channels(&infos, &channel_bytes, CompressorScheme::Unknown);
-        let split = split_planar_channels(&infos, CompressorScheme::Unknown, &packed).unwrap();
+        let split: Vec<Vec<u8>> =
+            planar_channel_ranges(&infos, CompressorScheme::Unknown, packed.len())
+                .unwrap()
+                .into_iter()
+                .map(|range| packed[range].to_vec())
+                .collect();
 
         assert_eq!(split, channel_bytes);
     }
@@ -299,14 +327,17 @@ mod test {
             .collect();
 
         let packed = pack_rle_channels(&infos, &channel_bytes);
-        // Mirror `mod.rs::decompress`: split the planar buffer per channel,
-        // then interleave each channel's byte planes back to sample order.
-        let planar_per_channel =
-            split_planar_channels(&infos, CompressorScheme::Rle, &packed).unwrap();
+        // Mirror `write_scanlines`: find each channel in the planar buffer,
+        // then interleave its byte planes back to sample order.
+        let ranges = planar_channel_ranges(&infos, CompressorScheme::Rle, packed.len()).unwrap();
         let decoded: Vec<Vec<u8>> = infos
             .iter()
-            .zip(&planar_per_channel)
-            .map(|(info, planar)| interleave_byte_planes(planar, info.bytes_per_sample))
+            .zip(ranges)
+            .map(|(info, range)| {
+                let mut interleaved = vec![0; range.len()];
+                interleave_byte_planes(&packed[range], info.bytes_per_sample, 0, &mut interleaved);
+                interleaved
+            })
             .collect();
 
         assert_eq!(decoded, channel_bytes);
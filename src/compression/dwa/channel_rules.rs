@@ -1,30 +1,86 @@
 // DWA channel classification rules (`Classifier` in
-// internal_dwa_classifier.h): the built-in encoder/legacy tables and the
-// serialization used by version >= 2 chunks.
+// internal_dwa_classifier.h): the built-in encoder/legacy tables, custom
+// rules supplied by the user, and the serialization used by version >= 2
+// chunks.
 
 use std::{borrow::Cow, convert::TryInto};
 
 use super::{channel_suffix, CompressorScheme};
 use crate::{
-    error::{Error, Result},
+    error::{Error, Result, UnitResult},
     meta::attribute::{ChannelList, SampleType},
 };
 
 /// One channel classification rule (`Classifier` in
 /// internal_dwa_classifier.h): matches a channel by name suffix and sample
 /// type, and assigns its compression scheme.
+///
+/// The rules used to compress a block are stored inside that block,
+/// so any reader can decode channels classified by custom rules.
 #[derive(Debug, Clone, PartialEq)]
-pub(super) struct Rule {
-    suffix: Cow<'static, str>,
-    pub(super) scheme: CompressorScheme,
-    sample_type: SampleType,
-    /// "Some(0/1/2)" marks this suffix as the R/G/B member of a potential
-    /// CSC triplet; "None" (like Y/RY/BY/A) is never CSC-grouped.
-    pub(super) csc_index: Option<usize>,
-    case_insensitive: bool,
+pub struct ChannelRule {
+    /// The part of the channel name after the last `.`, for example `red`
+    /// for a channel named `diffuse.red`. At most 128 bytes.
+    pub suffix: Cow<'static, str>,
+
+    /// How the matching channels are compressed.
+    pub scheme: CompressorScheme,
+
+    /// The matching channels must have exactly this sample type.
+    /// Lossy DCT compression only supports `F16` and `F32`.
+    pub sample_type: SampleType,
+
+    /// `Some(0/1/2)` marks this suffix as the R/G/B member of a potential
+    /// CSC triplet; `None` (like Y/RY/BY/A) is never CSC-grouped.
+    /// Three channels with the same prefix and the indices 0, 1 and 2 are
+    /// converted to luminance and chroma before compression.
+    pub csc_index: Option<usize>,
+
+    /// Whether the suffix is compared ignoring ASCII case.
+    pub case_insensitive: bool,
 }
 
-impl Rule {
+impl ChannelRule {
+    /// Create a case-sensitive rule.
+    /// The `csc_index` must be `None` or in the range `0..=2`.
+    pub fn new(
+        suffix: impl Into<Cow<'static, str>>,
+        sample_type: SampleType,
+        scheme: CompressorScheme,
+        csc_index: Option<usize>,
+    ) -> Self {
+        Self {
+            suffix: suffix.into(),
+            scheme,
+            sample_type,
+            csc_index,
+            case_insensitive: false,
+        }
+    }
+
+    /// Check whether this rule can be stored in a file and be used for
+    /// compression.
+    ///
+    /// # Errors
+    /// Returns an error if the suffix is too long or contains a null byte,
+    /// if the color space conversion index is larger than 2,
+    /// or if the rule compresses integer samples with the lossy DCT.
+    pub fn validate(&self) -> UnitResult {
+        if self.suffix.len() > 128 || self.suffix.contains('\0') {
+            return Err(Error::invalid("DWA channel rule suffix"));
+        }
+
+        if self.csc_index.is_some_and(|index| index > 2) {
+            return Err(Error::invalid("DWA channel rule csc index out of range"));
+        }
+
+        if self.scheme == CompressorScheme::LossyDct && self.sample_type == SampleType::U32 {
+            return Err(Error::unsupported("DWA lossy DCT compression of u32 channels"));
+        }
+
+        Ok(())
+    }
+
     /// "Classifier_match" exact suffix comparison, plus type equality.
     pub(super) fn matches(&self, suffix: &str, sample_type: SampleType) -> bool {
         self.sample_type == sample_type
@@ -40,14 +96,11 @@ impl Rule {
     }
 
     fn write(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.validate()?;
         out.extend_from_slice(self.suffix.as_bytes());
         out.push(0);
 
-        let csc_bits = match self.csc_index {
-            None => 0,
-            Some(index @ 0..=2) => index + 1,
-            Some(_) => return Err(Error::invalid("DWA channel rule csc index out of range")),
-        };
+        let csc_bits = self.csc_index.map_or(0, |index| index + 1);
 
         let scheme_bits = match self.scheme {
             CompressorScheme::Unknown => 0,
@@ -72,7 +125,11 @@ impl Rule {
 /// Current OpenEXR encoder rules for version-2 chunks. Unlike the legacy
 /// decoder fallback, these are case-sensitive and use canonical uppercase
 /// channel suffixes.
-pub(super) fn default_channel_rules() -> Vec<Rule> {
+///
+/// Used for compression unless the layer specifies custom rules.
+/// Extend this list to keep the defaults while adding your own rules.
+#[must_use]
+pub fn default_channel_rules() -> Vec<ChannelRule> {
     // OpenEXR's current encoder emits a small canonical rule table rather
     // than serializing the whole channel list. Only channels matching one of
     // these suffix/type pairs need to be recorded in the chunk header.
@@ -82,7 +139,7 @@ pub(super) fn default_channel_rules() -> Vec<Rule> {
     let mut rules = Vec::with_capacity(15);
     for (suffix, csc_index) in lossy {
         for sample_type in [SampleType::F16, SampleType::F32] {
-            rules.push(Rule {
+            rules.push(ChannelRule {
                 suffix: Cow::Borrowed(suffix),
                 scheme: CompressorScheme::LossyDct,
                 sample_type,
@@ -92,7 +149,7 @@ pub(super) fn default_channel_rules() -> Vec<Rule> {
         }
     }
     for sample_type in [SampleType::U32, SampleType::F16, SampleType::F32] {
-        rules.push(Rule {
+        rules.push(ChannelRule {
             suffix: Cow::Borrowed("A"),
             scheme: CompressorScheme::Rle,
             sample_type,
@@ -104,7 +161,7 @@ pub(super) fn default_channel_rules() -> Vec<Rule> {
 }
 
 /// "sLegacyChannelRules", implied by chunk versions <2.
-pub(super) fn legacy_channel_rules() -> Vec<Rule> {
+pub(super) fn legacy_channel_rules() -> Vec<ChannelRule> {
     // Version < 2 chunks relied on the older mixed-case naming conventions.
     // Keep those rules around so the decoder can still read historical files.
     let lossy: [(&'static str, Option<usize>); 11] = [
@@ -124,7 +181,7 @@ pub(super) fn legacy_channel_rules() -> Vec<Rule> {
     let mut rules = Vec::with_capacity(25);
     for (suffix, csc_index) in lossy {
         for sample_type in [SampleType::F16, SampleType::F32] {
-            rules.push(Rule {
+            rules.push(ChannelRule {
                 suffix: Cow::Borrowed(suffix),
                 scheme: CompressorScheme::LossyDct,
                 sample_type,
@@ -134,7 +191,7 @@ pub(super) fn legacy_channel_rules() -> Vec<Rule> {
         }
     }
     for sample_type in [SampleType::U32, SampleType::F16, SampleType::F32] {
-        rules.push(Rule {
+        rules.push(ChannelRule {
             suffix: Cow::Borrowed("a"),
             scheme: CompressorScheme::Rle,
             sample_type,
@@ -148,7 +205,7 @@ pub(super) fn legacy_channel_rules() -> Vec<Rule> {
 /// Version >= 2 chunks embed the rules they were encoded with, prefixed by
 /// a u16 little-endian total size that includes the size field itself
 /// (`DwaCompressor_readChannelRules`).
-pub(super) fn parse_channel_rules(input: &mut &[u8]) -> Result<Vec<Rule>> {
+pub(super) fn parse_channel_rules(input: &mut &[u8]) -> Result<Vec<ChannelRule>> {
     // The serialized rule block is prefixed by a u16 size that includes the
     // size field itself, so the parser can skip over the whole table at once.
     let (size_bytes, rest) = input
@@ -174,7 +231,7 @@ pub(super) fn parse_channel_rules(input: &mut &[u8]) -> Result<Vec<Rule>> {
 
 /// One serialized rule ("Classifier_read"): a NUL-terminated suffix
 /// (at most 128 chars), a packed flags byte, and a pixel type byte.
-fn parse_rule(data: &mut &[u8]) -> Result<Rule> {
+fn parse_rule(data: &mut &[u8]) -> Result<ChannelRule> {
     let corrupt = || Error::invalid("corrupt DWA channel rule");
 
     let suffix_len = data.iter().position(|&byte| byte == 0).ok_or_else(corrupt)?;
@@ -188,7 +245,7 @@ fn parse_rule(data: &mut &[u8]) -> Result<Rule> {
     let [flags, type_byte] = *chunk;
     *data = rest;
 
-    Ok(Rule {
+    Ok(ChannelRule {
         suffix: Cow::Owned(suffix),
         // The packed flags byte matches the C reference layout:
         // high nibble = cscIdx + 1, bits 2-3 = scheme, bit 0 = case-insensitive.
@@ -220,10 +277,10 @@ fn parse_rule(data: &mut &[u8]) -> Result<Rule> {
 }
 
 /// Encoder-side companion to `parse_channel_rules`: writes a u16 byte count
-/// including the size field itself, followed by only the default rules that
+/// including the size field itself, followed by only the rules that
 /// match at least one channel in this chunk's channel list.
 pub(super) fn write_relevant_channel_rules(
-    rules: &[Rule],
+    rules: &[ChannelRule],
     channels: &ChannelList,
 ) -> Result<Vec<u8>> {
     // The encoder only writes rules that are actually used by at least one
@@ -280,68 +280,103 @@ impl<'v> PackedStream<'v> {
     }
 }
 
+/// Reusable buffers of `decode_lossy_channels`.
+#[derive(Debug, Clone, Default)]
+pub(super) struct LossyDecodeScratch {
+    /// The decoded samples, indexed like the channels.
+    /// Only contains the samples of the current block for lossy DCT channels.
+    pub(super) samples: Vec<Vec<f16>>,
+
+    dct: DctScratch,
+}
+
+/// The DCT blocks of one channel group, see `decode_lossy_dct_group`.
+#[derive(Debug, Clone, Default)]
+struct DctScratch {
+    blocks: Vec<[[f32; 64]; 3]>,
+    needs_inverse: Vec<[bool; 3]>,
+}
+
 /// Decode all LOSSY_DCT channels: first every CSC group, then the
 /// standalone channels, both in channel order - the order in which the
 /// encoder appended them to the shared AC/DC streams.
+/// The samples are decoded into `scratch.samples`.
 pub(super) fn decode_lossy_channels(
     infos: &[ChannelInfo],
     csc_groups: &[[usize; 3]],
     ac_packed: &[u16],
     dc_packed: &[u16],
-) -> Result<Vec<Vec<f16>>> {
+    scratch: &mut LossyDecodeScratch,
+) -> Result<()> {
     // Decode CSC triplets first, then standalone lossy channels. The shared
     // AC/DC cursors advance in the same order the encoder wrote them.
     let mut ac = PackedStream::new(ac_packed);
     let mut dc = PackedStream::new(dc_packed);
 
-    let mut samples: Vec<Vec<f16>> = vec![vec![]; infos.len()];
+    let LossyDecodeScratch {
+        samples,
+        dct,
+    } = scratch;
+
+    samples.resize_with(infos.len(), Vec::new);
     let mut grouped = vec![false; infos.len()];
 
     for &group in csc_groups {
         // all three channels have identical sampling, hence identical size
         let info = &infos[group[0]];
-        let mut decoded: [Vec<f16>; 3] =
-            std::array::from_fn(|_| vec![f16::ZERO; info.width * info.height]);
 
-        decode_lossy_dct_group(
+        // move the buffers out of the list, so that all three can be mutated
+        let mut decoded = group.map(|channel| std::mem::take(&mut samples[channel]));
+
+        let result = decode_lossy_dct_group(
             &mut ac,
             &mut dc,
             info.width,
             info.height,
             Some(to_linear_table()),
+            dct,
             &mut decoded,
-        )?;
+        );
 
         for (&channel, channel_samples) in group.iter().zip(decoded) {
             samples[channel] = channel_samples;
             grouped[channel] = true;
         }
+
+        result?;
     }
 
     for (index, info) in infos.iter().enumerate() {
         if grouped[index] || info.scheme != CompressorScheme::LossyDct {
             continue;
         }
-        let mut decoded = [vec![f16::ZERO; info.width * info.height]];
-        let to_linear = (!info.quantize_linearly).then(to_linear_table);
-        decode_lossy_dct_group(&mut ac, &mut dc, info.width, info.height, to_linear, &mut decoded)?;
 
-        let [channel_samples] = decoded;
-        samples[index] = channel_samples;
+        let to_linear = (!info.quantize_linearly).then(to_linear_table);
+        decode_lossy_dct_group(
+            &mut ac,
+            &mut dc,
+            info.width,
+            info.height,
+            to_linear,
+            dct,
+            std::slice::from_mut(&mut samples[index]),
+        )?;
     }
 
-    Ok(samples)
+    Ok(())
 }
 
 /// Decode one standalone channel (decoded.len() == 1) or one CSC'd R/G/B
 /// triplet (decoded.len() == 3): per 8x8 block and component, read the
-/// DC value, un-RLE the AC values, inverse-DCT
+/// DC value, un-RLE the AC values, inverse-DCT.
+/// Resizes each of the decoded sample buffers to the size of the channel.
 fn decode_lossy_dct_group(
     ac: &mut PackedStream<'_>,
     dc: &mut PackedStream<'_>,
     width: usize,
     height: usize,
     to_linear: Option<&[u16; 65536]>,
+    dct: &mut DctScratch,
     decoded: &mut [Vec<f16>],
 ) -> Result<()> {
     let components = decoded.len();
@@ -352,8 +387,20 @@ fn decode_lossy_dct_group(
     // Buffer the whole group rather than one block at a time. That lets the
     // inverse DCT batch over every block that actually needs it, matching the
     // structure of the C reference.
-    let mut dct_blocks: Vec<[[f32; 64]; 3]> = vec![[[0.0f32; 64]; 3]; block_count];
-    let mut needs_inverse_dct: Vec<[bool; 3]> = vec![[false; 3]; block_count];
+    let DctScratch {
+        blocks: dct_blocks,
+        needs_inverse: needs_inverse_dct,
+    } = dct;
+
+    dct_blocks.clear();
+    dct_blocks.resize(block_count, [[0.0f32; 64]; 3]);
+    needs_inverse_dct.clear();
+    needs_inverse_dct.resize(block_count, [false; 3]);
+
+    // every sample is overwritten below
+    for output in decoded.iter_mut() {
+        output.resize(width * height, f16::ZERO);
+    }
 
     for block_y in 0..blocks_y {
         for block_x in 0..blocks_x {
@@ -1,3 +1,6 @@
+//! DWAA and DWAB compression.
+//! Use `ChannelRule` to decide which channels are compressed lossy.
+
 // DWA / DWAB (lossy DCT) compression and decompression, ported from
 // OpenEXRCores internal_dwa_compressor.h and alike.
 //
@@ -13,7 +16,7 @@
 
 use crate::{
     compression::ByteVec,
-    error::{Error, Result},
+    error::{Error, Result, UnitResult},
     meta::attribute::{ChannelList, IntegerBounds, SampleType},
 };
 
@@ -31,24 +34,28 @@ pub mod discrete_cosine_transform;
 mod tests;
 
 use channel_layout::{
-    interleave_byte_planes, pack_rle_channels, pack_unknown_channels, split_planar_channels,
-    split_scanline_channels, u16s_to_le_bytes, write_scanlines,
-};
-use channel_rules::{
-    default_channel_rules, legacy_channel_rules, parse_channel_rules, write_relevant_channel_rules,
-    Rule,
+    pack_rle_channels, pack_unknown_channels, split_scanline_channels, u16s_to_le_bytes,
+    write_scanlines,
 };
+pub use channel_rules::{default_channel_rules, ChannelRule};
+use channel_rules::{legacy_channel_rules, parse_channel_rules, write_relevant_channel_rules};
 use chunk_header::{AcCompression, DwaHeader};
-use lossy_dct::{decode_lossy_channels, encode_lossy_channels};
+use lossy_dct::{decode_lossy_channels, encode_lossy_channels, LossyDecodeScratch};
 use section_stream::{
     decode_ac_section, decode_dc_section, decode_rle_section, decode_unknown_section,
     split_sections, zip_deconstruct_bytes,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum CompressorScheme {
+/// How a DWA channel is compressed, as assigned by a `ChannelRule`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressorScheme {
+    /// Lossless, compressed with zlib. Used for channels matching no rule.
     Unknown,
+
+    /// Lossy, quantized in the frequency domain like JPEG.
     LossyDct,
+
+    /// Lossless run length encoding, suited for mattes and alpha.
     Rle,
 }
 
@@ -78,7 +85,7 @@ struct ChannelInfo {
 fn classify_channels(
     channels: &ChannelList,
     rectangle: IntegerBounds,
-    rules: &[Rule],
+    rules: &[ChannelRule],
 ) -> (Vec<ChannelInfo>, Vec<[usize; 3]>) {
     // Match channel names against the rule table, then collect R/G/B triplets
     // in the order the prefixes first appear. That order controls how the
@@ -138,11 +145,12 @@ fn classify_channels(
     (infos, csc_groups)
 }
 
-pub fn compress(
+pub(crate) fn compress(
     channels: &ChannelList,
     uncompressed_ne: ByteVec,
     rectangle: IntegerBounds,
     compression_level: Option<f32>,
+    custom_rules: Option<&[ChannelRule]>,
 ) -> Result<ByteVec> {
     if uncompressed_ne.is_empty() {
         return Ok(vec![]);
@@ -153,9 +161,22 @@ pub fn compress(
 
     // The chunk is written in the same section order the decoder expects:
     // header + rules, then UNKNOWN, AC, DC and RLE payloads.
-    let rules = default_channel_rules();
-    let rule_bytes = write_relevant_channel_rules(&rules, channels)?;
-    let (channel_infos, csc_groups) = classify_channels(channels, rectangle, &rules);
+    let default_rules;
+    let rules = match custom_rules {
+        Some(rules) => {
+            for rule in rules {
+                rule.validate()?;
+            }
+            rules
+        }
+        None => {
+            default_rules = default_channel_rules();
+            &default_rules
+        }
+    };
+
+    let rule_bytes = write_relevant_channel_rules(rules, channels)?;
+    let (channel_infos, csc_groups) = classify_channels(channels, rectangle, rules);
     let channel_bytes =
         split_scanline_channels(&uncompressed_le, channels, &channel_infos, rectangle)?;
 
@@ -227,27 +248,43 @@ pub fn compress(
     Ok(out)
 }
 
-pub fn decompress(
+/// Buffers reused by `decompress` from one block to the next.
+/// Only the inflated bytes of the zlib sections are still allocated for each
+/// block.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecompressionScratch {
+    ac: Vec<u16>,
+    dc: Vec<u16>,
+    rle_planar: Vec<u8>,
+    lossy: LossyDecodeScratch,
+}
+
+pub(crate) fn decompress(
     channels: &ChannelList,
-    compressed_le: ByteVec,
+    compressed_le: &[u8],
     rectangle: IntegerBounds,
-    expected_byte_size: usize,
     _pedantic: bool,
-) -> Result<ByteVec> {
+    scratch: &mut DecompressionScratch,
+    decompressed_ne: &mut [u8],
+) -> UnitResult {
+    let expected_byte_size = decompressed_ne.len();
+
     if compressed_le.is_empty() {
-        return Ok(vec![0u8; expected_byte_size]);
+        decompressed_ne.fill(0);
+        return Ok(());
     }
 
     // the writer stores chunks raw when compression would not have helped
     if compressed_le.len() == expected_byte_size {
-        return crate::compression::convert_little_endian_to_current(
-            compressed_le,
+        decompressed_ne.copy_from_slice(compressed_le);
+        return crate::compression::convert_little_endian_to_current_in_place(
+            decompressed_ne,
             channels,
             rectangle,
         );
     }
 
-    let mut input = compressed_le.as_slice();
+    let mut input = compressed_le;
     let header = DwaHeader::parse(&mut input)?;
 
     let rules = if header.version < 2 {
@@ -267,30 +304,31 @@ pub fn decompress(
     let [unknown_section, ac_section, dc_section, rle_section] = split_sections(input, &header)?;
 
     let unknown_planar = decode_unknown_section(unknown_section, &header)?;
-    let ac_packed = decode_ac_section(ac_section, &header)?;
-    let dc_packed = decode_dc_section(dc_section, &header)?;
-    let rle_planar = decode_rle_section(rle_section, &header)?;
-
-    let unknown_bytes =
-        split_planar_channels(&channel_infos, CompressorScheme::Unknown, &unknown_planar)?;
-    let rle_bytes: Vec<Vec<u8>> =
-        split_planar_channels(&channel_infos, CompressorScheme::Rle, &rle_planar)?
-            .into_iter()
-            .zip(&channel_infos)
-            .map(|(planar, info)| interleave_byte_planes(&planar, info.bytes_per_sample))
-            .collect();
-
-    let lossy_samples = decode_lossy_channels(&channel_infos, &csc_groups, &ac_packed, &dc_packed)?;
-
-    let out = write_scanlines(
+    decode_ac_section(ac_section, &header, &mut scratch.ac)?;
+    decode_dc_section(dc_section, &header, &mut scratch.dc)?;
+    decode_rle_section(rle_section, &header, &mut scratch.rle_planar)?;
+
+    decode_lossy_channels(
+        &channel_infos,
+        &csc_groups,
+        &scratch.ac,
+        &scratch.dc,
+        &mut scratch.lossy,
+    )?;
+
+    write_scanlines(
         channels,
         &channel_infos,
         rectangle,
-        &lossy_samples,
-        &unknown_bytes,
-        &rle_bytes,
-        expected_byte_size,
+        &scratch.lossy.samples,
+        &unknown_planar,
+        &scratch.rle_planar,
+        decompressed_ne,
     )?;
 
-    crate::compression::convert_little_endian_to_current(out, channels, rectangle)
+    crate::compression::convert_little_endian_to_current_in_place(
+        decompressed_ne,
+        channels,
+        rectangle,
+    )
 }
@@ -3,7 +3,10 @@
 // differencing transform applied to the DC stream.
 
 use super::chunk_header::{AcCompression, DwaHeader};
-use crate::error::{Error, Result};
+use crate::{
+    compression::{rle, zip},
+    error::{Error, Result, UnitResult},
+};
 
 /// Split the data after header + rules into the four sections, in on-disk
 /// order. Errors on truncation like the C parser.
@@ -27,12 +30,7 @@ pub(super) fn split_sections<'d>(data: &'d [u8], header: &DwaHeader) -> Result<[
 }
 
 fn inflate(compressed: &[u8], expected_size: usize) -> Result<Vec<u8>> {
-    let options = zune_inflate::DeflateOptions::default()
-        .set_limit(expected_size)
-        .set_size_hint(expected_size);
-
-    let inflated = zune_inflate::DeflateDecoder::new_with_options(compressed, options)
-        .decode_zlib()
+    let inflated = zip::inflate(compressed, expected_size)
         .map_err(|_| Error::invalid("DWA zlib data malformed"))?;
 
     if inflated.len() != expected_size {
@@ -51,43 +49,62 @@ pub(super) fn decode_unknown_section(section: &[u8], header: &DwaHeader) -> Resu
 }
 
 /// AC section: RLE DCT coefficients as u16, entropy coded with either the
-/// PIZ static Huffman coder or zlib.
-pub(super) fn decode_ac_section(section: &[u8], header: &DwaHeader) -> Result<Vec<u16>> {
+/// PIZ static Huffman coder or zlib. Reuses the allocation of the output.
+pub(super) fn decode_ac_section(
+    section: &[u8],
+    header: &DwaHeader,
+    ac: &mut Vec<u16>,
+) -> UnitResult {
+    ac.clear();
     if header.ac_count == 0 {
-        return Ok(vec![]);
+        return Ok(());
     }
 
     match header.ac_compression {
         AcCompression::StaticHuffman => {
-            crate::compression::piz::huffman::decompress(section, header.ac_count)
+            crate::compression::piz::huffman::decompress_into(section, header.ac_count, ac)
         }
         AcCompression::Deflate => {
             let bytes = inflate(section, header.ac_count * 2)?;
-            Ok(bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect())
+            ac.extend(bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])));
+            Ok(())
         }
     }
 }
 
 /// DC section: one u16 (half bits) per 8x8 block, zlib-compressed after
 /// the "zip reconstruct" transform (differencing + byte deinterleave).
-pub(super) fn decode_dc_section(section: &[u8], header: &DwaHeader) -> Result<Vec<u16>> {
+/// Reuses the allocation of the output.
+pub(super) fn decode_dc_section(
+    section: &[u8],
+    header: &DwaHeader,
+    dc: &mut Vec<u16>,
+) -> UnitResult {
+    dc.clear();
     if header.dc_count == 0 {
-        return Ok(vec![]);
+        return Ok(());
     }
 
     let bytes = inflate(section, header.dc_count * 2)?;
     let bytes = undo_zip_reconstruct(&bytes);
-    Ok(bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect())
+    dc.extend(bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])));
+    Ok(())
 }
 
 /// RLE section: zlib, then classic byte-oriented RLE. Result is planar per
 /// channel, each channel further split into byte planes.
-pub(super) fn decode_rle_section(section: &[u8], header: &DwaHeader) -> Result<Vec<u8>> {
+/// Reuses the allocation of the output.
+pub(super) fn decode_rle_section(
+    section: &[u8],
+    header: &DwaHeader,
+    rle_planar: &mut Vec<u8>,
+) -> UnitResult {
+    rle_planar.clear();
     if header.rle_raw_size == 0 {
-        return Ok(vec![]);
+        return Ok(());
     }
     let inflated = inflate(section, header.rle_uncompressed_size)?;
-    crate::compression::rle::unpack_rle_tokens(&inflated, header.rle_raw_size, false)
+    rle::unpack_rle_tokens_into(&inflated, header.rle_raw_size, false, rle_planar)
 }
 
 /// Ports "internal_zip_reconstruct_bytes": undo differencing, then
@@ -11,6 +11,18 @@ fn bounds(width: usize, height: usize) -> IntegerBounds {
     IntegerBounds::new(Vec2(0, 0), Vec2(width, height))
 }
 
+fn decompress_to_vec(
+    channels: &ChannelList,
+    compressed: &[u8],
+    rectangle: IntegerBounds,
+    byte_size: usize,
+) -> Result<ByteVec> {
+    let mut decompressed = vec![0; byte_size];
+    let mut scratch = DecompressionScratch::default();
+    decompress(channels, compressed, rectangle, true, &mut scratch, &mut decompressed)?;
+    Ok(decompressed)
+}
+
 #[test]
 fn compress_decompress_rle_only_is_lossless() {
     let channels = ChannelList::new(smallvec![ChannelDescription::named("A", SampleType::F16)]);
@@ -28,8 +40,8 @@ fn compress_decompress_rle_only_is_lossless() {
         }
     }
 
-    let compressed = compress(&channels, raw.clone(), rectangle, Some(45.0)).unwrap();
-    let decoded = decompress(&channels, compressed, rectangle, raw.len(), true).unwrap();
+    let compressed = compress(&channels, raw.clone(), rectangle, Some(45.0), None).unwrap();
+    let decoded = decompress_to_vec(&channels, &compressed, rectangle, raw.len()).unwrap();
 
     assert_eq!(decoded, raw);
 }
@@ -53,10 +65,119 @@ fn compress_decompress_rgb_lossy_chunk_is_valid() {
         }
     }
 
-    let compressed = compress(&channels, raw.clone(), rectangle, Some(45.0)).unwrap();
+    let compressed = compress(&channels, raw.clone(), rectangle, Some(45.0), None).unwrap();
     assert_ne!(compressed.len(), raw.len());
-    let decoded = decompress(&channels, compressed, rectangle, raw.len(), true).unwrap();
+    let decoded = decompress_to_vec(&channels, &compressed, rectangle, raw.len()).unwrap();
 
     assert_eq!(decoded.len(), raw.len());
     assert!(decoded.iter().any(|&byte| byte != 0));
 }
+
+#[test]
+fn reused_scratch_decompresses_like_new_scratch() {
+    let channels = ChannelList::new(smallvec![
+        ChannelDescription::named("A", SampleType::F16),
+        ChannelDescription::named("B", SampleType::F16),
+        ChannelDescription::named("G", SampleType::F16),
+        ChannelDescription::named("R", SampleType::F16),
+        ChannelDescription::named("Z", SampleType::F32),
+    ]);
+
+    let compressed_block = |width: u16, height: u16| {
+        let rectangle = bounds(usize::from(width), usize::from(height));
+        let mut raw = Vec::new();
+
+        for y in 0..height {
+            for channel in 0..4_u16 {
+                for x in 0..width {
+                    let angle = f32::from(x * y).mul_add(0.1, f32::from(channel));
+                    let value = f16::from_f32(angle.sin().mul_add(0.25, 0.5));
+                    raw.extend_from_slice(&value.to_bits().to_ne_bytes());
+                }
+            }
+
+            for x in 0..width {
+                raw.extend_from_slice(&f32::from(x + y).to_ne_bytes());
+            }
+        }
+
+        let compressed = compress(&channels, raw.clone(), rectangle, Some(45.0), None).unwrap();
+        (rectangle, raw.len(), compressed)
+    };
+
+    // blocks of different sizes, so that the buffers shrink and grow
+    let blocks = [compressed_block(32, 16), compressed_block(9, 3), compressed_block(32, 16)];
+    let mut scratch = DecompressionScratch::default();
+
+    for (rectangle, byte_size, compressed) in &blocks {
+        let mut decompressed = vec![0; *byte_size];
+        decompress(&channels, compressed, *rectangle, true, &mut scratch, &mut decompressed)
+            .unwrap();
+
+        let expected = decompress_to_vec(&channels, compressed, *rectangle, *byte_size).unwrap();
+        assert_eq!(decompressed, expected);
+    }
+}
+
+#[test]
+fn custom_rules_compress_suffixed_channels_lossy() {
+    let channels = ChannelList::new(smallvec![
+        ChannelDescription::named("diffuse.blue", SampleType::F16),
+        ChannelDescription::named("diffuse.green", SampleType::F16),
+        ChannelDescription::named("diffuse.red", SampleType::F16),
+    ]);
+    let rectangle = bounds(32, 32);
+    let mut raw = Vec::new();
+
+    for y in 0..rectangle.size.height() {
+        for channel in 0..3 {
+            for x in 0..rectangle.size.width() {
+                let value = 0.5
+                    + 0.25 * (x as f32 * 0.3 + channel as f32).sin()
+                    + 0.125 * (y as f32 * 0.2).cos();
+                raw.extend_from_slice(&f16::from_f32(value).to_bits().to_ne_bytes());
+            }
+        }
+    }
+
+    let rules = [
+        ChannelRule::new("red", SampleType::F16, CompressorScheme::LossyDct, Some(0)),
+        ChannelRule::new("green", SampleType::F16, CompressorScheme::LossyDct, Some(1)),
+        ChannelRule::new("blue", SampleType::F16, CompressorScheme::LossyDct, Some(2)),
+    ];
+
+    let lossless = compress(&channels, raw.clone(), rectangle, Some(45.0), None).unwrap();
+    let lossy = compress(&channels, raw.clone(), rectangle, Some(45.0), Some(&rules)).unwrap();
+    assert!(lossy.len() < lossless.len(), "custom rules must enable lossy compression");
+
+    let decoded_lossless = decompress_to_vec(&channels, &lossless, rectangle, raw.len()).unwrap();
+    assert_eq!(decoded_lossless, raw);
+
+    // the reader only knows the default rules, so it must use the rules stored in
+    // the block
+    let decoded_lossy = decompress_to_vec(&channels, &lossy, rectangle, raw.len()).unwrap();
+    assert_eq!(decoded_lossy.len(), raw.len());
+
+    let to_f32 = |bytes: &[u8]| f16::from_bits(u16::from_ne_bytes([bytes[0], bytes[1]])).to_f32();
+    for (decoded, original) in decoded_lossy.chunks_exact(2).zip(raw.chunks_exact(2)) {
+        assert!((to_f32(decoded) - to_f32(original)).abs() < 0.05);
+    }
+}
+
+#[test]
+fn invalid_custom_rules_are_rejected() {
+    let channels = ChannelList::new(smallvec![ChannelDescription::named("x.red", SampleType::F16)]);
+    let rectangle = bounds(8, 8);
+    let raw = vec![0; 8 * 8 * 2];
+
+    let invalid_rules = [
+        ChannelRule::new("red", SampleType::F16, CompressorScheme::LossyDct, Some(3)),
+        ChannelRule::new("red", SampleType::U32, CompressorScheme::LossyDct, None),
+        ChannelRule::new("r\0d", SampleType::F16, CompressorScheme::LossyDct, None),
+    ];
+
+    for rule in invalid_rules {
+        assert!(rule.validate().is_err());
+        assert!(compress(&channels, raw.clone(), rectangle, None, Some(&[rule])).is_err());
+    }
+}
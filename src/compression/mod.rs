@@ -4,19 +4,25 @@
 // private modules make non-breaking changes easier
 mod b44;
 
-// public only for benchmarking
-#[doc(hidden)]
+// public for the channel rules, and the transform for benchmarking
 pub mod dwa;
 
 mod piz;
 mod pxr24;
+
+// public only for benchmarking (benches/huffman.rs compares the piz huffman
+// decoders)
+#[cfg(feature = "huffman-benches")]
+#[doc(hidden)]
+pub use piz::huffman as piz_huffman;
 mod rle;
 mod zip;
 
-use std::convert::TryInto;
+use std::{convert::TryInto, sync::Arc};
 
 use crate::{
     error::{usize_to_i32, Error, Result, UnitResult},
+    math::Vec2,
     meta::{
         attribute::{ChannelList, IntegerBounds, SampleType},
         header::Header,
@@ -52,12 +58,13 @@ pub enum Compression {
     /// Uses ZIP compression to compress each line. Slowly produces small images
     /// which can be read with moderate speed. This compression method is
     /// lossless. Might be slightly faster but larger than `ZIP16´.
-    ZIP1, /* TODO ZIP { individual_lines: bool, compression_level: Option<u8> }  // TODO
-           * specify zip compression level? */
+    /// The deflate level can be chosen with `CompressionOptions`.
+    ZIP1, // TODO ZIP { individual_lines: bool }
+
     /// Uses ZIP compression to compress blocks of 16 lines. Slowly produces
     /// small images which can be read with moderate speed. This compression
     /// method is lossless. Might be slightly slower but smaller than
-    /// `ZIP1´.
+    /// `ZIP1´. The deflate level can be chosen with `CompressionOptions`.
     ZIP16, // TODO collapse with ZIP1
 
     /// PIZ compression works well for noisy and natural images. Works better
@@ -84,13 +91,13 @@ pub enum Compression {
     /// eliminating the pixels' 8 least significant bits, which tend to be
     /// very noisy, and therefore difficult to compress. This produces
     /// really small image files. Only supported for flat images, not for deep
-    /// data.
+    /// data. The deflate level can be chosen with `CompressionOptions`.
     // After reducing 32-bit floating-point data to 24 bits by rounding (while leaving 16-bit
     // floating-point data unchanged), differences between horizontally adjacent pixels
     // are compressed with zlib, similar to ZIP. PXR24 compression preserves image
     // channels of type HALF and UINT exactly, but the relative error of FLOAT data
     // increases to about ???.
-    PXR24, // TODO specify zip compression level?
+    PXR24,
 
     /// This is a lossy compression method for f16 images.
     /// It's the predecessor of the `B44A` compression,
@@ -134,14 +141,16 @@ pub enum Compression {
 
     /// Lossy DCT-based compression (DreamWorks Animation), 32 scanlines per
     /// block. Partial buffer access friendly.
-    /// Decoding support is implemented. Encoding is not implemented and returns
-    /// an error.
+    /// Which channels are compressed lossy is decided by
+    /// `CompressionOptions::dwa_channel_rules`, see
+    /// `image.write().compression_options(options)`.
     DWAA(Option<f32>),
 
     /// Lossy DCT-based compression (DreamWorks Animation), 256 scanlines per
     /// block. Better compression ratio for full frames.
-    /// Decoding support is implemented. Encoding is not implemented and returns
-    /// an error.
+    /// Which channels are compressed lossy is decided by
+    /// `CompressionOptions::dwa_channel_rules`, see
+    /// `image.write().compression_options(options)`.
     DWAB(Option<f32>),
 
     /// __This lossy compression is not yet supported by this implementation.__
@@ -176,24 +185,107 @@ impl std::fmt::Display for Compression {
     }
 }
 
+/// Settings of the compression methods that only affect writing a file.
+/// These settings are not stored in the file,
+/// so reading an image always yields the default options.
+///
+/// Use `image.write().compression_options(options)` to write an image with
+/// these settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionOptions {
+    /// The deflate level of `ZIP1`, `ZIP16` and `PXR24` compression,
+    /// from 0 (fastest) to 9 (smallest). Uses level 4 if not specified.
+    /// Also applies to deep data.
+    pub deflate_level: Option<u8>,
+
+    /// Decides which channels are compressed lossy when writing with
+    /// `Compression::DWAA` or `Compression::DWAB`. If `None`, uses
+    /// `dwa::default_channel_rules()`, which only matches channels named like
+    /// `R`, `G`, `B`, `Y`, `RY`, `BY` and `A`.
+    /// The rules are stored inside each compressed block,
+    /// so any reader can decompress the pixels.
+    /// Shared, so that cloning the options for each header is cheap.
+    pub dwa_channel_rules: Option<Arc<[dwa::ChannelRule]>>,
+
+    /// Whether `B44` and `B44A` compression store uniform 4x4 blocks in 3
+    /// instead of 14 bytes. If `None`, only `B44A` does. Any reader can
+    /// decompress both block sizes.
+    pub b44_optimize_uniform_areas: Option<bool>,
+}
+
+impl CompressionOptions {
+    /// The options that are used if nothing else is specified.
+    pub const DEFAULT: Self = Self {
+        deflate_level: None,
+        dwa_channel_rules: None,
+        b44_optimize_uniform_areas: None,
+    };
+
+    /// Use the specified deflate level for `ZIP1`, `ZIP16` and `PXR24`
+    /// compression, from 0 (fastest) to 9 (smallest).
+    #[must_use]
+    pub const fn with_deflate_level(mut self, level: u8) -> Self {
+        self.deflate_level = Some(level);
+        self
+    }
+
+    /// Use the specified rules to decide which channels are compressed lossy
+    /// with `DWAA` and `DWAB` compression.
+    #[must_use]
+    pub fn with_dwa_channel_rules(mut self, rules: impl Into<Arc<[dwa::ChannelRule]>>) -> Self {
+        self.dwa_channel_rules = Some(rules.into());
+        self
+    }
+
+    /// Whether `B44` and `B44A` compression store uniform 4x4 blocks in 3
+    /// instead of 14 bytes.
+    #[must_use]
+    pub const fn with_b44_optimize_uniform_areas(mut self, optimize: bool) -> Self {
+        self.b44_optimize_uniform_areas = Some(optimize);
+        self
+    }
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Reusable memory for the intermediate results of decompressing a block.
+///
+/// Decompressing all blocks of an image with the same scratch space
+/// avoids allocating these buffers again for each block.
+/// Only the zlib decoder of `ZIP1`, `ZIP16`, `PXR24`, `DWAA` and `DWAB`
+/// still allocates the inflated bytes for each block.
+/// See `Compression::decompress_image_section_from_le_into`.
+#[derive(Debug, Clone, Default)]
+pub struct DecompressionScratch {
+    /// Unpacked RLE bytes, or the B44 channels before interleaving.
+    bytes: ByteVec,
+
+    /// The PIZ samples before interleaving.
+    samples: Vec<u16>,
+
+    /// The sections and the lossy samples of DWA blocks.
+    dwa: dwa::DecompressionScratch,
+}
+
 impl Compression {
     /// Compress the image section, converting from native endian into with
     /// little-endian format.
+    ///
+    /// # Errors
+    /// Returns an error if the pixels cannot be compressed, if the pixel
+    /// section is larger than a block of the header, or if the header is deep
+    /// and this method cannot compress deep data.
     pub fn compress_image_section_to_le(
         self,
         header: &Header,
         uncompressed_native_endian: ByteVec,
         pixel_section: IntegerBounds,
     ) -> Result<ByteVec> {
-        let max_tile_size = header.max_block_pixel_size();
-
-        assert!(
-            pixel_section.validate(Some(max_tile_size)).is_ok(),
-            "decompress tile coordinate bug"
-        );
-        if header.deep {
-            assert!(self.supports_deep_data());
-        }
+        self.validate_pixel_section(header, pixel_section)?;
 
         use self::Compression::*;
         let compressed_little_endian = match self {
@@ -207,15 +299,11 @@ impl Compression {
 
             // we need to clone here, because we might have to fallback to the uncompressed data
             // later (when compressed data is larger than raw data)
-            ZIP16 => zip::compress_bytes(
-                &header.channels,
-                uncompressed_native_endian.clone(),
-                pixel_section,
-            ),
-            ZIP1 => zip::compress_bytes(
+            ZIP16 | ZIP1 => zip::compress_bytes(
                 &header.channels,
                 uncompressed_native_endian.clone(),
                 pixel_section,
+                header.compression_options.deflate_level,
             ),
             RLE => rle::compress_bytes(
                 &header.channels,
@@ -225,28 +313,32 @@ impl Compression {
             PIZ => {
                 piz::compress(&header.channels, uncompressed_native_endian.clone(), pixel_section)
             }
-            PXR24 => {
-                pxr24::compress(&header.channels, uncompressed_native_endian.clone(), pixel_section)
-            }
+            PXR24 => pxr24::compress(
+                &header.channels,
+                uncompressed_native_endian.clone(),
+                pixel_section,
+                header.compression_options.deflate_level,
+            ),
             B44 => b44::compress(
                 &header.channels,
                 uncompressed_native_endian.clone(),
                 pixel_section,
-                false,
+                header.compression_options.b44_optimize_uniform_areas.unwrap_or(false),
             ),
             B44A => b44::compress(
                 &header.channels,
                 uncompressed_native_endian.clone(),
                 pixel_section,
-                true,
+                header.compression_options.b44_optimize_uniform_areas.unwrap_or(true),
             ),
             DWAA(level) | DWAB(level) => dwa::compress(
                 &header.channels,
                 uncompressed_native_endian.clone(),
                 pixel_section,
                 level,
+                header.compression_options.dwa_channel_rules.as_deref(),
             ),
-            _ => {
+            HTJ2K32 | HTJ2K256 => {
                 return Err(Error::unsupported(format!(
                     "yet unimplemented compression method: {self}"
                 )));
@@ -272,6 +364,9 @@ impl Compression {
 
     /// Decompress the image section from bytes of little-endian format,
     /// returning native-endian format.
+    ///
+    /// # Errors
+    /// See `decompress_image_section_from_le_into`.
     pub fn decompress_image_section_from_le(
         self,
         header: &Header,
@@ -279,99 +374,227 @@ impl Compression {
         pixel_section: IntegerBounds,
         pedantic: bool,
     ) -> Result<ByteVec> {
-        let max_tile_size = header.max_block_pixel_size();
+        self.validate_pixel_section(header, pixel_section)?;
 
-        assert!(
-            pixel_section.validate(Some(max_tile_size)).is_ok(),
-            "decompress tile coordinate bug"
-        );
-        if header.deep {
-            assert!(self.supports_deep_data());
+        let expected_byte_size = pixel_section.size.area() * header.channels.bytes_per_pixel; // FIXME this needs to account for subsampling anywhere
+
+        // note: always true where self == Uncompressed
+        if compressed_le.len() == expected_byte_size {
+            // the compressed data was larger than the raw data, so the small raw data has
+            // been written, and its allocation can be reused
+            return convert_little_endian_to_current(
+                compressed_le,
+                &header.channels,
+                pixel_section,
+            );
         }
 
+        let mut decompressed_ne = vec![0; expected_byte_size];
+
+        self.decompress_image_section_from_le_into(
+            header,
+            &compressed_le,
+            pixel_section,
+            pedantic,
+            &mut DecompressionScratch::default(),
+            &mut decompressed_ne,
+        )?;
+
+        Ok(decompressed_ne)
+    }
+
+    /// Decompress the image section from bytes of little-endian format
+    /// into the specified buffer, in native-endian format.
+    /// The buffer must have exactly the byte size of the pixel section.
+    /// The intermediate buffers of the decompression are taken from the scratch
+    /// space, which can be reused for all blocks of an image.
+    /// Only the zlib decoder of `ZIP1`, `ZIP16`, `PXR24`, `DWAA` and `DWAB`
+    /// still allocates the inflated bytes for each block.
+    ///
+    /// # Errors
+    /// Returns an error if the data is invalid or does not match the size of
+    /// the pixel section, if the pixel section is larger than a block of the
+    /// header, or if the header is deep and this method cannot compress deep
+    /// data.
+    pub fn decompress_image_section_from_le_into(
+        self,
+        header: &Header,
+        compressed_le: &[u8],
+        pixel_section: IntegerBounds,
+        pedantic: bool,
+        scratch: &mut DecompressionScratch,
+        decompressed_ne: &mut [u8],
+    ) -> UnitResult {
+        self.validate_pixel_section(header, pixel_section)?;
+
         let expected_byte_size = pixel_section.size.area() * header.channels.bytes_per_pixel; // FIXME this needs to account for subsampling anywhere
 
+        if decompressed_ne.len() != expected_byte_size {
+            return Err(Error::invalid("decompression buffer size"));
+        }
+
         // note: always true where self == Uncompressed
         if compressed_le.len() == expected_byte_size {
             // the compressed data was larger than the raw data, so the small raw data has
             // been written
-            convert_little_endian_to_current(compressed_le, &header.channels, pixel_section)
-        } else {
-            use self::Compression::*;
-            let bytes_ne = match self {
-                Uncompressed => {
-                    convert_little_endian_to_current(compressed_le, &header.channels, pixel_section)
-                }
-                ZIP16 => zip::decompress_bytes(
-                    &header.channels,
-                    compressed_le,
-                    pixel_section,
-                    expected_byte_size,
-                    pedantic,
-                ),
-                ZIP1 => zip::decompress_bytes(
-                    &header.channels,
-                    compressed_le,
-                    pixel_section,
-                    expected_byte_size,
-                    pedantic,
-                ),
-                RLE => rle::decompress_bytes(
-                    &header.channels,
-                    compressed_le,
-                    pixel_section,
-                    expected_byte_size,
-                    pedantic,
-                ),
-                PIZ => piz::decompress(
-                    &header.channels,
-                    compressed_le,
-                    pixel_section,
-                    expected_byte_size,
-                    pedantic,
-                ),
-                PXR24 => pxr24::decompress(
-                    &header.channels,
-                    compressed_le,
-                    pixel_section,
-                    expected_byte_size,
-                    pedantic,
-                ),
-                B44 | B44A => b44::decompress(
-                    &header.channels,
-                    compressed_le,
-                    pixel_section,
-                    expected_byte_size,
-                    pedantic,
-                ),
-                DWAA(_) | DWAB(_) => dwa::decompress(
-                    &header.channels,
-                    compressed_le,
-                    pixel_section,
-                    expected_byte_size,
-                    pedantic,
-                ),
-                _ => {
-                    return Err(Error::unsupported(format!(
-                        "yet unimplemented compression method: {self}"
-                    )));
-                }
-            };
+            decompressed_ne.copy_from_slice(compressed_le);
+            return convert_little_endian_to_current_in_place(
+                decompressed_ne,
+                &header.channels,
+                pixel_section,
+            );
+        }
+
+        use self::Compression::*;
+        let channels = &header.channels;
+
+        let result = match self {
+            Uncompressed => Err(Error::invalid("uncompressed data size")),
+
+            ZIP16 | ZIP1 => zip::decompress_bytes(
+                channels,
+                compressed_le,
+                pixel_section,
+                pedantic,
+                decompressed_ne,
+            ),
 
-            // map all errors to compression errors
-            let bytes_ne = bytes_ne.map_err(|decompression_error| match decompression_error {
-                Error::NotSupported(message) => Error::unsupported(format!(
-                    "yet unimplemented compression special case ({message})"
-                )),
+            RLE => rle::decompress_bytes(
+                channels,
+                compressed_le,
+                pixel_section,
+                pedantic,
+                scratch,
+                decompressed_ne,
+            ),
 
-                error => Error::invalid(format!("compressed {self:?} data ({error})")),
-            })?;
+            PIZ => piz::decompress(
+                channels,
+                compressed_le,
+                pixel_section,
+                pedantic,
+                scratch,
+                decompressed_ne,
+            ),
 
-            if bytes_ne.len() == expected_byte_size {
-                Ok(bytes_ne)
-            } else {
-                Err(Error::invalid("decompressed data"))
+            PXR24 => {
+                pxr24::decompress(channels, compressed_le, pixel_section, pedantic, decompressed_ne)
             }
+
+            B44 | B44A => b44::decompress(
+                channels,
+                compressed_le,
+                pixel_section,
+                pedantic,
+                scratch,
+                decompressed_ne,
+            ),
+
+            DWAA(_) | DWAB(_) => dwa::decompress(
+                channels,
+                compressed_le,
+                pixel_section,
+                pedantic,
+                &mut scratch.dwa,
+                decompressed_ne,
+            ),
+
+            HTJ2K32 | HTJ2K256 => {
+                return Err(Error::unsupported(format!(
+                    "yet unimplemented compression method: {self}"
+                )));
+            }
+        };
+
+        // map all errors to compression errors
+        result.map_err(|decompression_error| match decompression_error {
+            Error::NotSupported(message) => Error::unsupported(format!(
+                "yet unimplemented compression special case ({message})"
+            )),
+
+            error => Error::invalid(format!("compressed {self:?} data ({error})")),
+        })
+    }
+
+    /// Panics if the pixel section exceeds the blocks of the header,
+    /// as the block reader only produces sections within these bounds.
+    fn validate_pixel_section(self, header: &Header, pixel_section: IntegerBounds) -> UnitResult {
+        pixel_section.validate(Some(header.max_block_pixel_size()))?;
+
+        if header.deep && !self.supports_deep_data() {
+            return Err(Error::unsupported(format!("deep data with {self} compression")));
+        }
+
+        Ok(())
+    }
+
+    /// Compress one of the two sections of a deep data block,
+    /// either the pixel offset table or the sample data.
+    /// Deep data can only be compressed with byte-oriented methods,
+    /// so this operates on little-endian bytes and needs no channel
+    /// information. Returns the uncompressed bytes if compression does not
+    /// make them smaller.
+    ///
+    /// # Errors
+    /// Returns an error if this method cannot compress deep data.
+    pub fn compress_deep_bytes_le(
+        self,
+        options: &CompressionOptions,
+        uncompressed_le: ByteVec,
+    ) -> Result<ByteVec> {
+        use self::Compression::*;
+
+        // we need to clone here, because we might have to fallback to the uncompressed
+        // data
+        let compressed_le = match self {
+            Uncompressed => return Ok(uncompressed_le),
+            RLE => rle::compress_le_bytes(uncompressed_le.clone()),
+            ZIP1 | ZIP16 => zip::compress_le_bytes(uncompressed_le.clone(), options.deflate_level),
+            _ => return Err(Error::invalid(format!("deep data cannot use {self}"))),
+        };
+
+        if compressed_le.len() < uncompressed_le.len() {
+            Ok(compressed_le)
+        } else {
+            Ok(uncompressed_le)
+        }
+    }
+
+    /// Decompress one of the two sections of a deep data block,
+    /// either the pixel offset table or the sample data.
+    /// Returns little-endian bytes, as no channel information is used.
+    ///
+    /// # Errors
+    /// Returns an error if the data is invalid, if this method cannot
+    /// compress deep data, or if the result does not have the expected size.
+    pub fn decompress_deep_bytes_le(
+        self,
+        compressed_le: ByteVec,
+        expected_byte_size: usize,
+        pedantic: bool,
+    ) -> Result<ByteVec> {
+        // note: always true where self == Uncompressed
+        if compressed_le.len() == expected_byte_size {
+            // the compressed data was larger than the raw data, so the small raw data has
+            // been written
+            return Ok(compressed_le);
+        }
+
+        use self::Compression::*;
+        let decompressed_le = match self {
+            Uncompressed => Err(Error::invalid("uncompressed deep data size")),
+            RLE => rle::decompress_le_bytes(&compressed_le, expected_byte_size, pedantic),
+            ZIP1 | ZIP16 => zip::decompress_le_bytes(&compressed_le, expected_byte_size),
+            _ => return Err(Error::invalid(format!("deep data cannot use {self}"))),
+        };
+
+        let decompressed_le = decompressed_le
+            .map_err(|error| Error::invalid(format!("compressed {self:?} deep data ({error})")))?;
+
+        if decompressed_le.len() == expected_byte_size {
+            Ok(decompressed_le)
+        } else {
+            Err(Error::invalid("decompressed deep data"))
         }
     }
 
@@ -392,9 +615,9 @@ impl Compression {
     pub const fn supports_deep_data(self) -> bool {
         use self::Compression::*;
         match self {
-            Uncompressed | RLE | ZIP1 => true,
+            Uncompressed | RLE | ZIP1 | ZIP16 => true,
 
-            ZIP16 | PXR24 | PIZ | B44 | B44A | DWAA(_) | DWAB(_) | HTJ2K256 | HTJ2K32 => false,
+            PXR24 | PIZ | B44 | B44A | DWAA(_) | DWAB(_) | HTJ2K256 | HTJ2K32 => false,
         }
     }
 
@@ -450,6 +673,85 @@ impl Compression {
 
 // see https://github.com/AcademySoftwareFoundation/openexr/blob/6a9f8af6e89547bcd370ae3cec2b12849eee0b54/OpenEXR/IlmImf/ImfMisc.cpp#L1456-L1541
 
+/// Call the closure with the channel index and the absolute difference between
+/// the original and the decompressed sample, for each sample of a
+/// native-endian pixel section.
+/// A changed NaN or infinity counts as an infinite error.
+/// Returns an error if either of the sections does not have the size of the
+/// pixel section, and does not support subsampled channels.
+pub(crate) fn for_each_sample_error(
+    channels: &ChannelList,
+    pixel_section: IntegerBounds,
+    original_ne: &[u8],
+    decompressed_ne: &[u8],
+    mut on_sample_error: impl FnMut(usize, f64),
+) -> UnitResult {
+    if channels.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+        return Err(Error::unsupported("sample error of subsampled channels"));
+    }
+
+    let expected_byte_size = pixel_section.size.area() * channels.bytes_per_pixel;
+    if original_ne.len() != expected_byte_size {
+        return Err(Error::invalid("original pixel section byte size"));
+    }
+
+    if decompressed_ne.len() != expected_byte_size {
+        return Err(Error::invalid("decompressed pixel section byte size"));
+    }
+
+    // each line contains all samples of the first channel, then the second channel,
+    // and so on
+    let mut original = original_ne;
+    let mut decompressed = decompressed_ne;
+
+    for _ in 0..pixel_section.size.height() {
+        for (channel_index, channel) in channels.list.iter().enumerate() {
+            let bytes_per_sample = channel.sample_type.bytes_per_sample();
+            let line_bytes = pixel_section.size.width() * bytes_per_sample;
+
+            let (original_line, original_rest) = original.split_at(line_bytes);
+            let (decompressed_line, decompressed_rest) = decompressed.split_at(line_bytes);
+            original = original_rest;
+            decompressed = decompressed_rest;
+
+            let samples = original_line
+                .chunks_exact(bytes_per_sample)
+                .zip(decompressed_line.chunks_exact(bytes_per_sample));
+
+            for (original_sample, decompressed_sample) in samples {
+                let original_value = sample_bytes_to_f64(channel.sample_type, original_sample);
+                let decompressed_value =
+                    sample_bytes_to_f64(channel.sample_type, decompressed_sample);
+
+                let error = if original_value == decompressed_value
+                    || (original_value.is_nan() && decompressed_value.is_nan())
+                {
+                    0.0
+                } else {
+                    let difference = (original_value - decompressed_value).abs();
+                    if difference.is_nan() {
+                        f64::INFINITY
+                    } else {
+                        difference
+                    }
+                };
+
+                on_sample_error(channel_index, error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn sample_bytes_to_f64(sample_type: SampleType, bytes: &[u8]) -> f64 {
+    match sample_type {
+        SampleType::F16 => half::f16::from_ne_bytes([bytes[0], bytes[1]]).to_f64(),
+        SampleType::F32 => f64::from(f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        SampleType::U32 => f64::from(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+    }
+}
+
 #[allow(unused)] // allows the extra parameters to be unused
 fn convert_current_to_little_endian(
     mut bytes: ByteVec,
@@ -467,14 +769,85 @@ fn convert_little_endian_to_current(
     mut bytes: ByteVec,
     channels: &ChannelList,
     rectangle: IntegerBounds,
+) -> Result<ByteVec> {
+    convert_little_endian_to_current_in_place(&mut bytes, channels, rectangle)?;
+    Ok(bytes)
+}
+
+fn convert_little_endian_to_current_in_place(
+    bytes: &mut [u8],
+    channels: &ChannelList,
+    rectangle: IntegerBounds,
+) -> UnitResult {
+    if cfg!(target_endian = "big") {
+        reverse_block_endianness(bytes, channels, rectangle)?;
+    }
+
+    Ok(())
+}
+
+/// Deep sample data is stored line by line. Each line contains all samples of
+/// the first channel, then all samples of the second channel, and so on.
+/// The number of samples in each line is required to find the channel
+/// boundaries.
+#[allow(unused)] // allows the extra parameters to be unused
+pub(crate) fn convert_deep_little_endian_to_current(
+    mut bytes: ByteVec,
+    channels: &ChannelList,
+    line_sample_counts: impl Iterator<Item = usize>,
 ) -> Result<ByteVec> {
     #[cfg(target_endian = "big")]
-    reverse_block_endianness(&mut bytes, channels, rectangle)?;
+    reverse_deep_block_endianness(&mut bytes, channels, line_sample_counts)?;
+
+    Ok(bytes)
+}
+
+/// Converts native-endian deep sample data to little-endian sample data.
+/// See `convert_deep_little_endian_to_current`.
+#[allow(unused)] // allows the extra parameters to be unused
+pub(crate) fn convert_deep_current_to_little_endian(
+    mut bytes: ByteVec,
+    channels: &ChannelList,
+    line_sample_counts: impl Iterator<Item = usize>,
+) -> Result<ByteVec> {
+    #[cfg(target_endian = "big")]
+    reverse_deep_block_endianness(&mut bytes, channels, line_sample_counts)?;
 
     Ok(bytes)
 }
 
 #[allow(unused)] // unused when on little endian system
+fn reverse_deep_block_endianness(
+    bytes: &mut [u8],
+    channels: &ChannelList,
+    line_sample_counts: impl Iterator<Item = usize>,
+) -> UnitResult {
+    let mut remaining_bytes: &mut [u8] = bytes;
+
+    for line_sample_count in line_sample_counts {
+        for channel in &channels.list {
+            let byte_count = line_sample_count * channel.sample_type.bytes_per_sample();
+            if byte_count > remaining_bytes.len() {
+                return Err(Error::invalid("deep sample data size"));
+            }
+
+            let (line_bytes, rest) = std::mem::take(&mut remaining_bytes).split_at_mut(byte_count);
+            let reverse = match channel.sample_type {
+                SampleType::F16 => reverse_2_bytes,
+                SampleType::F32 | SampleType::U32 => reverse_4_bytes,
+            };
+
+            for value_bytes in line_bytes.chunks_exact_mut(channel.sample_type.bytes_per_sample()) {
+                reverse(value_bytes);
+            }
+
+            remaining_bytes = rest;
+        }
+    }
+
+    Ok(())
+}
+
 fn reverse_block_endianness(
     bytes: &mut [u8],
     channels: &ChannelList,
@@ -568,9 +941,25 @@ const fn mod_p(x: i32, y: i32) -> i32 {
 
 /// A collection of functions used to prepare data for compression.
 mod optimize_bytes {
+    // SIMD kernels for the transforms below, which fall back to the scalar
+    // loops on other platforms and for the remaining bytes
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    mod x86;
+
     /// Integrate over all differences to the previous value in order to
     /// reconstruct sample values.
     pub fn differences_to_samples(buffer: &mut [u8]) {
+        // continue after the last sample that was reconstructed with simd
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let buffer = {
+            let last_index = x86::try_differences_to_samples(buffer);
+            &mut buffer[last_index..]
+        };
+
+        differences_to_samples_scalar(buffer);
+    }
+
+    fn differences_to_samples_scalar(buffer: &mut [u8]) {
         // The naive implementation is very simple:
         //
         // for index in 1..buffer.len() {
@@ -610,6 +999,17 @@ mod optimize_bytes {
     /// Derive over all values in order to produce differences to the previous
     /// value.
     pub fn samples_to_differences(buffer: &mut [u8]) {
+        // simd processes the end of the buffer, leaving the start for the scalar loop
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let buffer = {
+            let remaining = x86::try_samples_to_differences(buffer);
+            &mut buffer[..remaining]
+        };
+
+        samples_to_differences_scalar(buffer);
+    }
+
+    fn samples_to_differences_scalar(buffer: &mut [u8]) {
         // naive version:
         // for index in (1..buffer.len()).rev() {
         //     buffer[index] = (buffer[index] as i32 - buffer[index - 1] as i32 + 128)
@@ -712,43 +1112,60 @@ mod optimize_bytes {
     /// other byte.
     pub fn interleave_byte_blocks(separated: &mut [u8]) {
         with_reused_buffer(separated.len(), |interleaved| {
-            // Split the two halves that we are going to interleave.
-            let (first_half, second_half) = separated.split_at((separated.len() + 1) / 2);
-            // The first half can be 1 byte longer than the second if the length of the
-            // input is odd, but the loop below only processes numbers in pairs.
-            // To handle it, preserve the last element of the first slice, to be handled
-            // after the loop.
-            let first_half_last = first_half.last();
-            // Truncate the first half to match the lenght of the second one; more
-            // optimizer-friendly
-            let first_half_iter = &first_half[..second_half.len()];
-
-            // Main loop that performs the interleaving
-            for ((first, second), interleaved) in
-                first_half_iter.iter().zip(second_half.iter()).zip(interleaved.chunks_exact_mut(2))
-            {
-                // The length of each chunk is known to be 2 at compile time,
-                // and each index is also a constant.
-                // This allows the compiler to remove the bounds checks.
-                interleaved[0] = *first;
-                interleaved[1] = *second;
-            }
-
-            // If the length of the slice was odd, restore the last element of the first
-            // half that we saved
-            if interleaved.len() % 2 == 1 {
-                if let Some(value) = first_half_last {
-                    // we can unwrap() here because we just checked that the lenght is non-zero:
-                    // `% 2 == 1` will fail for zero
-                    *interleaved.last_mut().unwrap() = *value;
-                }
-            }
+            interleave_byte_blocks_into(separated, interleaved);
 
             // write out the results
             separated.copy_from_slice(interleaved);
         });
     }
 
+    /// Interleave the bytes into the target, such that the second half of the
+    /// source is every other byte. Both slices must have the same length.
+    pub fn interleave_byte_blocks_into(separated: &[u8], interleaved: &mut [u8]) {
+        debug_assert_eq!(separated.len(), interleaved.len());
+
+        // Split the two halves that we are going to interleave.
+        let (first_half, second_half) = separated.split_at((separated.len() + 1) / 2);
+        // The first half can be 1 byte longer than the second if the length of the
+        // input is odd, but the loop below only processes numbers in pairs.
+        // To handle it, preserve the last element of the first slice, to be handled
+        // after the loop.
+        let first_half_last = first_half.last();
+        // Truncate the first half to match the lenght of the second one; more
+        // optimizer-friendly
+        let first_half_iter = &first_half[..second_half.len()];
+
+        // Interleave as many pairs as possible with simd
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let pairs = x86::try_interleave_byte_blocks(first_half_iter, second_half, interleaved);
+
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        let pairs = 0;
+
+        // Main loop that performs the interleaving of the remaining pairs
+        for ((first, second), interleaved) in first_half_iter[pairs..]
+            .iter()
+            .zip(second_half[pairs..].iter())
+            .zip(interleaved[pairs * 2..].chunks_exact_mut(2))
+        {
+            // The length of each chunk is known to be 2 at compile time,
+            // and each index is also a constant.
+            // This allows the compiler to remove the bounds checks.
+            interleaved[0] = *first;
+            interleaved[1] = *second;
+        }
+
+        // If the length of the slice was odd, restore the last element of the first
+        // half that we saved
+        if interleaved.len() % 2 == 1 {
+            if let Some(value) = first_half_last {
+                // we can unwrap() here because we just checked that the lenght is non-zero:
+                // `% 2 == 1` will fail for zero
+                *interleaved.last_mut().unwrap() = *value;
+            }
+        }
+    }
+
     /// Separate the bytes such that the second half contains every other byte.
     /// This performs deinterleaving - the inverse of interleaving.
     pub fn separate_bytes_fragments(source: &mut [u8]) {
@@ -762,9 +1179,18 @@ mod optimize_bytes {
             let last = source.last();
             let first_half_iter = &mut first_half[..second_half.len()];
 
-            // Main loop that performs the deinterleaving
-            for ((first, second), interleaved) in
-                first_half_iter.iter_mut().zip(second_half.iter_mut()).zip(source.chunks_exact(2))
+            // Deinterleave as many pairs as possible with simd
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            let pairs = x86::try_separate_bytes_fragments(source, first_half_iter, second_half);
+
+            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            let pairs = 0;
+
+            // Main loop that performs the deinterleaving of the remaining pairs
+            for ((first, second), interleaved) in first_half_iter[pairs..]
+                .iter_mut()
+                .zip(second_half[pairs..].iter_mut())
+                .zip(source[pairs * 2..].chunks_exact(2))
             {
                 // The length of each chunk is known to be 2 at compile time,
                 // and each index is also a constant.
@@ -811,6 +1237,65 @@ mod optimize_bytes {
 
             assert_eq!(source, modified);
         }
+
+        // xorshift bytes of every length up to a few simd chunks,
+        // so that each kernel also has to handle the remaining bytes
+        fn pseudo_random_buffers() -> impl Iterator<Item = Vec<u8>> {
+            let mut state: u32 = 0x9e3779b9;
+
+            (0..100).map(move |length| {
+                (0..length)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 17;
+                        state ^= state << 5;
+                        (state >> 24) as u8
+                    })
+                    .collect()
+            })
+        }
+
+        #[test]
+        fn simd_derive_equals_scalar() {
+            for source in pseudo_random_buffers() {
+                let mut expected = source.clone();
+                let mut actual = source.clone();
+
+                super::samples_to_differences_scalar(&mut expected);
+                super::samples_to_differences(&mut actual);
+                assert_eq!(expected, actual, "length {}", source.len());
+
+                super::differences_to_samples_scalar(&mut expected);
+                super::differences_to_samples(&mut actual);
+                assert_eq!(expected, actual, "length {}", source.len());
+                assert_eq!(source, actual, "length {}", source.len());
+            }
+        }
+
+        #[test]
+        fn simd_interleave_equals_scalar() {
+            for source in pseudo_random_buffers() {
+                let length = source.len();
+                let (first_half, second_half) = source.split_at((length + 1) / 2);
+
+                let mut expected = Vec::with_capacity(length);
+                for index in 0..length {
+                    let half = if index % 2 == 0 {
+                        first_half
+                    } else {
+                        second_half
+                    };
+                    expected.push(half[index / 2]);
+                }
+
+                let mut actual = source.clone();
+                super::interleave_byte_blocks(&mut actual);
+                assert_eq!(expected, actual, "length {}", length);
+
+                super::separate_bytes_fragments(&mut actual);
+                assert_eq!(source, actual, "length {}", length);
+            }
+        }
     }
 }
 
@@ -843,6 +1328,179 @@ mod test {
         roundtrip_convert_endianness(data, &channels, IntegerBounds::from_dimensions((2, 2)));
     }
 
+    #[test]
+    fn decompress_into_reused_buffers_matches_allocating() {
+        let channels = smallvec![
+            ChannelDescription::new("Y", SampleType::F16, true),
+            ChannelDescription::new("Z", SampleType::F32, true),
+        ];
+
+        let compressions = [
+            Compression::Uncompressed,
+            Compression::RLE,
+            Compression::ZIP1,
+            Compression::ZIP16,
+            Compression::PIZ,
+            Compression::PXR24,
+            Compression::B44,
+            Compression::B44A,
+            Compression::DWAA(None),
+            Compression::DWAB(None),
+        ];
+
+        let mut scratch = DecompressionScratch::default();
+
+        for compression in compressions {
+            let header = Header::new("into".into(), (19, 40), channels.clone()).with_encoding(
+                compression,
+                crate::meta::BlockDescription::ScanLines,
+                crate::meta::attribute::LineOrder::Increasing,
+            );
+
+            // the largest block first, so that the smaller blocks reuse larger buffers
+            let block_height = compression.scan_lines_per_block().min(40);
+            for height in [block_height, 1] {
+                let section = IntegerBounds::from_dimensions((19, height));
+                let samples = (0..19 * height).map(|index| index as f32 * 0.25);
+
+                let pixels_ne: ByteVec = (0..height)
+                    .flat_map(|y| {
+                        let line = samples.clone().skip(y * 19).take(19);
+                        let y_line = line.clone().flat_map(|sample| sample.to_f16().to_ne_bytes());
+                        let z_line = line.flat_map(f32::to_ne_bytes);
+                        y_line.chain(z_line).collect::<Vec<u8>>()
+                    })
+                    .collect();
+
+                let compressed_le = compression
+                    .compress_image_section_to_le(&header, pixels_ne.clone(), section)
+                    .unwrap();
+
+                let allocated = compression
+                    .decompress_image_section_from_le(&header, compressed_le.clone(), section, true)
+                    .unwrap();
+
+                let mut decompressed_ne = vec![0xAB; pixels_ne.len()];
+                compression
+                    .decompress_image_section_from_le_into(
+                        &header,
+                        &compressed_le,
+                        section,
+                        true,
+                        &mut scratch,
+                        &mut decompressed_ne,
+                    )
+                    .unwrap();
+
+                assert_eq!(decompressed_ne, allocated, "{compression}");
+
+                let mut too_small = vec![0; pixels_ne.len() - 1];
+                let result = compression.decompress_image_section_from_le_into(
+                    &header,
+                    &compressed_le,
+                    section,
+                    true,
+                    &mut scratch,
+                    &mut too_small,
+                );
+
+                assert!(result.is_err(), "{}", compression);
+            }
+        }
+    }
+
+    #[test]
+    fn htj2k_decompression_is_not_supported() {
+        let channels = smallvec![ChannelDescription::new("Y", SampleType::F16, true)];
+        let section = IntegerBounds::from_dimensions((4, 4));
+
+        for compression in [Compression::HTJ2K32, Compression::HTJ2K256] {
+            let header = Header::new("htj2k".into(), (4, 4), channels.clone()).with_encoding(
+                compression,
+                crate::meta::BlockDescription::ScanLines,
+                crate::meta::attribute::LineOrder::Increasing,
+            );
+
+            // smaller than the uncompressed block, so it is not stored raw
+            let result =
+                compression.decompress_image_section_from_le(&header, vec![0; 7], section, false);
+
+            assert!(matches!(result, Err(Error::NotSupported(_))), "{}", compression);
+        }
+    }
+
+    #[test]
+    fn invalid_pixel_sections_are_errors() {
+        let channels = smallvec![ChannelDescription::new("Y", SampleType::F16, true)];
+        let mut header = Header::new("section".into(), (4, 4), channels).with_encoding(
+            Compression::PIZ,
+            crate::meta::BlockDescription::ScanLines,
+            crate::meta::attribute::LineOrder::Increasing,
+        );
+
+        let too_wide = IntegerBounds::from_dimensions((8, 4));
+        let mut pixels = vec![0; too_wide.size.area() * 2];
+        let result = Compression::PIZ.decompress_image_section_from_le_into(
+            &header,
+            &[0; 7],
+            too_wide,
+            false,
+            &mut DecompressionScratch::default(),
+            &mut pixels,
+        );
+
+        assert!(matches!(result, Err(Error::Invalid(_))), "section exceeds the block");
+
+        let result = Compression::PIZ.compress_image_section_to_le(&header, pixels, too_wide);
+        assert!(matches!(result, Err(Error::Invalid(_))), "section exceeds the block");
+
+        header.deep = true;
+        let section = IntegerBounds::from_dimensions((4, 4));
+        let result =
+            Compression::PIZ.decompress_image_section_from_le(&header, vec![0; 7], section, false);
+
+        assert!(matches!(result, Err(Error::NotSupported(_))), "deep piz data");
+    }
+
+    #[test]
+    fn sample_errors_require_matching_sections() {
+        let channels = ChannelList::new(smallvec![
+            ChannelDescription::new("Y", SampleType::F16, true),
+            ChannelDescription::new("Z", SampleType::U32, true),
+        ]);
+
+        let section = IntegerBounds::from_dimensions((2, 1));
+        let original: ByteVec = [1.0_f32.to_f16().to_ne_bytes(), 2.0_f32.to_f16().to_ne_bytes()]
+            .iter()
+            .flatten()
+            .chain(&7_u32.to_ne_bytes())
+            .chain(&9_u32.to_ne_bytes())
+            .copied()
+            .collect();
+
+        let mut changed = original.clone();
+        changed[4..8].copy_from_slice(&4_u32.to_ne_bytes());
+
+        let mut errors = Vec::new();
+        for_each_sample_error(&channels, section, &original, &changed, |channel, error| {
+            errors.push((channel, error));
+        })
+        .unwrap();
+
+        assert_eq!(errors, vec![(0, 0.0), (0, 0.0), (1, 3.0), (1, 0.0)]);
+
+        let truncated = &changed[..changed.len() - 1];
+        let truncated_result =
+            for_each_sample_error(&channels, section, &original, truncated, |_, _| {});
+        assert!(truncated_result.is_err());
+
+        let mut subsampled = channels;
+        subsampled.list[1].sampling = Vec2(2, 1);
+        let subsampled_result =
+            for_each_sample_error(&subsampled, section, &original, &changed, |_, _| {});
+        assert!(subsampled_result.is_err());
+    }
+
     fn roundtrip_convert_endianness(
         current_endian: ByteVec,
         channels: &ChannelList,
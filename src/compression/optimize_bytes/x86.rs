@@ -0,0 +1,176 @@
+// Runtime x86 SIMD dispatch for the byte transforms of ZIP, RLE and PXR24.
+// Each `try_*` runs the SSE2 V1 kernel when available and reports how far it
+// got, so the caller only runs its scalar loop on the remaining tail.
+//
+// There is no AVX2 tier: the prefix sum and the byte interleaving would have
+// to cross the 128-bit lanes of AVX2, which needs additional permutations.
+// The kernels produce exactly the same bytes as the scalar loops.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m128i;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::__m128i;
+use std::convert::TryInto;
+
+use pulp::{bytemuck::cast, x86::V1};
+
+const LANES: usize = 16;
+
+fn load(bytes: &[u8]) -> __m128i {
+    let array: [u8; LANES] = bytes.try_into().expect("simd chunk size bug");
+    cast(array)
+}
+
+fn store(bytes: &mut [u8], vector: __m128i) {
+    let array: [u8; LANES] = cast(vector);
+    bytes.copy_from_slice(&array);
+}
+
+/// Computes the differences of all bytes at the end of the buffer.
+/// Returns the length of the leading part of the buffer that still needs to be
+/// processed by the scalar loop.
+pub(super) fn try_samples_to_differences(buffer: &mut [u8]) -> usize {
+    let Some(v1) = V1::try_new() else {
+        return buffer.len();
+    };
+
+    v1.vectorize(move || {
+        let bias = cast(v1.splat_u8x16(128));
+        let mut end = buffer.len();
+
+        // walk backwards, so the previous sample of each byte has not been
+        // overwritten yet
+        while end > LANES {
+            let start = end - LANES;
+            let samples = load(&buffer[start..end]);
+            let previous = load(&buffer[start - 1..end - 1]);
+
+            let differences = v1.sse2._mm_sub_epi8(samples, previous);
+            store(&mut buffer[start..end], v1.sse2._mm_add_epi8(differences, bias));
+
+            end = start;
+        }
+
+        end
+    })
+}
+
+/// Reconstructs the samples of all full chunks at the start of the buffer.
+/// Returns the index of the last reconstructed sample,
+/// from where the scalar loop must continue.
+pub(super) fn try_differences_to_samples(buffer: &mut [u8]) -> usize {
+    let Some(v1) = V1::try_new() else {
+        return 0;
+    };
+
+    let Some(&first) = buffer.first() else {
+        return 0;
+    };
+
+    v1.vectorize(move || {
+        let sse2 = v1.sse2;
+        let bias = cast(v1.splat_u8x16(128));
+        let mut previous = cast(v1.splat_u8x16(first));
+        let mut last_index = 0;
+
+        for chunk in buffer[1..].chunks_exact_mut(LANES) {
+            // prefix sum of all differences within the chunk, in four steps
+            let mut sum = sse2._mm_sub_epi8(load(chunk), bias);
+            sum = sse2._mm_add_epi8(sum, sse2._mm_slli_si128::<1>(sum));
+            sum = sse2._mm_add_epi8(sum, sse2._mm_slli_si128::<2>(sum));
+            sum = sse2._mm_add_epi8(sum, sse2._mm_slli_si128::<4>(sum));
+            sum = sse2._mm_add_epi8(sum, sse2._mm_slli_si128::<8>(sum));
+
+            let samples = sse2._mm_add_epi8(sum, previous);
+            store(chunk, samples);
+
+            // broadcast the last sample to all lanes for the next chunk
+            let high_pairs = sse2._mm_unpackhi_epi8(samples, samples);
+            let last_pair = sse2._mm_shufflehi_epi16::<0xFF>(high_pairs);
+            previous = sse2._mm_unpackhi_epi64(last_pair, last_pair);
+
+            last_index += LANES;
+        }
+
+        last_index
+    })
+}
+
+/// Interleaves the two halves into the target, as long as there are full
+/// chunks in both halves. Returns the number of interleaved pairs.
+pub(super) fn try_interleave_byte_blocks(
+    first_half: &[u8],
+    second_half: &[u8],
+    interleaved: &mut [u8],
+) -> usize {
+    let Some(v1) = V1::try_new() else {
+        return 0;
+    };
+
+    v1.vectorize(move || {
+        let mut pairs = 0;
+
+        for ((first, second), interleaved) in first_half
+            .chunks_exact(LANES)
+            .zip(second_half.chunks_exact(LANES))
+            .zip(interleaved.chunks_exact_mut(LANES * 2))
+        {
+            let first = load(first);
+            let second = load(second);
+
+            let (low, high) = interleaved.split_at_mut(LANES);
+            store(low, v1.sse2._mm_unpacklo_epi8(first, second));
+            store(high, v1.sse2._mm_unpackhi_epi8(first, second));
+
+            pairs += LANES;
+        }
+
+        pairs
+    })
+}
+
+/// Separates the even and the odd bytes of the source into the two halves,
+/// as long as there are full chunks in both halves.
+/// Returns the number of separated pairs.
+pub(super) fn try_separate_bytes_fragments(
+    source: &[u8],
+    first_half: &mut [u8],
+    second_half: &mut [u8],
+) -> usize {
+    let Some(v1) = V1::try_new() else {
+        return 0;
+    };
+
+    v1.vectorize(move || {
+        let sse2 = v1.sse2;
+        let even_mask = cast(v1.splat_u16x8(0x00FF));
+        let mut pairs = 0;
+
+        for ((interleaved, first), second) in source
+            .chunks_exact(LANES * 2)
+            .zip(first_half.chunks_exact_mut(LANES))
+            .zip(second_half.chunks_exact_mut(LANES))
+        {
+            let (low, high) = interleaved.split_at(LANES);
+            let low = load(low);
+            let high = load(high);
+
+            // each 16-bit lane holds an even byte in its low half and an odd
+            // byte in its high half, which are then packed back into bytes
+            let even = sse2._mm_packus_epi16(
+                sse2._mm_and_si128(low, even_mask),
+                sse2._mm_and_si128(high, even_mask),
+            );
+
+            let odd = sse2
+                ._mm_packus_epi16(sse2._mm_srli_epi16::<8>(low), sse2._mm_srli_epi16::<8>(high));
+
+            store(first, even);
+            store(second, odd);
+
+            pairs += LANES;
+        }
+
+        pairs
+    })
+}
@@ -6,7 +6,7 @@
 use std::{
     cmp::Ordering,
     collections::BinaryHeap,
-    convert::TryFrom,
+    convert::{TryFrom, TryInto},
     io::{Cursor, Read, Write},
 };
 
@@ -18,7 +18,42 @@ use crate::{
     math::RoundingMode,
 };
 
+/// Decompress the huffman encoded `u16` values.
+// the decoders of the crate use `decompress_into`
+#[cfg(any(test, feature = "huffman-benches"))]
 pub fn decompress(compressed: &[u8], expected_size: usize) -> Result<Vec<u16>> {
+    decompress_with(compressed, expected_size, true, Vec::with_capacity(expected_size))
+}
+
+/// Decompress the huffman encoded `u16` values into the cleared buffer,
+/// reusing its allocation.
+pub fn decompress_into(
+    compressed: &[u8],
+    expected_size: usize,
+    output: &mut Vec<u16>,
+) -> UnitResult {
+    let mut buffer = std::mem::take(output);
+    buffer.clear();
+
+    *output = decompress_with(compressed, expected_size, true, buffer)?;
+    Ok(())
+}
+
+/// Decompress one code after another, without the lookup table for multiple
+/// short codes. Produces the same result as `decompress`.
+// public only for benchmarking (benches/huffman.rs compares the two decoders)
+#[cfg(any(test, feature = "huffman-benches"))]
+#[doc(hidden)]
+pub fn decompress_symbol_by_symbol(compressed: &[u8], expected_size: usize) -> Result<Vec<u16>> {
+    decompress_with(compressed, expected_size, false, Vec::with_capacity(expected_size))
+}
+
+fn decompress_with(
+    compressed: &[u8],
+    expected_size: usize,
+    use_short_codes_table: bool,
+    mut output: Vec<u16>,
+) -> Result<Vec<u16>> {
     let mut remaining_compressed = compressed;
 
     let min_code_index = usize::try_from(u32::read_le(&mut remaining_compressed)?)?;
@@ -43,19 +78,42 @@ pub fn decompress(compressed: &[u8], expected_size: usize) -> Result<Vec<u16>> {
     }
 
     let decoding_table = build_decoding_table(&encoding_table, min_code_index, max_code_index)?;
+    debug_assert!(output.is_empty());
+    output.reserve(expected_size);
+
+    let tables = DecodingTables {
+        encoding_table,
+        decoding_table,
+        run_length_code: max_code_index_32,
+    };
+
+    // decode most of the input quickly, leaving only the last few bytes
+    let bit_position = if use_short_codes_table {
+        let short_codes_table =
+            build_short_codes_table(&tables.decoding_table, tables.run_length_code);
+
+        decode_with_short_codes_table(
+            &short_codes_table,
+            &tables,
+            remaining_compressed,
+            expected_size,
+            &mut output,
+        )?
+    } else {
+        0
+    };
 
-    let result = decode_with_tables(
-        &encoding_table,
-        &decoding_table,
+    decode_with_tables(
+        &tables,
         remaining_compressed,
+        bit_position,
         i32::try_from(bit_count)?,
-        max_code_index_32,
         expected_size,
-    )?;
-
-    Ok(result)
+        output,
+    )
 }
 
+/// Compress the `u16` values with huffman encoding.
 pub fn compress(uncompressed: &[u16]) -> Result<Vec<u8>> {
     if uncompressed.is_empty() {
         return Ok(vec![]);
@@ -94,6 +152,18 @@ const ENCODING_TABLE_SIZE: usize = ((1 << ENCODE_BITS) + 1) as usize;
 const DECODING_TABLE_SIZE: usize = (1 << DECODE_BITS) as usize;
 const DECODE_MASK: u64 = DECODING_TABLE_SIZE as u64 - 1;
 
+/// The maximum number of short codes decoded with a single table lookup.
+const MAX_SHORT_CODES_PER_LOOKUP: usize = 3;
+
+/// Index bit size of the short codes table, small enough for the table to
+/// stay in the cache. Longer codes are resolved with the decoding table.
+const SHORT_CODES_BITS: u64 = 12;
+const SHORT_CODES_TABLE_SIZE: usize = 1 << SHORT_CODES_BITS;
+
+/// The number of short codes table lookups that fit into the 57 bits of a
+/// single read.
+const LOOKUPS_PER_READ: usize = 4;
+
 const SHORT_ZEROCODE_RUN: u64 = 59;
 const LONG_ZEROCODE_RUN: u64 = 63;
 const SHORTEST_LONG_RUN: u64 = 2 + LONG_ZEROCODE_RUN - SHORT_ZEROCODE_RUN;
@@ -119,19 +189,171 @@ impl ShortCode {
     }
 }
 
-/// Decode (uncompress) n bits based on encoding & decoding tables:
+/// The tables of a huffman stream, and its run length code.
+struct DecodingTables {
+    encoding_table: Vec<u64>,
+    decoding_table: Vec<Code>,
+    run_length_code: u32,
+}
+
+/// All consecutive short codes that fit into the bits of a decoding table
+/// index. Does not contain the run length code.
+#[derive(Clone, Copy, Debug, Default)]
+struct ShortCodes {
+    symbols: [u16; MAX_SHORT_CODES_PER_LOOKUP],
+    count: u8,
+    bit_count: u8,
+}
+
+/// Decode as many codes as possible while enough bytes of input remain,
+/// using a single table lookup for up to three short codes.
+/// Returns the bit position at which `decode_with_tables` must continue.
+/// The decoded codes are the same as in `decode_with_tables`,
+/// because both decode each code as soon as at least `DECODE_BITS` are
+/// available.
+fn decode_with_short_codes_table(
+    short_codes_table: &[ShortCodes],
+    tables: &DecodingTables,
+    input: &[u8],
+    expected_output_size: usize,
+    output: &mut Vec<u16>,
+) -> Result<usize> {
+    let encoding_table = &tables.encoding_table;
+    let decoding_table = &tables.decoding_table;
+    let run_length_code = tables.run_length_code;
+    let mut bit_position = 0;
+
+    // write into initialized memory, so that all symbols of a lookup can be
+    // copied at once, even if only some of them are valid
+    let mut output_len = output.len();
+    output.resize(expected_output_size, 0);
+
+    // the lookups between two reads may advance by six bytes,
+    // after which 16 bytes must remain for the long codes
+    while bit_position / 8 + 6 + 16 <= input.len() {
+        // read once for multiple lookups, because each lookup depends on the previous
+        // one
+        let mut next_bits = peek_u64(input, bit_position);
+        let mut lookups = 0;
+
+        while lookups < LOOKUPS_PER_READ {
+            let short_codes = &short_codes_table[(next_bits >> (64 - SHORT_CODES_BITS)) as usize];
+
+            if short_codes.count == 0
+                || output_len + MAX_SHORT_CODES_PER_LOOKUP > expected_output_size
+            {
+                break;
+            }
+
+            output[output_len..output_len + MAX_SHORT_CODES_PER_LOOKUP]
+                .copy_from_slice(&short_codes.symbols);
+
+            output_len += usize::from(short_codes.count);
+            bit_position += usize::from(short_codes.bit_count);
+            next_bits <<= short_codes.bit_count;
+            lookups += 1;
+        }
+
+        if lookups == LOOKUPS_PER_READ {
+            continue;
+        }
+
+        // run length codes, long codes, or the end of the output
+        let next_bits = peek_u128(input, bit_position);
+        let code_index = (next_bits >> (128 - DECODE_BITS)) as usize;
+
+        let (code, length) = match &decoding_table[code_index] {
+            Code::Short(short_code) => (short_code.value, short_code.len()),
+
+            Code::Long(long_codes) => long_codes
+                .iter()
+                .find_map(|&long_code| {
+                    let encoded_long_code =
+                        encoding_table[u32_to_usize(long_code, "huffman long code").ok()?];
+                    let length = length(encoded_long_code);
+                    let required_code = next_bits >> (128 - length);
+
+                    if u128::from(self::code(encoded_long_code)) == required_code {
+                        Some((long_code, length))
+                    } else {
+                        None
+                    }
+                })
+                .ok_or_else(|| Error::invalid(INVALID_CODE))?,
+
+            Code::Empty => return Err(Error::invalid(INVALID_CODE)),
+        };
+
+        bit_position += u64_to_usize(length, "huffman code length")?;
+
+        if code == run_length_code {
+            // the peeked bits contain at least the longest code and the repetition count
+            let code_repetitions = usize::from(((next_bits << length) >> 120) as u8);
+            bit_position += 8;
+
+            if output_len + code_repetitions > expected_output_size {
+                return Err(Error::invalid(TOO_MUCH_DATA));
+            } else if output_len == 0 {
+                return Err(Error::invalid(NOT_ENOUGH_DATA));
+            }
+
+            let repeated_code = output[output_len - 1];
+            output[output_len..output_len + code_repetitions].fill(repeated_code);
+            output_len += code_repetitions;
+        } else if output_len < expected_output_size {
+            output[output_len] = u16::try_from(code)?;
+            output_len += 1;
+        } else {
+            return Err(Error::invalid(TOO_MUCH_DATA));
+        }
+    }
+
+    output.truncate(output_len);
+    Ok(bit_position)
+}
+
+/// The next 57 or more bits at the bit position, starting at the most
+/// significant bit. Requires 8 bytes of input from the byte of the position.
+#[inline]
+fn peek_u64(input: &[u8], bit_position: usize) -> u64 {
+    let start = bit_position / 8;
+    let bytes: [u8; 8] = input[start..start + 8].try_into().expect("huffman input bug");
+    u64::from_be_bytes(bytes) << (bit_position % 8)
+}
+
+/// The next 121 or more bits at the bit position, starting at the most
+/// significant bit. Requires 16 bytes of input from the byte of the position.
+#[inline]
+fn peek_u128(input: &[u8], bit_position: usize) -> u128 {
+    let start = bit_position / 8;
+    let bytes: [u8; 16] = input[start..start + 16].try_into().expect("huffman input bug");
+    u128::from_be_bytes(bytes) << (bit_position % 8)
+}
+
+/// Decode (uncompress) n bits based on encoding & decoding tables,
+/// starting at the bit position:
 fn decode_with_tables(
-    encoding_table: &[u64],
-    decoding_table: &[Code],
-    mut input: &[u8],
+    tables: &DecodingTables,
+    input: &[u8],
+    bit_position: usize,
     input_bit_count: i32,
-    run_length_code: u32,
     expected_output_size: usize,
+    mut output: Vec<u16>,
 ) -> Result<Vec<u16>> {
-    let mut output = Vec::with_capacity(expected_output_size);
+    let encoding_table = &tables.encoding_table;
+    let decoding_table = &tables.decoding_table;
+    let run_length_code = tables.run_length_code;
+    let mut input = &input[bit_position / 8..];
     let mut code_bits = 0_u64;
     let mut code_bit_count = 0_u64;
 
+    // skip the bits of the current byte that have already been decoded
+    let decoded_bits = (bit_position % 8) as u64;
+    if decoded_bits != 0 {
+        read_byte(&mut code_bits, &mut code_bit_count, &mut input)?;
+        code_bit_count -= decoded_bits;
+    }
+
     while !input.is_empty() {
         read_byte(&mut code_bits, &mut code_bit_count, &mut input)?;
 
@@ -289,6 +511,42 @@ fn build_decoding_table(
     Ok(decoding_table)
 }
 
+/// Build a table that resolves up to three consecutive short codes with
+/// a single access, indexed by the next `SHORT_CODES_BITS` bits.
+/// Codes that do not completely fit into the index, long codes, and the run
+/// length code are left to the decoding table.
+fn build_short_codes_table(decoding_table: &[Code], run_length_code: u32) -> Vec<ShortCodes> {
+    let mut short_codes_table = vec![ShortCodes::default(); SHORT_CODES_TABLE_SIZE];
+
+    for (index, short_codes) in short_codes_table.iter_mut().enumerate() {
+        while usize::from(short_codes.count) < MAX_SHORT_CODES_PER_LOOKUP {
+            let bit_count = u64::from(short_codes.bit_count);
+            let code_index =
+                ((index as u64) << (DECODE_BITS - SHORT_CODES_BITS + bit_count)) & DECODE_MASK;
+
+            let Code::Short(short_code) = &decoding_table[code_index as usize] else {
+                break;
+            };
+
+            if short_code.len() > SHORT_CODES_BITS - bit_count
+                || short_code.value == run_length_code
+            {
+                break;
+            }
+
+            match u16::try_from(short_code.value) {
+                Ok(symbol) => short_codes.symbols[usize::from(short_codes.count)] = symbol,
+                Err(_) => break,
+            }
+
+            short_codes.count += 1;
+            short_codes.bit_count += short_code.len;
+        }
+    }
+
+    short_codes_table
+}
+
 /// Run-length-decompresses all zero runs from the packed table to the encoding
 /// table
 fn read_encoding_table(
@@ -1004,6 +1262,69 @@ mod test {
         assert_eq!(uncompressed, decompressed.as_slice());
     }
 
+    fn assert_decoders_equal(compressed: &[u8], expected_size: usize) {
+        let with_table = decompress(compressed, expected_size).map_err(|error| error.to_string());
+
+        let symbol_by_symbol = decompress_symbol_by_symbol(compressed, expected_size)
+            .map_err(|error| error.to_string());
+
+        assert_eq!(with_table, symbol_by_symbol);
+    }
+
+    #[test]
+    fn table_decoder_equals_symbol_by_symbol() {
+        let mut random = rand::rngs::StdRng::from_seed(SEED);
+
+        for size in [1, 7, 30, 100, 1000, 50_000] {
+            let noise = fill(&mut random, size);
+
+            // small values produce many short codes, which are decoded together
+            let small: Vec<u16> = (0..size).map(|_| random.gen_range(0_u16..6)).collect();
+
+            // zeroes produce run length codes
+            let sparse: Vec<u16> = (0..size)
+                .map(|_| {
+                    if random.gen_bool(0.1) {
+                        random.gen()
+                    } else {
+                        0
+                    }
+                })
+                .collect();
+
+            for raw in [noise, small, sparse] {
+                let compressed = compress(&raw).unwrap();
+                assert_eq!(decompress(&compressed, raw.len()).unwrap(), raw);
+                assert_decoders_equal(&compressed, raw.len());
+
+                // invalid expected sizes
+                assert_decoders_equal(&compressed, raw.len() - 1);
+                assert_decoders_equal(&compressed, raw.len() + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn table_decoder_equals_symbol_by_symbol_for_invalid_data() {
+        let mut random = rand::rngs::StdRng::from_seed(SEED);
+        let raw: Vec<u16> = (0..4000).map(|_| random.gen_range(0_u16..300)).collect();
+        let compressed = compress(&raw).unwrap();
+
+        for _ in 0..200 {
+            let mut corrupted = compressed.clone();
+
+            // keep the header intact, but corrupt the table or the codes
+            let index = random.gen_range(20..corrupted.len());
+            corrupted[index] = random.gen();
+
+            assert_decoders_equal(&corrupted, raw.len());
+        }
+
+        for length in (20..compressed.len()).step_by(37) {
+            assert_decoders_equal(&compressed[..length], raw.len());
+        }
+    }
+
     const SEED: [u8; 32] = [
         12, 155, 32, 34, 112, 109, 98, 54, 12, 255, 32, 34, 112, 109, 98, 55, 12, 155, 32, 34, 12,
         109, 98, 54, 12, 35, 32, 34, 112, 109, 48, 54,
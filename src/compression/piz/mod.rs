@@ -2,14 +2,15 @@
 //! based on the PIZ image format, customized for `OpenEXR`.
 // inspired by  https://github.com/AcademySoftwareFoundation/openexr/blob/master/OpenEXR/IlmImf/ImfPizCompressor.cpp
 
-pub(crate) mod huffman;
+// public only for benchmarking
+pub mod huffman;
 mod wavelet;
 
 use std::convert::TryFrom;
 
 use crate::{
-    compression::{mod_p, ByteVec, Bytes},
-    error::{usize_to_i32, usize_to_u16},
+    compression::{mod_p, ByteVec, Bytes, DecompressionScratch},
+    error::{usize_to_i32, usize_to_u16, UnitResult},
     io::Data,
     meta::attribute::*,
     prelude::*,
@@ -30,25 +31,30 @@ struct ChannelData {
 
 pub fn decompress(
     channels: &ChannelList,
-    compressed_le: ByteVec,
+    compressed_le: &[u8],
     rectangle: IntegerBounds,
-    expected_byte_size: usize, /* TODO remove expected byte size as it can be computed with
-                                * `rectangle.size.area() * channels.bytes_per_pixel` */
     pedantic: bool,
-) -> Result<ByteVec> {
+    scratch: &mut DecompressionScratch,
+    decompressed_ne: &mut [u8],
+) -> UnitResult {
+    let expected_byte_size = decompressed_ne.len();
     let expected_u16_count = expected_byte_size / 2;
     debug_assert_eq!(expected_byte_size, rectangle.size.area() * channels.bytes_per_pixel);
     debug_assert!(!channels.list.is_empty());
 
     if compressed_le.is_empty() {
-        return Ok(Vec::new());
+        return if expected_byte_size == 0 {
+            Ok(())
+        } else {
+            Err(Error::invalid("decompressed data"))
+        };
     }
 
     debug_assert_ne!(expected_u16_count, 0);
 
     let mut bitmap = vec![0_u8; BITMAP_SIZE]; // FIXME use bit_vec!
 
-    let mut remaining_input_le = compressed_le.as_slice();
+    let mut remaining_input_le = compressed_le;
     let min_non_zero = u16::read_le(&mut remaining_input_le)? as usize;
     let max_non_zero = u16::read_le(&mut remaining_input_le)? as usize;
 
@@ -70,7 +76,8 @@ pub fn decompress(
         }
     }
 
-    let mut tmp_u16_buffer = huffman::decompress(remaining_input_le, expected_u16_count)?;
+    let tmp_u16_buffer = &mut scratch.samples;
+    huffman::decompress_into(remaining_input_le, expected_u16_count, tmp_u16_buffer)?;
 
     let mut channel_data: SmallVec<[ChannelData; 6]> = {
         let mut tmp_read_index = 0;
@@ -114,11 +121,9 @@ pub fn decompress(
     }
 
     // Expand the pixel data to their original range
-    apply_lookup_table(&mut tmp_u16_buffer, &lookup_table);
+    apply_lookup_table(tmp_u16_buffer, &lookup_table);
 
-    // let out_buffer_size = (max_scan_line_size * scan_line_count) + 65536 + 8192;
-    // // TODO not use expected byte size?
-    let mut out = Vec::with_capacity(expected_byte_size);
+    let mut out: &mut [u8] = decompressed_ne;
 
     for y in rectangle.position.y()..rectangle.end().y() {
         for channel in &mut channel_data {
@@ -136,7 +141,7 @@ pub fn decompress(
             // We can support uncompressed data in the machine's native format
             // if all image channels are of type HALF, and if the Xdr and the
             // native representations of a half have the same size.
-            u16::write_slice_le(&mut out, values).expect("write to in-memory failed");
+            u16::write_slice_le(&mut out, values)?;
         }
     }
 
@@ -145,12 +150,12 @@ pub fn decompress(
     }
 
     debug_assert_eq!(channel_data.last().unwrap().tmp_end_index, tmp_u16_buffer.len());
-    debug_assert_eq!(out.len(), expected_byte_size);
+    debug_assert!(out.is_empty());
 
     // TODO optimize for when all channels are f16!
     //      we should be able to omit endianness conversions in that case
     //      see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    super::convert_little_endian_to_current(out, channels, rectangle)
+    super::convert_little_endian_to_current_in_place(decompressed_ne, channels, rectangle)
 }
 
 pub fn compress(
@@ -312,7 +317,7 @@ fn apply_lookup_table(data: &mut [u16], table: &[u16]) {
 #[cfg(test)]
 mod test {
     use crate::{
-        compression::{piz, ByteVec},
+        compression::{piz, ByteVec, DecompressionScratch},
         meta::attribute::*,
         prelude::*,
     };
@@ -327,8 +332,16 @@ mod test {
             .collect();
 
         let compressed = piz::compress(&channels, pixel_bytes.clone(), rectangle).unwrap();
-        let decompressed =
-            piz::decompress(&channels, compressed, rectangle, pixel_bytes.len(), true).unwrap();
+        let mut decompressed = vec![0; pixel_bytes.len()];
+        piz::decompress(
+            &channels,
+            &compressed,
+            rectangle,
+            true,
+            &mut DecompressionScratch::default(),
+            &mut decompressed,
+        )
+        .unwrap();
 
         assert_eq!(pixel_bytes, decompressed);
     }
@@ -3,6 +3,24 @@
 
 use crate::{error::IoResult, math::Vec2};
 
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use x86::{try_decode_quads, try_encode_quads};
+
+/// Leaves all quads of the finest level to the scalar loop.
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn try_encode_quads(_: &mut [u16], _: &mut [u16], _: bool) -> usize {
+    0
+}
+
+/// Leaves all quads of the finest level to the scalar loop.
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn try_decode_quads(_: &mut [u16], _: &mut [u16], _: bool) -> usize {
+    0
+}
+
 #[allow(unused)]
 #[inline]
 pub fn encode(
@@ -21,10 +39,24 @@ pub fn encode(
 #[allow(unused)]
 #[inline]
 pub fn encode_14_or_16_bit(
+    buffer: &mut [u16],
+    count: Vec2<usize>,
+    offset: Vec2<usize>,
+    is_14_bit: bool, // true if maximum buffer[i] value < (1 << 14)
+) -> IoResult<()> {
+    encode_levels(buffer, count, offset, is_14_bit, try_encode_quads)
+}
+
+/// Encodes all levels. The closure encodes the quads of the finest level
+/// where the samples of a row are contiguous, see `x86::try_encode_quads`.
+/// The coarser levels are always encoded by the scalar loop.
+#[inline]
+fn encode_levels(
     buffer: &mut [u16],
     Vec2(count_x, count_y): Vec2<usize>,
     Vec2(offset_x, offset_y): Vec2<usize>,
-    is_14_bit: bool, // true if maximum buffer[i] value < (1 << 14)
+    is_14_bit: bool,
+    encode_quads: impl Fn(&mut [u16], &mut [u16], bool) -> usize,
 ) -> IoResult<()> {
     let count = count_x.min(count_y);
     let encode = if is_14_bit {
@@ -49,6 +81,11 @@ pub fn encode_14_or_16_bit(
             let mut position_x = position_y;
             let end_x = position_x + offset_x * (count_x - p2);
 
+            if p == 1 && offset_x == 1 && offset_y >= count_x {
+                let (row, next_row) = finest_quad_rows(buffer, position_x, end_x, offset_y);
+                position_x += encode_quads(row, next_row, is_14_bit);
+            }
+
             // x-loop
             while position_x <= end_x {
                 let pos_right = position_x + offset1_x;
@@ -130,10 +167,24 @@ pub fn decode(
 
 #[inline]
 pub fn decode_14_or_16_bit(
+    buffer: &mut [u16],
+    count: Vec2<usize>,
+    offset: Vec2<usize>,
+    is_14_bit: bool, // true if maximum buffer[i] value < (1 << 14)
+) -> IoResult<()> {
+    decode_levels(buffer, count, offset, is_14_bit, try_decode_quads)
+}
+
+/// Decodes all levels. The closure decodes the quads of the finest level
+/// where the samples of a row are contiguous, see `x86::try_decode_quads`.
+/// The coarser levels are always decoded by the scalar loop.
+#[inline]
+fn decode_levels(
     buffer: &mut [u16],
     Vec2(count_x, count_y): Vec2<usize>,
     Vec2(offset_x, offset_y): Vec2<usize>,
-    is_14_bit: bool, // true if maximum buffer[i] value < (1 << 14)
+    is_14_bit: bool,
+    decode_quads: impl Fn(&mut [u16], &mut [u16], bool) -> usize,
 ) -> IoResult<()> {
     let count = count_x.min(count_y);
     let decode = if is_14_bit {
@@ -168,6 +219,11 @@ pub fn decode_14_or_16_bit(
             let mut position_x = position_y;
             let end_x = position_x + offset_x * (count_x - p2);
 
+            if p == 1 && offset_x == 1 && offset_y >= count_x {
+                let (row, next_row) = finest_quad_rows(buffer, position_x, end_x, offset_y);
+                position_x += decode_quads(row, next_row, is_14_bit);
+            }
+
             while position_x <= end_x {
                 let pos_right = position_x + offset1_x;
                 let pos_top = position_x + offset1_y;
@@ -227,6 +283,20 @@ pub fn decode_14_or_16_bit(
     Ok(())
 }
 
+/// The upper and the lower row of the quads of the finest level,
+/// from the quad at `start` to the quad at `end`, if the samples of a row are
+/// contiguous.
+fn finest_quad_rows(
+    buffer: &mut [u16],
+    start: usize,
+    end: usize,
+    offset_y: usize,
+) -> (&mut [u16], &mut [u16]) {
+    let length = end + 2 - start;
+    let (row, next_row) = buffer[start..].split_at_mut(offset_y);
+    (&mut row[..length], &mut next_row[..length])
+}
+
 #[inline]
 fn is_14_bit(value: u16) -> bool {
     value < (1 << 14)
@@ -376,6 +446,57 @@ mod test {
         assert_eq!(data, transformed);
     }
 
+    #[test]
+    fn simd_transform_equals_scalar_transform() {
+        assert_transform_matches_scalar(super::try_encode_quads, super::try_decode_quads);
+    }
+
+    /// Compares the transform of all levels using the quad kernel of the finest
+    /// level with the scalar transform, for different sizes and row strides.
+    pub(super) fn assert_transform_matches_scalar(
+        encode_quads: impl Copy + Fn(&mut [u16], &mut [u16], bool) -> usize,
+        decode_quads: impl Copy + Fn(&mut [u16], &mut [u16], bool) -> usize,
+    ) {
+        let scalar = |_: &mut [u16], _: &mut [u16], _: bool| 0;
+
+        // sizes around the number of samples per vector, with and without row padding
+        let sizes =
+            [(1, 1), (2, 2), (3, 7), (16, 2), (17, 5), (33, 9), (64, 64), (97, 31), (37, 99)];
+
+        for &(width, height) in &sizes {
+            for padding in [0, 3] {
+                for is_14_bit in [true, false] {
+                    let max_value = if is_14_bit {
+                        0x3fff
+                    } else {
+                        0xffff
+                    };
+
+                    let count = Vec2(width, height);
+                    let offset = Vec2(1, width + padding);
+                    let noise = || -> Vec<u16> {
+                        (0..offset.1 * height).map(|_| rand::random::<u16>() & max_value).collect()
+                    };
+
+                    let mut simd = noise();
+                    let mut expected = simd.clone();
+                    super::encode_levels(&mut simd, count, offset, is_14_bit, encode_quads)
+                        .unwrap();
+                    super::encode_levels(&mut expected, count, offset, is_14_bit, scalar).unwrap();
+                    assert_eq!(simd, expected, "encoding {count:?} (14 bit: {is_14_bit})");
+
+                    // decode arbitrary values, not only the encoded ones
+                    let mut simd = noise();
+                    let mut expected = simd.clone();
+                    super::decode_levels(&mut simd, count, offset, is_14_bit, decode_quads)
+                        .unwrap();
+                    super::decode_levels(&mut expected, count, offset, is_14_bit, scalar).unwrap();
+                    assert_eq!(simd, expected, "decoding {count:?} (14 bit: {is_14_bit})");
+                }
+            }
+        }
+    }
+
     /// inspired by https://github.com/AcademySoftwareFoundation/openexr/blob/master/OpenEXR/IlmImfTest/testWav.cpp
     #[test]
     fn ground_truth() {
@@ -477,3 +598,41 @@ mod test {
         }
     }
 }
+
+// AVX2 tier correctness tests. Opt-in via the `avx2-tests` feature and meant to
+// run under a CPU (or SDE emulator) exposing AVX2.
+#[cfg(all(test, feature = "avx2-tests"))]
+mod avx2_tests {
+    use pulp::x86::V3;
+
+    use super::{test::assert_transform_matches_scalar, x86::avx2};
+
+    #[test]
+    fn avx2_transform_equals_scalar_transform() {
+        let v3 = V3::try_new().expect("AVX2 SIMD mode requested, but the AVX2 tier is unavailable");
+
+        assert_transform_matches_scalar(
+            |row, next_row, is_14_bit| avx2::encode_quads(v3, row, next_row, is_14_bit),
+            |row, next_row, is_14_bit| avx2::decode_quads(v3, row, next_row, is_14_bit),
+        );
+    }
+}
+
+// SSE2 tier correctness tests. Opt-in via the `sse2-tests` feature. The kernels
+// are called directly, so these also run on CPUs that support AVX2.
+#[cfg(all(test, feature = "sse2-tests"))]
+mod sse2_tests {
+    use pulp::x86::V1;
+
+    use super::{test::assert_transform_matches_scalar, x86::sse2};
+
+    #[test]
+    fn sse2_transform_equals_scalar_transform() {
+        let v1 = V1::try_new().expect("SSE2 SIMD mode requested, but the SSE2 tier is unavailable");
+
+        assert_transform_matches_scalar(
+            |row, next_row, is_14_bit| sse2::encode_quads(v1, row, next_row, is_14_bit),
+            |row, next_row, is_14_bit| sse2::decode_quads(v1, row, next_row, is_14_bit),
+        );
+    }
+}
@@ -0,0 +1,190 @@
+// AVX2 V3 tier: transforms eight quads of two rows at once, sixteen samples per
+// row. Uses the same steps as the SSE2 tier, which never cross the 128-bit
+// lanes.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m256i;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::__m256i;
+use std::convert::TryInto;
+
+use pulp::{bytemuck::cast, x86::V3};
+
+const LANES: usize = 16;
+
+#[inline]
+fn load(samples: &[u16]) -> __m256i {
+    let array: [u16; LANES] = samples.try_into().expect("simd chunk size bug");
+    cast(array)
+}
+
+#[inline]
+fn store(samples: &mut [u16], vector: __m256i) {
+    let array: [u16; LANES] = cast(vector);
+    samples.copy_from_slice(&array);
+}
+
+struct Constants {
+    one: __m256i,
+    offset: __m256i,
+    even_samples: __m256i,
+}
+
+impl Constants {
+    #[inline]
+    fn new(v3: V3) -> Self {
+        Self {
+            one: cast(v3.splat_u16x16(1)),
+            offset: cast(v3.splat_u16x16(0x8000)),
+            even_samples: cast(v3.splat_u32x8(0xFFFF)),
+        }
+    }
+}
+
+#[inline]
+fn encode_14bit(v3: V3, a: __m256i, b: __m256i) -> (__m256i, __m256i) {
+    let avx2 = v3.avx2;
+    let m = avx2._mm256_srai_epi16::<1>(avx2._mm256_add_epi16(a, b));
+    (m, avx2._mm256_sub_epi16(a, b))
+}
+
+#[inline]
+fn decode_14bit(v3: V3, constants: &Constants, l: __m256i, h: __m256i) -> (__m256i, __m256i) {
+    let avx2 = v3.avx2;
+    let rounding = avx2
+        ._mm256_add_epi16(avx2._mm256_and_si256(h, constants.one), avx2._mm256_srai_epi16::<1>(h));
+    let a = avx2._mm256_add_epi16(l, rounding);
+    (a, avx2._mm256_sub_epi16(a, h))
+}
+
+#[inline]
+fn encode_16bit(v3: V3, constants: &Constants, a: __m256i, b: __m256i) -> (__m256i, __m256i) {
+    let avx2 = v3.avx2;
+    let a_offset = avx2._mm256_xor_si256(a, constants.offset);
+
+    // the mean of two 16-bit values without overflowing 16 bits
+    let m = avx2._mm256_add_epi16(
+        avx2._mm256_and_si256(a_offset, b),
+        avx2._mm256_srli_epi16::<1>(avx2._mm256_xor_si256(a_offset, b)),
+    );
+
+    // unsigned `a_offset < b`, compared as signed values
+    let negative = avx2._mm256_cmpgt_epi16(avx2._mm256_xor_si256(b, constants.offset), a);
+    let m = avx2._mm256_xor_si256(m, avx2._mm256_and_si256(negative, constants.offset));
+
+    (m, avx2._mm256_sub_epi16(a_offset, b))
+}
+
+#[inline]
+fn decode_16bit(v3: V3, constants: &Constants, m: __m256i, d: __m256i) -> (__m256i, __m256i) {
+    let avx2 = v3.avx2;
+    let b = avx2._mm256_sub_epi16(m, avx2._mm256_srli_epi16::<1>(d));
+    let a = avx2._mm256_xor_si256(avx2._mm256_add_epi16(d, b), constants.offset);
+    (a, b)
+}
+
+#[inline]
+fn encode(
+    v3: V3,
+    constants: &Constants,
+    is_14_bit: bool,
+    a: __m256i,
+    b: __m256i,
+) -> (__m256i, __m256i) {
+    if is_14_bit {
+        encode_14bit(v3, a, b)
+    } else {
+        encode_16bit(v3, constants, a, b)
+    }
+}
+
+#[inline]
+fn decode(
+    v3: V3,
+    constants: &Constants,
+    is_14_bit: bool,
+    l: __m256i,
+    h: __m256i,
+) -> (__m256i, __m256i) {
+    if is_14_bit {
+        decode_14bit(v3, constants, l, h)
+    } else {
+        decode_16bit(v3, constants, l, h)
+    }
+}
+
+/// Moves the odd samples onto the even samples of each 32-bit lane.
+#[inline]
+fn split_pairs(v3: V3, samples: __m256i) -> (__m256i, __m256i) {
+    (samples, v3.avx2._mm256_srli_epi32::<16>(samples))
+}
+
+/// Merges the even samples of each 32-bit lane back into pairs.
+#[inline]
+fn merge_pairs(v3: V3, constants: &Constants, even: __m256i, odd: __m256i) -> __m256i {
+    let avx2 = v3.avx2;
+    avx2._mm256_or_si256(
+        avx2._mm256_and_si256(even, constants.even_samples),
+        avx2._mm256_slli_epi32::<16>(odd),
+    )
+}
+
+pub fn encode_quads(v3: V3, row: &mut [u16], next_row: &mut [u16], is_14_bit: bool) -> usize {
+    let chunks = row.chunks_exact_mut(LANES).zip(next_row.chunks_exact_mut(LANES));
+
+    // must inline into the `vectorize` trampoline, which enables the target
+    // features of this tier
+    v3.vectorize(
+        #[inline(always)]
+        move || {
+            let constants = Constants::new(v3);
+            let mut samples = 0;
+
+            for (row, next_row) in chunks {
+                let (left, right) = split_pairs(v3, load(row));
+                let (left, right) = encode(v3, &constants, is_14_bit, left, right);
+                let upper = merge_pairs(v3, &constants, left, right);
+
+                let (left, right) = split_pairs(v3, load(next_row));
+                let (left, right) = encode(v3, &constants, is_14_bit, left, right);
+                let lower = merge_pairs(v3, &constants, left, right);
+
+                let (upper, lower) = encode(v3, &constants, is_14_bit, upper, lower);
+                store(row, upper);
+                store(next_row, lower);
+                samples += LANES;
+            }
+
+            samples
+        },
+    )
+}
+
+pub fn decode_quads(v3: V3, row: &mut [u16], next_row: &mut [u16], is_14_bit: bool) -> usize {
+    let chunks = row.chunks_exact_mut(LANES).zip(next_row.chunks_exact_mut(LANES));
+
+    // must inline into the `vectorize` trampoline, which enables the target
+    // features of this tier
+    v3.vectorize(
+        #[inline(always)]
+        move || {
+            let constants = Constants::new(v3);
+            let mut samples = 0;
+
+            for (row, next_row) in chunks {
+                let (upper, lower) = decode(v3, &constants, is_14_bit, load(row), load(next_row));
+
+                let (left, right) = split_pairs(v3, upper);
+                let (left, right) = decode(v3, &constants, is_14_bit, left, right);
+                store(row, merge_pairs(v3, &constants, left, right));
+
+                let (left, right) = split_pairs(v3, lower);
+                let (left, right) = decode(v3, &constants, is_14_bit, left, right);
+                store(next_row, merge_pairs(v3, &constants, left, right));
+                samples += LANES;
+            }
+
+            samples
+        },
+    )
+}
@@ -0,0 +1,40 @@
+// Runtime x86 SIMD dispatch for the PIZ wavelet transform: `try_*_quads` select
+// the AVX2 tier when available and fall back to the SSE2 tier, otherwise let
+// the caller use the scalar loop on all quads.
+//
+// Only the finest level of single-sample pixels is vectorized. Its quads cover
+// two contiguous rows, and it contains three quarters of all quads.
+// Both tiers produce exactly the same samples as the scalar loop.
+
+use pulp::x86::{V1, V3};
+
+pub mod avx2;
+pub mod sse2;
+
+/// Encodes the quads of the finest level, where `row` and `next_row`
+/// contain the pairs of the upper and the lower samples of each quad.
+/// Returns the number of samples encoded in each row,
+/// from where the scalar loop must continue.
+pub(super) fn try_encode_quads(row: &mut [u16], next_row: &mut [u16], is_14_bit: bool) -> usize {
+    if let Some(v3) = V3::try_new() {
+        return avx2::encode_quads(v3, row, next_row, is_14_bit);
+    }
+    if let Some(v1) = V1::try_new() {
+        return sse2::encode_quads(v1, row, next_row, is_14_bit);
+    }
+    0
+}
+
+/// Decodes the quads of the finest level, where `row` and `next_row`
+/// contain the pairs of the upper and the lower samples of each quad.
+/// Returns the number of samples decoded in each row,
+/// from where the scalar loop must continue.
+pub(super) fn try_decode_quads(row: &mut [u16], next_row: &mut [u16], is_14_bit: bool) -> usize {
+    if let Some(v3) = V3::try_new() {
+        return avx2::decode_quads(v3, row, next_row, is_14_bit);
+    }
+    if let Some(v1) = V1::try_new() {
+        return sse2::decode_quads(v1, row, next_row, is_14_bit);
+    }
+    0
+}
@@ -0,0 +1,197 @@
+// SSE2 V1 tier: transforms four quads of two rows at once, eight samples per
+// row.
+//
+// The vertical step transforms the two rows element-wise. The horizontal step
+// transforms the adjacent samples in each 32-bit lane: the odd samples are
+// shifted onto the even samples, and both results are merged back afterwards,
+// which avoids shuffling the samples of a row.
+//
+// The 14-bit functions wrap around at 16 bits like the scalar functions, and
+// the 16-bit functions compute the scalar 32-bit arithmetic within 16 bits.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m128i;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::__m128i;
+use std::convert::TryInto;
+
+use pulp::{bytemuck::cast, x86::V1};
+
+const LANES: usize = 8;
+
+#[inline]
+fn load(samples: &[u16]) -> __m128i {
+    let array: [u16; LANES] = samples.try_into().expect("simd chunk size bug");
+    cast(array)
+}
+
+#[inline]
+fn store(samples: &mut [u16], vector: __m128i) {
+    let array: [u16; LANES] = cast(vector);
+    samples.copy_from_slice(&array);
+}
+
+struct Constants {
+    one: __m128i,
+    offset: __m128i,
+    even_samples: __m128i,
+}
+
+impl Constants {
+    #[inline]
+    fn new(v1: V1) -> Self {
+        Self {
+            one: cast(v1.splat_u16x8(1)),
+            offset: cast(v1.splat_u16x8(0x8000)),
+            even_samples: cast(v1.splat_u32x4(0xFFFF)),
+        }
+    }
+}
+
+#[inline]
+fn encode_14bit(v1: V1, a: __m128i, b: __m128i) -> (__m128i, __m128i) {
+    let sse2 = v1.sse2;
+    let m = sse2._mm_srai_epi16::<1>(sse2._mm_add_epi16(a, b));
+    (m, sse2._mm_sub_epi16(a, b))
+}
+
+#[inline]
+fn decode_14bit(v1: V1, constants: &Constants, l: __m128i, h: __m128i) -> (__m128i, __m128i) {
+    let sse2 = v1.sse2;
+    let rounding =
+        sse2._mm_add_epi16(sse2._mm_and_si128(h, constants.one), sse2._mm_srai_epi16::<1>(h));
+    let a = sse2._mm_add_epi16(l, rounding);
+    (a, sse2._mm_sub_epi16(a, h))
+}
+
+#[inline]
+fn encode_16bit(v1: V1, constants: &Constants, a: __m128i, b: __m128i) -> (__m128i, __m128i) {
+    let sse2 = v1.sse2;
+    let a_offset = sse2._mm_xor_si128(a, constants.offset);
+
+    // the mean of two 16-bit values without overflowing 16 bits
+    let m = sse2._mm_add_epi16(
+        sse2._mm_and_si128(a_offset, b),
+        sse2._mm_srli_epi16::<1>(sse2._mm_xor_si128(a_offset, b)),
+    );
+
+    // unsigned `a_offset < b`, compared as signed values
+    let negative = sse2._mm_cmplt_epi16(a, sse2._mm_xor_si128(b, constants.offset));
+    let m = sse2._mm_xor_si128(m, sse2._mm_and_si128(negative, constants.offset));
+
+    (m, sse2._mm_sub_epi16(a_offset, b))
+}
+
+#[inline]
+fn decode_16bit(v1: V1, constants: &Constants, m: __m128i, d: __m128i) -> (__m128i, __m128i) {
+    let sse2 = v1.sse2;
+    let b = sse2._mm_sub_epi16(m, sse2._mm_srli_epi16::<1>(d));
+    let a = sse2._mm_xor_si128(sse2._mm_add_epi16(d, b), constants.offset);
+    (a, b)
+}
+
+#[inline]
+fn encode(
+    v1: V1,
+    constants: &Constants,
+    is_14_bit: bool,
+    a: __m128i,
+    b: __m128i,
+) -> (__m128i, __m128i) {
+    if is_14_bit {
+        encode_14bit(v1, a, b)
+    } else {
+        encode_16bit(v1, constants, a, b)
+    }
+}
+
+#[inline]
+fn decode(
+    v1: V1,
+    constants: &Constants,
+    is_14_bit: bool,
+    l: __m128i,
+    h: __m128i,
+) -> (__m128i, __m128i) {
+    if is_14_bit {
+        decode_14bit(v1, constants, l, h)
+    } else {
+        decode_16bit(v1, constants, l, h)
+    }
+}
+
+/// Moves the odd samples onto the even samples of each 32-bit lane.
+#[inline]
+fn split_pairs(v1: V1, samples: __m128i) -> (__m128i, __m128i) {
+    (samples, v1.sse2._mm_srli_epi32::<16>(samples))
+}
+
+/// Merges the even samples of each 32-bit lane back into pairs.
+#[inline]
+fn merge_pairs(v1: V1, constants: &Constants, even: __m128i, odd: __m128i) -> __m128i {
+    let sse2 = v1.sse2;
+    sse2._mm_or_si128(
+        sse2._mm_and_si128(even, constants.even_samples),
+        sse2._mm_slli_epi32::<16>(odd),
+    )
+}
+
+pub fn encode_quads(v1: V1, row: &mut [u16], next_row: &mut [u16], is_14_bit: bool) -> usize {
+    let chunks = row.chunks_exact_mut(LANES).zip(next_row.chunks_exact_mut(LANES));
+
+    // must inline into the `vectorize` trampoline, which enables the target
+    // features of this tier
+    v1.vectorize(
+        #[inline(always)]
+        move || {
+            let constants = Constants::new(v1);
+            let mut samples = 0;
+
+            for (row, next_row) in chunks {
+                let (left, right) = split_pairs(v1, load(row));
+                let (left, right) = encode(v1, &constants, is_14_bit, left, right);
+                let upper = merge_pairs(v1, &constants, left, right);
+
+                let (left, right) = split_pairs(v1, load(next_row));
+                let (left, right) = encode(v1, &constants, is_14_bit, left, right);
+                let lower = merge_pairs(v1, &constants, left, right);
+
+                let (upper, lower) = encode(v1, &constants, is_14_bit, upper, lower);
+                store(row, upper);
+                store(next_row, lower);
+                samples += LANES;
+            }
+
+            samples
+        },
+    )
+}
+
+pub fn decode_quads(v1: V1, row: &mut [u16], next_row: &mut [u16], is_14_bit: bool) -> usize {
+    let chunks = row.chunks_exact_mut(LANES).zip(next_row.chunks_exact_mut(LANES));
+
+    // must inline into the `vectorize` trampoline, which enables the target
+    // features of this tier
+    v1.vectorize(
+        #[inline(always)]
+        move || {
+            let constants = Constants::new(v1);
+            let mut samples = 0;
+
+            for (row, next_row) in chunks {
+                let (upper, lower) = decode(v1, &constants, is_14_bit, load(row), load(next_row));
+
+                let (left, right) = split_pairs(v1, upper);
+                let (left, right) = decode(v1, &constants, is_14_bit, left, right);
+                store(row, merge_pairs(v1, &constants, left, right));
+
+                let (left, right) = split_pairs(v1, lower);
+                let (left, right) = decode(v1, &constants, is_14_bit, left, right);
+                store(next_row, merge_pairs(v1, &constants, left, right));
+                samples += LANES;
+            }
+
+            samples
+        },
+    )
+}
@@ -38,7 +38,12 @@ use crate::error::Result;
 //    machine-independent representation
 // 4. Fill the frame buffer with pixel data, respective to sampling and whatnot
 
-pub fn compress(channels: &ChannelList, bytes_ne: ByteVec, area: IntegerBounds) -> Result<ByteVec> {
+pub fn compress(
+    channels: &ChannelList,
+    bytes_ne: ByteVec,
+    area: IntegerBounds,
+    level: Option<u8>,
+) -> Result<ByteVec> {
     if bytes_ne.is_empty() {
         return Ok(Vec::new());
     }
@@ -153,26 +158,20 @@ pub fn compress(channels: &ChannelList, bytes_ne: ByteVec, area: IntegerBounds)
         debug_assert_eq!(write.len(), 0, "bytes left after compression");
     }
 
-    Ok(miniz_oxide::deflate::compress_to_vec_zlib(encoded_be.as_slice(), 4))
+    Ok(super::zip::deflate(encoded_be.as_slice(), level))
 }
 
 pub fn decompress(
     channels: &ChannelList,
-    bytes_le: ByteVec,
+    bytes_le: &[u8],
     area: IntegerBounds,
-    expected_byte_size: usize,
     pedantic: bool,
-) -> Result<ByteVec> {
-    let options = zune_inflate::DeflateOptions::default()
-        .set_limit(expected_byte_size)
-        .set_size_hint(expected_byte_size);
-    let mut decompressor = zune_inflate::DeflateDecoder::new_with_options(&bytes_le, options);
-
-    let encoded_be =
-        decompressor.decode_zlib().map_err(|_| Error::invalid("zlib-compressed data malformed"))?; // TODO share code with zip?
-
+    decompressed_ne: &mut [u8],
+) -> UnitResult {
+    // the encoded bytes are never larger than the decompressed samples
+    let encoded_be = super::zip::inflate(bytes_le, decompressed_ne.len())?;
     let mut encoded_be = encoded_be.as_slice();
-    let mut out = Vec::with_capacity(expected_byte_size.min(2048 * 4));
+    let mut out: &mut [u8] = decompressed_ne;
 
     for y in area.position.1..area.end().1 {
         for channel in &channels.list {
@@ -190,15 +189,25 @@ pub fn decompress(
                 Ok(samples)
             };
 
+            let line_byte_count = sample_count_x * channel.sample_type.bytes_per_sample();
+            if line_byte_count > out.len() {
+                return Err(Error::invalid("too much data"));
+            }
+
+            let (out_line, rest) = std::mem::take(&mut out).split_at_mut(line_byte_count);
+            out = rest;
+
             match channel.sample_type {
                 SampleType::F16 => {
                     let sample_byte_pairs = read_sample_line()?.iter().zip(read_sample_line()?);
 
                     let mut pixel_accumulation: u32 = 0;
-                    for (&in_byte_0, &in_byte_1) in sample_byte_pairs {
+                    for ((&in_byte_0, &in_byte_1), out_bytes) in
+                        sample_byte_pairs.zip(out_line.chunks_exact_mut(2))
+                    {
                         let difference = u16::from_be_bytes([in_byte_0, in_byte_1]) as u32;
                         pixel_accumulation = pixel_accumulation.overflowing_add(difference).0;
-                        out.extend_from_slice(&(pixel_accumulation as u16).to_ne_bytes());
+                        out_bytes.copy_from_slice(&(pixel_accumulation as u16).to_ne_bytes());
                     }
                 }
 
@@ -210,11 +219,13 @@ pub fn decompress(
                         .zip(read_sample_line()?);
 
                     let mut pixel_accumulation: u32 = 0;
-                    for (((&in_byte_0, &in_byte_1), &in_byte_2), &in_byte_3) in sample_byte_quads {
+                    for ((((&in_byte_0, &in_byte_1), &in_byte_2), &in_byte_3), out_bytes) in
+                        sample_byte_quads.zip(out_line.chunks_exact_mut(4))
+                    {
                         let difference =
                             u32::from_be_bytes([in_byte_0, in_byte_1, in_byte_2, in_byte_3]);
                         pixel_accumulation = pixel_accumulation.overflowing_add(difference).0;
-                        out.extend_from_slice(&pixel_accumulation.to_ne_bytes());
+                        out_bytes.copy_from_slice(&pixel_accumulation.to_ne_bytes());
                     }
                 }
 
@@ -225,21 +236,27 @@ pub fn decompress(
                         .zip(read_sample_line()?);
 
                     let mut pixel_accumulation: u32 = 0;
-                    for ((&in_byte_0, &in_byte_1), &in_byte_2) in sample_byte_triplets {
+                    for (((&in_byte_0, &in_byte_1), &in_byte_2), out_bytes) in
+                        sample_byte_triplets.zip(out_line.chunks_exact_mut(4))
+                    {
                         let difference = u32::from_be_bytes([in_byte_0, in_byte_1, in_byte_2, 0]);
                         pixel_accumulation = pixel_accumulation.overflowing_add(difference).0;
-                        out.extend_from_slice(&pixel_accumulation.to_ne_bytes());
+                        out_bytes.copy_from_slice(&pixel_accumulation.to_ne_bytes());
                     }
                 }
             }
         }
     }
 
+    if !out.is_empty() {
+        return Err(Error::invalid("not enough data"));
+    }
+
     if pedantic && !encoded_be.is_empty() {
         return Err(Error::invalid("too much data"));
     }
 
-    Ok(out)
+    Ok(())
 }
 
 /// Conversion from 32-bit to 24-bit floating-point numbers.
@@ -7,29 +7,61 @@ const MAX_RUN_LENGTH: usize = 127;
 
 pub fn decompress_bytes(
     channels: &ChannelList,
-    compressed_le: ByteVec,
+    compressed_le: &[u8],
     rectangle: IntegerBounds,
+    pedantic: bool,
+    scratch: &mut DecompressionScratch,
+    decompressed_ne: &mut [u8],
+) -> UnitResult {
+    let separated_le = &mut scratch.bytes;
+    unpack_rle_tokens_into(compressed_le, decompressed_ne.len(), pedantic, separated_le)?;
+    if separated_le.len() != decompressed_ne.len() {
+        return Err(Error::invalid("decompressed data"));
+    }
+
+    differences_to_samples(separated_le);
+    interleave_byte_blocks_into(separated_le, decompressed_ne);
+    super::convert_little_endian_to_current_in_place(decompressed_ne, channels, rectangle)
+}
+
+/// Unpack the tokens and reverse the byte prediction,
+/// without any knowledge about the channels.
+/// Also used for both sections of deep data blocks.
+pub(super) fn decompress_le_bytes(
+    compressed_le: &[u8],
     expected_byte_size: usize,
     pedantic: bool,
 ) -> Result<ByteVec> {
-    let mut decompressed_le = unpack_rle_tokens(&compressed_le, expected_byte_size, pedantic)?;
+    let mut decompressed_le = unpack_rle_tokens(compressed_le, expected_byte_size, pedantic)?;
     differences_to_samples(&mut decompressed_le);
     interleave_byte_blocks(&mut decompressed_le);
-    super::convert_little_endian_to_current(decompressed_le, channels, rectangle)
-    // TODO no alloc
+    Ok(decompressed_le)
 }
 
+/// Unpack the tokens into a new buffer.
+fn unpack_rle_tokens(
+    compressed_le: &[u8],
+    expected_byte_size: usize,
+    pedantic: bool,
+) -> Result<ByteVec> {
+    let mut decompressed_le = Vec::with_capacity(expected_byte_size.min(8 * 2048));
+    unpack_rle_tokens_into(compressed_le, expected_byte_size, pedantic, &mut decompressed_le)?;
+    Ok(decompressed_le)
+}
+
+/// Unpack the tokens into the cleared buffer, reusing its allocation.
 /// Shared by this compression method and DWA's RLE scheme (both port OpenEXR's
 /// `internal_rle_decompress`, see `compression::dwa`) - kept separate from
 /// `decompress_bytes` because DWA does not apply the delta prediction /
 /// byte-block interleaving done there.
-pub(super) fn unpack_rle_tokens(
+pub(super) fn unpack_rle_tokens_into(
     compressed_le: &[u8],
     expected_byte_size: usize,
     pedantic: bool,
-) -> Result<ByteVec> {
+    decompressed_le: &mut ByteVec,
+) -> UnitResult {
     let mut remaining_le = compressed_le;
-    let mut decompressed_le = Vec::with_capacity(expected_byte_size.min(8 * 2048));
+    decompressed_le.clear();
 
     while !remaining_le.is_empty() && decompressed_le.len() != expected_byte_size {
         let count = take_1(&mut remaining_le)? as i8 as i32;
@@ -49,7 +81,7 @@ pub(super) fn unpack_rle_tokens(
         return Err(Error::invalid("data amount"));
     }
 
-    Ok(decompressed_le)
+    Ok(())
 }
 
 pub fn compress_bytes(
@@ -58,13 +90,17 @@ pub fn compress_bytes(
     rectangle: IntegerBounds,
 ) -> Result<ByteVec> {
     // see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    let mut data_le =
-        super::convert_current_to_little_endian(uncompressed_ne, channels, rectangle)?; // TODO no alloc
+    let data_le = super::convert_current_to_little_endian(uncompressed_ne, channels, rectangle)?; // TODO no alloc
+    Ok(compress_le_bytes(data_le))
+}
 
+/// Apply the byte prediction and pack the bytes into tokens,
+/// without any knowledge about the channels.
+/// Also used for both sections of deep data blocks.
+pub(super) fn compress_le_bytes(mut data_le: ByteVec) -> ByteVec {
     separate_bytes_fragments(&mut data_le);
     samples_to_differences(&mut data_le);
-
-    Ok(pack_rle_tokens(&data_le))
+    pack_rle_tokens(&data_le)
 }
 
 /// Shared by this compression method and DWA's RLE section. This only emits
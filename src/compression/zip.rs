@@ -1,7 +1,7 @@
 // see https://github.com/openexr/openexr/blob/master/OpenEXR/IlmImf/ImfCompressor.cpp
 
 use super::{optimize_bytes::*, *};
-use crate::error::Result;
+use crate::error::{Result, UnitResult};
 
 // scanline decompression routine, see https://github.com/openexr/openexr/blob/master/OpenEXR/IlmImf/ImfScanLineInputFile.cpp
 // 1. Uncompress the data, if necessary (If the line is uncompressed, it's in
@@ -12,35 +12,70 @@ use crate::error::Result;
 
 pub fn decompress_bytes(
     channels: &ChannelList,
-    data_le: ByteVec,
+    data_le: &[u8],
     rectangle: IntegerBounds,
-    expected_byte_size: usize,
     _pedantic: bool,
-) -> Result<ByteVec> {
-    let options = zune_inflate::DeflateOptions::default()
-        .set_limit(expected_byte_size)
-        .set_size_hint(expected_byte_size);
-    let mut decoder = zune_inflate::DeflateDecoder::new_with_options(&data_le, options);
-    let mut decompressed_le =
-        decoder.decode_zlib().map_err(|_| Error::invalid("zlib-compressed data malformed"))?;
+    decompressed_ne: &mut [u8],
+) -> UnitResult {
+    // the inflated bytes are allocated by the zlib decoder,
+    // which is faster than inflating into an existing buffer with miniz
+    let mut separated_le = inflate(data_le, decompressed_ne.len())?;
+    if separated_le.len() != decompressed_ne.len() {
+        return Err(Error::invalid("decompressed data"));
+    }
 
+    differences_to_samples(&mut separated_le);
+    interleave_byte_blocks_into(&separated_le, decompressed_ne);
+    super::convert_little_endian_to_current_in_place(decompressed_ne, channels, rectangle)
+}
+
+/// Inflate the bytes and reverse the byte prediction,
+/// without any knowledge about the channels.
+/// Also used for both sections of deep data blocks.
+pub(super) fn decompress_le_bytes(data_le: &[u8], expected_byte_size: usize) -> Result<ByteVec> {
+    let mut decompressed_le = inflate(data_le, expected_byte_size)?;
     differences_to_samples(&mut decompressed_le);
     interleave_byte_blocks(&mut decompressed_le);
+    Ok(decompressed_le)
+}
 
-    super::convert_little_endian_to_current(decompressed_le, channels, rectangle)
-    // TODO no alloc
+/// Inflate the zlib stream, which must not contain more than the expected
+/// bytes. Also used by PXR24 and DWA decompression.
+pub(super) fn inflate(data_le: &[u8], expected_byte_size: usize) -> Result<ByteVec> {
+    let options = zune_inflate::DeflateOptions::default()
+        .set_limit(expected_byte_size)
+        .set_size_hint(expected_byte_size);
+
+    let mut decoder = zune_inflate::DeflateDecoder::new_with_options(data_le, options);
+    decoder.decode_zlib().map_err(|_| Error::invalid("zlib-compressed data malformed"))
 }
 
 pub fn compress_bytes(
     channels: &ChannelList,
     uncompressed_ne: ByteVec,
     rectangle: IntegerBounds,
+    level: Option<u8>,
 ) -> Result<ByteVec> {
     // see https://github.com/AcademySoftwareFoundation/openexr/blob/3bd93f85bcb74c77255f28cdbb913fdbfbb39dfe/OpenEXR/IlmImf/ImfTiledOutputFile.cpp#L750-L842
-    let mut packed_le = convert_current_to_little_endian(uncompressed_ne, channels, rectangle)?;
+    let packed_le = convert_current_to_little_endian(uncompressed_ne, channels, rectangle)?;
+    Ok(compress_le_bytes(packed_le, level))
+}
 
+/// Apply the byte prediction and deflate the bytes,
+/// without any knowledge about the channels.
+/// Also used for both sections of deep data blocks.
+pub(super) fn compress_le_bytes(mut packed_le: ByteVec, level: Option<u8>) -> ByteVec {
     separate_bytes_fragments(&mut packed_le);
     samples_to_differences(&mut packed_le);
 
-    Ok(miniz_oxide::deflate::compress_to_vec_zlib(packed_le.as_slice(), 4))
+    deflate(packed_le.as_slice(), level)
+}
+
+/// The deflate level used when no level is specified, same as in OpenEXR.
+const DEFAULT_LEVEL: u8 = 4;
+
+/// Compress the bytes with zlib, using the specified or the default level.
+/// Also used by PXR24 compression.
+pub(super) fn deflate(bytes: &[u8], level: Option<u8>) -> ByteVec {
+    miniz_oxide::deflate::compress_to_vec_zlib(bytes, level.unwrap_or(DEFAULT_LEVEL))
 }
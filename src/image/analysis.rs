@@ -0,0 +1,379 @@
+//! Measure how well each compression method performs on the layers of an
+//! image.
+//!
+//! Use `analyze_compression` for an image in memory, or
+//! `analyze_file_compression` for a file. Every block of every flat layer is
+//! compressed and decompressed again with each compression method, measuring
+//! the size, the time, and the error introduced by lossy methods.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    block::BlockIndex,
+    compression::{for_each_sample_error, Compression},
+    error::{Error, Result},
+    image::{
+        read::read_all_data_from_file,
+        write::layers::{LayersWriter, WritableLayers},
+        Image,
+    },
+    meta::{attribute::Text, header::Header},
+};
+
+/// All compression methods that can be written by this library.
+pub const WRITABLE_COMPRESSIONS: [Compression; 10] = [
+    Compression::Uncompressed,
+    Compression::RLE,
+    Compression::ZIP1,
+    Compression::ZIP16,
+    Compression::PIZ,
+    Compression::PXR24,
+    Compression::B44,
+    Compression::B44A,
+    Compression::DWAA(None),
+    Compression::DWAB(None),
+];
+
+/// Compression statistics for each layer of an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionReport {
+    /// One report for each layer of the image, in the same order as the
+    /// layers.
+    pub layers: Vec<LayerCompressionReport>,
+}
+
+/// Compression statistics of a single layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerCompressionReport {
+    /// The name of the layer, if any.
+    pub layer_name: Option<Text>,
+
+    /// The number of bytes of all pixels in this layer, without compression.
+    /// `None` for deep layers, as their size depends on the sample counts.
+    pub uncompressed_byte_size: Option<usize>,
+
+    /// The statistics for each analyzed compression method.
+    /// Compression methods that do not support this layer are not included.
+    /// Empty for deep layers and for layers with subsampled channels.
+    pub compressions: Vec<CompressionStatistics>,
+}
+
+/// How a single compression method performs on a single layer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionStatistics {
+    /// The analyzed compression method.
+    pub compression: Compression,
+
+    /// The number of bytes of all compressed blocks in this layer.
+    /// Does not include the headers and offset tables of the file.
+    pub compressed_byte_size: usize,
+
+    /// The uncompressed size divided by the compressed size.
+    /// Larger is better.
+    pub ratio: f64,
+
+    /// The time spent compressing all blocks of this layer.
+    pub compress_duration: Duration,
+
+    /// The time spent decompressing all blocks of this layer.
+    pub decompress_duration: Duration,
+
+    /// The largest absolute difference between an original and a
+    /// decompressed sample. Always zero for lossless compression methods.
+    /// Infinite if a NaN or infinity was not preserved.
+    pub max_error: f64,
+
+    /// The average absolute difference between the original and the
+    /// decompressed samples.
+    pub mean_error: f64,
+}
+
+/// Analyze the specified compression methods for the flat layers of a file.
+/// Use `WRITABLE_COMPRESSIONS` to analyze all compression methods.
+/// Loads all resolution levels of the file, but no deep data.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, or if a layer cannot be
+/// compressed or decompressed.
+pub fn analyze_file_compression(
+    path: impl AsRef<Path>,
+    compressions: &[Compression],
+) -> Result<CompressionReport> {
+    let image = read_all_data_from_file(path)?;
+    analyze_compression(&image, compressions)
+}
+
+/// Analyze the specified compression methods for each flat layer of the image.
+/// Use `WRITABLE_COMPRESSIONS` to analyze all compression methods.
+/// The blocks are split up like they would be when writing the image with
+/// the respective compression method.
+/// Skips the compression methods that do not support a layer.
+///
+/// # Errors
+/// Returns an error if a layer cannot be compressed or decompressed.
+pub fn analyze_compression<'img, Layers>(
+    image: &'img Image<Layers>,
+    compressions: &[Compression],
+) -> Result<CompressionReport>
+where
+    Layers: WritableLayers<'img>,
+{
+    let headers = image.layer_data.infer_headers(&image.attributes);
+    let layers = image.layer_data.create_writer(&headers);
+    let mut trial_headers = headers.to_vec();
+
+    let mut reports = Vec::with_capacity(headers.len());
+
+    for (layer_index, header) in headers.iter().enumerate() {
+        let mut statistics = Vec::with_capacity(compressions.len());
+
+        if !header.deep {
+            for &compression in compressions {
+                trial_headers[layer_index] =
+                    header.clone().with_encoding(compression, header.blocks, header.line_order);
+
+                match analyze_layer(&trial_headers, layer_index, &layers) {
+                    Ok(layer_statistics) => statistics.push(layer_statistics),
+                    Err(Error::NotSupported(_)) => {}
+                    Err(error) => return Err(error),
+                }
+            }
+
+            trial_headers[layer_index] = header.clone();
+        }
+
+        reports.push(LayerCompressionReport {
+            layer_name: header.own_attributes.layer_name.clone(),
+            uncompressed_byte_size: if header.deep {
+                None
+            } else {
+                Some(header.total_pixel_bytes())
+            },
+            compressions: statistics,
+        });
+    }
+
+    Ok(CompressionReport {
+        layers: reports,
+    })
+}
+
+/// Compress and decompress all blocks of a layer with the compression method
+/// of its header.
+fn analyze_layer(
+    headers: &[Header],
+    layer_index: usize,
+    layers: &impl LayersWriter,
+) -> Result<CompressionStatistics> {
+    let header = &headers[layer_index];
+    let compression = header.compression;
+
+    let mut uncompressed_byte_size = 0;
+    let mut compressed_byte_size = 0;
+    let mut compress_duration = Duration::ZERO;
+    let mut decompress_duration = Duration::ZERO;
+
+    let mut max_error = 0.0_f64;
+    let mut error_sum = 0.0_f64;
+    let mut sample_count = 0_usize;
+
+    for tile in header.blocks_increasing_y_order() {
+        let data_indices = header.get_absolute_block_pixel_coordinates(tile.location)?;
+
+        let block_index = BlockIndex {
+            layer: layer_index,
+            level: tile.location.level_index,
+            pixel_position: data_indices.position.to_usize("data indices start")?,
+            pixel_size: data_indices.size,
+        };
+
+        let data = layers.extract_uncompressed_block(headers, block_index)?;
+        uncompressed_byte_size += data.len();
+
+        let start = Instant::now();
+        let compressed =
+            compression.compress_image_section_to_le(header, data.clone(), data_indices)?;
+        compress_duration += start.elapsed();
+        compressed_byte_size += compressed.len();
+
+        let start = Instant::now();
+        let decompressed =
+            compression.decompress_image_section_from_le(header, compressed, data_indices, true)?;
+        decompress_duration += start.elapsed();
+
+        for_each_sample_error(&header.channels, data_indices, &data, &decompressed, |_, error| {
+            max_error = max_error.max(error);
+            error_sum += error;
+            sample_count += 1;
+        })?;
+    }
+
+    Ok(CompressionStatistics {
+        compression,
+        compressed_byte_size,
+        ratio: uncompressed_byte_size as f64 / compressed_byte_size.max(1) as f64,
+        compress_duration,
+        decompress_duration,
+        max_error,
+        mean_error: if sample_count == 0 {
+            0.0
+        } else {
+            error_sum / sample_count as f64
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{
+        image::{
+            read::{image::ReadLayers, layers::ReadChannels, read},
+            write::WritableImage,
+            AnyChannel, AnyChannels, Encoding, FlatSamples, SpecificChannels,
+        },
+        math::Vec2,
+        prelude::f16,
+    };
+
+    #[test]
+    fn analyze_rgb_compression() {
+        let image = Image::from_encoded_channels(
+            Vec2(40, 70),
+            Encoding::FAST_LOSSLESS,
+            SpecificChannels::rgb(|Vec2(x, y): Vec2<usize>| {
+                (x as f32 * 0.1, (y as f32 * 0.21).sin(), 1000.0 + (x * y) as f32 * 0.001)
+            }),
+        );
+
+        let mut compressions = WRITABLE_COMPRESSIONS.to_vec();
+        compressions.push(Compression::HTJ2K32);
+
+        let report = analyze_compression(&image, &compressions).unwrap();
+        assert_eq!(report.layers.len(), 1);
+
+        let layer = &report.layers[0];
+        let uncompressed_byte_size = layer.uncompressed_byte_size.unwrap();
+        assert_eq!(uncompressed_byte_size, 40 * 70 * 3 * 4);
+
+        // htj2k is not supported and therefore not included
+        assert_eq!(layer.compressions.len(), WRITABLE_COMPRESSIONS.len());
+
+        for statistics in &layer.compressions {
+            assert!(statistics.compressed_byte_size <= uncompressed_byte_size);
+            assert!(statistics.ratio >= 1.0);
+            assert!(statistics.mean_error <= statistics.max_error);
+
+            if !statistics.compression.may_loose_data() {
+                assert_eq!(statistics.max_error, 0.0, "{}", statistics.compression);
+            }
+        }
+
+        let uncompressed = &layer.compressions[0];
+        assert_eq!(uncompressed.compressed_byte_size, uncompressed_byte_size);
+        assert_eq!(uncompressed.ratio, 1.0);
+
+        // pxr24 reduces f32 samples to 24 bits
+        let pxr24 = layer
+            .compressions
+            .iter()
+            .find(|statistics| statistics.compression == Compression::PXR24)
+            .unwrap();
+
+        assert!(pxr24.max_error > 0.0);
+        assert!(pxr24.max_error < 0.01);
+    }
+
+    #[test]
+    fn analyze_mixed_sample_types() {
+        let size = Vec2(37, 53);
+        let positions = || (0..size.area()).map(|index| (index % size.0, index / size.0));
+
+        let luminance = positions().map(|(x, y)| f16::from_f32((x as f32 * 0.3).sin() + y as f32));
+        let depth = positions().map(|(x, y)| 1000.0 + x as f32 * 0.123 + y as f32 * 7.654);
+
+        // u32 values above 2^24 would be rounded if compared as f32
+        let id = positions().map(|(x, y)| 0x0100_0001 + (x * 1009 + y * 17) as u32);
+
+        let channels = AnyChannels::sort(smallvec![
+            AnyChannel::new("Y", FlatSamples::F16(luminance.collect())),
+            AnyChannel::new("depth", FlatSamples::F32(depth.collect())),
+            AnyChannel::new("id", FlatSamples::U32(id.collect())),
+        ]);
+
+        let image = Image::from_encoded_channels(size, Encoding::UNCOMPRESSED, channels);
+        let report = analyze_compression(&image, &WRITABLE_COMPRESSIONS).unwrap();
+        let layer = &report.layers[0];
+        assert_eq!(layer.uncompressed_byte_size, Some(size.area() * (2 + 4 + 4)));
+        assert_eq!(layer.compressions.len(), WRITABLE_COMPRESSIONS.len());
+
+        for statistics in &layer.compressions {
+            let mut encoded = image.clone();
+            encoded.layer_data.encoding.compression = statistics.compression;
+
+            let mut bytes = Vec::new();
+            encoded.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+
+            let decoded = read()
+                .no_deep_data()
+                .largest_resolution_level()
+                .all_channels()
+                .first_valid_layer()
+                .all_attributes()
+                .non_parallel()
+                .from_buffered(Cursor::new(bytes))
+                .unwrap();
+
+            let original_channels = &image.layer_data.channel_data.list;
+            let decoded_channels = &decoded.layer_data.channel_data.list;
+
+            let errors: Vec<f64> = original_channels
+                .iter()
+                .zip(decoded_channels)
+                .flat_map(|(original, decoded)| {
+                    let original = sample_values(&original.sample_data);
+                    let decoded = sample_values(&decoded.sample_data);
+                    original.into_iter().zip(decoded).map(|(a, b)| (a - b).abs())
+                })
+                .collect();
+
+            let max_error = errors.iter().copied().fold(0.0, f64::max);
+            let mean_error = errors.iter().sum::<f64>() / errors.len() as f64;
+
+            assert_eq!(statistics.max_error, max_error, "{}", statistics.compression);
+            assert!(
+                (statistics.mean_error - mean_error).abs() <= mean_error * 1e-9,
+                "{}",
+                statistics.compression
+            );
+
+            if !statistics.compression.may_loose_data() {
+                assert_eq!(statistics.max_error, 0.0, "{}", statistics.compression);
+            }
+        }
+
+        // pxr24 only changes the f32 channel
+        let pxr24 = layer
+            .compressions
+            .iter()
+            .find(|statistics| statistics.compression == Compression::PXR24)
+            .unwrap();
+
+        assert!(pxr24.max_error > 0.0);
+    }
+
+    fn sample_values(samples: &FlatSamples) -> Vec<f64> {
+        match samples {
+            FlatSamples::F16(samples) => samples.iter().map(|sample| sample.to_f64()).collect(),
+            FlatSamples::F32(samples) => samples.iter().copied().map(f64::from).collect(),
+            FlatSamples::U32(samples) => samples.iter().copied().map(f64::from).collect(),
+        }
+    }
+}
@@ -3,6 +3,7 @@
 
 use crate::{
     block::BlockIndex,
+    error::Result,
     image::{
         write::channels::{ChannelsWriter, GetPixel, WritableChannels},
         AnyChannel, AnyChannels, FlatSamples, FlatSamplesPixel, Layer, SpecificChannels,
@@ -221,7 +222,7 @@ impl<'c, Channels> ChannelsWriter for CroppedWriter<Channels>
 where
     Channels: ChannelsWriter,
 {
-    fn extract_uncompressed_block(&self, header: &Header, block: BlockIndex) -> Vec<u8> {
+    fn extract_uncompressed_block(&self, header: &Header, block: BlockIndex) -> Result<Vec<u8>> {
         let block = BlockIndex {
             pixel_position: block.pixel_position + self.offset,
             ..block
@@ -0,0 +1,305 @@
+//! Composite deep data into flat pixels.
+//!
+//! Follows the rules of the OpenEXR document "Interpreting Deep Pixels":
+//! The samples of each pixel are tidied as described in `block::deep`,
+//! and then composited front to back with the `over` operation.
+
+use half::f16;
+
+use crate::{
+    block::deep::{DeepChannelRoles, DeepPixel, Role},
+    error::{Error, Result},
+    image::{
+        write::samples::WritableSamples, AnyChannel, AnyChannels, DeepAndFlatSamples, DeepSamples,
+        FlatSamples, Layer, Levels,
+    },
+    meta::attribute::{SampleType, Text},
+};
+
+impl Layer<AnyChannels<DeepAndFlatSamples>> {
+    /// Composite all deep samples of this layer into a flat layer.
+    /// Returns a copy of the channels if this layer does not contain deep data.
+    ///
+    /// The layer must contain a `Z` channel and may contain a `ZBack` channel.
+    /// Color channels are composited using the alpha channel with the same
+    /// prefix (for example, `diffuse.R` uses `diffuse.A`), or the `A` channel.
+    /// The `R`, `G` and `B` channels use `AR`, `AG` and `AB` if they exist.
+    /// Samples without any alpha channel are assumed to be opaque.
+    /// The flat `Z` channel contains the depth of the front-most sample,
+    /// or infinity if the pixel contains no samples.
+    /// Unsigned integer channels, such as object ids, also contain the value of
+    /// the front-most sample. The `ZBack` channel is not part of the result.
+    ///
+    /// # Errors
+    /// Returns an error if the layer contains deep and flat channels,
+    /// if there is no `Z` channel, or if the deep channels have different
+    /// sample counts.
+    pub fn composite_deep_samples(&self) -> Result<Layer<AnyChannels<FlatSamples>>> {
+        let channels: Vec<(&Text, &DeepAndFlatSamples)> = self
+            .channel_data
+            .list
+            .iter()
+            .map(|channel| (&channel.name, &channel.sample_data))
+            .collect();
+
+        self.with_composited_channels(&channels)
+    }
+}
+
+impl Layer<AnyChannels<Levels<DeepAndFlatSamples>>> {
+    /// Composite all deep samples of the largest resolution level into a flat
+    /// layer. See
+    /// `Layer<AnyChannels<DeepAndFlatSamples>>::composite_deep_samples` for
+    /// details.
+    ///
+    /// # Errors
+    /// Returns an error if the deep samples cannot be composited.
+    pub fn composite_deep_samples(&self) -> Result<Layer<AnyChannels<FlatSamples>>> {
+        let channels: Vec<(&Text, &DeepAndFlatSamples)> = self
+            .channel_data
+            .list
+            .iter()
+            .map(|channel| (&channel.name, &channel.sample_data.levels_as_slice()[0]))
+            .collect();
+
+        self.with_composited_channels(&channels)
+    }
+}
+
+impl<C> Layer<C> {
+    fn with_composited_channels(
+        &self,
+        channels: &[(&Text, &DeepAndFlatSamples)],
+    ) -> Result<Layer<AnyChannels<FlatSamples>>> {
+        let flat_channels: Option<Vec<(&Text, &FlatSamples)>> =
+            channels.iter().map(|&(name, samples)| Some((name, samples.as_flat()?))).collect();
+
+        let composited = match flat_channels {
+            Some(flat_channels) => flat_channels
+                .into_iter()
+                .map(|(name, samples)| (name.clone(), samples.clone()))
+                .collect(),
+
+            None => {
+                let deep_channels: Option<Vec<(&Text, &DeepSamples)>> = channels
+                    .iter()
+                    .map(|&(name, samples)| Some((name, samples.as_deep()?)))
+                    .collect();
+
+                let deep_channels = deep_channels
+                    .ok_or_else(|| Error::invalid("layer with deep and flat channels"))?;
+
+                composite_deep_channels(self.size.area(), &deep_channels)?
+            }
+        };
+
+        Ok(Layer {
+            channel_data: AnyChannels::sort(
+                composited
+                    .into_iter()
+                    .map(|(name, samples)| AnyChannel::new(name, samples))
+                    .collect(),
+            ),
+            attributes: self.attributes.clone(),
+            size: self.size,
+            encoding: self.encoding,
+        })
+    }
+}
+
+/// Composite a list of deep channels into flat channels,
+/// following the rules of "Interpreting Deep Pixels".
+/// Returns the name and flat samples of each resulting channel.
+///
+/// # Errors
+/// Returns an error if there is no `Z` channel, or if the sample counts of the
+/// channels differ or do not match the pixel count.
+pub fn composite_deep_channels(
+    pixel_count: usize,
+    channels: &[(&Text, &DeepSamples)],
+) -> Result<Vec<(Text, FlatSamples)>> {
+    let roles = DeepChannelRoles::new(
+        channels.iter().map(|&(name, samples)| (name, samples.samples.sample_type())),
+    )?;
+
+    let (_, first_channel) = channels[roles.depth];
+    for (_, samples) in channels {
+        if samples.pixel_count() != pixel_count
+            || samples.samples.len() != samples.total_sample_count()
+        {
+            return Err(Error::invalid("deep sample count does not match the layer size"));
+        }
+
+        if samples.sample_offsets != first_channel.sample_offsets {
+            return Err(Error::invalid("deep channels with different sample counts"));
+        }
+    }
+
+    let mut results: Vec<Vec<f32>> =
+        channels.iter().map(|_| Vec::with_capacity(pixel_count)).collect();
+
+    let mut front_results: Vec<Vec<u32>> = channels.iter().map(|_| Vec::new()).collect();
+
+    let mut pixel = DeepPixel::default();
+    for pixel_index in 0..pixel_count {
+        let sample_range = first_channel.pixel_sample_range(pixel_index);
+
+        pixel.clear(channels.len());
+        for sample_index in sample_range.clone() {
+            let values = channels
+                .iter()
+                .map(|(_, samples)| samples.samples.value_by_flat_index(sample_index).to_f32());
+
+            pixel.push_sample(values, &roles);
+        }
+
+        let front_sample = pixel.front_sample();
+        let composited = pixel.composite(&roles.roles);
+
+        for (channel_index, role) in roles.roles.iter().enumerate() {
+            match role {
+                Role::Depth => results[channel_index]
+                    .push(front_sample.map_or(f32::INFINITY, |(_, depth)| depth)),
+                Role::DepthBack => {}
+                Role::Alpha
+                | Role::Color {
+                    ..
+                } => results[channel_index].push(composited[channel_index]),
+                Role::Front => {
+                    front_results[channel_index].push(front_sample.map_or(0, |(sample, _)| {
+                        let (_, samples) = channels[channel_index];
+                        let sample_index = sample_range.start + sample;
+                        samples.samples.value_by_flat_index(sample_index).to_u32()
+                    }));
+                }
+            }
+        }
+    }
+
+    let flat_channels = channels
+        .iter()
+        .zip(roles.roles)
+        .zip(results.into_iter().zip(front_results))
+        .filter(|((_, role), _)| *role != Role::DepthBack)
+        .map(|((&(name, samples), role), (values, front_values))| {
+            let flat_samples = if role == Role::Front {
+                FlatSamples::U32(front_values)
+            } else {
+                match samples.samples.sample_type() {
+                    SampleType::F16 => {
+                        FlatSamples::F16(values.into_iter().map(f16::from_f32).collect())
+                    }
+                    SampleType::F32 | SampleType::U32 => FlatSamples::F32(values),
+                }
+            };
+
+            (name.clone(), flat_samples)
+        })
+        .collect();
+
+    Ok(flat_channels)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn deep(counts: &[usize], values: &[f32]) -> DeepSamples {
+        DeepSamples::from_sample_counts(counts.iter().copied(), FlatSamples::F32(values.to_vec()))
+            .unwrap()
+    }
+
+    fn composite(channels: &[(&str, DeepSamples)]) -> Vec<(Text, FlatSamples)> {
+        let names: Vec<Text> = channels.iter().map(|(name, _)| Text::from(*name)).collect();
+        let channels: Vec<(&Text, &DeepSamples)> =
+            names.iter().zip(channels).map(|(name, (_, samples))| (name, samples)).collect();
+
+        composite_deep_channels(channels[0].1.pixel_count(), &channels).unwrap()
+    }
+
+    fn values(result: &[(Text, FlatSamples)], name: &str) -> Vec<f32> {
+        let (_, samples) = result.iter().find(|(own, _)| own.bytes() == name.as_bytes()).unwrap();
+        samples.values_as_f32().collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                actual.total_cmp(expected).is_eq() || (actual - expected).abs() < 1e-5,
+                "expected {}, found {}",
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn composite_point_samples_front_to_back() {
+        // the second sample is in front of the first sample
+        let result = composite(&[
+            ("A", deep(&[2, 0], &[1.0, 0.5])),
+            ("R", deep(&[2, 0], &[1.0, 0.25])),
+            ("Z", deep(&[2, 0], &[2.0, 1.0])),
+        ]);
+
+        assert_close(&values(&result, "A"), &[1.0, 0.0]);
+        // the front sample with the color 0.25 covers half of the back sample with the
+        // color 1
+        assert_close(&values(&result, "R"), &[0.75, 0.0]);
+        assert_close(&values(&result, "Z"), &[1.0, f32::INFINITY]);
+    }
+
+    #[test]
+    fn split_volume_sample_at_overlap() {
+        // a volume from 0 to 2, and an opaque point sample in the middle
+        let result = composite(&[
+            ("A", deep(&[2], &[0.75, 1.0])),
+            ("G", deep(&[2], &[0.75, 1.0])),
+            ("Z", deep(&[2], &[0.0, 1.0])),
+            ("ZBack", deep(&[2], &[2.0, 1.0])),
+        ]);
+
+        // the front half of the volume has the alpha 1 - (1 - 0.75)^0.5 = 0.5,
+        // and covers half of the point sample with the color 1
+        assert_close(&values(&result, "A"), &[1.0]);
+        assert_close(&values(&result, "G"), &[1.0]);
+        assert!(result.iter().all(|(name, _)| name.bytes() != b"ZBack"));
+    }
+
+    #[test]
+    fn composite_colors_with_their_own_alpha() {
+        // the front sample is opaque in red only
+        let result = composite(&[
+            ("A", deep(&[2], &[0.5, 1.0])),
+            ("AR", deep(&[2], &[1.0, 1.0])),
+            ("G", deep(&[2], &[0.25, 1.0])),
+            ("R", deep(&[2], &[0.25, 1.0])),
+            ("Z", deep(&[2], &[1.0, 2.0])),
+        ]);
+
+        assert_close(&values(&result, "AR"), &[1.0]);
+        assert_close(&values(&result, "R"), &[0.25]);
+        assert_close(&values(&result, "G"), &[0.75]);
+    }
+
+    #[test]
+    fn merge_coincident_samples() {
+        let result = composite(&[
+            ("A", deep(&[2], &[0.5, 0.5])),
+            ("B", deep(&[2], &[0.5, 0.0])),
+            ("Z", deep(&[2], &[3.0, 3.0])),
+        ]);
+
+        // merging does not depend on the order of the samples
+        let swapped = composite(&[
+            ("A", deep(&[2], &[0.5, 0.5])),
+            ("B", deep(&[2], &[0.0, 0.5])),
+            ("Z", deep(&[2], &[3.0, 3.0])),
+        ]);
+
+        assert_close(&values(&result, "A"), &[0.75]);
+        assert_close(&values(&result, "B"), &values(&swapped, "B"));
+        assert_close(&values(&result, "B"), &[0.375]);
+    }
+}
@@ -16,8 +16,10 @@
 //! 1. `RgbaLayersImage`: Multiple layers, fixed set of channels: rgb, optional
 //!    a.
 //! 1. `FlatImage`: Multiple layers, any channels, no deep data.
-//! 1. `AnyImage`: All supported data (multiple layers, arbitrary channels, no
-//!    deep data yet)
+//! 1. `AnyImage`: All supported flat data (multiple layers, arbitrary channels,
+//!    resolution levels, no deep data)
+//! 1. `DeepAndFlatImage`: Like `AnyImage`, but layers may also contain deep
+//!    data
 //!
 //! You can also use your own types inside an image,
 //! for example if you want to use a custom sample storage.
@@ -25,10 +27,14 @@
 //! This is the high-level interface for the pixels of an image.
 //! See `exr::blocks` module for a low-level interface.
 
+pub mod analysis;
 pub mod crop;
+pub mod deep;
+pub mod multipart;
 pub mod pixel_vec;
 pub mod read;
 pub mod recursive;
+pub mod transcode;
 pub mod write;
 // pub mod channel_groups;
 
@@ -40,8 +46,9 @@ use crate::{
     error::Error,
     math::{RoundingMode, Vec2},
     meta::{
-        attribute::{LineOrder, Text},
-        header::{ImageAttributes, LayerAttributes},
+        attribute::{LineOrder, Text, TileDescription},
+        header::{Header, ImageAttributes, LayerAttributes},
+        BlockDescription,
     },
 };
 
@@ -49,9 +56,13 @@ use crate::{
 pub(crate) const fn ignore_progress(_progress: f64) {}
 
 /// This image type contains all supported exr features and can represent almost
-/// any image. It currently does not support deep data yet.
+/// any image. Does not support deep data, see `DeepAndFlatImage` instead.
 pub type AnyImage = Image<Layers<AnyChannels<Levels<FlatSamples>>>>;
 
+/// This image type contains all supported exr features, including deep data.
+/// Each channel contains either deep or flat samples, depending on its layer.
+pub type DeepAndFlatImage = Image<Layers<AnyChannels<Levels<DeepAndFlatSamples>>>>;
+
 /// This image type contains the most common exr features and can represent
 /// almost any plain image. Does not contain resolution levels. Does not support
 /// deep data.
@@ -244,8 +255,8 @@ pub enum Levels<Samples> {
     },
 }
 
-/// A list of resolution levels. `Samples` can currently only be `FlatSamples`.
-// or `DeepAndFlatSamples` (not yet implemented).
+/// A list of resolution levels. `Samples` can be `FlatSamples` or
+/// `DeepAndFlatSamples`.
 pub type LevelMaps<Samples> = Vec<Samples>;
 
 /// In addition to the full resolution image,
@@ -261,12 +272,16 @@ pub struct RipMaps<Samples> {
     pub level_count: Vec2<usize>,
 }
 
-// TODO deep data
-// #[derive(Clone, PartialEq)]
-// pub enum DeepAndFlatSamples {
-// Deep(DeepSamples),
-// Flat(FlatSamples)
-// }
+/// The samples of a single channel, which are either deep or flat.
+/// Deep layers contain `Deep` samples, all other layers contain `Flat` samples.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeepAndFlatSamples {
+    /// A variable number of samples per pixel.
+    Deep(DeepSamples),
+
+    /// Exactly one sample per pixel.
+    Flat(FlatSamples),
+}
 
 /// A vector of non-deep values (one value per pixel per channel).
 /// Stores row after row in a single vector.
@@ -288,12 +303,23 @@ pub enum FlatSamples {
     U32(Vec<u32>),
 }
 
-// #[derive(Clone, PartialEq)]
-// pub enum DeepSamples {
-// F16(Vec<Vec<f16>>),
-// F32(Vec<Vec<f32>>),
-// U32(Vec<Vec<u32>>),
-// }
+/// A variable number of samples per pixel, for a single channel.
+///
+/// The samples of all pixels are stored in a single flat vector,
+/// pixel after pixel, row after row.
+/// All channels of a deep layer have the same number of samples per pixel.
+#[derive(Clone, PartialEq)] // debug is implemented manually
+pub struct DeepSamples {
+    /// For each pixel, the number of samples in that pixel plus all previous
+    /// pixels. Contains one entry per pixel, row after row.
+    /// The samples of the pixel at index `i` are located at
+    /// `sample_offsets[i - 1] .. sample_offsets[i]`, starting at zero.
+    pub sample_offsets: Vec<usize>,
+
+    /// The values of all samples of all pixels.
+    /// Contains `sample_offsets.last()` values.
+    pub samples: FlatSamples,
+}
 
 use std::{marker::PhantomData, ops::Not};
 
@@ -813,6 +839,109 @@ impl FlatSamples {
     }
 }
 
+impl DeepSamples {
+    /// Create deep samples from the number of samples in each pixel, row after
+    /// row. Returns an error if the number of values does not match the sum
+    /// of all sample counts.
+    ///
+    /// # Errors
+    /// Returns an error if the values do not match the sample counts.
+    pub fn from_sample_counts(
+        sample_counts: impl IntoIterator<Item = usize>,
+        samples: FlatSamples,
+    ) -> Result<Self> {
+        let sample_offsets: Vec<usize> = sample_counts
+            .into_iter()
+            .scan(0_usize, |total, count| {
+                *total += count;
+                Some(*total)
+            })
+            .collect();
+
+        let deep = Self {
+            sample_offsets,
+            samples,
+        };
+
+        if deep.total_sample_count() == deep.samples.len() {
+            Ok(deep)
+        } else {
+            Err(Error::invalid("deep sample count"))
+        }
+    }
+
+    /// The number of pixels, which is the width times the height.
+    pub fn pixel_count(&self) -> usize {
+        self.sample_offsets.len()
+    }
+
+    /// The number of samples in all pixels together.
+    pub fn total_sample_count(&self) -> usize {
+        self.sample_offsets.last().copied().unwrap_or(0)
+    }
+
+    /// The range of sample indices that belong to the pixel
+    /// with the specified flat index.
+    /// The flat index can be obtained using `Vec2::flatten_for_width`.
+    pub fn pixel_sample_range(&self, pixel_index: usize) -> std::ops::Range<usize> {
+        let start = if pixel_index == 0 {
+            0
+        } else {
+            self.sample_offsets[pixel_index - 1]
+        };
+        start..self.sample_offsets[pixel_index]
+    }
+
+    /// The number of samples in the pixel with the specified flat index.
+    pub fn pixel_sample_count(&self, pixel_index: usize) -> usize {
+        self.pixel_sample_range(pixel_index).len()
+    }
+
+    /// The number of samples of each pixel, row after row.
+    pub fn sample_counts(&self) -> impl '_ + Iterator<Item = usize> {
+        (0..self.pixel_count()).map(move |index| self.pixel_sample_count(index))
+    }
+
+    /// All samples of the pixel with the specified flat index.
+    /// Matches the underlying sample type again for every sample. Does not
+    /// allocate.
+    pub fn pixel_samples(&self, pixel_index: usize) -> impl '_ + Iterator<Item = Sample> {
+        self.pixel_sample_range(pixel_index)
+            .map(move |sample_index| self.samples.value_by_flat_index(sample_index))
+    }
+}
+
+impl DeepAndFlatSamples {
+    /// Whether these samples contain a variable number of samples per pixel.
+    pub const fn is_deep(&self) -> bool {
+        matches!(self, Self::Deep(_))
+    }
+
+    /// The deep samples, if these samples are deep.
+    pub const fn as_deep(&self) -> Option<&DeepSamples> {
+        match self {
+            Self::Deep(deep) => Some(deep),
+            Self::Flat(_) => None,
+        }
+    }
+
+    /// The flat samples, if these samples are not deep.
+    pub const fn as_flat(&self) -> Option<&FlatSamples> {
+        match self {
+            Self::Flat(flat) => Some(flat),
+            Self::Deep(_) => None,
+        }
+    }
+
+    /// The type of the values, either `f16`, `f32` or `u32`.
+    pub fn sample_type(&self) -> SampleType {
+        match self {
+            Self::Deep(deep) => deep.samples.sample_type(),
+            Self::Flat(flat) => flat.sample_type(),
+        }
+    }
+}
+
 impl<'s, ChannelData: 's> Layer<ChannelData> {
     /// Create a layer with the specified size, attributes, encoding and
     /// channels. The channels can be either `SpecificChannels` or
@@ -896,6 +1025,21 @@ impl Encoding {
         blocks: Blocks::ScanLines,         // longest lines, faster memcpy
         line_order: LineOrder::Increasing, // presumably fastest?
     };
+
+    /// The encoding of the layer that is described by this header.
+    pub(crate) const fn from_header(header: &Header) -> Self {
+        Self {
+            compression: header.compression,
+            line_order: header.line_order,
+            blocks: match header.blocks {
+                BlockDescription::ScanLines => Blocks::ScanLines,
+                BlockDescription::Tiles(TileDescription {
+                    tile_size,
+                    ..
+                }) => Blocks::Tiles(tile_size),
+            },
+        }
+    }
 }
 
 impl Default for Encoding {
@@ -1041,6 +1185,16 @@ impl std::fmt::Debug for FlatSamples {
     }
 }
 
+impl std::fmt::Debug for DeepSamples {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("DeepSamples")
+            .field("pixel_count", &self.pixel_count())
+            .field("samples", &self.samples)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Compare the result of a round trip test with the original method.
 /// Supports lossy compression methods.
 // #[cfg(test)] TODO do not ship this code
@@ -1341,6 +1495,40 @@ pub mod validate_results {
         }
     }
 
+    impl ValidateResult for DeepSamples {
+        fn validate_result(
+            &self,
+            other: &Self,
+            options: ValidationOptions,
+            location: impl Fn() -> String,
+        ) -> ValidationResult {
+            if self.sample_offsets != other.sample_offsets {
+                return Err(location() + " > deep sample counts do not match");
+            }
+
+            self.samples.validate_result(&other.samples, options, || location() + " > deep")
+        }
+    }
+
+    impl ValidateResult for DeepAndFlatSamples {
+        fn validate_result(
+            &self,
+            other: &Self,
+            options: ValidationOptions,
+            location: impl Fn() -> String,
+        ) -> ValidationResult {
+            match (self, other) {
+                (Self::Deep(own), Self::Deep(other)) => {
+                    own.validate_result(other, options, location)
+                }
+                (Self::Flat(own), Self::Flat(other)) => {
+                    own.validate_result(other, options, location)
+                }
+                _ => Err(location() + " > deep and flat samples mismatch"),
+            }
+        }
+    }
+
     impl<T> ValidateResult for &[T]
     where
         T: ValidateResult,
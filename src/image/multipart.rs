@@ -0,0 +1,297 @@
+//! Combine multiple exr files into a single multi-part file,
+//! or split a multi-part file into one file for each part.
+//! The compressed chunks are copied without decompressing any pixels.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use smallvec::smallvec;
+
+use crate::{
+    block::{
+        chunk::{Chunk, TileCoordinates},
+        writer::ChunksWriter,
+    },
+    error::{Error, Result, UnitResult},
+    meta::{
+        attribute::{IntegerBounds, Text},
+        compute_chunk_count,
+        header::{Header, ImageAttributes},
+        Headers,
+    },
+};
+
+/// Combine the files into a single multi-part file.
+/// Parts without a name are named after the file name, without extension.
+/// Uses relaxed error handling. See `combine`.
+///
+/// # Errors
+/// Returns an error if a file cannot be read or written,
+/// or if the parts cannot be combined.
+pub fn combine_files(from: &[impl AsRef<Path>], to: impl AsRef<Path>) -> UnitResult {
+    let sources = from
+        .iter()
+        .map(|path| {
+            let path = path.as_ref();
+
+            let name = path
+                .file_stem()
+                .and_then(|stem| Text::new_or_none(stem.to_string_lossy()))
+                .filter(|name| !name.as_slice().is_empty())
+                .unwrap_or_else(|| Text::from("part"));
+
+            Ok((name, BufReader::new(std::fs::File::open(path)?)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    crate::io::attempt_delete_file_on_write_error(to.as_ref(), move |write| {
+        combine(sources, BufWriter::new(write), false)
+    })
+}
+
+/// Combine all parts of all sources into a single multi-part file,
+/// copying the compressed chunks without decompressing them.
+///
+/// Each source consists of a name and a buffered reader. Parts without a name
+/// are named after their source, or `part` if the name of the source is empty.
+/// When multiple parts have the same name, the later parts are renamed by
+/// appending `_1`, `_2`, and so on.
+///
+/// The attributes that must be equal for all parts are reconciled:
+/// The display window is the bounding box of all display windows.
+/// The chromaticities, the time code, and any other shared attribute are
+/// taken from the first part that contains them.
+///
+/// # Errors
+/// Returns an error if a source cannot be read or the result cannot be written,
+/// if the pixel aspect ratios differ, or if a renamed part name
+/// would be longer than 255 bytes.
+pub fn combine<R: Read + Seek>(
+    sources: impl IntoIterator<Item = (Text, R)>,
+    write: impl Write + Seek,
+    pedantic: bool,
+) -> UnitResult {
+    let mut readers = Vec::new();
+    let mut headers = Headers::new();
+
+    for (name, read) in sources {
+        let reader = crate::block::read(read, pedantic)?;
+
+        for header in reader.headers() {
+            let mut header = header.clone();
+            header.own_attributes.layer_name.get_or_insert_with(|| name.clone());
+            headers.push(header);
+        }
+
+        readers.push(reader);
+    }
+
+    let shared_attributes = combine_shared_attributes(&headers)?;
+    rename_duplicate_layers(&mut headers)?;
+
+    for header in &mut headers {
+        header.shared_attributes = shared_attributes.clone();
+        header.chunk_count =
+            compute_chunk_count(header.compression, header.layer_size, header.blocks);
+    }
+
+    crate::block::write(write, headers, pedantic, move |_, chunk_writer| {
+        let mut first_layer_index = 0;
+
+        for reader in readers {
+            let headers = reader.headers().to_vec();
+            let block_indices: Vec<_> = headers.iter().map(block_indices).collect();
+
+            for chunk in reader.all_chunks(pedantic)? {
+                let mut chunk = chunk?;
+                let layer_index = chunk.layer_index;
+
+                let index_in_header =
+                    index_in_header(&headers[layer_index], &block_indices[layer_index], &chunk)?;
+
+                chunk.layer_index += first_layer_index;
+                chunk_writer.write_chunk(index_in_header, chunk)?;
+            }
+
+            first_layer_index += headers.len();
+        }
+
+        Ok(())
+    })
+}
+
+/// Write each part of the file to a separate file.
+///
+/// The closure returns the path of each part.
+/// Uses relaxed error handling. See `split`.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or a part cannot be written.
+pub fn split_file(
+    from: impl AsRef<Path>,
+    mut path_of_part: impl FnMut(usize, &Header) -> PathBuf,
+) -> UnitResult {
+    let mut read = BufReader::new(std::fs::File::open(from)?);
+    let headers = crate::block::read(&mut read, false)?.headers().to_vec();
+
+    for (part_index, header) in headers.iter().enumerate() {
+        read.seek(SeekFrom::Start(0))?;
+
+        let path = path_of_part(part_index, header);
+        crate::io::attempt_delete_file_on_write_error(&path, |write| {
+            extract_part(&mut read, part_index, BufWriter::new(write), false)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Write each part of the file to a separate writer.
+///
+/// The closure creates the writer of each part.
+/// Copies the compressed chunks without decompressing them.
+/// Keeps all attributes of each part, including its name.
+/// The reader must be buffered and positioned at the start of the file.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, if a writer cannot be created,
+/// or if a part cannot be written.
+pub fn split<R: Read + Seek, W: Write + Seek>(
+    mut read: R,
+    pedantic: bool,
+    mut create_writer: impl FnMut(usize, &Header) -> Result<W>,
+) -> UnitResult {
+    let start = read.stream_position()?;
+    let headers = crate::block::read(&mut read, pedantic)?.headers().to_vec();
+
+    for (part_index, header) in headers.iter().enumerate() {
+        read.seek(SeekFrom::Start(start))?;
+        extract_part(&mut read, part_index, create_writer(part_index, header)?, pedantic)?;
+    }
+
+    Ok(())
+}
+
+/// Write a single part of the file to the writer.
+///
+/// Copies the compressed chunks without decompressing them.
+/// Keeps all attributes of the part, including its name.
+/// Both the reader and the writer are assumed to be buffered.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, if it has no part with this
+/// index, or if the part cannot be written.
+pub fn extract_part(
+    read: impl Read + Seek,
+    part_index: usize,
+    write: impl Write + Seek,
+    pedantic: bool,
+) -> UnitResult {
+    let reader = crate::block::read(read, pedantic)?;
+
+    let mut header =
+        reader.headers().get(part_index).ok_or_else(|| Error::invalid("part index"))?.clone();
+
+    header.chunk_count = compute_chunk_count(header.compression, header.layer_size, header.blocks);
+    let block_indices = block_indices(&header);
+
+    let chunks = reader.filter_chunks(pedantic, |_, _, block| block.layer == part_index)?;
+
+    crate::block::write(write, smallvec![header.clone()], pedantic, move |_, chunk_writer| {
+        for chunk in chunks {
+            let mut chunk = chunk?;
+            let index_in_header = index_in_header(&header, &block_indices, &chunk)?;
+
+            chunk.layer_index = 0;
+            chunk_writer.write_chunk(index_in_header, chunk)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// The index of each block in the offset table of the header, by its tile
+/// coordinates.
+fn block_indices(header: &Header) -> HashMap<TileCoordinates, usize> {
+    header
+        .blocks_increasing_y_order()
+        .enumerate()
+        .map(|(index_in_header, tile)| (tile.location, index_in_header))
+        .collect()
+}
+
+/// The index of the chunk in the offset table of its header.
+fn index_in_header(
+    header: &Header,
+    block_indices: &HashMap<TileCoordinates, usize>,
+    chunk: &Chunk,
+) -> Result<usize> {
+    let coordinates = header.get_block_data_indices(&chunk.compressed_block)?;
+    block_indices.get(&coordinates).copied().ok_or_else(|| Error::invalid("chunk tile coordinates"))
+}
+
+/// The attributes that all headers of the combined file share.
+fn combine_shared_attributes(headers: &[Header]) -> Result<ImageAttributes> {
+    let (first, rest) =
+        headers.split_first().ok_or_else(|| Error::invalid("at least one layer is required"))?;
+
+    let mut combined = first.shared_attributes.clone();
+
+    for header in rest {
+        let attributes = &header.shared_attributes;
+
+        if attributes.pixel_aspect != combined.pixel_aspect {
+            return Err(Error::invalid("pixel aspect of the parts differs"));
+        }
+
+        let start = combined.display_window.position.min(attributes.display_window.position);
+        let end = combined.display_window.end().max(attributes.display_window.end());
+        combined.display_window =
+            IntegerBounds::new(start, (end - start).to_usize("display window")?);
+
+        combined.chromaticities = combined.chromaticities.or(attributes.chromaticities);
+        combined.time_code = combined.time_code.or(attributes.time_code);
+
+        for (name, value) in &attributes.other {
+            combined.other.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    Ok(combined)
+}
+
+/// Append a number to each layer name that already appeared in a previous
+/// header. Headers without a name or with an empty name are named `part`.
+fn rename_duplicate_layers(headers: &mut [Header]) -> UnitResult {
+    let mut names = HashSet::with_capacity(headers.len());
+
+    for header in headers {
+        let name = header
+            .own_attributes
+            .layer_name
+            .clone()
+            .filter(|name| !name.as_slice().is_empty())
+            .unwrap_or_else(|| Text::from("part"));
+
+        let mut unique_name = name.clone();
+
+        let mut suffix = 1;
+        while names.contains(&unique_name) {
+            unique_name = Text::new_or_none(format!("{name}_{suffix}"))
+                .ok_or_else(|| Error::invalid("layer name"))?;
+
+            suffix += 1;
+        }
+
+        let mut long_names = false;
+        unique_name.validate(true, Some(&mut long_names))?;
+
+        names.insert(unique_name.clone());
+        header.own_attributes.layer_name = Some(unique_name);
+    }
+
+    Ok(())
+}
@@ -1,8 +1,12 @@
 //! How to read arbitrary channels.
 
 use crate::{
-    block::{chunk::TileCoordinates, lines::LineRef, UncompressedBlock},
-    error::{Result, UnitResult},
+    block::{
+        chunk::TileCoordinates,
+        lines::{DeepLineRef, LineRef},
+        UncompressedBlock, UncompressedDeepBlock,
+    },
+    error::{Error, Result, UnitResult},
     image::{
         read::layers::{ChannelsReader, ReadChannels},
         *,
@@ -77,6 +81,18 @@ pub trait SamplesReader {
     /// accumulating the sample data
     fn read_line(&mut self, line: LineRef<'_>) -> UnitResult;
 
+    /// Load a single line of deep pixels, which has not been filtered, into the
+    /// reader, accumulating the sample data. Fails by default, as most
+    /// readers only support flat samples.
+    ///
+    /// # Errors
+    /// Returns an error if this reader does not support deep data,
+    /// or if the deep samples cannot be read.
+    fn read_deep_line(&mut self, line: DeepLineRef<'_>) -> UnitResult {
+        let _ = line;
+        Err(Error::unsupported("deep data with this sample reader"))
+    }
+
     /// Deliver the final accumulated sample storage for the image
     fn into_samples(self) -> Self::Samples;
 }
@@ -126,6 +142,18 @@ impl<S: SamplesReader> ChannelsReader for AnyChannelsReader<S> {
         Ok(())
     }
 
+    fn read_deep_block(
+        &mut self,
+        header: &Header,
+        decompressed: UncompressedDeepBlock,
+    ) -> UnitResult {
+        for line in decompressed.lines(&header.channels) {
+            self.sample_channels_reader[line.location.channel].samples.read_deep_line(line)?;
+        }
+
+        Ok(())
+    }
+
     fn into_channels(self) -> Self::Channels {
         AnyChannels {
             // not using `new()` as the channels are already sorted
@@ -8,8 +8,11 @@ use std::{
 };
 
 use crate::{
-    block::{chunk::TileCoordinates, reader::ChunksReader, BlockIndex, UncompressedBlock},
-    error::{Result, UnitResult},
+    block::{
+        chunk::TileCoordinates, reader::ChunksReader, BlockIndex, UncompressedBlock,
+        UncompressedDeepBlock,
+    },
+    error::{Error, Result, UnitResult},
     image::*,
     meta::{
         header::{Header, ImageAttributes},
@@ -22,10 +25,10 @@ use crate::{
 /// and a callback for the reading progress.
 #[derive(Debug, Clone)]
 pub struct ReadImage<OnProgress, ReadLayers> {
-    on_progress: OnProgress,
-    read_layers: ReadLayers,
-    pedantic: bool,
-    parallel: bool,
+    pub(super) on_progress: OnProgress,
+    pub(super) read_layers: ReadLayers,
+    pub(super) pedantic: bool,
+    pub(super) parallel: bool,
 }
 
 impl<F, L> ReadImage<F, L>
@@ -145,6 +148,18 @@ where
         mut self,
         chunks_reader: crate::block::reader::Reader<impl Read + Seek>,
     ) -> Result<Image<Layers>>
+    where
+        for<'s> L: ReadLayers<'s, Layers = Layers>,
+    {
+        self.read_chunks(chunks_reader)
+    }
+
+    /// Read the exr image from an initialized chunks reader,
+    /// without consuming this reader, so that it can read more images.
+    pub(super) fn read_chunks<Layers>(
+        &mut self,
+        chunks_reader: crate::block::reader::Reader<impl Read + Seek>,
+    ) -> Result<Image<Layers>>
     where
         for<'s> L: ReadLayers<'s, Layers = Layers>,
     {
@@ -153,20 +168,66 @@ where
             parallel,
             ref mut on_progress,
             ref mut read_layers,
-        } = self;
+        } = *self;
 
         let layers_reader = read_layers.create_layers_reader(chunks_reader.headers())?;
         let mut image_collector =
             ImageWithAttributesReader::new(chunks_reader.headers(), layers_reader)?;
 
-        let block_reader = chunks_reader
-            .filter_chunks(pedantic, |meta, tile, block| {
-                image_collector.filter_block(meta, tile, block)
-            })?
-            .on_progress(on_progress);
+        let block_reader = chunks_reader.filter_chunks(pedantic, |meta, tile, block| {
+            image_collector.filter_block(meta, tile, block)
+        })?;
+
+        // only the chunks that can actually be located are read
+        #[cfg(feature = "rayon")]
+        let flat_chunk_count = block_reader.expected_flat_chunk_count();
+
+        let block_reader = block_reader.on_progress(on_progress);
 
+        // deep blocks are decompressed in this thread,
+        // as their size is unknown before decompressing them
+        if block_reader.headers().iter().any(|header| header.deep) {
+            let meta_data = block_reader.meta_data().clone();
+
+            // the flat blocks are still decompressed in parallel,
+            // and the deep chunks are set aside until all flat blocks are done
+            #[cfg(feature = "rayon")]
+            if parallel {
+                let mut deep_chunks = Vec::new();
+
+                FlatChunksReader {
+                    chunks_reader: block_reader,
+                    remaining_flat_chunk_count: flat_chunk_count,
+                    deep_chunks: &mut deep_chunks,
+                }
+                .decompress_parallel(pedantic, |meta_data, block| {
+                    image_collector.read_block(&meta_data.headers, block)
+                })?;
+
+                for chunk in deep_chunks {
+                    let block =
+                        UncompressedDeepBlock::decompress_chunk(chunk, &meta_data, pedantic)?;
+                    image_collector.read_deep_block(&meta_data.headers, block)?;
+                }
+
+                return Ok(image_collector.into_image());
+            }
+
+            for chunk in block_reader {
+                let chunk = chunk?;
+
+                if meta_data.headers.get(chunk.layer_index).is_some_and(|header| header.deep) {
+                    let block =
+                        UncompressedDeepBlock::decompress_chunk(chunk, &meta_data, pedantic)?;
+                    image_collector.read_deep_block(&meta_data.headers, block)?;
+                } else {
+                    let block = UncompressedBlock::decompress_chunk(chunk, &meta_data, pedantic)?;
+                    image_collector.read_block(&meta_data.headers, block)?;
+                }
+            }
+        }
         // TODO propagate send requirement further upwards
-        if parallel {
+        else if parallel {
             #[cfg(not(feature = "rayon"))]
             return Err(crate::error::Error::unsupported(
                 "parallel decompression requires the rayon feature",
@@ -186,6 +247,56 @@ where
     }
 }
 
+/// Returns only the flat chunks of a file that contains deep layers,
+/// so that they can be decompressed in parallel.
+/// The deep chunks are collected in a vector instead.
+#[cfg(feature = "rayon")]
+#[derive(Debug)]
+struct FlatChunksReader<'d, R> {
+    chunks_reader: R,
+    remaining_flat_chunk_count: usize,
+    deep_chunks: &'d mut Vec<crate::block::chunk::Chunk>,
+}
+
+#[cfg(feature = "rayon")]
+impl<R: ChunksReader> ChunksReader for FlatChunksReader<'_, R> {
+    fn meta_data(&self) -> &MetaData {
+        self.chunks_reader.meta_data()
+    }
+
+    fn expected_chunk_count(&self) -> usize {
+        self.remaining_flat_chunk_count
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<R: ChunksReader> ExactSizeIterator for FlatChunksReader<'_, R> {}
+
+#[cfg(feature = "rayon")]
+impl<R: ChunksReader> Iterator for FlatChunksReader<'_, R> {
+    type Item = Result<crate::block::chunk::Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let chunk = match self.chunks_reader.next()? {
+                Ok(chunk) => chunk,
+                Err(error) => return Some(Err(error)),
+            };
+
+            if self.meta_data().headers[chunk.layer_index].deep {
+                self.deep_chunks.push(chunk);
+            } else {
+                self.remaining_flat_chunk_count -= 1;
+                return Some(Ok(chunk));
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining_flat_chunk_count, Some(self.remaining_flat_chunk_count))
+    }
+}
+
 /// Processes blocks from a file and collects them into a complete `Image`.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ImageWithAttributesReader<L> {
@@ -211,18 +322,37 @@ where
     }
 
     /// Specify whether a single block of pixels should be loaded from the file
-    fn filter_block(&self, meta: &MetaData, tile: TileCoordinates, block: BlockIndex) -> bool {
+    pub(super) fn filter_block(
+        &self,
+        meta: &MetaData,
+        tile: TileCoordinates,
+        block: BlockIndex,
+    ) -> bool {
         self.layers_reader.filter_block(meta, tile, block)
     }
 
     /// Load a single pixel block, which has not been filtered, into the reader,
     /// accumulating the image
-    fn read_block(&mut self, headers: &[Header], block: UncompressedBlock) -> UnitResult {
+    pub(super) fn read_block(
+        &mut self,
+        headers: &[Header],
+        block: UncompressedBlock,
+    ) -> UnitResult {
         self.layers_reader.read_block(headers, block)
     }
 
+    /// Load a single deep pixel block, which has not been filtered, into the
+    /// reader, accumulating the image
+    pub(super) fn read_deep_block(
+        &mut self,
+        headers: &[Header],
+        block: UncompressedDeepBlock,
+    ) -> UnitResult {
+        self.layers_reader.read_deep_block(headers, block)
+    }
+
     /// Deliver the complete accumulated image
-    fn into_image(self) -> Image<L::Layers> {
+    pub(super) fn into_image(self) -> Image<L::Layers> {
         Image {
             attributes: self.image_attributes,
             layer_data: self.layers_reader.into_layers(),
@@ -265,6 +395,18 @@ pub trait LayersReader {
     /// accumulating the layer
     fn read_block(&mut self, headers: &[Header], block: UncompressedBlock) -> UnitResult;
 
+    /// Load a single deep pixel block, which has not been filtered, into the
+    /// reader, accumulating the layer. Fails by default, as most readers
+    /// only support flat samples.
+    ///
+    /// # Errors
+    /// Returns an error if this reader does not support deep data,
+    /// or if the deep layer cannot be read.
+    fn read_deep_block(&mut self, headers: &[Header], block: UncompressedDeepBlock) -> UnitResult {
+        let _ = (headers, block);
+        Err(Error::unsupported("deep data with this layers reader"))
+    }
+
     /// Deliver the final accumulated layers for the image
     fn into_layers(self) -> Self::Layers;
 }
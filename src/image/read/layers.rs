@@ -1,7 +1,7 @@
 //! How to read either a single or a list of layers.
 
 use crate::{
-    block::{chunk::TileCoordinates, BlockIndex, UncompressedBlock},
+    block::{chunk::TileCoordinates, BlockIndex, UncompressedBlock, UncompressedDeepBlock},
     error::{Error, Result, UnitResult},
     image::{
         read::image::{LayersReader, ReadLayers},
@@ -114,6 +114,18 @@ pub trait ChannelsReader {
     /// accumulating the channel data
     fn read_block(&mut self, header: &Header, block: UncompressedBlock) -> UnitResult;
 
+    /// Load a single deep pixel block, which has not been filtered, into the
+    /// reader, accumulating the channel data. Fails by default, as most
+    /// readers only support flat samples.
+    ///
+    /// # Errors
+    /// Returns an error if this reader does not support deep data,
+    /// or if the deep channels cannot be read.
+    fn read_deep_block(&mut self, header: &Header, block: UncompressedDeepBlock) -> UnitResult {
+        let _ = (header, block);
+        Err(Error::unsupported("deep data with this channels reader"))
+    }
+
     /// Deliver the final accumulated channel collection for the image
     fn into_channels(self) -> Self::Channels;
 }
@@ -124,17 +136,7 @@ impl<C> LayerReader<C> {
             channels_reader,
             attributes: header.own_attributes.clone(),
             size: header.layer_size,
-            encoding: Encoding {
-                compression: header.compression,
-                line_order: header.line_order,
-                blocks: match header.blocks {
-                    crate::meta::BlockDescription::ScanLines => Blocks::ScanLines,
-                    crate::meta::BlockDescription::Tiles(TileDescription {
-                        tile_size,
-                        ..
-                    }) => Blocks::Tiles(tile_size),
-                },
-            },
+            encoding: Encoding::from_header(header),
         })
     }
 }
@@ -182,6 +184,17 @@ where
             )
     }
 
+    fn read_deep_block(&mut self, headers: &[Header], block: UncompressedDeepBlock) -> UnitResult {
+        self.layer_readers
+            .get_mut(block.index.layer)
+            .expect("invalid layer index argument")
+            .channels_reader
+            .read_deep_block(
+                headers.get(block.index.layer).expect("invalid header index in block"),
+                block,
+            )
+    }
+
     fn into_layers(self) -> Self::Layers {
         self.layer_readers
             .into_iter()
@@ -242,6 +255,14 @@ where
         self.layer_reader.channels_reader.read_block(&headers[self.layer_index], block)
     }
 
+    fn read_deep_block(&mut self, headers: &[Header], block: UncompressedDeepBlock) -> UnitResult {
+        debug_assert_eq!(
+            block.index.layer, self.layer_index,
+            "block should have been filtered out"
+        );
+        self.layer_reader.channels_reader.read_deep_block(&headers[self.layer_index], block)
+    }
+
     fn into_layers(self) -> Self::Layers {
         Layer {
             channel_data: self.layer_reader.channels_reader.into_channels(),
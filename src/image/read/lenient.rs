@@ -0,0 +1,267 @@
+//! Read damaged or incomplete files, for example a frame whose render crashed
+//! or a file that was only partially copied.
+//!
+//! Create a lenient reader with
+//! `read()...all_attributes().lenient(fill_value)`. All blocks that can be
+//! decoded are loaded into the image, and all other blocks are filled with the
+//! specified value. The result lists the status of each block, so that the
+//! damaged regions of the image can be displayed.
+
+use std::{
+    collections::HashMap,
+    io::{BufReader, Read, Seek},
+    path::Path,
+};
+
+use crate::{
+    block::{
+        chunk::Chunk, lines::LineRefMut, reader::ChunksReader, samples::Sample, BlockIndex,
+        UncompressedBlock, UncompressedDeepBlock,
+    },
+    error::{Result, UnitResult},
+    image::{
+        read::image::{ImageWithAttributesReader, ReadImage, ReadLayers},
+        Image,
+    },
+    meta::{attribute::SampleType, MetaData},
+};
+
+/// Reads as much of a damaged file as possible, instead of returning an error.
+/// Created with `ReadImage::lenient`.
+#[derive(Debug, Clone)]
+pub struct ReadLenientImage<OnProgress, ReadLayers> {
+    read_image: ReadImage<OnProgress, ReadLayers>,
+    fill_value: Sample,
+}
+
+/// An image that may be incomplete, together with the status of each block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialImage<Layers> {
+    /// The image. Blocks that could not be decoded contain the fill value.
+    pub image: Image<Layers>,
+
+    /// The status of each block that was requested from the file,
+    /// in the order of the headers and in increasing y order.
+    /// The block index contains the layer, the level, and the pixel section.
+    pub blocks: Vec<(BlockIndex, BlockStatus)>,
+}
+
+/// Whether a block was successfully loaded from the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockStatus {
+    /// The block was decoded and loaded into the image.
+    Complete,
+
+    /// The file does not contain the block, or the block could not be read,
+    /// for example because the file ends early.
+    Missing,
+
+    /// The block was found in the file, but could not be decompressed.
+    Corrupt,
+}
+
+impl<F, L> ReadImage<F, L>
+where
+    F: FnMut(f64),
+{
+    /// Read damaged or incomplete files without failing, as far as possible.
+    /// Blocks that are missing or cannot be decompressed are filled with the
+    /// specified value, which is converted to the sample type of each channel.
+    /// Only fails if the headers of the file cannot be read.
+    /// Ignores `pedantic`, and decompresses the blocks in this thread,
+    /// so that each damaged block can be identified. See `ReadLenientImage`.
+    pub fn lenient(self, fill_value: impl Into<Sample>) -> ReadLenientImage<F, L> {
+        ReadLenientImage {
+            read_image: self,
+            fill_value: fill_value.into(),
+        }
+    }
+}
+
+impl<F, L> ReadLenientImage<F, L>
+where
+    F: FnMut(f64),
+{
+    /// Read the damaged exr image from a file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or its headers cannot be
+    /// read, or if the requested layers and channels are not in the file.
+    /// Damaged blocks are not an error.
+    #[inline]
+    pub fn from_file<Layers>(self, path: impl AsRef<Path>) -> Result<PartialImage<Layers>>
+    where
+        for<'s> L: ReadLayers<'s, Layers = Layers>,
+    {
+        self.from_unbuffered(std::fs::File::open(path)?)
+    }
+
+    /// Buffer the reader and then read the damaged exr image from it.
+    ///
+    /// # Errors
+    /// Returns an error if the headers cannot be read, or if the requested
+    /// layers and channels are not in the file. Damaged blocks are not an
+    /// error.
+    #[inline]
+    pub fn from_unbuffered<Layers>(
+        self,
+        unbuffered: impl Read + Seek,
+    ) -> Result<PartialImage<Layers>>
+    where
+        for<'s> L: ReadLayers<'s, Layers = Layers>,
+    {
+        self.from_buffered(BufReader::new(unbuffered))
+    }
+
+    /// Read the damaged exr image from a buffered reader.
+    ///
+    /// # Errors
+    /// Returns an error if the headers cannot be read, or if the requested
+    /// layers and channels are not in the file. Damaged blocks are not an
+    /// error.
+    pub fn from_buffered<Layers>(self, buffered: impl Read + Seek) -> Result<PartialImage<Layers>>
+    where
+        for<'s> L: ReadLayers<'s, Layers = Layers>,
+    {
+        self.from_chunks(crate::block::read(buffered, false)?)
+    }
+
+    /// Read the damaged exr image from an initialized chunks reader
+    /// that has already extracted the meta data from the file.
+    ///
+    /// # Errors
+    /// Returns an error if the requested layers and channels are not in the
+    /// file. Damaged blocks are not an error.
+    pub fn from_chunks<Layers>(
+        self,
+        chunks_reader: crate::block::reader::Reader<impl Read + Seek>,
+    ) -> Result<PartialImage<Layers>>
+    where
+        for<'s> L: ReadLayers<'s, Layers = Layers>,
+    {
+        let Self {
+            read_image:
+                ReadImage {
+                    on_progress,
+                    read_layers,
+                    ..
+                },
+            fill_value,
+        } = self;
+
+        let layers_reader = read_layers.create_layers_reader(chunks_reader.headers())?;
+        let mut image_collector =
+            ImageWithAttributesReader::new(chunks_reader.headers(), layers_reader)?;
+
+        let mut requested_blocks = Vec::new();
+        let chunks = chunks_reader
+            .filter_located_chunks(|meta, tile, block| {
+                let is_requested = image_collector.filter_block(meta, tile, block);
+
+                if is_requested {
+                    requested_blocks.push(block);
+                }
+
+                is_requested
+            })?
+            .on_progress(on_progress);
+
+        let meta_data = chunks.meta_data().clone();
+        let mut statuses: HashMap<BlockIndex, BlockStatus> =
+            requested_blocks.iter().map(|&block| (block, BlockStatus::Missing)).collect();
+
+        // chunks that cannot be read or located remain missing
+        for chunk in chunks.flatten() {
+            let Ok(index) = block_index_of_chunk(&meta_data, &chunk) else {
+                continue;
+            };
+
+            let Some(status) = statuses.get_mut(&index) else {
+                continue;
+            };
+            if *status == BlockStatus::Complete {
+                continue;
+            }
+
+            *status = match read_chunk(&mut image_collector, &meta_data, chunk) {
+                Ok(()) => BlockStatus::Complete,
+                Err(_) => BlockStatus::Corrupt,
+            };
+        }
+
+        for &block in &requested_blocks {
+            let header = &meta_data.headers[block.layer];
+
+            // deep layers contain no samples where blocks are missing
+            if statuses[&block] != BlockStatus::Complete && !header.deep {
+                let filled = UncompressedBlock::from_lines(&header.channels, block, |line| {
+                    let sample_type = header.channels.list[line.location.channel].sample_type;
+                    fill_line(line, sample_type, fill_value);
+                });
+
+                image_collector.read_block(&meta_data.headers, filled)?;
+            }
+        }
+
+        Ok(PartialImage {
+            image: image_collector.into_image(),
+            blocks: requested_blocks.into_iter().map(|block| (block, statuses[&block])).collect(),
+        })
+    }
+}
+
+impl<Layers> PartialImage<Layers> {
+    /// Whether all requested blocks were loaded from the file.
+    pub fn is_complete(&self) -> bool {
+        self.blocks.iter().all(|&(_, status)| status == BlockStatus::Complete)
+    }
+
+    /// The blocks that are missing or corrupt, and were filled with the fill
+    /// value.
+    pub fn damaged_blocks(&self) -> impl '_ + Iterator<Item = (BlockIndex, BlockStatus)> {
+        self.blocks.iter().copied().filter(|&(_, status)| status != BlockStatus::Complete)
+    }
+}
+
+/// Decompress the chunk and load it into the image.
+fn read_chunk<L>(
+    image_collector: &mut ImageWithAttributesReader<L>,
+    meta_data: &MetaData,
+    chunk: Chunk,
+) -> UnitResult
+where
+    L: crate::image::read::image::LayersReader,
+{
+    if meta_data.headers[chunk.layer_index].deep {
+        let block = UncompressedDeepBlock::decompress_chunk(chunk, meta_data, false)?;
+        image_collector.read_deep_block(&meta_data.headers, block)
+    } else {
+        let block = UncompressedBlock::decompress_chunk(chunk, meta_data, false)?;
+        image_collector.read_block(&meta_data.headers, block)
+    }
+}
+
+/// The block index of a compressed chunk, computed from its coordinates.
+fn block_index_of_chunk(meta_data: &MetaData, chunk: &Chunk) -> Result<BlockIndex> {
+    let header = &meta_data.headers[chunk.layer_index];
+    let tile = header.get_block_data_indices(&chunk.compressed_block)?;
+    let bounds = header.get_absolute_block_pixel_coordinates(tile)?;
+
+    Ok(BlockIndex {
+        layer: chunk.layer_index,
+        level: tile.level_index,
+        pixel_position: bounds.position.to_usize("data indices start")?,
+        pixel_size: bounds.size,
+    })
+}
+
+/// Set all samples of the line to the fill value.
+fn fill_line(line: LineRefMut<'_>, sample_type: SampleType, fill_value: Sample) {
+    let result = match sample_type {
+        SampleType::F16 => line.write_samples(|_| fill_value.to_f16()),
+        SampleType::F32 => line.write_samples(|_| fill_value.to_f32()),
+        SampleType::U32 => line.write_samples(|_| fill_value.to_u32()),
+    };
+
+    result.expect("writing to a block buffer bug");
+}
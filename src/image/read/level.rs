@@ -0,0 +1,205 @@
+//! Read a single resolution level of each layer, for example a small mip map
+//! level for a thumbnail.
+//!
+//! Call `specific_resolution_level(select_level)` or
+//! `smallest_resolution_level_at_least(min_width)` on the result of
+//! `all_attributes()`. Only the blocks of the selected level are read and
+//! decompressed. The selected level is presented to the layer readers as the
+//! only level of the layer, so that `largest_resolution_level()` reads it.
+
+use crate::{
+    block::{chunk::TileCoordinates, BlockIndex, UncompressedBlock, UncompressedDeepBlock},
+    error::{Error, Result, UnitResult},
+    image::read::image::{LayersReader, ReadImage, ReadLayers},
+    math::Vec2,
+    meta::{
+        attribute::{LevelMode, TileDescription},
+        compute_chunk_count,
+        header::Header,
+        mip_map_levels, rip_map_levels, BlockDescription, Headers, MetaData,
+    },
+};
+
+/// The size of a resolution level, in pixels.
+type LevelSize = Vec2<usize>;
+
+/// Specify to read only a single resolution level of each layer.
+/// Created with `ReadImage::specific_resolution_level`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReadSpecificLevel<ReadLayers, SelectLevel> {
+    /// The layers reading specification.
+    pub read_layers: ReadLayers,
+
+    /// Returns the index of the level to read, given the sizes of all levels of
+    /// a layer.
+    pub select_level: SelectLevel,
+}
+
+/// Processes the pixel blocks of the selected resolution level of each layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecificLevelReader<LayersReader> {
+    layers_reader: LayersReader,
+
+    /// The headers as seen by the layers reader, containing only the selected
+    /// level.
+    level_headers: Headers,
+
+    /// The selected level index of each layer.
+    selected_levels: Vec<Vec2<usize>>,
+}
+
+impl<F, L> ReadImage<F, L>
+where
+    F: FnMut(f64),
+{
+    /// Read only the smallest resolution level of each layer that is at least
+    /// the specified number of pixels wide, or the largest level if no level is
+    /// wide enough. See `specific_resolution_level`.
+    pub fn smallest_resolution_level_at_least(
+        self,
+        min_width: usize,
+    ) -> ReadImage<F, ReadSpecificLevel<L, impl Fn(&[LevelSize]) -> usize>> {
+        self.specific_resolution_level(move |level_sizes: &[LevelSize]| {
+            level_sizes
+                .iter()
+                .enumerate()
+                .filter(|(_, size)| size.width() >= min_width)
+                .min_by_key(|(_, size)| size.area())
+                .map_or(0, |(index, _)| index)
+        })
+    }
+
+    /// Read only a single resolution level of each layer.
+    /// The closure receives the sizes of all levels of a layer, beginning with
+    /// the largest level, and returns the index of the level to read.
+    /// Layers without mip maps or rip maps contain a single level. The
+    /// levels of rip maps are listed in increasing y order, and each row of
+    /// the rip map contains the levels of decreasing width.
+    ///
+    /// Only reads and decompresses the blocks of the selected level.
+    /// The selected level is presented to the layer readers as the only level
+    /// of the layer, so that `largest_resolution_level()` reads the
+    /// selected level, and the size of each layer is the size of its
+    /// selected level.
+    pub fn specific_resolution_level<S>(
+        self,
+        select_level: S,
+    ) -> ReadImage<F, ReadSpecificLevel<L, S>>
+    where
+        S: Fn(&[Vec2<usize>]) -> usize,
+    {
+        ReadImage {
+            on_progress: self.on_progress,
+            read_layers: ReadSpecificLevel {
+                read_layers: self.read_layers,
+                select_level,
+            },
+            pedantic: self.pedantic,
+            parallel: self.parallel,
+        }
+    }
+}
+
+impl<'s, L, S> ReadLayers<'s> for ReadSpecificLevel<L, S>
+where
+    L: ReadLayers<'s>,
+    S: Fn(&[Vec2<usize>]) -> usize,
+{
+    type Layers = L::Layers;
+    type Reader = SpecificLevelReader<L::Reader>;
+
+    fn create_layers_reader(&'s self, headers: &[Header]) -> Result<Self::Reader> {
+        let mut level_headers = Headers::with_capacity(headers.len());
+        let mut selected_levels = Vec::with_capacity(headers.len());
+
+        for header in headers {
+            let levels = levels_of_header(header);
+            let sizes: Vec<Vec2<usize>> = levels.iter().map(|&(_, size)| size).collect();
+
+            let &(level, size) = levels
+                .get((self.select_level)(&sizes))
+                .ok_or_else(|| Error::invalid("selected resolution level index"))?;
+
+            level_headers.push(level_header(header, size));
+            selected_levels.push(level);
+        }
+
+        Ok(SpecificLevelReader {
+            layers_reader: self.read_layers.create_layers_reader(&level_headers)?,
+            level_headers,
+            selected_levels,
+        })
+    }
+}
+
+impl<L> LayersReader for SpecificLevelReader<L>
+where
+    L: LayersReader,
+{
+    type Layers = L::Layers;
+
+    fn filter_block(&self, meta: &MetaData, tile: TileCoordinates, block: BlockIndex) -> bool {
+        tile.level_index == self.selected_levels[block.layer]
+            && self.layers_reader.filter_block(
+                meta,
+                TileCoordinates {
+                    level_index: Vec2(0, 0),
+                    ..tile
+                },
+                BlockIndex {
+                    level: Vec2(0, 0),
+                    ..block
+                },
+            )
+    }
+
+    fn read_block(&mut self, _: &[Header], mut block: UncompressedBlock) -> UnitResult {
+        block.index.level = Vec2(0, 0);
+        self.layers_reader.read_block(&self.level_headers, block)
+    }
+
+    fn read_deep_block(&mut self, _: &[Header], mut block: UncompressedDeepBlock) -> UnitResult {
+        block.index.level = Vec2(0, 0);
+        self.layers_reader.read_deep_block(&self.level_headers, block)
+    }
+
+    fn into_layers(self) -> Self::Layers {
+        self.layers_reader.into_layers()
+    }
+}
+
+/// The index and the size of each resolution level of the layer, beginning with
+/// the largest level.
+fn levels_of_header(header: &Header) -> Vec<(Vec2<usize>, Vec2<usize>)> {
+    match header.blocks {
+        BlockDescription::Tiles(tiles) => match tiles.level_mode {
+            LevelMode::Singular => vec![(Vec2(0, 0), header.layer_size)],
+
+            LevelMode::MipMap => mip_map_levels(tiles.rounding_mode, header.layer_size)
+                .map(|(index, size)| (Vec2(index, index), size))
+                .collect(),
+
+            LevelMode::RipMap => rip_map_levels(tiles.rounding_mode, header.layer_size).collect(),
+        },
+
+        BlockDescription::ScanLines => vec![(Vec2(0, 0), header.layer_size)],
+    }
+}
+
+/// A header that describes the selected level as the only level of the layer.
+fn level_header(header: &Header, level_size: Vec2<usize>) -> Header {
+    let blocks = match header.blocks {
+        BlockDescription::Tiles(tiles) => BlockDescription::Tiles(TileDescription {
+            level_mode: LevelMode::Singular,
+            ..tiles
+        }),
+        BlockDescription::ScanLines => BlockDescription::ScanLines,
+    };
+
+    Header {
+        layer_size: level_size,
+        chunk_count: compute_chunk_count(header.compression, level_size, blocks),
+        blocks,
+        ..header.clone()
+    }
+}
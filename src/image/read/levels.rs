@@ -1,7 +1,11 @@
 //! How to read a set of resolution levels.
 
 use crate::{
-    block::{chunk::TileCoordinates, lines::LineRef, samples::*},
+    block::{
+        chunk::TileCoordinates,
+        lines::{DeepLineRef, LineRef},
+        samples::*,
+    },
     error::*,
     image::{
         read::{any_channels::*, specific_channels::*},
@@ -266,6 +270,10 @@ impl<S: SamplesReader> SamplesReader for AllLevelsReader<S> {
         self.levels.get_level_mut(line.location.level)?.read_line(line)
     }
 
+    fn read_deep_line(&mut self, line: DeepLineRef<'_>) -> UnitResult {
+        self.levels.get_level_mut(line.location.level)?.read_deep_line(line)
+    }
+
     fn into_samples(self) -> Self::Samples {
         match self.levels {
             Levels::Singular(level) => Levels::Singular(level.into_samples()),
@@ -28,8 +28,11 @@
 //! 1. `read_all_data_from_file(path)`: All layers with arbitrary channels and
 //!    all resolution levels are extracted from the file.
 //!
-//!     Note: Currently does not support deep data, and currently fails
-//!     if any layer in the image contains deep data.
+//!    Note: Does not support deep data, and fails if any layer in the image
+//!    contains deep data.
+//!
+//! 1. `read_all_deep_and_flat_data_from_file(path)`: Like
+//!    `read_all_data_from_file`, but also loads layers containing deep data.
 
 // The following three stages are internally used to read an image.
 // 1. `ReadImage` - The specification. Contains everything the user wants to
@@ -45,8 +48,12 @@
 pub mod any_channels;
 pub mod image;
 pub mod layers;
+pub mod lenient;
+pub mod level;
 pub mod levels;
+pub mod region;
 pub mod samples;
+pub mod session;
 pub mod specific_channels;
 
 use std::path::Path;
@@ -55,9 +62,13 @@ use crate::{
     block::samples::FromNativeSample,
     error::Result,
     image::{
-        read::{image::ReadLayers, layers::ReadChannels, samples::ReadFlatSamples},
-        AnyChannels, AnyImage, FlatImage, FlatSamples, Image, Layer, PixelLayersImage,
-        RgbaChannels,
+        read::{
+            image::ReadLayers,
+            layers::ReadChannels,
+            samples::{ReadDeepAndFlatSamples, ReadFlatSamples},
+        },
+        AnyChannels, AnyImage, DeepAndFlatImage, FlatImage, FlatSamples, Image, Layer, Layers,
+        PixelLayersImage, RgbaChannels,
     },
     math::Vec2,
     prelude::PixelImage,
@@ -65,12 +76,29 @@ use crate::{
 
 /// All resolution levels, all channels, all layers.
 ///
-/// Does not support deep data yet. Uses parallel decompression and relaxed
-/// error handling. Inspect the source code of this function if you need
-/// customization.
+/// Does not support deep data, see `read_all_deep_and_flat_data_from_file`.
+/// Uses parallel decompression and relaxed error handling.
+/// Inspect the source code of this function if you need customization.
 pub fn read_all_data_from_file(path: impl AsRef<Path>) -> Result<AnyImage> {
     read()
-        .no_deep_data() // TODO deep data
+        .no_deep_data()
+        .all_resolution_levels()
+        .all_channels()
+        .all_layers()
+        .all_attributes()
+        .from_file(path)
+}
+
+/// Deep and flat data, all resolution levels, all channels, all layers.
+///
+/// Uses relaxed error handling. Deep layers are decompressed in a single
+/// thread. Inspect the source code of this function if you need customization.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or contains invalid data.
+pub fn read_all_deep_and_flat_data_from_file(path: impl AsRef<Path>) -> Result<DeepAndFlatImage> {
+    read()
+        .flat_and_deep_data()
         .all_resolution_levels()
         .all_channels()
         .all_layers()
@@ -78,6 +106,34 @@ pub fn read_all_data_from_file(path: impl AsRef<Path>) -> Result<AnyImage> {
         .from_file(path)
 }
 
+/// Deep and flat data, no resolution levels, all channels, all layers.
+/// Composites the samples of each deep layer into flat samples,
+/// see `Layer::composite_deep_samples`.
+///
+/// Uses relaxed error handling. Deep layers are decompressed in a single
+/// thread. Inspect the source code of this function if you need customization.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or contains invalid data,
+/// or if a deep layer cannot be composited.
+pub fn read_all_composited_layers_from_file(path: impl AsRef<Path>) -> Result<FlatImage> {
+    let image = read()
+        .flat_and_deep_data()
+        .largest_resolution_level()
+        .all_channels()
+        .all_layers()
+        .all_attributes()
+        .from_file(path)?;
+
+    let layers = image
+        .layer_data
+        .iter()
+        .map(|layer| layer.composite_deep_samples())
+        .collect::<Result<Layers<_>>>()?;
+
+    Ok(Image::new(image.attributes, layers))
+}
+
 // FIXME do not throw error on deep data but just skip it!
 /// No deep data, no resolution levels, all channels, all layers.
 /// Uses parallel decompression and relaxed error handling.
@@ -196,7 +252,7 @@ pub struct ReadBuilder;
 ///
 /// // the type of the this image depends on the chosen options
 /// let image = read()
-///     .no_deep_data() // (currently required)
+///     .no_deep_data() // or `flat_and_deep_data()`
 ///     .largest_resolution_level() // or `all_resolution_levels()`
 ///     .all_channels() // or `rgba_channels(constructor, setter)`
 ///     .all_layers() // or `first_valid_layer()`
@@ -211,6 +267,7 @@ pub struct ReadBuilder;
 /// 1. `read_all_rgba_layers_from_file`
 /// 1. `read_all_flat_layers_from_file`
 /// 1. `read_all_data_from_file`
+/// 1. `read_all_deep_and_flat_data_from_file`
 // TODO not panic but skip deep layers!
 pub fn read() -> ReadBuilder {
     ReadBuilder
@@ -232,5 +289,11 @@ impl ReadBuilder {
     // f32_samples[index] = sample as f32)` pub fn no_deep_data_with <S> (self,
     // storage: S) -> FlatSamplesWith<S> {  }
 
-    // pub fn flat_and_deep_data(self) -> ReadAnySamples { ReadAnySamples }
+    /// Specify to handle a variable number of samples per pixel in deep
+    /// layers, and one sample per pixel in all other layers.
+    /// Only supports reading all channels.
+    #[must_use]
+    pub const fn flat_and_deep_data(self) -> ReadDeepAndFlatSamples {
+        ReadDeepAndFlatSamples
+    }
 }
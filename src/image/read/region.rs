@@ -0,0 +1,242 @@
+//! Read only a rectangular section of each layer, for example the visible part
+//! of an image in a viewer.
+//!
+//! Call `within_bounds(bounds)` or `within_bounds_of_layers(bounds_of_layer)`
+//! on the result of `all_attributes()`. Only the blocks that intersect the
+//! bounds are read and decompressed, and each layer is cropped to the bounds.
+
+use crate::{
+    block::{
+        chunk::TileCoordinates, lines::LineIndex, BlockIndex, UncompressedBlock,
+        UncompressedDeepBlock,
+    },
+    error::{Error, Result, UnitResult},
+    image::read::image::{LayersReader, ReadImage, ReadLayers},
+    math::Vec2,
+    meta::{
+        attribute::{IntegerBounds, LevelMode, TileDescription},
+        compute_chunk_count,
+        header::Header,
+        BlockDescription, Headers, MetaData,
+    },
+};
+
+/// Specify to read only the pixels within a rectangle of each layer.
+/// Created with `ReadImage::within_bounds` or
+/// `ReadImage::within_bounds_of_layers`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReadWithinBounds<ReadLayers, BoundsOfLayer> {
+    /// The layers reading specification.
+    pub read_layers: ReadLayers,
+
+    /// The bounds of each layer in absolute pixel coordinates, like the data
+    /// window. Returns `None` to read the whole layer.
+    pub bounds_of_layer: BoundsOfLayer,
+}
+
+/// Processes pixel blocks from a file, cropping them to the bounds of each
+/// layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithinBoundsReader<LayersReader> {
+    layers_reader: LayersReader,
+
+    /// The headers as seen by the layers reader, with the size of the cropped
+    /// layers.
+    cropped_headers: Headers,
+
+    /// For each layer, the section of the layer that is read, relative to the
+    /// layer position. Contains `None` where the whole layer is read.
+    crop_of_layer: Vec<Option<IntegerBounds>>,
+}
+
+impl<F, L> ReadImage<F, L>
+where
+    F: FnMut(f64),
+{
+    /// Read only the pixels of each layer within the specified rectangle,
+    /// in absolute pixel coordinates, like the data window.
+    /// See `within_bounds_of_layers`.
+    pub fn within_bounds(
+        self,
+        bounds: IntegerBounds,
+    ) -> ReadImage<F, ReadWithinBounds<L, impl Fn(&Header) -> Option<IntegerBounds>>> {
+        self.within_bounds_of_layers(move |_| Some(bounds))
+    }
+
+    /// Read only the pixels of each layer within the rectangle that the closure
+    /// returns for that layer, in absolute pixel coordinates, like the data
+    /// window. Where the closure returns `None`, the whole layer is read.
+    ///
+    /// Only reads and decompresses the scan line blocks or tiles that intersect
+    /// the bounds. Each cropped layer contains only the intersection of the
+    /// bounds and its data window. Its size and `layer_position` attribute
+    /// describe this intersection, and it can be empty. Cropped layers
+    /// contain only the largest resolution level. Deep layers are not cropped,
+    /// but read completely.
+    pub fn within_bounds_of_layers<B>(
+        self,
+        bounds_of_layer: B,
+    ) -> ReadImage<F, ReadWithinBounds<L, B>>
+    where
+        B: Fn(&Header) -> Option<IntegerBounds>,
+    {
+        ReadImage {
+            on_progress: self.on_progress,
+            read_layers: ReadWithinBounds {
+                read_layers: self.read_layers,
+                bounds_of_layer,
+            },
+            pedantic: self.pedantic,
+            parallel: self.parallel,
+        }
+    }
+}
+
+impl<'s, L, B> ReadLayers<'s> for ReadWithinBounds<L, B>
+where
+    L: ReadLayers<'s>,
+    B: Fn(&Header) -> Option<IntegerBounds>,
+{
+    type Layers = L::Layers;
+    type Reader = WithinBoundsReader<L::Reader>;
+
+    fn create_layers_reader(&'s self, headers: &[Header]) -> Result<Self::Reader> {
+        let crop_of_layer: Vec<Option<IntegerBounds>> = headers
+            .iter()
+            .map(|header| {
+                // deep layers are read completely
+                if header.deep {
+                    return None;
+                }
+
+                (self.bounds_of_layer)(header).map(|bounds| {
+                    let layer_bounds = IntegerBounds::from_dimensions(header.layer_size);
+                    let relative_bounds = bounds.with_origin(-header.own_attributes.layer_position);
+
+                    layer_bounds.intersection(relative_bounds).unwrap_or_else(|| {
+                        let start = relative_bounds.position.max(layer_bounds.position);
+                        IntegerBounds::new(start, (0, 0))
+                    })
+                })
+            })
+            .collect();
+
+        let cropped_headers: Headers = headers
+            .iter()
+            .zip(&crop_of_layer)
+            .map(|(header, crop)| {
+                crop.map_or_else(|| header.clone(), |crop| crop_header(header, crop))
+            })
+            .collect();
+
+        Ok(WithinBoundsReader {
+            layers_reader: self.read_layers.create_layers_reader(&cropped_headers)?,
+            cropped_headers,
+            crop_of_layer,
+        })
+    }
+}
+
+impl<L> LayersReader for WithinBoundsReader<L>
+where
+    L: LayersReader,
+{
+    type Layers = L::Layers;
+
+    fn filter_block(&self, meta: &MetaData, tile: TileCoordinates, block: BlockIndex) -> bool {
+        let intersects = match self.crop_of_layer[block.layer] {
+            None => true,
+            Some(crop) => {
+                tile.is_largest_resolution_level()
+                    && crop.intersection(block_bounds(block)).is_some()
+            }
+        };
+
+        intersects && self.layers_reader.filter_block(meta, tile, block)
+    }
+
+    fn read_block(&mut self, headers: &[Header], block: UncompressedBlock) -> UnitResult {
+        let block = match self.crop_of_layer[block.index.layer] {
+            None => block,
+            Some(crop) => crop_block(&headers[block.index.layer], block, crop)?,
+        };
+
+        self.layers_reader.read_block(&self.cropped_headers, block)
+    }
+
+    fn read_deep_block(&mut self, _: &[Header], block: UncompressedDeepBlock) -> UnitResult {
+        // deep layers are never cropped
+        self.layers_reader.read_deep_block(&self.cropped_headers, block)
+    }
+
+    fn into_layers(self) -> Self::Layers {
+        self.layers_reader.into_layers()
+    }
+}
+
+/// A header that describes the cropped section of the layer.
+/// Contains only the largest resolution level.
+fn crop_header(header: &Header, crop: IntegerBounds) -> Header {
+    let blocks = match header.blocks {
+        BlockDescription::Tiles(tiles) => BlockDescription::Tiles(TileDescription {
+            level_mode: LevelMode::Singular,
+            ..tiles
+        }),
+        BlockDescription::ScanLines => BlockDescription::ScanLines,
+    };
+
+    let mut cropped = Header {
+        layer_size: crop.size,
+        chunk_count: compute_chunk_count(header.compression, crop.size, blocks),
+        blocks,
+        ..header.clone()
+    };
+
+    cropped.own_attributes.layer_position = header.own_attributes.layer_position + crop.position;
+    cropped
+}
+
+/// The pixel section of the block, relative to the layer position.
+fn block_bounds(block: BlockIndex) -> IntegerBounds {
+    IntegerBounds::new(block.pixel_position.to_i32(), block.pixel_size)
+}
+
+/// Copy the pixels of the block that are inside the crop.
+/// The resulting block is positioned relative to the crop.
+fn crop_block(
+    header: &Header,
+    block: UncompressedBlock,
+    crop: IntegerBounds,
+) -> Result<UncompressedBlock> {
+    let section = crop
+        .intersection(block_bounds(block.index))
+        .ok_or_else(|| Error::invalid("block outside of the bounds"))?;
+    if section == block_bounds(block.index) && crop.position == Vec2(0, 0) {
+        return Ok(block); // the block is completely inside the crop, which
+                          // starts at the layer origin
+    }
+
+    let section_start = section.position.to_usize("block section")?;
+    let section_end = section_start + section.size;
+    let mut data = Vec::with_capacity(section.size.area() * header.channels.bytes_per_pixel);
+
+    for (byte_range, line) in LineIndex::lines_in_block(block.index, &header.channels) {
+        if line.position.y() < section_start.y() || line.position.y() >= section_end.y() {
+            continue;
+        }
+
+        let sample_size = header.channels.list[line.channel].sample_type.bytes_per_sample();
+        let start = byte_range.start + (section_start.x() - line.position.x()) * sample_size;
+        let end = byte_range.start + (section_end.x() - line.position.x()) * sample_size;
+        data.extend_from_slice(&block.data[start..end]);
+    }
+
+    Ok(UncompressedBlock {
+        index: BlockIndex {
+            pixel_position: (section.position - crop.position).to_usize("block section")?,
+            pixel_size: section.size,
+            ..block.index
+        },
+        data,
+    })
+}
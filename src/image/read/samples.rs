@@ -1,8 +1,13 @@
 //! How to read samples (a grid of `f32`, `f16` or `u32` values).
 
+use std::collections::BTreeMap;
+
 use crate::{
-    block::{chunk::TileCoordinates, lines::LineRef},
-    error::{Result, UnitResult},
+    block::{
+        chunk::TileCoordinates,
+        lines::{DeepLineRef, LineRef},
+    },
+    error::{Error, Result, UnitResult},
     image::{
         read::{
             any_channels::{ReadSamples, SamplesReader},
@@ -22,7 +27,11 @@ use crate::{
 // FIXME do not throw error on deep data but just skip it!
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct ReadFlatSamples;
-// pub struct ReadAnySamples;
+
+/// Specify to read flat samples from flat layers and deep samples from deep
+/// layers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReadDeepAndFlatSamples;
 
 impl ReadFlatSamples {
     // TODO
@@ -44,15 +53,26 @@ impl ReadFlatSamples {
         }
     }
 
-    // TODO pub fn specific_resolution_level<F: Fn(&[Vec2<usize>])->usize >(self,
-    // select_level: F) -> ReadLevelBy<Self> { ReadAllLevels { read_samples: self }
-    // }
+    // to read a single smaller resolution level of each layer,
+    // use `ReadImage::specific_resolution_level` after `all_attributes()`
 }
 
-// pub struct AnySamplesReader { TODO
-// resolution: Vec2<usize>,
-// samples: DeepAndFlatSamples
-// }
+impl ReadDeepAndFlatSamples {
+    /// Specify to read only the highest resolution level, skipping all smaller
+    /// variations.
+    pub fn largest_resolution_level(self) -> ReadLargestLevel<Self> {
+        ReadLargestLevel {
+            read_samples: self,
+        }
+    }
+
+    /// Specify to read all contained resolution levels from the image, if any.
+    pub fn all_resolution_levels(self) -> ReadAllLevels<Self> {
+        ReadAllLevels {
+            read_samples: self,
+        }
+    }
+}
 
 /// Processes pixel blocks from a file and accumulates them into a grid of
 /// samples, for example "Red" or "Alpha".
@@ -81,11 +101,15 @@ impl ReadSamplesLevel for ReadFlatSamples {
 
     fn create_samples_level_reader(
         &self,
-        _header: &Header,
+        header: &Header,
         channel: &ChannelDescription,
         level: Vec2<usize>,
         resolution: Vec2<usize>,
     ) -> Result<Self::Reader> {
+        if header.deep {
+            return Err(Error::unsupported("deep data without `flat_and_deep_data()`"));
+        }
+
         Ok(FlatSamplesReader {
             level,
             resolution, // TODO sampling
@@ -149,3 +173,168 @@ impl SamplesReader for FlatSamplesReader {
         self.samples
     }
 }
+
+/// Processes pixel blocks from a file and accumulates them into either
+/// flat or deep samples, depending on the layer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeepAndFlatSamplesReader {
+    /// Reads the samples of a flat layer.
+    Flat(FlatSamplesReader),
+
+    /// Reads the samples of a deep layer.
+    Deep(DeepSamplesReader),
+}
+
+/// Processes deep pixel blocks from a file and accumulates them into a
+/// variable number of samples per pixel, for example "Z" or "Alpha".
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepSamplesReader {
+    level: Vec2<usize>,
+    resolution: Vec2<usize>,
+    sample_type: SampleType,
+
+    /// The number of samples of each pixel in this level.
+    sample_counts: Vec<u32>,
+
+    /// The samples of each line that has been read so far,
+    /// by the flat index of the first pixel in the line.
+    /// Sorted, as the blocks can appear in any order in the file.
+    lines: BTreeMap<usize, FlatSamples>,
+}
+
+impl ReadSamples for ReadDeepAndFlatSamples {
+    type Reader = DeepAndFlatSamplesReader;
+
+    fn create_sample_reader(
+        &self,
+        header: &Header,
+        channel: &ChannelDescription,
+    ) -> Result<Self::Reader> {
+        self.create_samples_level_reader(header, channel, Vec2(0, 0), header.layer_size)
+    }
+}
+
+impl ReadSamplesLevel for ReadDeepAndFlatSamples {
+    type Reader = DeepAndFlatSamplesReader;
+
+    fn create_samples_level_reader(
+        &self,
+        header: &Header,
+        channel: &ChannelDescription,
+        level: Vec2<usize>,
+        resolution: Vec2<usize>,
+    ) -> Result<Self::Reader> {
+        if header.deep {
+            Ok(DeepAndFlatSamplesReader::Deep(DeepSamplesReader {
+                level,
+                resolution,
+                sample_type: channel.sample_type,
+                sample_counts: vec![0; resolution.area()],
+                lines: BTreeMap::new(),
+            }))
+        } else {
+            ReadFlatSamples
+                .create_samples_level_reader(header, channel, level, resolution)
+                .map(DeepAndFlatSamplesReader::Flat)
+        }
+    }
+}
+
+impl SamplesReader for DeepAndFlatSamplesReader {
+    type Samples = DeepAndFlatSamples;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool {
+        match self {
+            Self::Flat(reader) => reader.filter_block(tile),
+            Self::Deep(reader) => reader.filter_block(tile),
+        }
+    }
+
+    fn read_line(&mut self, line: LineRef<'_>) -> UnitResult {
+        match self {
+            Self::Flat(reader) => reader.read_line(line),
+            Self::Deep(_) => Err(Error::invalid("flat block in deep layer")),
+        }
+    }
+
+    fn read_deep_line(&mut self, line: DeepLineRef<'_>) -> UnitResult {
+        match self {
+            Self::Deep(reader) => reader.read_deep_line(line),
+            Self::Flat(_) => Err(Error::invalid("deep block in flat layer")),
+        }
+    }
+
+    fn into_samples(self) -> DeepAndFlatSamples {
+        match self {
+            Self::Flat(reader) => DeepAndFlatSamples::Flat(reader.into_samples()),
+            Self::Deep(reader) => DeepAndFlatSamples::Deep(reader.into_samples()),
+        }
+    }
+}
+
+impl SamplesReader for DeepSamplesReader {
+    type Samples = DeepSamples;
+
+    fn filter_block(&self, tile: TileCoordinates) -> bool {
+        tile.level_index == self.level
+    }
+
+    fn read_line(&mut self, _: LineRef<'_>) -> UnitResult {
+        Err(Error::invalid("flat block in deep layer"))
+    }
+
+    fn read_deep_line(&mut self, line: DeepLineRef<'_>) -> UnitResult {
+        let index = line.location;
+        let resolution = self.resolution;
+
+        // the index is generated by ourselves and must always be correct
+        debug_assert_eq!(index.level, self.level, "line should have been filtered");
+        debug_assert!(
+            index.position.x() + index.sample_count <= resolution.width(),
+            "line index calculation bug"
+        );
+        debug_assert!(index.position.y() < resolution.height(), "line index calculation bug");
+
+        let start_index = index.position.y() * resolution.width() + index.position.x();
+        let sample_counts = &mut self.sample_counts[start_index..start_index + index.sample_count];
+
+        let mut previous_offset = 0;
+        for (count, &offset) in sample_counts.iter_mut().zip(line.pixel_offset_table) {
+            *count = offset - previous_offset;
+            previous_offset = offset;
+        }
+
+        let samples = match self.sample_type {
+            SampleType::F16 => FlatSamples::F16(line.read_samples().collect::<Result<_>>()?),
+            SampleType::F32 => FlatSamples::F32(line.read_samples().collect::<Result<_>>()?),
+            SampleType::U32 => FlatSamples::U32(line.read_samples().collect::<Result<_>>()?),
+        };
+
+        self.lines.insert(start_index, samples);
+        Ok(())
+    }
+
+    fn into_samples(self) -> DeepSamples {
+        let total_sample_count = self.sample_counts.iter().map(|&count| count as usize).sum();
+        let mut samples = match self.sample_type {
+            SampleType::F16 => FlatSamples::F16(Vec::with_capacity(total_sample_count)),
+            SampleType::F32 => FlatSamples::F32(Vec::with_capacity(total_sample_count)),
+            SampleType::U32 => FlatSamples::U32(Vec::with_capacity(total_sample_count)),
+        };
+
+        for line_samples in self.lines.into_values() {
+            match (&mut samples, line_samples) {
+                (FlatSamples::F16(all), FlatSamples::F16(line)) => all.extend(line),
+                (FlatSamples::F32(all), FlatSamples::F32(line)) => all.extend(line),
+                (FlatSamples::U32(all), FlatSamples::U32(line)) => all.extend(line),
+                _ => unreachable!("deep line sample type bug"),
+            }
+        }
+
+        DeepSamples::from_sample_counts(
+            self.sample_counts.into_iter().map(|count| count as usize),
+            samples,
+        )
+        .expect("deep sample count bug")
+    }
+}
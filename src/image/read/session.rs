@@ -0,0 +1,501 @@
+//! Read a sequence of images with the same layout, such as the frames of a
+//! video, into the same image.
+//!
+//! Create a session with `read()...all_attributes().session()`.
+//! Read the first frame with `ReadSession::from_file`, and every following
+//! frame with `ReadSession::read_file_into`. This overwrites the samples of
+//! the existing image instead of allocating new sample vectors, and keeps
+//! the thread pool and the decompression buffers alive between frames.
+
+use std::{
+    io::{BufReader, Read, Seek},
+    path::Path,
+};
+
+use crate::{
+    block::{
+        chunk::TileCoordinates,
+        lines::{LineIndex, LineSlice},
+        reader::ChunksReader,
+        BlockIndex, UncompressedBlock,
+    },
+    compression::{ByteVec, DecompressionScratch},
+    error::{Error, Result, UnitResult},
+    image::{
+        read::image::{ReadImage, ReadLayers},
+        write::samples::WritableSamples,
+        AnyChannels, Encoding, FlatSamples, Image, Layer, Layers,
+    },
+    math::Vec2,
+    meta::header::Header,
+};
+
+/// Reads a sequence of images with the same layout, reusing the memory of the
+/// previous image. Created with `ReadImage::session`.
+#[derive(Debug)]
+pub struct ReadSession<OnProgress, ReadLayers> {
+    read_image: ReadImage<OnProgress, ReadLayers>,
+
+    /// Buffers that are currently not used by any decompression job.
+    buffers: Vec<BlockBuffers>,
+
+    /// Created when the first compressed image is read in parallel.
+    #[cfg(feature = "rayon")]
+    thread_pool: Option<rayon_core::ThreadPool>,
+}
+
+/// The memory required to decompress a single block.
+#[derive(Debug, Default)]
+struct BlockBuffers {
+    scratch: DecompressionScratch,
+    pixels: ByteVec,
+}
+
+/// Layers whose samples can be overwritten by the samples of another file with
+/// the same layout. Implemented for the flat layers with arbitrary channels
+/// that are loaded by
+/// `read().no_deep_data().largest_resolution_level().all_channels()`.
+pub trait ReadLayersInto {
+    /// Check that the headers of a file have the same layout as these layers,
+    /// then replace the attributes and the encoding of these layers.
+    ///
+    /// # Errors
+    /// Returns an error and changes nothing if the layouts differ.
+    fn update_from_headers(&mut self, headers: &[Header]) -> UnitResult;
+
+    /// Whether the blocks of the specified layer are loaded into these layers.
+    fn contains_layer(&self, headers: &[Header], layer_index: usize) -> bool;
+
+    /// Overwrite the samples of these layers with a decompressed block.
+    ///
+    /// # Errors
+    /// Returns an error if the bytes do not match the samples of these layers.
+    fn read_block_bytes(
+        &mut self,
+        headers: &[Header],
+        index: BlockIndex,
+        pixels_ne: &[u8],
+    ) -> UnitResult;
+}
+
+impl<F, L> ReadImage<F, L>
+where
+    F: FnMut(f64),
+{
+    /// Create a session that reads a sequence of images with the same layout,
+    /// for example the frames of a video. See `ReadSession`.
+    pub fn session(self) -> ReadSession<F, L> {
+        ReadSession {
+            read_image: self,
+            buffers: Vec::new(),
+
+            #[cfg(feature = "rayon")]
+            thread_pool: None,
+        }
+    }
+}
+
+impl<F, L> ReadSession<F, L>
+where
+    F: FnMut(f64),
+{
+    /// Read the first image of the sequence from a file.
+    /// Use `read_file_into` for all following images.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or contains invalid data.
+    pub fn from_file<Layers>(&mut self, path: impl AsRef<Path>) -> Result<Image<Layers>>
+    where
+        for<'s> L: ReadLayers<'s, Layers = Layers>,
+    {
+        self.from_buffered(BufReader::new(std::fs::File::open(path)?))
+    }
+
+    /// Read the first image of the sequence from a buffered reader.
+    /// Use `read_into` for all following images.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or contains invalid data.
+    pub fn from_buffered<Layers>(&mut self, buffered: impl Read + Seek) -> Result<Image<Layers>>
+    where
+        for<'s> L: ReadLayers<'s, Layers = Layers>,
+    {
+        let chunks = crate::block::read(buffered, self.read_image.pedantic)?;
+        self.read_image.read_chunks(chunks)
+    }
+
+    /// Read the next image of the sequence from a file into an existing image,
+    /// overwriting its samples and attributes.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or contains invalid data,
+    /// or if it does not have the same layout as the image.
+    pub fn read_file_into<Layers>(
+        &mut self,
+        image: &mut Image<Layers>,
+        path: impl AsRef<Path>,
+    ) -> UnitResult
+    where
+        Layers: ReadLayersInto,
+    {
+        self.read_into(image, BufReader::new(std::fs::File::open(path)?))
+    }
+
+    /// Read the next image of the sequence from a buffered reader into an
+    /// existing image, overwriting its samples and attributes.
+    /// The file must have the same layout as the image,
+    /// which means the same layer sizes, channel names and sample types.
+    /// The compression and the attributes may differ.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or contains invalid data,
+    /// or if it does not have the same layout as the image.
+    /// If decompressing a block fails, the image contains samples of both
+    /// files.
+    pub fn read_into<Layers>(
+        &mut self,
+        image: &mut Image<Layers>,
+        buffered: impl Read + Seek,
+    ) -> UnitResult
+    where
+        Layers: ReadLayersInto,
+    {
+        let pedantic = self.read_image.pedantic;
+        let chunks = crate::block::read(buffered, pedantic)?;
+
+        image.layer_data.update_from_headers(chunks.headers())?;
+        image.attributes = chunks.headers()[0].shared_attributes.clone();
+
+        let layers = &mut image.layer_data;
+        let chunks = chunks
+            .filter_chunks(pedantic, |meta, tile, block| {
+                is_largest_level(tile) && layers.contains_layer(&meta.headers, block.layer)
+            })?
+            .on_progress(&mut self.read_image.on_progress);
+
+        #[cfg(feature = "rayon")]
+        {
+            use crate::compression::Compression;
+
+            let is_entirely_uncompressed = chunks
+                .headers()
+                .iter()
+                .all(|header| header.compression == Compression::Uncompressed);
+
+            if self.read_image.parallel && !is_entirely_uncompressed {
+                if self.thread_pool.is_none() {
+                    // in case thread pool creation fails (for example on WASM currently),
+                    // we revert to sequential decompression
+                    self.thread_pool = rayon_core::ThreadPoolBuilder::new()
+                        .thread_name(|index| format!("OpenEXR Block Decompressor Thread #{index}"))
+                        .build()
+                        .ok();
+                }
+
+                if let Some(pool) = &self.thread_pool {
+                    return decompress_parallel(chunks, pool, &mut self.buffers, pedantic, layers);
+                }
+            }
+        }
+
+        decompress_sequential(chunks, &mut self.buffers, pedantic, layers)
+    }
+}
+
+fn is_largest_level(tile: TileCoordinates) -> bool {
+    tile.level_index == Vec2(0, 0)
+}
+
+/// Decompress all chunks in this thread, using the same buffers for all blocks.
+fn decompress_sequential(
+    mut chunks: impl ChunksReader,
+    buffers: &mut Vec<BlockBuffers>,
+    pedantic: bool,
+    layers: &mut impl ReadLayersInto,
+) -> UnitResult {
+    if buffers.is_empty() {
+        buffers.push(BlockBuffers::default());
+    }
+
+    let buffers = &mut buffers[0];
+
+    while let Some(chunk) = chunks.next() {
+        let chunk = chunk?;
+        let meta_data = chunks.meta_data();
+
+        let index = UncompressedBlock::decompress_chunk_into(
+            &chunk,
+            meta_data,
+            pedantic,
+            &mut buffers.scratch,
+            &mut buffers.pixels,
+        )?;
+
+        layers.read_block_bytes(&meta_data.headers, index, &buffers.pixels)?;
+    }
+
+    Ok(())
+}
+
+/// Decompress the chunks in the thread pool. Each job takes buffers from the
+/// pool of unused buffers, which are returned after the block has been read.
+#[cfg(feature = "rayon")]
+fn decompress_parallel(
+    mut chunks: impl ChunksReader,
+    pool: &rayon_core::ThreadPool,
+    buffers: &mut Vec<BlockBuffers>,
+    pedantic: bool,
+    layers: &mut impl ReadLayersInto,
+) -> UnitResult {
+    let meta_data = std::sync::Arc::new(chunks.meta_data().clone());
+    let max_jobs = pool.current_num_threads().max(1) + 2; // ca one block for each thread at all times
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut currently_decompressing_count = 0;
+
+    loop {
+        while currently_decompressing_count < max_jobs {
+            let chunk = match chunks.next() {
+                Some(chunk) => chunk?,
+                None => break,
+            };
+
+            let mut block_buffers = buffers.pop().unwrap_or_default();
+            let sender = sender.clone();
+            let meta_data = meta_data.clone();
+            currently_decompressing_count += 1;
+
+            pool.spawn(move || {
+                let index = UncompressedBlock::decompress_chunk_into(
+                    &chunk,
+                    &meta_data,
+                    pedantic,
+                    &mut block_buffers.scratch,
+                    &mut block_buffers.pixels,
+                );
+
+                // by now, decompressing could have failed in another thread.
+                // the error is then already handled, so we simply
+                // don't send the decompressed block and do nothing
+                let _ = sender.send(index.map(|index| (index, block_buffers)));
+            });
+        }
+
+        if currently_decompressing_count == 0 {
+            return Ok(());
+        }
+
+        let (index, block_buffers) = receiver
+            .recv()
+            .expect("all decompressing senders hung up but more messages were expected")?;
+
+        currently_decompressing_count -= 1;
+        layers.read_block_bytes(&meta_data.headers, index, &block_buffers.pixels)?;
+        buffers.push(block_buffers);
+    }
+}
+
+impl ReadLayersInto for Layers<AnyChannels<FlatSamples>> {
+    fn update_from_headers(&mut self, headers: &[Header]) -> UnitResult {
+        if headers.len() != self.len() {
+            return Err(Error::invalid("layer count differs from the previous image"));
+        }
+
+        for (layer, header) in self.iter().zip(headers) {
+            validate_layout(layer, header)?;
+        }
+
+        for (layer, header) in self.iter_mut().zip(headers) {
+            update_attributes(layer, header);
+        }
+
+        Ok(())
+    }
+
+    fn contains_layer(&self, _: &[Header], layer_index: usize) -> bool {
+        layer_index < self.len()
+    }
+
+    fn read_block_bytes(
+        &mut self,
+        headers: &[Header],
+        index: BlockIndex,
+        pixels_ne: &[u8],
+    ) -> UnitResult {
+        read_block_bytes(&mut self[index.layer], &headers[index.layer], index, pixels_ne)
+    }
+}
+
+impl ReadLayersInto for Layer<AnyChannels<FlatSamples>> {
+    fn update_from_headers(&mut self, headers: &[Header]) -> UnitResult {
+        let header = first_flat_header(headers)
+            .map(|index| &headers[index])
+            .ok_or_else(|| Error::invalid("no flat layer in the image"))?;
+
+        validate_layout(self, header)?;
+        update_attributes(self, header);
+        Ok(())
+    }
+
+    fn contains_layer(&self, headers: &[Header], layer_index: usize) -> bool {
+        first_flat_header(headers) == Some(layer_index)
+    }
+
+    fn read_block_bytes(
+        &mut self,
+        headers: &[Header],
+        index: BlockIndex,
+        pixels_ne: &[u8],
+    ) -> UnitResult {
+        read_block_bytes(self, &headers[index.layer], index, pixels_ne)
+    }
+}
+
+/// The layer that `read().no_deep_data().first_valid_layer()` loads.
+fn first_flat_header(headers: &[Header]) -> Option<usize> {
+    headers.iter().position(|header| !header.deep)
+}
+
+fn validate_layout(layer: &Layer<AnyChannels<FlatSamples>>, header: &Header) -> UnitResult {
+    let channels = &layer.channel_data.list;
+    let sample_count = header.layer_size.area();
+
+    let same_layout = !header.deep
+        && layer.size == header.layer_size
+        && channels.len() == header.channels.list.len()
+        && channels.iter().zip(&header.channels.list).all(|(channel, description)| {
+            channel.name == description.name
+                && channel.sampling == description.sampling
+                && channel.sample_data.sample_type() == description.sample_type
+                && channel.sample_data.len() == sample_count
+        });
+
+    if same_layout {
+        Ok(())
+    } else {
+        Err(Error::invalid("layer layout differs from the previous image"))
+    }
+}
+
+fn update_attributes(layer: &mut Layer<AnyChannels<FlatSamples>>, header: &Header) {
+    layer.attributes = header.own_attributes.clone();
+    layer.encoding = Encoding::from_header(header);
+
+    for (channel, description) in layer.channel_data.list.iter_mut().zip(&header.channels.list) {
+        channel.quantize_linearly = description.quantize_linearly;
+    }
+}
+
+fn read_block_bytes(
+    layer: &mut Layer<AnyChannels<FlatSamples>>,
+    header: &Header,
+    index: BlockIndex,
+    pixels_ne: &[u8],
+) -> UnitResult {
+    let width = layer.size.width();
+
+    for (byte_range, line_index) in LineIndex::lines_in_block(index, &header.channels) {
+        let line = LineSlice {
+            location: line_index,
+            value: &pixels_ne[byte_range],
+        };
+
+        let start = line_index.position.y() * width + line_index.position.x();
+        let samples = start..start + line_index.sample_count;
+
+        match &mut layer.channel_data.list[line_index.channel].sample_data {
+            FlatSamples::F16(values) => line.read_samples_into_slice(&mut values[samples])?,
+            FlatSamples::F32(values) => line.read_samples_into_slice(&mut values[samples])?,
+            FlatSamples::U32(values) => line.read_samples_into_slice(&mut values[samples])?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        image::{
+            read::{layers::ReadChannels, read},
+            write::WritableImage,
+            SpecificChannels,
+        },
+        prelude::f16,
+    };
+
+    fn write_frame(frame: usize, size: Vec2<usize>, encoding: Encoding) -> Vec<u8> {
+        let image = Image::from_encoded_channels(
+            size,
+            encoding,
+            SpecificChannels::rgba(|Vec2(x, y): Vec2<usize>| {
+                let value = ((x + frame) as f32 * 0.1).sin() + (y * frame) as f32 * 0.01;
+                (f16::from_f32(value), f16::from_f32(-value), f16::from_f32(0.5), f16::ONE)
+            }),
+        );
+
+        let mut bytes = Vec::new();
+        image.write().non_parallel().to_buffered(Cursor::new(&mut bytes)).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn read_into_matches_reading_new_image() {
+        for parallel in [false, true] {
+            let reader = read().no_deep_data().largest_resolution_level().all_channels();
+            let reader = reader.all_layers().all_attributes();
+            let reader = if parallel {
+                reader
+            } else {
+                reader.non_parallel()
+            };
+            let mut session = reader.session();
+
+            let first = write_frame(0, Vec2(67, 45), Encoding::SMALL_FAST_LOSSLESS);
+            let mut image = session.from_buffered(Cursor::new(&first)).unwrap();
+
+            let encodings =
+                [Encoding::FAST_LOSSLESS, Encoding::SMALL_LOSSLESS, Encoding::UNCOMPRESSED];
+            for (frame, encoding) in encodings.iter().enumerate() {
+                let bytes = write_frame(frame + 1, Vec2(67, 45), *encoding);
+                session.read_into(&mut image, Cursor::new(&bytes)).unwrap();
+
+                let expected = read()
+                    .no_deep_data()
+                    .largest_resolution_level()
+                    .all_channels()
+                    .all_layers()
+                    .all_attributes()
+                    .from_buffered(Cursor::new(&bytes))
+                    .unwrap();
+
+                assert_eq!(image, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn read_into_rejects_different_layout() {
+        let mut session = read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+            .session();
+
+        let first = write_frame(0, Vec2(20, 30), Encoding::FAST_LOSSLESS);
+        let mut image = session.from_buffered(Cursor::new(&first)).unwrap();
+        let previous = image.clone();
+
+        let resized = write_frame(1, Vec2(21, 30), Encoding::FAST_LOSSLESS);
+        assert!(session.read_into(&mut image, Cursor::new(&resized)).is_err());
+        assert_eq!(image, previous, "image changed despite the error");
+
+        let next = write_frame(1, Vec2(20, 30), Encoding::FAST_LOSSLESS);
+        session.read_into(&mut image, Cursor::new(&next)).unwrap();
+        assert_ne!(image, previous);
+    }
+}
@@ -0,0 +1,509 @@
+//! Change the compression of an exr file without loading the whole image.
+//! Each block is decompressed and immediately compressed again,
+//! while all other attributes of the file are kept.
+
+use std::{
+    collections::HashMap,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use crate::{
+    block::{
+        reader::ChunksReader,
+        writer::{ChunksWriter, SortedBlocksWriter},
+        BlockIndex, UncompressedBlock, UncompressedDeepBlock,
+    },
+    compression::Compression,
+    error::{Error, Result, UnitResult},
+    math::Vec2,
+    meta::{compute_chunk_count, header::Header, Headers, MetaData},
+};
+
+/// Read the file and write it to another path, compressing all layers with the
+/// specified compression. Uses relaxed error handling. See `transcode`.
+///
+/// # Errors
+/// Returns an error if a file cannot be opened or created, or if transcoding
+/// fails. The new file is deleted if writing fails.
+pub fn transcode_file(
+    from: impl AsRef<Path>,
+    to: impl AsRef<Path>,
+    compression: Compression,
+) -> UnitResult {
+    let read = BufReader::new(std::fs::File::open(from)?);
+
+    crate::io::attempt_delete_file_on_write_error(to.as_ref(), move |write| {
+        transcode(read, BufWriter::new(write), false, |_| compression)
+    })
+}
+
+/// Read an exr file and write it again, compressing each layer with the
+/// compression that the closure returns for the header of that layer.
+///
+/// Decompresses and compresses one block after another, using multiple threads
+/// if the `rayon` feature is enabled, without loading the whole image into
+/// memory. Reads the blocks in the order of the new file. Keeps all headers and
+/// attributes, except for the compression and the chunk count. When the number
+/// of scan lines per block changes, for example from `ZIP1` to `PIZ`, the lines
+/// of the blocks are combined or split up accordingly. For deep scan line
+/// layers, the new compression must have the same number of lines per block as
+/// the old compression. Lossy compression methods may change the samples. If
+/// pedantic is true, both the old and the new file are validated strictly. Both
+/// the reader and the writer are assumed to be buffered.
+///
+/// # Errors
+/// Returns an error if the file is invalid, if the new compression is not
+/// supported for a layer, or if reading or writing fails.
+pub fn transcode(
+    read: impl Read + Seek,
+    write: impl Write + Seek,
+    pedantic: bool,
+    compression_of_layer: impl FnMut(&Header) -> Compression,
+) -> UnitResult {
+    transcode_counting_buffered_blocks(read, write, pedantic, compression_of_layer)?;
+    Ok(())
+}
+
+/// Transcode the file, see `transcode`. Returns the maximum number of
+/// decompressed blocks that waited for other blocks at the same time.
+fn transcode_counting_buffered_blocks(
+    read: impl Read + Seek,
+    write: impl Write + Seek,
+    pedantic: bool,
+    mut compression_of_layer: impl FnMut(&Header) -> Compression,
+) -> Result<usize> {
+    let reader = crate::block::read(read, pedantic)?;
+
+    let headers: Headers = reader
+        .headers()
+        .iter()
+        .map(|header| {
+            let compression = compression_of_layer(header);
+
+            Header {
+                // the number of scan lines per block depends on the compression
+                chunk_count: compute_chunk_count(compression, header.layer_size, header.blocks),
+                compression,
+                ..header.clone()
+            }
+        })
+        .collect();
+
+    for (old_header, new_header) in reader.headers().iter().zip(&headers) {
+        if old_header.deep && old_header.max_block_pixel_size() != new_header.max_block_pixel_size()
+        {
+            return Err(Error::unsupported("changing the number of lines per block of deep data"));
+        }
+    }
+
+    let mut max_buffered_blocks = 0;
+    let max_buffered_blocks_ref = &mut max_buffered_blocks;
+
+    crate::block::write(write, headers, pedantic, move |meta, chunk_writer| {
+        let mut regroup = RegroupBlocks::new(reader.headers(), &meta);
+
+        // reading the blocks in the order of the new file,
+        // only the few blocks that are decompressed at the same time wait for other
+        // blocks
+        let chunks = reader.sorted_chunks(pedantic, |index| {
+            regroup.locate_first_line(index).map(|new_block| new_block.index_in_file)
+        })?;
+
+        // deep blocks vary in size and are decompressed in this thread
+        let max_ordered_blocks = if meta.headers.iter().any(|header| header.deep) {
+            transcode_deep_and_flat(chunks, &meta, pedantic, &mut regroup, chunk_writer)?;
+            0
+        } else {
+            transcode_flat(chunks, &meta, pedantic, &mut regroup, chunk_writer)?
+        };
+
+        regroup.validate_complete()?;
+        *max_buffered_blocks_ref = regroup.max_incomplete_blocks + max_ordered_blocks;
+        Ok(())
+    })?;
+
+    Ok(max_buffered_blocks)
+}
+
+/// Decompress the blocks with multiple threads where possible,
+/// and compress the blocks with multiple threads where possible.
+/// Returns the maximum number of blocks that waited for the blocks before them.
+fn transcode_flat(
+    chunks: impl ChunksReader,
+    meta: &MetaData,
+    pedantic: bool,
+    regroup: &mut RegroupBlocks,
+    chunk_writer: &mut impl ChunksWriter,
+) -> Result<usize> {
+    // falls back to sequential compression where threads are not available
+    #[cfg(feature = "rayon")]
+    if let Some(mut compressor) = chunk_writer.parallel_blocks_compressor(meta) {
+        // the compressor writes the blocks in the order they are added,
+        // but the decompressed blocks arrive in any order
+        let mut ordered_blocks = OrderedBlocks::default();
+
+        chunks.decompress_parallel(pedantic, |_, block| {
+            regroup.insert(block, |index_in_file, index_in_header, block| {
+                ordered_blocks.insert(index_in_file, index_in_header, block, |index, block| {
+                    compressor.add_block_to_compression_queue(index, block)
+                })
+            })
+        })?;
+
+        return Ok(ordered_blocks.max_pending_blocks);
+    }
+
+    let mut compressor = chunk_writer.sequential_blocks_compressor(meta);
+    chunks.decompress_sequential(pedantic, |_, block| {
+        regroup.insert(block, |_, index_in_header, block| {
+            compressor.compress_block(index_in_header, block)
+        })
+    })?;
+
+    Ok(0)
+}
+
+/// Decompress and compress all blocks in this thread.
+fn transcode_deep_and_flat(
+    chunks: impl ChunksReader,
+    meta: &MetaData,
+    pedantic: bool,
+    regroup: &mut RegroupBlocks,
+    chunk_writer: &mut impl ChunksWriter,
+) -> UnitResult {
+    let old_meta = chunks.meta_data().clone();
+    let mut sorted_writer = SortedBlocksWriter::new(meta, chunk_writer);
+
+    for chunk in chunks {
+        let chunk = chunk?;
+
+        if old_meta.headers.get(chunk.layer_index).is_some_and(|header| header.deep) {
+            let block = UncompressedDeepBlock::decompress_chunk(chunk, &old_meta, pedantic)?;
+            let new_block = regroup.locate(block.index)?;
+            let chunk = block.compress_to_chunk(&meta.headers)?;
+            sorted_writer.write_or_stash_chunk(
+                new_block.index_in_file,
+                new_block.index_in_header,
+                chunk,
+            )?;
+        } else {
+            let block = UncompressedBlock::decompress_chunk(chunk, &old_meta, pedantic)?;
+            regroup.insert(block, |index_in_file, index_in_header, block| {
+                let chunk = block.compress_to_chunk(&meta.headers)?;
+                sorted_writer.write_or_stash_chunk(index_in_file, index_in_header, chunk)
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The layer, the level, and the pixel position of a block.
+type BlockLocation = (usize, Vec2<usize>, Vec2<usize>);
+
+/// Finds the block in the new file for each block of the old file.
+/// Combines or splits the blocks of scan line layers where the number of lines
+/// per block changes.
+#[derive(Debug)]
+struct RegroupBlocks {
+    /// All blocks of the new file.
+    new_blocks: HashMap<BlockLocation, NewBlock>,
+
+    /// For each layer whose blocks are combined or split,
+    /// the new number of lines per block and the byte size of a line.
+    regrouped_layers: Vec<Option<(usize, usize)>>,
+
+    /// The blocks of the new file that do not yet contain all lines,
+    /// by their index in the file.
+    incomplete_blocks: HashMap<usize, IncompleteBlock>,
+
+    /// The maximum number of incomplete blocks at the same time.
+    max_incomplete_blocks: usize,
+}
+
+/// Where a block is placed in the new file.
+#[derive(Clone, Copy, Debug)]
+struct NewBlock {
+    index_in_file: usize,
+    index_in_header: usize,
+    index: BlockIndex,
+}
+
+#[derive(Debug)]
+struct IncompleteBlock {
+    index_in_header: usize,
+    missing_lines: usize,
+    block: UncompressedBlock,
+}
+
+impl RegroupBlocks {
+    fn new(old_headers: &[Header], meta: &MetaData) -> Self {
+        let new_blocks = meta
+            .enumerate_ordered_header_block_indices()
+            .enumerate()
+            .map(|(index_in_file, (index_in_header, index))| {
+                let new_block = NewBlock {
+                    index_in_file,
+                    index_in_header,
+                    index,
+                };
+
+                ((index.layer, index.level, index.pixel_position), new_block)
+            })
+            .collect();
+
+        let regrouped_layers = old_headers
+            .iter()
+            .zip(&meta.headers)
+            .map(|(old_header, new_header)| {
+                let lines_per_block = new_header.compression.scan_lines_per_block();
+
+                let is_regrouped = !new_header.blocks.has_tiles()
+                    && old_header.compression.scan_lines_per_block() != lines_per_block;
+
+                is_regrouped.then(|| {
+                    (
+                        lines_per_block,
+                        new_header.layer_size.width() * new_header.channels.bytes_per_pixel,
+                    )
+                })
+            })
+            .collect();
+
+        Self {
+            new_blocks,
+            regrouped_layers,
+            incomplete_blocks: HashMap::new(),
+            max_incomplete_blocks: 0,
+        }
+    }
+
+    /// The block at the location of this block in the new file.
+    fn locate(&self, index: BlockIndex) -> Result<NewBlock> {
+        self.new_blocks
+            .get(&(index.layer, index.level, index.pixel_position))
+            .copied()
+            .ok_or_else(|| Error::invalid("block index"))
+    }
+
+    /// The block of the new file that contains the first line of this block.
+    fn locate_first_line(&self, index: BlockIndex) -> Result<NewBlock> {
+        match self.regrouped_layers.get(index.layer).copied().flatten() {
+            Some((lines_per_block, _)) => {
+                let y = index.pixel_position.y();
+                self.locate(BlockIndex {
+                    pixel_position: Vec2(0, y - y % lines_per_block),
+                    ..index
+                })
+            }
+
+            None => self.locate(index),
+        }
+    }
+
+    /// Pass each block of the new file to the closure as soon as it contains
+    /// all lines.
+    fn insert(
+        &mut self,
+        block: UncompressedBlock,
+        mut write_block: impl FnMut(usize, usize, UncompressedBlock) -> UnitResult,
+    ) -> UnitResult {
+        let Some((lines_per_block, line_byte_size)) =
+            self.regrouped_layers.get(block.index.layer).copied().flatten()
+        else {
+            let new_block = self.locate(block.index)?;
+            return write_block(new_block.index_in_file, new_block.index_in_header, block);
+        };
+
+        if block.data.len() != block.index.pixel_size.height() * line_byte_size {
+            return Err(Error::invalid("block size"));
+        }
+
+        for (line_index, line) in block.data.chunks_exact(line_byte_size).enumerate() {
+            let y = block.index.pixel_position.y() + line_index;
+            let block_y = y - y % lines_per_block;
+
+            let new_block = self.locate(BlockIndex {
+                pixel_position: Vec2(0, block_y),
+                ..block.index
+            })?;
+
+            let incomplete =
+                self.incomplete_blocks.entry(new_block.index_in_file).or_insert_with(|| {
+                    IncompleteBlock {
+                        index_in_header: new_block.index_in_header,
+                        missing_lines: new_block.index.pixel_size.height(),
+                        block: UncompressedBlock {
+                            index: new_block.index,
+                            data: vec![0; new_block.index.pixel_size.height() * line_byte_size],
+                        },
+                    }
+                });
+
+            let start = (y - block_y) * line_byte_size;
+            incomplete.block.data[start..start + line_byte_size].copy_from_slice(line);
+            incomplete.missing_lines -= 1;
+
+            let is_complete = incomplete.missing_lines == 0;
+            self.max_incomplete_blocks =
+                self.max_incomplete_blocks.max(self.incomplete_blocks.len());
+
+            if is_complete {
+                let complete = self
+                    .incomplete_blocks
+                    .remove(&new_block.index_in_file)
+                    .expect("incomplete block bug");
+                write_block(new_block.index_in_file, complete.index_in_header, complete.block)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if any block of the new file is missing some lines.
+    fn validate_complete(&self) -> UnitResult {
+        if self.incomplete_blocks.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::invalid("missing scan lines"))
+        }
+    }
+}
+
+/// Blocks that wait until all blocks before them have been written,
+/// so that the new file contains the blocks in the order of its line order
+/// attribute.
+#[cfg(feature = "rayon")]
+#[derive(Debug, Default)]
+struct OrderedBlocks {
+    next_index_in_file: usize,
+    pending_blocks: std::collections::BTreeMap<usize, (usize, UncompressedBlock)>,
+
+    /// The maximum number of pending blocks at the same time.
+    max_pending_blocks: usize,
+}
+
+#[cfg(feature = "rayon")]
+impl OrderedBlocks {
+    /// Insert the block and write all blocks that are next in the file.
+    fn insert(
+        &mut self,
+        index_in_file: usize,
+        index_in_header: usize,
+        block: UncompressedBlock,
+        mut write_block: impl FnMut(usize, UncompressedBlock) -> UnitResult,
+    ) -> UnitResult {
+        self.pending_blocks.insert(index_in_file, (index_in_header, block));
+        self.max_pending_blocks = self.max_pending_blocks.max(self.pending_blocks.len());
+
+        while let Some((index_in_header, block)) =
+            self.pending_blocks.remove(&self.next_index_in_file)
+        {
+            write_block(index_in_header, block)?;
+            self.next_index_in_file += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::TryFrom, io::Cursor};
+
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::{meta::BlockDescription, prelude::*};
+
+    /// A file with single line blocks, which are not stored in the order of
+    /// their lines, as if written by a writer that ignores the line order.
+    fn shuffled_scan_line_file(height: usize) -> Vec<u8> {
+        let header = Header::new(
+            "shuffled".into(),
+            Vec2(8, height),
+            smallvec![ChannelDescription::named("Y", SampleType::F16)],
+        )
+        .with_encoding(
+            Compression::ZIP1,
+            BlockDescription::ScanLines,
+            LineOrder::Increasing,
+        );
+
+        let mut bytes = Vec::new();
+        crate::block::write(Cursor::new(&mut bytes), smallvec![header], true, |meta, writer| {
+            let mut blocks: Vec<(usize, BlockIndex)> =
+                meta.enumerate_ordered_header_block_indices().collect();
+
+            // visit the blocks in an order that is far from the order of the lines
+            blocks.sort_by_key(|&(index_in_header, _)| index_in_header * 101 % height);
+
+            for (index_in_header, index) in blocks {
+                // finite and distinct samples, which compare equal after reading
+                let samples = (0..index.pixel_size.area())
+                    .map(|x| f16::from_bits(u16::try_from(index_in_header * 8 + x).unwrap()));
+
+                let block = UncompressedBlock {
+                    index,
+                    data: samples.flat_map(f16::to_ne_bytes).collect(),
+                };
+                writer.write_chunk(index_in_header, block.compress_to_chunk(&meta.headers)?)?;
+            }
+
+            Ok(())
+        })
+        .unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn transcoding_shuffled_blocks_buffers_few_blocks() {
+        let original = shuffled_scan_line_file(512);
+
+        let read_samples = |bytes: &[u8]| {
+            read()
+                .no_deep_data()
+                .largest_resolution_level()
+                .all_channels()
+                .first_valid_layer()
+                .all_attributes()
+                .from_buffered(Cursor::new(bytes))
+                .unwrap()
+                .layer_data
+                .channel_data
+        };
+
+        #[cfg(feature = "rayon")]
+        let threads = rayon_core::current_num_threads();
+
+        #[cfg(not(feature = "rayon"))]
+        let threads = 0;
+
+        // at most the blocks that are decompressed at the same time wait for other
+        // blocks
+        let max_expected_buffered_blocks = 2 * (threads + 3);
+
+        // the same number of lines per block, and more lines per block
+        for compression in [Compression::RLE, Compression::ZIP16, Compression::PIZ] {
+            let mut transcoded = Vec::new();
+            let buffered_blocks = transcode_counting_buffered_blocks(
+                Cursor::new(&original),
+                Cursor::new(&mut transcoded),
+                true,
+                |_| compression,
+            )
+            .unwrap();
+
+            assert!(
+                buffered_blocks <= max_expected_buffered_blocks,
+                "{}: {} buffered blocks",
+                compression,
+                buffered_blocks
+            );
+
+            assert_eq!(read_samples(&transcoded), read_samples(&original));
+        }
+    }
+}
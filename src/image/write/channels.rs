@@ -20,6 +20,13 @@ pub trait WritableChannels<'slf> {
     /// be stored in the file
     fn infer_level_modes(&self) -> (LevelMode, RoundingMode);
 
+    /// Generate the file meta data regarding deep data.
+    /// Returns the largest number of samples in any pixel,
+    /// or `None` if this list of channels does not contain deep data.
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        None
+    }
+
     /// The type of temporary writer
     type Writer: ChannelsWriter;
 
@@ -31,7 +38,24 @@ pub trait WritableChannels<'slf> {
 pub trait ChannelsWriter: Sync {
     /// Deliver a block of pixels, containing all channel data, to be stored in
     /// the file
-    fn extract_uncompressed_block(&self, header: &Header, block: BlockIndex) -> Vec<u8>; // TODO return uncompressed block?
+    ///
+    /// # Errors
+    /// Returns an error if the channels contain deep samples.
+    fn extract_uncompressed_block(&self, header: &Header, block: BlockIndex) -> Result<Vec<u8>>; // TODO return uncompressed block?
+
+    /// Deliver a block of deep pixels, containing all channel data, to be
+    /// stored in the file
+    ///
+    /// # Errors
+    /// Returns an error if these channels do not support deep data.
+    fn extract_uncompressed_deep_block(
+        &self,
+        header: &Header,
+        block: BlockIndex,
+    ) -> Result<UncompressedDeepBlock> {
+        let _ = (header, block);
+        Err(Error::unsupported("deep data with these channels"))
+    }
 }
 
 /// Define how to get a pixel from your custom pixel storage.
@@ -93,6 +117,14 @@ where
         mode
     }
 
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        self.list
+            .iter()
+            .map(|channel| channel.sample_data.infer_max_samples_per_pixel())
+            .max()
+            .flatten()
+    }
+
     fn create_writer(&'samples self, header: &Header) -> Self::Writer {
         let channels =
             self.list.iter().map(|chan| chan.sample_data.create_samples_writer(header)).collect();
@@ -113,12 +145,36 @@ impl<Samples> ChannelsWriter for AnyChannelsWriter<Samples>
 where
     Samples: SamplesWriter,
 {
-    fn extract_uncompressed_block(&self, header: &Header, block_index: BlockIndex) -> Vec<u8> {
-        UncompressedBlock::collect_block_data_from_lines(
+    fn extract_uncompressed_block(
+        &self,
+        header: &Header,
+        block_index: BlockIndex,
+    ) -> Result<Vec<u8>> {
+        let mut result = Ok(());
+
+        let block_bytes = UncompressedBlock::collect_block_data_from_lines(
             &header.channels,
             block_index,
             |line_ref| {
-                self.channels[line_ref.location.channel].extract_line(line_ref);
+                if result.is_ok() {
+                    result = self.channels[line_ref.location.channel].extract_line(line_ref);
+                }
+            },
+        );
+
+        result.map(|()| block_bytes)
+    }
+
+    fn extract_uncompressed_deep_block(
+        &self,
+        header: &Header,
+        block_index: BlockIndex,
+    ) -> Result<UncompressedDeepBlock> {
+        UncompressedDeepBlock::from_lines(
+            &header.channels,
+            block_index,
+            |line, sample_counts, bytes| {
+                self.channels[line.channel].extract_deep_line(line, sample_counts, bytes)
             },
         )
     }
@@ -187,7 +243,11 @@ where
     Storage::Pixel: IntoRecursive,
     PxWriter: Sync + RecursivePixelWriter<<Storage::Pixel as IntoRecursive>::Recursive>,
 {
-    fn extract_uncompressed_block(&self, header: &Header, block_index: BlockIndex) -> Vec<u8> {
+    fn extract_uncompressed_block(
+        &self,
+        header: &Header,
+        block_index: BlockIndex,
+    ) -> Result<Vec<u8>> {
         let block_bytes = block_index.pixel_size.area() * header.channels.bytes_per_pixel;
         let mut block_bytes = vec![0_u8; block_bytes];
 
@@ -212,7 +272,7 @@ where
             self.recursive_channel_writer.write_pixels(line_bytes, pixel_line.as_slice(), |px| px);
         }
 
-        block_bytes
+        Ok(block_bytes)
     }
 }
 
@@ -1,7 +1,9 @@
 //! How to write either a single or a list of layers.
 
 use crate::{
-    block::BlockIndex,
+    block::{BlockIndex, UncompressedDeepBlock},
+    compression::CompressionOptions,
+    error::{Error, Result},
     image::{
         recursive::{NoneMore, Recursive},
         write::channels::{ChannelsWriter, WritableChannels},
@@ -31,7 +33,24 @@ pub trait WritableLayers<'slf> {
 /// A temporary writer for a list of channels
 pub trait LayersWriter: Sync {
     /// Deliver a block of pixels from a single layer to be stored in the file
-    fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Vec<u8>;
+    ///
+    /// # Errors
+    /// Returns an error if the layer contains deep samples.
+    fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Result<Vec<u8>>;
+
+    /// Deliver a block of deep pixels from a single layer to be stored in the
+    /// file
+    ///
+    /// # Errors
+    /// Returns an error if these layers do not support deep data.
+    fn extract_uncompressed_deep_block(
+        &self,
+        headers: &[Header],
+        block: BlockIndex,
+    ) -> Result<UncompressedDeepBlock> {
+        let _ = (headers, block);
+        Err(Error::unsupported("deep data with these layers"))
+    }
 }
 
 /// A temporary writer for an arbitrary list of layers
@@ -100,10 +119,12 @@ impl<'slf, Channels: WritableChannels<'slf>> WritableLayers<'slf> for Layer<Chan
         };
 
         let chunk_count = compute_chunk_count(self.encoding.compression, self.size, blocks);
+        let max_samples_per_pixel = self.channel_data.infer_max_samples_per_pixel();
 
         let header = Header {
             channels: self.channel_data.infer_channel_list(),
             compression: self.encoding.compression,
+            compression_options: CompressionOptions::default(),
 
             blocks,
             chunk_count,
@@ -113,9 +134,9 @@ impl<'slf, Channels: WritableChannels<'slf>> WritableLayers<'slf> for Layer<Chan
             shared_attributes: image_attributes.clone(),
             own_attributes: self.attributes.clone(),
 
-            deep: false, // TODO deep data
-            deep_data_version: None,
-            max_samples_per_pixel: None,
+            deep: max_samples_per_pixel.is_some(),
+            deep_data_version: max_samples_per_pixel.map(|_| 1),
+            max_samples_per_pixel,
         };
 
         smallvec![header] // TODO no array-vs-first
@@ -135,22 +156,42 @@ impl<C> LayersWriter for AllLayersWriter<C>
 where
     C: ChannelsWriter,
 {
-    fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Vec<u8> {
+    fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Result<Vec<u8>> {
         self.layers[block.layer]
             .extract_uncompressed_block(std::slice::from_ref(&headers[block.layer]), block)
         // TODO no array-vs-first
     }
+
+    fn extract_uncompressed_deep_block(
+        &self,
+        headers: &[Header],
+        block: BlockIndex,
+    ) -> Result<UncompressedDeepBlock> {
+        self.layers[block.layer]
+            .extract_uncompressed_deep_block(std::slice::from_ref(&headers[block.layer]), block)
+    }
 }
 
 impl<C> LayersWriter for LayerWriter<C>
 where
     C: ChannelsWriter,
 {
-    fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Vec<u8> {
+    fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Result<Vec<u8>> {
         self.channels
             .extract_uncompressed_block(headers.first().expect("invalid inferred header"), block)
         // TODO no array-vs-first
     }
+
+    fn extract_uncompressed_deep_block(
+        &self,
+        headers: &[Header],
+        block: BlockIndex,
+    ) -> Result<UncompressedDeepBlock> {
+        self.channels.extract_uncompressed_deep_block(
+            headers.first().expect("invalid inferred header"),
+            block,
+        )
+    }
 }
 
 impl<'slf> WritableLayers<'slf> for NoneMore {
@@ -198,7 +239,19 @@ impl LayersWriter for NoneMore {
     /// Panics if called, as this indicates a recursive length mismatch bug
     /// where a block is being extracted for a layer index that doesn't
     /// exist in the recursive layer structure.
-    fn extract_uncompressed_block(&self, _: &[Header], _: BlockIndex) -> Vec<u8> {
+    fn extract_uncompressed_block(&self, _: &[Header], _: BlockIndex) -> Result<Vec<u8>> {
+        unreachable!(
+            "recursive length mismatch bug: attempted to extract block for non-existent layer"
+        )
+    }
+
+    /// # Panics
+    /// Panics if called, as this indicates a recursive length mismatch bug.
+    fn extract_uncompressed_deep_block(
+        &self,
+        _: &[Header],
+        _: BlockIndex,
+    ) -> Result<UncompressedDeepBlock> {
         unreachable!(
             "recursive length mismatch bug: attempted to extract block for non-existent layer"
         )
@@ -211,7 +264,7 @@ where
     InnerLayersWriter: LayersWriter,
     Channels: ChannelsWriter,
 {
-    fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Vec<u8> {
+    fn extract_uncompressed_block(&self, headers: &[Header], block: BlockIndex) -> Result<Vec<u8>> {
         let (layer_index, layer) = &self.value;
         if *layer_index == block.layer {
             let header = headers.get(*layer_index).expect("layer index bug");
@@ -221,4 +274,18 @@ where
             self.inner.extract_uncompressed_block(headers, block)
         }
     }
+
+    fn extract_uncompressed_deep_block(
+        &self,
+        headers: &[Header],
+        block: BlockIndex,
+    ) -> Result<UncompressedDeepBlock> {
+        let (layer_index, layer) = &self.value;
+        if *layer_index == block.layer {
+            let header = headers.get(*layer_index).expect("layer index bug");
+            layer.extract_uncompressed_deep_block(std::slice::from_ref(header), block)
+        } else {
+            self.inner.extract_uncompressed_deep_block(headers, block)
+        }
+    }
 }
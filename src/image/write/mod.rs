@@ -19,8 +19,13 @@ pub mod samples;
 use std::io::{BufWriter, Seek};
 
 use crate::{
-    block::writer::ChunksWriter,
-    error::UnitResult,
+    block::{
+        chunk::{Chunk, CompressedBlock},
+        writer::ChunksWriter,
+        BlockIndex, UncompressedBlock,
+    },
+    compression::{for_each_sample_error, Compression, CompressionOptions, DecompressionScratch},
+    error::{Error, Result, UnitResult},
     image::{
         ignore_progress,
         write::layers::{LayersWriter, WritableLayers},
@@ -28,7 +33,11 @@ use crate::{
     },
     io::Write,
     math::Vec2,
-    meta::Headers,
+    meta::{
+        attribute::{IntegerBounds, Text},
+        header::Header,
+        Headers,
+    },
 };
 
 /// An oversimplified function for "just write the damn file already" use cases.
@@ -100,6 +109,10 @@ impl<'img, WritableLayers> WritableImage<'img, WritableLayers> for &'img Image<W
             parallel: true,
 
             on_progress: ignore_progress,
+            max_lossy_error: None,
+            max_lossy_channel_errors: Vec::new(),
+            smallest_lossless_compression: false,
+            compression_options: CompressionOptions::DEFAULT,
         }
     }
 }
@@ -113,6 +126,10 @@ pub struct WriteImageWithOptions<'img, Layers, OnProgress> {
     on_progress: OnProgress,
     check_compatibility: bool,
     parallel: bool,
+    max_lossy_error: Option<f32>,
+    max_lossy_channel_errors: Vec<(Text, f32)>,
+    smallest_lossless_compression: bool,
+    compression_options: CompressionOptions,
 }
 
 impl<'img, L, F> WriteImageWithOptions<'img, L, F>
@@ -124,7 +141,13 @@ where
     /// to the data in the file.
     pub fn infer_meta_data(&self) -> Headers {
         // TODO this should perform all validity checks? and none after that?
-        self.image.layer_data.infer_headers(&self.image.attributes)
+        let mut headers = self.image.layer_data.infer_headers(&self.image.attributes);
+
+        for header in &mut headers {
+            header.compression_options = self.compression_options.clone();
+        }
+
+        headers
     }
 
     /// Do not compress multiple pixel blocks on multiple threads at once.
@@ -154,6 +177,74 @@ where
         }
     }
 
+    /// Guarantee that lossy compression does not change any sample by more than
+    /// the specified absolute error. Each block of a layer compressed with
+    /// `PXR24`, `B44`, `B44A`, `DWAA` or `DWAB` is decompressed again right
+    /// after compressing it, and each channel is compared to the original
+    /// pixels. If any sample of the layer changes too much, only that layer
+    /// is compressed again, with the lossless `ZIP16` compression. Combine
+    /// this with `smallest_lossless_compression()` to choose the lossless
+    /// method instead. The compressed blocks of the other checked layers are
+    /// kept in memory until they are written.
+    ///
+    /// The error of subsampled channels is not measured,
+    /// so checked layers with subsampled channels are always written with
+    /// `ZIP16`. Use `0.0` to guarantee that no sample is quantized at all.
+    /// Use `max_lossy_channel_error` to specify the error for single channels.
+    /// Writing fails if the error is negative, infinite or not a number.
+    #[must_use]
+    pub fn max_lossy_error(self, max_error: f32) -> Self {
+        Self {
+            max_lossy_error: Some(max_error),
+            ..self
+        }
+    }
+
+    /// Guarantee that lossy compression does not change any sample of the
+    /// channels with this name by more than the specified absolute error,
+    /// for example `0.0` for depth or id channels.
+    /// Replaces the error of `max_lossy_error` for these channels,
+    /// and channels without any specified error are not measured.
+    /// See `max_lossy_error` for details.
+    #[must_use]
+    pub fn max_lossy_channel_error(
+        mut self,
+        channel_name: impl Into<Text>,
+        max_error: f32,
+    ) -> Self {
+        self.max_lossy_channel_errors.push((channel_name.into(), max_error));
+        self
+    }
+
+    /// Replace the compression of each losslessly compressed layer with the
+    /// lossless compression method that produces the smallest file.
+    /// Before writing, the same few ranges of pixel rows of each layer are
+    /// compressed with `RLE`, `ZIP1`, `ZIP16` and `PIZ`, and also `PXR24`
+    /// where the layer contains no `f32` channels. The method with the
+    /// smallest output is used for the whole layer.
+    ///
+    /// Uncompressed layers are also replaced.
+    /// Layers with lossy compression and deep layers are not changed.
+    #[must_use]
+    pub fn smallest_lossless_compression(self) -> Self {
+        Self {
+            smallest_lossless_compression: true,
+            ..self
+        }
+    }
+
+    /// Use these settings of the compression methods for all layers,
+    /// for example a deflate level or custom DWA channel rules.
+    /// The settings are not stored in the file.
+    /// Writing fails if the settings are invalid.
+    #[must_use]
+    pub fn compression_options(self, compression_options: CompressionOptions) -> Self {
+        Self {
+            compression_options,
+            ..self
+        }
+    }
+
     /// Specify a function to be called regularly throughout the writing
     /// process. Replaces all previously specified progress functions in
     /// this reader.
@@ -169,6 +260,10 @@ where
             image: self.image,
             check_compatibility: self.check_compatibility,
             parallel: self.parallel,
+            max_lossy_error: self.max_lossy_error,
+            max_lossy_channel_errors: self.max_lossy_channel_errors,
+            smallest_lossless_compression: self.smallest_lossless_compression,
+            compression_options: self.compression_options,
         }
     }
 
@@ -201,15 +296,88 @@ where
     /// bytes first.
     #[must_use]
     pub fn to_buffered(self, write: impl Write + Seek) -> UnitResult {
-        let headers = self.infer_meta_data();
+        let mut headers = self.infer_meta_data();
         let layers = self.image.layer_data.create_writer(&headers);
 
+        let precompressed_layers =
+            if self.max_lossy_error.is_some() || !self.max_lossy_channel_errors.is_empty() {
+                compress_lossy_layers_within_error(
+                    &mut headers,
+                    &layers,
+                    self.max_lossy_error,
+                    &self.max_lossy_channel_errors,
+                    self.parallel,
+                )?
+            } else {
+                Vec::new()
+            };
+
+        if self.smallest_lossless_compression {
+            choose_smallest_lossless_compression(&mut headers, &layers)?;
+        }
+
         crate::block::write(write, headers, self.check_compatibility, move |meta, chunk_writer| {
-            let blocks = meta.collect_ordered_block_data(|block_index| {
-                layers.extract_uncompressed_block(&meta.headers, block_index)
-            });
+            let mut chunk_writer = chunk_writer.on_progress(self.on_progress);
+
+            // layers that were compressed to measure their error are written as they are
+            let is_precompressed: Vec<bool> =
+                precompressed_layers.iter().map(Option::is_some).collect();
+
+            let is_precompressed =
+                |layer_index: usize| is_precompressed.get(layer_index) == Some(&true);
+
+            for (index_in_header, chunk) in precompressed_layers.into_iter().flatten().flatten() {
+                chunk_writer.write_chunk(index_in_header, chunk)?;
+            }
+
+            // deep blocks vary in size and are compressed one after another
+            if meta.headers.iter().any(|header| header.deep) {
+                for (index_in_header, block_index) in meta.enumerate_ordered_header_block_indices()
+                {
+                    if is_precompressed(block_index.layer) {
+                        continue;
+                    }
+
+                    let chunk = if meta.headers[block_index.layer].deep {
+                        layers
+                            .extract_uncompressed_deep_block(&meta.headers, block_index)?
+                            .compress_to_chunk(&meta.headers)?
+                    } else {
+                        UncompressedBlock {
+                            index: block_index,
+                            data: layers.extract_uncompressed_block(&meta.headers, block_index)?,
+                        }
+                        .compress_to_chunk(&meta.headers)?
+                    };
+
+                    chunk_writer.write_chunk(index_in_header, chunk)?;
+                }
+
+                return Ok(());
+            }
+
+            // the blocks end early if a block cannot be extracted,
+            // and the error is returned after compressing the previous blocks
+            let mut extraction_result = Ok(());
+            let blocks = meta
+                .enumerate_ordered_header_block_indices()
+                .filter(|(_, block_index)| !is_precompressed(block_index.layer))
+                .map_while(|(index_in_header, block_index)| {
+                    match layers.extract_uncompressed_block(&meta.headers, block_index) {
+                        Ok(data) => Some((
+                            index_in_header,
+                            UncompressedBlock {
+                                index: block_index,
+                                data,
+                            },
+                        )),
+                        Err(error) => {
+                            extraction_result = Err(error);
+                            None
+                        }
+                    }
+                });
 
-            let chunk_writer = chunk_writer.on_progress(self.on_progress);
             if self.parallel {
                 #[cfg(not(feature = "rayon"))]
                 return Err(crate::error::Error::unsupported(
@@ -221,7 +389,378 @@ where
             } else {
                 chunk_writer.compress_all_blocks_sequential(&meta, blocks)?;
             }
-            Ok(())
+
+            extraction_result
         })
     }
 }
+
+/// The compressed chunks of a layer, each with its index in the header.
+type LayerChunks = Vec<(usize, Chunk)>;
+
+/// Compress each flat layer with lossy compression once, and measure the error
+/// of each block by decompressing it again. Layers where a channel changes by
+/// more than its maximum error are compressed with `ZIP16` instead. Returns
+/// the chunks of the layers that stay within the error, which do not need to
+/// be compressed again.
+fn compress_lossy_layers_within_error(
+    headers: &mut [Header],
+    layers: &impl LayersWriter,
+    max_error: Option<f32>,
+    max_channel_errors: &[(Text, f32)],
+    parallel: bool,
+) -> Result<Vec<Option<LayerChunks>>> {
+    let all_errors = max_error.iter().chain(max_channel_errors.iter().map(|(_, error)| error));
+    for &error in all_errors {
+        if !error.is_finite() || error < 0.0 {
+            return Err(Error::invalid("max lossy error"));
+        }
+    }
+
+    let mut max_layer_errors = Vec::with_capacity(headers.len());
+    for header in headers.iter_mut() {
+        let max_errors: Vec<f64> = header
+            .channels
+            .list
+            .iter()
+            .map(|channel| {
+                let channel_error =
+                    max_channel_errors.iter().rev().find(|(name, _)| name == &channel.name);
+                channel_error
+                    .map(|&(_, error)| error)
+                    .or(max_error)
+                    .map_or(f64::INFINITY, f64::from)
+            })
+            .collect();
+
+        let is_limited_and_lossy =
+            header.channels.list.iter().zip(&max_errors).any(|(channel, &max_error)| {
+                max_error.is_finite() && !header.compression.is_lossless_for(channel.sample_type)
+            });
+
+        if header.deep || !is_limited_and_lossy {
+            max_layer_errors.push(None);
+        }
+        // the error of subsampled channels is not measured
+        else if header.channels.list.iter().any(|channel| channel.sampling != Vec2(1, 1)) {
+            *header =
+                header.clone().with_encoding(Compression::ZIP16, header.blocks, header.line_order);
+            max_layer_errors.push(None);
+        } else {
+            max_layer_errors.push(Some(max_errors));
+        }
+    }
+
+    let mut layer_chunks: Vec<Option<LayerChunks>> =
+        max_layer_errors.iter().map(|max_errors| max_errors.as_ref().map(|_| Vec::new())).collect();
+
+    #[cfg(feature = "rayon")]
+    let pool = if parallel && layer_chunks.iter().any(Option::is_some) {
+        // in case thread pool creation fails (for example on WASM currently),
+        // we revert to sequential compression
+        rayon_core::ThreadPoolBuilder::new()
+            .thread_name(|index| format!("OpenEXR Block Compressor Thread #{index}"))
+            .build()
+            .ok()
+    } else {
+        None
+    };
+
+    #[cfg(not(feature = "rayon"))]
+    let _ = parallel;
+
+    #[cfg(feature = "rayon")]
+    if let Some(pool) = &pool {
+        compress_blocks_within_error_parallel(
+            pool,
+            headers,
+            layers,
+            &max_layer_errors,
+            &mut layer_chunks,
+        )?;
+    } else {
+        compress_blocks_within_error_sequential(
+            headers,
+            layers,
+            &max_layer_errors,
+            &mut layer_chunks,
+        )?;
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    compress_blocks_within_error_sequential(headers, layers, &max_layer_errors, &mut layer_chunks)?;
+
+    for ((header, max_errors), chunks) in
+        headers.iter_mut().zip(&max_layer_errors).zip(&layer_chunks)
+    {
+        if max_errors.is_some() && chunks.is_none() {
+            *header =
+                header.clone().with_encoding(Compression::ZIP16, header.blocks, header.line_order);
+        }
+    }
+
+    Ok(layer_chunks)
+}
+
+/// Compress and measure each block of the checked layers in this thread.
+fn compress_blocks_within_error_sequential(
+    headers: &[Header],
+    layers: &impl LayersWriter,
+    max_layer_errors: &[Option<Vec<f64>>],
+    layer_chunks: &mut [Option<LayerChunks>],
+) -> UnitResult {
+    for (index_in_header, block_index) in
+        crate::block::enumerate_ordered_header_block_indices(headers)
+    {
+        // the remaining blocks of a layer that exceeds the error are not measured
+        let (Some(max_errors), Some(_)) =
+            (&max_layer_errors[block_index.layer], &layer_chunks[block_index.layer])
+        else {
+            continue;
+        };
+
+        let block = UncompressedBlock {
+            index: block_index,
+            data: layers.extract_uncompressed_block(headers, block_index)?,
+        };
+
+        let chunk = compress_within_error(headers, block, max_errors)?;
+        keep_chunk_within_error(&mut layer_chunks[block_index.layer], index_in_header, chunk);
+    }
+
+    Ok(())
+}
+
+/// Compress and measure each block of the checked layers on multiple threads.
+/// The blocks are extracted in this thread.
+#[cfg(feature = "rayon")]
+fn compress_blocks_within_error_parallel(
+    pool: &rayon_core::ThreadPool,
+    headers: &[Header],
+    layers: &impl LayersWriter,
+    max_layer_errors: &[Option<Vec<f64>>],
+    layer_chunks: &mut [Option<LayerChunks>],
+) -> UnitResult {
+    let shared_headers = std::sync::Arc::new(headers.to_vec());
+    let max_jobs = pool.current_num_threads().max(1) + 2; // ca one block for each thread at all times
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut currently_compressing_count = 0;
+
+    let mut blocks = crate::block::enumerate_ordered_header_block_indices(headers);
+
+    loop {
+        while currently_compressing_count < max_jobs {
+            let Some((index_in_header, block_index)) = blocks.next() else {
+                break;
+            };
+
+            // the remaining blocks of a layer that exceeds the error are not measured
+            let (Some(max_errors), Some(_)) =
+                (&max_layer_errors[block_index.layer], &layer_chunks[block_index.layer])
+            else {
+                continue;
+            };
+
+            let block = UncompressedBlock {
+                index: block_index,
+                data: layers.extract_uncompressed_block(headers, block_index)?,
+            };
+
+            let sender = sender.clone();
+            let headers = shared_headers.clone();
+            currently_compressing_count += 1;
+
+            let max_errors = max_errors.clone();
+            pool.spawn(move || {
+                let chunk = compress_within_error(&headers, block, &max_errors);
+
+                // by now, compressing could have failed in another thread.
+                // the error is then already handled, so we simply
+                // don't send the compressed block and do nothing
+                let _ = sender.send(chunk.map(|chunk| (block_index.layer, index_in_header, chunk)));
+            });
+        }
+
+        if currently_compressing_count == 0 {
+            return Ok(());
+        }
+
+        let (layer_index, index_in_header, chunk) = receiver
+            .recv()
+            .expect("all compressing senders hung up but more messages were expected")?;
+
+        currently_compressing_count -= 1;
+        keep_chunk_within_error(&mut layer_chunks[layer_index], index_in_header, chunk);
+    }
+}
+
+/// Compress the block, then decompress the chunk again and compare each
+/// channel to the original samples. Returns none if any channel changes by
+/// more than its maximum error.
+fn compress_within_error(
+    headers: &[Header],
+    block: UncompressedBlock,
+    max_errors: &[f64],
+) -> Result<Option<Chunk>> {
+    let header = &headers[block.index.layer];
+    let pixel_section =
+        IntegerBounds::new(block.index.pixel_position.to_i32(), block.index.pixel_size);
+
+    let original = block.data.clone();
+    let chunk = block.compress_to_chunk(headers)?;
+
+    let compressed_le = match &chunk.compressed_block {
+        CompressedBlock::ScanLine(block) => &block.compressed_pixels_le,
+        CompressedBlock::Tile(block) => &block.compressed_pixels_le,
+        CompressedBlock::DeepScanLine(_) | CompressedBlock::DeepTile(_) => {
+            unreachable!("deep layer error check bug")
+        }
+    };
+
+    let mut decompressed = vec![0_u8; original.len()];
+    header.compression.decompress_image_section_from_le_into(
+        header,
+        compressed_le,
+        pixel_section,
+        true,
+        &mut DecompressionScratch::default(),
+        &mut decompressed,
+    )?;
+
+    let mut is_within_error = true;
+    for_each_sample_error(
+        &header.channels,
+        pixel_section,
+        &original,
+        &decompressed,
+        |channel_index, error| {
+            is_within_error &= error <= max_errors[channel_index];
+        },
+    )?;
+
+    Ok(Some(chunk).filter(|_| is_within_error))
+}
+
+/// Remember the chunk of a layer, or forget all chunks of that layer
+/// if this chunk exceeds the error.
+fn keep_chunk_within_error(
+    layer_chunks: &mut Option<LayerChunks>,
+    index_in_header: usize,
+    chunk: Option<Chunk>,
+) {
+    match chunk {
+        Some(chunk) => {
+            if let Some(chunks) = layer_chunks {
+                chunks.push((index_in_header, chunk));
+            }
+        }
+        None => *layer_chunks = None,
+    }
+}
+
+/// The number of pixel row ranges per layer that are compressed to find the
+/// smallest compression method.
+const COMPRESSION_SAMPLE_ROWS_COUNT: usize = 8;
+
+/// Replace the compression of each losslessly compressed flat layer with the
+/// lossless compression method that results in the smallest sample blocks.
+/// Every method compresses the same pixel rows of the layer.
+fn choose_smallest_lossless_compression(
+    headers: &mut [Header],
+    layers: &impl LayersWriter,
+) -> UnitResult {
+    let candidates = [
+        Compression::RLE,
+        Compression::ZIP1,
+        Compression::ZIP16,
+        Compression::PIZ,
+        Compression::PXR24,
+    ];
+
+    for layer_index in 0..headers.len() {
+        let header = &headers[layer_index];
+        if header.deep || header.compression.may_loose_data() {
+            continue;
+        }
+
+        let candidate_headers: Vec<Header> = candidates
+            .iter()
+            .filter(|candidate| {
+                header
+                    .channels
+                    .list
+                    .iter()
+                    .all(|channel| candidate.is_lossless_for(channel.sample_type))
+            })
+            .map(|&candidate| {
+                header.clone().with_encoding(candidate, header.blocks, header.line_order)
+            })
+            .collect();
+
+        // the largest block height is a multiple of all other block heights,
+        // so each range of sample rows contains whole blocks of every method
+        let rows_height = candidate_headers
+            .iter()
+            .map(|candidate_header| candidate_header.max_block_pixel_size().height())
+            .max()
+            .unwrap_or(1);
+
+        let rows_count = header.layer_size.height().div_ceil(rows_height);
+        let sample_rows: Vec<std::ops::Range<usize>> = (0..rows_count)
+            .step_by((rows_count / COMPRESSION_SAMPLE_ROWS_COUNT).max(1))
+            .take(COMPRESSION_SAMPLE_ROWS_COUNT)
+            .map(|rows_index| rows_index * rows_height..(rows_index + 1) * rows_height)
+            .collect();
+
+        let mut trial_headers = headers.to_vec();
+        let mut smallest: Option<(Compression, usize)> = None;
+
+        for candidate_header in candidate_headers {
+            let candidate = candidate_header.compression;
+            trial_headers[layer_index] = candidate_header;
+
+            let trial_header = &trial_headers[layer_index];
+            let mut compressed_byte_count = 0;
+
+            for tile in trial_header.blocks_increasing_y_order() {
+                if tile.location.level_index != Vec2(0, 0) {
+                    continue;
+                }
+
+                let data_indices =
+                    trial_header.get_absolute_block_pixel_coordinates(tile.location)?;
+
+                let block_index = BlockIndex {
+                    layer: layer_index,
+                    level: tile.location.level_index,
+                    pixel_position: data_indices.position.to_usize("data indices start")?,
+                    pixel_size: data_indices.size,
+                };
+
+                let is_sampled =
+                    sample_rows.iter().any(|rows| rows.contains(&block_index.pixel_position.y()));
+
+                if !is_sampled {
+                    continue;
+                }
+
+                let data = layers.extract_uncompressed_block(&trial_headers, block_index)?;
+                compressed_byte_count +=
+                    candidate.compress_image_section_to_le(trial_header, data, data_indices)?.len();
+            }
+
+            if smallest
+                .is_none_or(|(_, smallest_byte_count)| compressed_byte_count < smallest_byte_count)
+            {
+                smallest = Some((candidate, compressed_byte_count));
+            }
+        }
+
+        if let Some((compression, _)) = smallest {
+            let header = &mut headers[layer_index];
+            *header = header.clone().with_encoding(compression, header.blocks, header.line_order);
+        }
+    }
+
+    Ok(())
+}
@@ -1,8 +1,11 @@
 //! How to write samples (a grid of `f32`, `f16` or `u32` values).
 
+use std::convert::TryFrom;
+
 use crate::{
-    block::lines::LineRefMut,
-    image::{FlatSamples, Levels, RipMaps},
+    block::lines::{LineIndex, LineRefMut},
+    error::{Error, UnitResult},
+    image::{DeepAndFlatSamples, DeepSamples, FlatSamples, Levels, RipMaps},
     math::{RoundingMode, Vec2},
     meta::{
         attribute::{LevelMode, SampleType, TileDescription},
@@ -15,11 +18,16 @@ use crate::{
 /// Also can contain multiple resolution levels.
 /// Usually contained within `Channels`.
 pub trait WritableSamples<'slf> {
-    // fn is_deep(&self) -> bool;
-
     /// Generate the file meta data regarding the number type of this storage
     fn sample_type(&self) -> SampleType;
 
+    /// Generate the file meta data regarding deep data.
+    /// Returns the largest number of samples in any pixel of any level,
+    /// or `None` if these samples are not deep.
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        None
+    }
+
     /// Generate the file meta data regarding resolution levels
     fn infer_level_modes(&self) -> (LevelMode, RoundingMode);
 
@@ -36,6 +44,13 @@ pub trait WritableLevel<'slf> {
     /// Generate the file meta data regarding the number type of these samples
     fn sample_type(&self) -> SampleType;
 
+    /// Generate the file meta data regarding deep data.
+    /// Returns the largest number of samples in any pixel,
+    /// or `None` if these samples are not deep.
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        None
+    }
+
     /// The type of the temporary writer for this single level of samples
     type Writer: SamplesWriter;
 
@@ -47,7 +62,28 @@ pub trait WritableLevel<'slf> {
 pub trait SamplesWriter: Sync {
     /// Deliver a single short horizontal list of samples for a specific
     /// channel.
-    fn extract_line(&self, line: LineRefMut<'_>);
+    ///
+    /// # Errors
+    /// Returns an error for deep samples.
+    fn extract_line(&self, line: LineRefMut<'_>) -> UnitResult;
+
+    /// Deliver a single short horizontal list of deep samples for a specific
+    /// channel. Appends the number of samples of each pixel in the line
+    /// to `sample_counts`, and the native-endian bytes of all samples
+    /// in the line to `bytes`.
+    ///
+    /// # Errors
+    /// Returns an error for flat samples, or if the deep samples
+    /// do not match the image size.
+    fn extract_deep_line(
+        &self,
+        location: LineIndex,
+        sample_counts: &mut Vec<u32>,
+        bytes: &mut Vec<u8>,
+    ) -> UnitResult {
+        let _ = (location, sample_counts, bytes);
+        Err(Error::invalid("flat samples in a deep layer"))
+    }
 }
 
 /// A temporary writer for a predefined non-deep sample storage
@@ -104,7 +140,7 @@ impl<'samples> WritableLevel<'samples> for FlatSamples {
 }
 
 impl SamplesWriter for FlatSamplesWriter<'_> {
-    fn extract_line(&self, line: LineRefMut<'_>) {
+    fn extract_line(&self, line: LineRefMut<'_>) -> UnitResult {
         let image_width = self.resolution.width(); // header.layer_size.width();
         debug_assert_ne!(image_width, 0, "image width calculation bug");
 
@@ -129,7 +165,187 @@ impl SamplesWriter for FlatSamplesWriter<'_> {
                 line.write_samples_from_slice(&samples[start_index..end_index])
             }
         }
-        .expect("writing line bytes failed");
+    }
+}
+
+/// A temporary writer for a predefined deep sample storage
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DeepSamplesWriter<'samples> {
+    resolution: Vec2<usize>, // respects resolution level
+    samples: &'samples DeepSamples,
+}
+
+impl DeepSamples {
+    fn max_samples_per_pixel(&self) -> usize {
+        (0..self.pixel_count()).map(|index| self.pixel_sample_count(index)).max().unwrap_or(0)
+    }
+}
+
+// used if no layers are used and the deep samples are directly inside the
+// channels
+impl<'samples> WritableSamples<'samples> for DeepSamples {
+    type Writer = DeepSamplesWriter<'samples>;
+
+    fn sample_type(&self) -> SampleType {
+        WritableLevel::sample_type(&self.samples)
+    }
+
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        Some(self.max_samples_per_pixel())
+    }
+
+    fn infer_level_modes(&self) -> (LevelMode, RoundingMode) {
+        (LevelMode::Singular, RoundingMode::Down)
+    }
+
+    fn create_samples_writer(&'samples self, header: &Header) -> Self::Writer {
+        DeepSamplesWriter {
+            resolution: header.layer_size,
+            samples: self,
+        }
+    }
+}
+
+// used if layers are used and the deep samples are inside the levels
+impl<'samples> WritableLevel<'samples> for DeepSamples {
+    type Writer = DeepSamplesWriter<'samples>;
+
+    fn sample_type(&self) -> SampleType {
+        WritableLevel::sample_type(&self.samples)
+    }
+
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        Some(self.max_samples_per_pixel())
+    }
+
+    fn create_level_writer(&'samples self, size: Vec2<usize>) -> Self::Writer {
+        DeepSamplesWriter {
+            resolution: size,
+            samples: self,
+        }
+    }
+}
+
+impl SamplesWriter for DeepSamplesWriter<'_> {
+    fn extract_line(&self, _line: LineRefMut<'_>) -> UnitResult {
+        Err(Error::invalid("deep samples in a flat layer"))
+    }
+
+    fn extract_deep_line(
+        &self,
+        location: LineIndex,
+        sample_counts: &mut Vec<u32>,
+        bytes: &mut Vec<u8>,
+    ) -> UnitResult {
+        let image_width = self.resolution.width();
+        debug_assert_ne!(image_width, 0, "image width calculation bug");
+
+        if self.samples.pixel_count() != self.resolution.area()
+            || self.samples.sample_offsets.last().copied().unwrap_or(0)
+                != self.samples.samples.len()
+        {
+            return Err(Error::invalid("deep sample offsets do not match the image size"));
+        }
+
+        let start_pixel = location.position.y() * image_width + location.position.x();
+        let end_pixel = start_pixel + location.sample_count;
+
+        for pixel_index in start_pixel..end_pixel {
+            let count = self.samples.pixel_sample_count(pixel_index);
+            sample_counts
+                .push(u32::try_from(count).map_err(|_| Error::invalid("deep sample count"))?);
+        }
+
+        let start_sample = self.samples.pixel_sample_range(start_pixel).start;
+        let end_sample = self.samples.pixel_sample_range(end_pixel - 1).end;
+
+        match &self.samples.samples {
+            FlatSamples::F16(samples) => {
+                crate::io::Data::write_slice_ne(bytes, &samples[start_sample..end_sample])
+            }
+            FlatSamples::F32(samples) => {
+                crate::io::Data::write_slice_ne(bytes, &samples[start_sample..end_sample])
+            }
+            FlatSamples::U32(samples) => {
+                crate::io::Data::write_slice_ne(bytes, &samples[start_sample..end_sample])
+            }
+        }
+    }
+}
+
+/// A temporary writer for samples that may be either deep or flat
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DeepAndFlatSamplesWriter<'samples> {
+    /// Writes flat samples
+    Flat(FlatSamplesWriter<'samples>),
+
+    /// Writes deep samples
+    Deep(DeepSamplesWriter<'samples>),
+}
+
+// used if no layers are used and the samples are directly inside the channels
+impl<'samples> WritableSamples<'samples> for DeepAndFlatSamples {
+    type Writer = DeepAndFlatSamplesWriter<'samples>;
+
+    fn sample_type(&self) -> SampleType {
+        Self::sample_type(self)
+    }
+
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        self.as_deep().map(DeepSamples::max_samples_per_pixel)
+    }
+
+    fn infer_level_modes(&self) -> (LevelMode, RoundingMode) {
+        (LevelMode::Singular, RoundingMode::Down)
+    }
+
+    fn create_samples_writer(&'samples self, header: &Header) -> Self::Writer {
+        self.create_level_writer(header.layer_size)
+    }
+}
+
+// used if layers are used and the samples are inside the levels
+impl<'samples> WritableLevel<'samples> for DeepAndFlatSamples {
+    type Writer = DeepAndFlatSamplesWriter<'samples>;
+
+    fn sample_type(&self) -> SampleType {
+        Self::sample_type(self)
+    }
+
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        self.as_deep().map(DeepSamples::max_samples_per_pixel)
+    }
+
+    fn create_level_writer(&'samples self, size: Vec2<usize>) -> Self::Writer {
+        match self {
+            Self::Flat(samples) => {
+                DeepAndFlatSamplesWriter::Flat(samples.create_level_writer(size))
+            }
+            Self::Deep(samples) => {
+                DeepAndFlatSamplesWriter::Deep(samples.create_level_writer(size))
+            }
+        }
+    }
+}
+
+impl SamplesWriter for DeepAndFlatSamplesWriter<'_> {
+    fn extract_line(&self, line: LineRefMut<'_>) -> UnitResult {
+        match self {
+            Self::Flat(writer) => writer.extract_line(line),
+            Self::Deep(writer) => writer.extract_line(line),
+        }
+    }
+
+    fn extract_deep_line(
+        &self,
+        location: LineIndex,
+        sample_counts: &mut Vec<u32>,
+        bytes: &mut Vec<u8>,
+    ) -> UnitResult {
+        match self {
+            Self::Flat(writer) => writer.extract_deep_line(location, sample_counts, bytes),
+            Self::Deep(writer) => writer.extract_deep_line(location, sample_counts, bytes),
+        }
     }
 }
 
@@ -150,6 +366,14 @@ where
         sample_type
     }
 
+    fn infer_max_samples_per_pixel(&self) -> Option<usize> {
+        self.levels_as_slice()
+            .iter()
+            .map(WritableLevel::infer_max_samples_per_pixel)
+            .max()
+            .flatten()
+    }
+
     fn infer_level_modes(&self) -> (LevelMode, RoundingMode) {
         match self {
             Self::Singular(_) => (LevelMode::Singular, RoundingMode::Down),
@@ -260,10 +484,23 @@ impl<Samples> SamplesWriter for LevelsWriter<Samples>
 where
     Samples: SamplesWriter,
 {
-    fn extract_line(&self, line: LineRefMut<'_>) {
+    fn extract_line(&self, line: LineRefMut<'_>) -> UnitResult {
         self.levels
             .get_level(line.location.level)
             .expect("invalid level index") // TODO compute level size from line index??
-            .extract_line(line);
+            .extract_line(line)
+    }
+
+    fn extract_deep_line(
+        &self,
+        location: LineIndex,
+        sample_counts: &mut Vec<u32>,
+        bytes: &mut Vec<u8>,
+    ) -> UnitResult {
+        self.levels.get_level(location.level).expect("invalid level index").extract_deep_line(
+            location,
+            sample_counts,
+            bytes,
+        )
     }
 }
@@ -175,6 +175,12 @@ impl<T: Read + Seek> PeekRead<Tracking<T>> {
         self.peeked = None;
         Ok(())
     }
+
+    /// The total number of bytes, including the bytes that were already read.
+    /// Keeps the current position and any previously peeked value.
+    pub fn byte_size(&mut self) -> std::io::Result<usize> {
+        self.inner.byte_size()
+    }
 }
 
 impl<T: Read> PeekRead<Tracking<T>> {
@@ -240,8 +246,8 @@ impl<T: Read + Seek> Tracking<T> {
         if delta > 0 && delta < 16 {
             // TODO profile that this is indeed faster than a syscall! (should be because of
             // bufread buffer discard)
+            // reading through `self` already advances the position
             skip_bytes(self, delta as usize)?;
-            self.position += delta as usize;
         } else if delta != 0 {
             self.inner.seek(SeekFrom::Start(u64::try_from(target_position).unwrap()))?;
             self.position = target_position;
@@ -249,6 +255,14 @@ impl<T: Read + Seek> Tracking<T> {
 
         Ok(())
     }
+
+    /// The total number of bytes, including the bytes that were already read.
+    /// Seeks to the end and back to the current position.
+    pub fn byte_size(&mut self) -> std::io::Result<usize> {
+        let end = self.inner.seek(SeekFrom::End(0))?;
+        self.inner.seek(SeekFrom::Start(u64::try_from(self.position).unwrap()))?;
+        Ok(usize::try_from(end).unwrap_or(usize::MAX))
+    }
 }
 
 impl<T: Write + Seek> Tracking<T> {
@@ -552,9 +566,9 @@ impl Data for f16 {
 
 #[cfg(test)]
 mod test {
-    use std::io::Read;
+    use std::io::{Cursor, Read};
 
-    use crate::io::PeekRead;
+    use crate::io::{PeekRead, Tracking};
 
     #[test]
     fn peek() {
@@ -579,4 +593,23 @@ mod test {
 
         assert!(u8::read_from_little_endian(&mut peek).is_err());
     }
+
+    #[test]
+    fn seek_read_to() {
+        use lebe::prelude::*;
+        let bytes: Vec<u8> = (0..64).collect();
+        let mut tracking = Tracking::new(Cursor::new(bytes));
+
+        // skips a few bytes by reading
+        tracking.seek_read_to(3).unwrap();
+        assert_eq!(tracking.byte_position(), 3);
+        assert_eq!(u8::read_from_little_endian(&mut tracking).unwrap(), 3);
+
+        tracking.seek_read_to(40).unwrap();
+        assert_eq!(u8::read_from_little_endian(&mut tracking).unwrap(), 40);
+
+        tracking.seek_read_to(2).unwrap();
+        assert_eq!(tracking.byte_position(), 2);
+        assert_eq!(u8::read_from_little_endian(&mut tracking).unwrap(), 2);
+    }
 }
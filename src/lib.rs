@@ -72,7 +72,8 @@ pub mod prelude {
     pub use crate::error::{Error, Result};
     pub use crate::image::{
         read::{
-            read_all_data_from_file, read_all_flat_layers_from_file,
+            read_all_composited_layers_from_file, read_all_data_from_file,
+            read_all_deep_and_flat_data_from_file, read_all_flat_layers_from_file,
             read_all_rgba_layers_from_file, read_first_flat_layer_from_file,
             read_first_rgba_layer_from_file,
         },
@@ -83,6 +84,7 @@ pub mod prelude {
     // image data structures
     pub use crate::{
         block::samples::Sample,
+        compression::CompressionOptions,
         image::*,
         meta::{
             attribute,
@@ -26,6 +26,10 @@ pub enum AttributeValue {
     /// Order of the bocks in the file.
     LineOrder(LineOrder),
 
+    /// Whether the samples of each pixel in a deep image are sorted and
+    /// non-overlapping.
+    DeepImageState(DeepImageState),
+
     /// A 3x3 matrix of floats.
     Matrix3x3(Matrix3x3),
 
@@ -360,6 +364,23 @@ pub enum LineOrder {
     Unspecified,
 }
 
+/// Whether the samples of each pixel in a deep image are sorted and
+/// non-overlapping. Stored as a single byte in the file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum DeepImageState {
+    /// The samples may be in any order and may overlap.
+    Messy,
+
+    /// The samples are sorted by depth, but may overlap.
+    Sorted,
+
+    /// The samples do not overlap, but may be in any order.
+    NonOverlapping,
+
+    /// The samples are sorted by depth and do not overlap.
+    Tidy,
+}
+
 /// A small `rgba` image of `i8` values that approximates the real exr image.
 // TODO is this linear?
 #[derive(Clone, Eq, PartialEq)]
@@ -980,6 +1001,16 @@ impl IntegerBounds {
             && subset.end().x() <= self.end().x()
             && subset.end().y() <= self.end().y()
     }
+
+    /// Returns the rectangle that is inside both rectangles,
+    /// or `None` if the rectangles do not overlap.
+    pub fn intersection(self, other: Self) -> Option<Self> {
+        let start = self.position.max(other.position);
+        let end = self.end().min(other.end());
+
+        let size = (end - start).to_usize("intersection size").ok()?;
+        (size.area() > 0).then_some(Self::new(start, size))
+    }
 }
 
 impl FloatRect {
@@ -1607,6 +1638,46 @@ impl LineOrder {
     }
 }
 
+impl DeepImageState {
+    /// Number of bytes this would consume in an exr file.
+    pub const fn byte_size() -> usize {
+        u8::BYTE_SIZE
+    }
+
+    /// Without validation, write this instance to the byte stream.
+    ///
+    /// # Errors
+    /// Returns an error if the byte stream cannot be written.
+    pub fn write<W: Write>(self, write: &mut W) -> UnitResult {
+        use self::DeepImageState::*;
+        match self {
+            Messy => 0_u8,
+            Sorted => 1_u8,
+            NonOverlapping => 2_u8,
+            Tidy => 3_u8,
+        }
+        .write_le(write)?;
+
+        Ok(())
+    }
+
+    /// Read the value without validating.
+    ///
+    /// # Errors
+    /// Returns an error if the byte stream cannot be read,
+    /// or if the value is not a known deep image state.
+    pub fn read<R: Read>(read: &mut R) -> Result<Self> {
+        use self::DeepImageState::*;
+        Ok(match u8::read_le(read)? {
+            0 => Messy,
+            1 => Sorted,
+            2 => NonOverlapping,
+            3 => Tidy,
+            _ => return Err(Error::invalid("deep image state attribute value")),
+        })
+    }
+}
+
 impl Preview {
     /// Number of bytes this would consume in an exr file.
     pub fn byte_size(&self) -> usize {
@@ -1818,6 +1889,7 @@ impl AttributeValue {
 
             KeyCode(_) => self::KeyCode::byte_size(),
             LineOrder(_) => self::LineOrder::byte_size(),
+            DeepImageState(_) => self::DeepImageState::byte_size(),
 
             Matrix3x3(ref value) => value.len() * f32::BYTE_SIZE,
             Matrix4x4(ref value) => value.len() * f32::BYTE_SIZE,
@@ -1865,6 +1937,7 @@ impl AttributeValue {
             EnvironmentMap(_) => ty::ENVIRONMENT_MAP,
             KeyCode(_) => ty::KEY_CODE,
             LineOrder(_) => ty::LINE_ORDER,
+            DeepImageState(_) => ty::DEEP_IMAGE_STATE,
             Matrix3x3(_) => ty::F32MATRIX3X3,
             Matrix4x4(_) => ty::F32MATRIX4X4,
             Preview(_) => ty::PREVIEW,
@@ -1925,6 +1998,7 @@ impl AttributeValue {
 
             KeyCode(value) => value.write(write)?,
             LineOrder(value) => value.write(write)?,
+            DeepImageState(value) => value.write(write)?,
 
             Matrix3x3(value) => f32::write_slice_le(write, &value)?,
             Matrix4x4(value) => f32::write_slice_le(write, &value)?,
@@ -2036,6 +2110,7 @@ impl AttributeValue {
 
                 ty::KEY_CODE => KeyCode(self::KeyCode::read(reader)?),
                 ty::LINE_ORDER => LineOrder(self::LineOrder::read(reader)?),
+                ty::DEEP_IMAGE_STATE => DeepImageState(self::DeepImageState::read(reader)?),
 
                 ty::F32MATRIX3X3 => Matrix3x3({
                     let mut result = [0.0_f32; 9];
@@ -2186,6 +2261,7 @@ pub mod type_names {
         ENVIRONMENT_MAP:b"envmap",
         KEY_CODE:       b"keycode",
         LINE_ORDER:     b"lineOrder",
+        DEEP_IMAGE_STATE: b"deepImageState",
         F32MATRIX3X3:   b"m33f",
         F32MATRIX4X4:   b"m44f",
         PREVIEW:        b"preview",
@@ -2394,6 +2470,42 @@ mod test {
         }
     }
 
+    #[test]
+    fn deep_image_state_byte_layout() {
+        let states = [
+            (DeepImageState::Messy, 0),
+            (DeepImageState::Sorted, 1),
+            (DeepImageState::NonOverlapping, 2),
+            (DeepImageState::Tidy, 3),
+        ];
+
+        for &(state, byte) in &states {
+            let value = AttributeValue::DeepImageState(state);
+            let mut bytes = Vec::new();
+            super::write(b"deepImageState", &value, &mut bytes).unwrap();
+
+            let mut expected = b"deepImageState\0deepImageState\0".to_vec();
+            expected.extend_from_slice(&1_i32.to_le_bytes());
+            expected.push(byte);
+
+            assert_eq!(bytes, expected, "{:?}", state);
+            assert_eq!(super::byte_size(&Text::from("deepImageState"), &value), bytes.len());
+
+            let (name, read_value) =
+                super::read(&mut PeekRead::new(Cursor::new(bytes)), 300).unwrap();
+
+            assert_eq!(name, Text::from("deepImageState"));
+            assert_eq!(read_value.unwrap(), value);
+        }
+
+        let mut invalid = b"deepImageState\0deepImageState\0".to_vec();
+        invalid.extend_from_slice(&1_i32.to_le_bytes());
+        invalid.push(4);
+
+        let (_, read_value) = super::read(&mut PeekRead::new(Cursor::new(invalid)), 300).unwrap();
+        assert!(read_value.is_err());
+    }
+
     #[test]
     fn time_code_pack() {
         let mut rng = thread_rng();
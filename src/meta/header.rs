@@ -4,14 +4,14 @@
 use std::collections::HashMap;
 
 use crate::meta::attribute::*; // FIXME shouldn't this need some more imports????
-use crate::{math::Vec2, meta::*};
+use crate::{compression::CompressionOptions, math::Vec2, meta::*};
 
 // TODO rename header to LayerDescription!
 
 /// Describes a single layer in a file.
 /// A file can have any number of layers.
 /// The meta data contains one header per layer.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Header {
     /// List of channels in this layer.
     pub channels: ChannelList,
@@ -20,6 +20,11 @@ pub struct Header {
     /// `Compression::Uncompressed`.
     pub compression: Compression,
 
+    /// Settings of the compression method that only affect writing.
+    /// Not stored in the file, and therefore always the default after reading.
+    /// Ignored when comparing headers.
+    pub compression_options: CompressionOptions,
+
     /// Describes how the pixels of this layer are divided into smaller blocks.
     /// A single block can be loaded without processing all bytes of a file.
     ///
@@ -79,6 +84,39 @@ pub struct Header {
     pub own_attributes: LayerAttributes,
 }
 
+// the compression options are not stored in the file,
+// so they are ignored to keep headers equal after writing and reading them
+impl PartialEq for Header {
+    fn eq(&self, other: &Self) -> bool {
+        let Self {
+            channels,
+            compression,
+            compression_options: _,
+            blocks,
+            line_order,
+            layer_size,
+            deep,
+            deep_data_version,
+            chunk_count,
+            max_samples_per_pixel,
+            shared_attributes,
+            own_attributes,
+        } = self;
+
+        *channels == other.channels
+            && *compression == other.compression
+            && *blocks == other.blocks
+            && *line_order == other.line_order
+            && *layer_size == other.layer_size
+            && *deep == other.deep
+            && *deep_data_version == other.deep_data_version
+            && *chunk_count == other.chunk_count
+            && *max_samples_per_pixel == other.max_samples_per_pixel
+            && *shared_attributes == other.shared_attributes
+            && *own_attributes == other.own_attributes
+    }
+}
+
 /// Includes mandatory fields like pixel aspect or display window
 /// which must be the same for all layers.
 /// For more attributes, see struct `LayerAttributes`.
@@ -221,7 +259,7 @@ pub struct LayerAttributes {
 
     /// Specifies whether the pixels in a deep image are sorted and
     /// non-overlapping.
-    pub deep_image_state: Option<Rational>,
+    pub deep_image_state: Option<DeepImageState>,
 
     /// If the image was cropped, contains the original data window.
     pub original_data_window: Option<IntegerBounds>,
@@ -344,6 +382,7 @@ impl Header {
         Self {
             layer_size: data_size,
             compression,
+            compression_options: CompressionOptions::default(),
             blocks,
 
             channels: ChannelList::new(channels),
@@ -393,6 +432,15 @@ impl Header {
         }
     }
 
+    /// Set the settings of the compression method that only affect writing.
+    #[must_use]
+    pub fn with_compression_options(self, compression_options: CompressionOptions) -> Self {
+        Self {
+            compression_options,
+            ..self
+        }
+    }
+
     /// Set **all** attributes of the header that are not shared with all other
     /// headers in the image.
     #[must_use]
@@ -604,26 +652,15 @@ impl Header {
         Ok(match block {
             CompressedBlock::Tile(ref tile) => tile.coordinates,
 
-            CompressedBlock::ScanLine(ref block) => {
-                let size = self.compression.scan_lines_per_block() as i32;
-
-                let diff = block
-                    .y_coordinate
-                    .checked_sub(self.own_attributes.layer_position.y())
-                    .ok_or_else(|| Error::invalid("invalid header"))?;
-                let y = diff.checked_div(size).ok_or_else(|| Error::invalid("invalid header"))?;
-
-                if y < 0 {
-                    return Err(Error::invalid("scan block y coordinate"));
-                }
+            CompressedBlock::DeepTile(ref tile) => tile.coordinates,
 
-                TileCoordinates {
-                    tile_index: Vec2(0, y as usize),
-                    level_index: Vec2(0, 0),
-                }
+            CompressedBlock::ScanLine(ref block) => {
+                self.get_scan_line_block_tile_coordinates(block.y_coordinate)?
             }
 
-            _ => return Err(Error::unsupported("deep data not supported yet")),
+            CompressedBlock::DeepScanLine(ref block) => {
+                self.get_scan_line_block_tile_coordinates(block.y_coordinate)?
+            }
         })
     }
 
@@ -652,13 +689,27 @@ impl Header {
     /// Maximum byte length of an uncompressed or compressed block, used for
     /// validation.
     pub fn max_block_byte_size(&self) -> usize {
-        self.channels.bytes_per_pixel
-            * match self.blocks {
-                BlockDescription::Tiles(tiles) => tiles.tile_size.area(),
-                BlockDescription::ScanLines => {
-                    self.compression.scan_lines_per_block() * self.layer_size.width()
-                } // TODO What about deep data???
+        let pixel_count = match self.blocks {
+            BlockDescription::Tiles(tiles) => tiles.tile_size.area(),
+            BlockDescription::ScanLines => {
+                self.compression.scan_lines_per_block() * self.layer_size.width()
             }
+        };
+
+        if self.deep {
+            // the offset table and the sample data are limited separately
+            pixel_count.saturating_mul(self.max_deep_pixel_bytes().max(4))
+        } else {
+            self.channels.bytes_per_pixel * pixel_count
+        }
+    }
+
+    /// The maximum number of sample bytes a single deep pixel may contain.
+    /// Without the max samples attribute, the size is not limited.
+    fn max_deep_pixel_bytes(&self) -> usize {
+        self.max_samples_per_pixel.map_or(usize::MAX, |max_samples| {
+            max_samples.saturating_mul(self.channels.bytes_per_pixel)
+        })
     }
 
     /// Returns the number of bytes that the pixels of this header will require
@@ -667,41 +718,51 @@ impl Header {
     pub fn total_pixel_bytes(&self) -> usize {
         assert!(!self.deep);
 
-        let pixel_count_of_levels = |size: Vec2<usize>| -> usize {
-            match self.blocks {
-                BlockDescription::ScanLines => size.area(),
-                BlockDescription::Tiles(tile_description) => match tile_description.level_mode {
-                    LevelMode::Singular => size.area(),
-
-                    LevelMode::MipMap => mip_map_levels(tile_description.rounding_mode, size)
-                        .map(|(_, size)| size.area())
-                        .sum(),
-
-                    LevelMode::RipMap => rip_map_levels(tile_description.rounding_mode, size)
-                        .map(|(_, size)| size.area())
-                        .sum(),
-                },
-            }
-        };
-
         self.channels
             .list
             .iter()
             .map(|channel: &ChannelDescription| {
-                pixel_count_of_levels(channel.subsampled_resolution(self.layer_size))
+                self.pixel_count_of_levels(channel.subsampled_resolution(self.layer_size))
                     * channel.sample_type.bytes_per_sample()
             })
             .sum()
     }
 
+    /// The number of pixels in all resolution levels, given the size of the
+    /// largest level.
+    fn pixel_count_of_levels(&self, size: Vec2<usize>) -> usize {
+        match self.blocks {
+            BlockDescription::ScanLines => size.area(),
+            BlockDescription::Tiles(tile_description) => match tile_description.level_mode {
+                LevelMode::Singular => size.area(),
+
+                LevelMode::MipMap => mip_map_levels(tile_description.rounding_mode, size)
+                    .map(|(_, size)| size.area())
+                    .sum(),
+
+                LevelMode::RipMap => rip_map_levels(tile_description.rounding_mode, size)
+                    .map(|(_, size)| size.area())
+                    .sum(),
+            },
+        }
+    }
+
     /// Approximates the maximum number of bytes that the pixels of this header
     /// will consume in a file. Due to compression, the actual byte size may
-    /// be smaller.
+    /// be smaller. For deep data, this is only limited by the max samples
+    /// attribute.
     pub fn max_pixel_file_bytes(&self) -> usize {
-        assert!(!self.deep);
+        let pixel_bytes = if self.deep {
+            // each pixel has an entry in the offset table, plus its samples
+            self.pixel_count_of_levels(self.layer_size)
+                .saturating_mul(self.max_deep_pixel_bytes().saturating_add(4))
+        } else {
+            self.total_pixel_bytes()
+        };
 
-        self.chunk_count * 64 // at most 64 bytes overhead for each chunk (header index, tile description, chunk size, and more)
-            + self.total_pixel_bytes()
+        (self.chunk_count * 64) // at most 64 bytes overhead for each chunk (header index, tile description, chunk size,
+            // and more)
+            .saturating_add(pixel_bytes)
     }
 
     /// Validate this instance.
@@ -816,6 +877,16 @@ impl Header {
             }
         }
 
+        if self.compression_options.deflate_level.is_some_and(|level| level > 9) {
+            return Err(Error::invalid("zip compression level must not be greater than 9"));
+        }
+
+        if let Some(rules) = &self.compression_options.dwa_channel_rules {
+            for rule in rules.iter() {
+                rule.validate()?;
+            }
+        }
+
         Ok(())
     }
 
@@ -924,6 +995,12 @@ impl Header {
         let block_type_and_tiles = expect_is_iter(
             once_with(move || {
                 let (block_type, tiles) = match self.blocks {
+                    BlockDescription::ScanLines if self.deep => {
+                        (attribute::BlockType::DeepScanLine, None)
+                    }
+                    BlockDescription::Tiles(tiles) if self.deep => {
+                        (attribute::BlockType::DeepTile, Some(tiles))
+                    }
                     BlockDescription::ScanLines => (attribute::BlockType::ScanLine, None),
                     BlockDescription::Tiles(tiles) => (attribute::BlockType::Tile, Some(tiles)),
                 };
@@ -999,7 +1076,7 @@ impl Header {
             MULTI_VIEW: TextVector = &self.own_attributes.multi_view_names,
             WORLD_TO_CAMERA: Matrix4x4 = &self.own_attributes.world_to_camera,
             WORLD_TO_NDC: Matrix4x4 = &self.own_attributes.world_to_normalized_device,
-            DEEP_IMAGE_STATE: Rational = &self.own_attributes.deep_image_state,
+            DEEP_IMAGE_STATE: DeepImageState = &self.own_attributes.deep_image_state,
             ORIGINAL_DATA_WINDOW: IntegerBounds = &self.own_attributes.original_data_window,
             CHROMATICITIES: Chromaticities = &self.shared_attributes.chromaticities,
             PREVIEW: Preview = &self.own_attributes.preview,
@@ -1148,7 +1225,7 @@ impl Header {
                         (name::WORLD_TO_NDC, Matrix4x4(value)) => {
                             layer_attributes.world_to_normalized_device = Some(value);
                         }
-                        (name::DEEP_IMAGE_STATE, Rational(value)) => {
+                        (name::DEEP_IMAGE_STATE, DeepImageState(value)) => {
                             layer_attributes.deep_image_state = Some(value);
                         }
                         (name::ORIGINAL_DATA_WINDOW, IntegerBounds(value)) => {
@@ -1242,6 +1319,7 @@ impl Header {
 
         let header = Self {
             compression,
+            compression_options: CompressionOptions::default(),
 
             // always compute ourselves, because we cannot trust anyone out there 😱
             chunk_count: computed_chunk_count,
@@ -5,14 +5,19 @@
 pub mod attribute;
 pub mod header;
 
-use std::{collections::HashSet, convert::TryFrom, fs::File, io::BufReader};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    fs::File,
+    io::{BufReader, Seek},
+};
 
 use ::smallvec::SmallVec;
 
 use self::attribute::*;
 use crate::{
     block::{
-        chunk::{CompressedBlock, TileCoordinates},
+        chunk::{Chunk, CompressedBlock, TileCoordinates},
         BlockIndex, UncompressedBlock,
     },
     error::*,
@@ -466,6 +471,68 @@ impl MetaData {
             .collect()
     }
 
+    /// Rebuild the offset tables of a file whose offset tables are damaged,
+    /// for example because the writing process crashed before writing them.
+    /// The reader must be positioned at the first chunk, directly after the
+    /// offset tables. Reads one chunk after another until the file ends or
+    /// a chunk cannot be read, and stores the position of each chunk in the
+    /// table of its header. Chunks that were not found have the offset
+    /// zero, which is never a valid chunk position, as every file starts
+    /// with the magic number. A chunk cannot be larger than the rest of the
+    /// file, which limits deep chunks of headers without the max samples
+    /// attribute.
+    pub fn reconstruct_offset_tables(
+        read: &mut PeekRead<Tracking<impl Read + Seek>>,
+        meta_data: &Self,
+    ) -> OffsetTables {
+        let block_indices: SmallVec<[HashMap<TileCoordinates, usize>; 3]> = meta_data
+            .headers
+            .iter()
+            .map(|header| {
+                header
+                    .blocks_increasing_y_order()
+                    .enumerate()
+                    .map(|(index_in_header, tile)| (tile.location, index_in_header))
+                    .collect()
+            })
+            .collect();
+
+        let mut offset_tables: OffsetTables =
+            meta_data.headers.iter().map(|header| vec![0; header.chunk_count]).collect();
+
+        let Ok(file_byte_size) = read.byte_size() else {
+            return offset_tables;
+        };
+
+        // stops at the end of the file, or where the file is damaged
+        loop {
+            let chunk_start = read.byte_position();
+            let remaining_byte_size = file_byte_size.saturating_sub(chunk_start);
+
+            let Ok(chunk) = Chunk::read_limited(read, meta_data, remaining_byte_size) else {
+                break;
+            };
+
+            let header = &meta_data.headers[chunk.layer_index];
+            let Ok(coordinates) = header.get_block_data_indices(&chunk.compressed_block) else {
+                break;
+            };
+
+            let Some(&index_in_header) = block_indices[chunk.layer_index].get(&coordinates) else {
+                break;
+            };
+
+            // keep the first chunk if a block appears multiple times
+            match offset_tables[chunk.layer_index].get_mut(index_in_header) {
+                Some(offset) if *offset == 0 => *offset = chunk_start as u64,
+                Some(_) => {}
+                None => break,
+            }
+        }
+
+        offset_tables
+    }
+
     /// Skip the offset tables by advancing the reader by the required byte
     /// count.
     // TODO use seek for large (probably all) tables!
@@ -520,7 +587,7 @@ impl MetaData {
             return Err(Error::invalid("at least one layer is required"));
         }
 
-        let deep = false; // TODO deep data
+        let deep = headers.iter().any(|header| header.deep);
         let is_multilayer = headers.len() > 1;
         let first_header_has_tiles =
             headers.iter().next().map_or(false, |header| header.blocks.has_tiles());
@@ -534,17 +601,13 @@ impl MetaData {
             // start as low as possible, later increasing if required
             has_long_names: false,
 
-            is_single_layer_and_tiled: !is_multilayer && first_header_has_tiles,
+            // the single tile flag is only used for flat images
+            is_single_layer_and_tiled: !is_multilayer && first_header_has_tiles && !deep,
             has_multiple_layers: is_multilayer,
             has_deep_data: deep,
         };
 
         for header in headers {
-            if header.deep {
-                // TODO deep data (and then remove this check)
-                return Err(Error::unsupported("deep data not supported yet"));
-            }
-
             header.validate(is_multilayer, &mut minimal_requirements.has_long_names, pedantic)?;
         }
 
@@ -731,6 +794,7 @@ mod test {
                 sampling: Vec2(1, 1)
             }]),
             compression: Compression::Uncompressed,
+            compression_options: crate::compression::CompressionOptions::default(),
             line_order: LineOrder::Increasing,
             deep_data_version: Some(1),
             chunk_count: compute_chunk_count(
@@ -787,6 +851,7 @@ mod test {
                 sampling: Vec2(1, 1)
             }]),
             compression: Compression::Uncompressed,
+            compression_options: crate::compression::CompressionOptions::default(),
             line_order: LineOrder::Increasing,
             deep_data_version: Some(1),
             chunk_count: compute_chunk_count(
@@ -834,6 +899,7 @@ mod test {
                 sampling: Vec2(1, 1)
             }]),
             compression: Compression::Uncompressed,
+            compression_options: crate::compression::CompressionOptions::default(),
             line_order: LineOrder::Increasing,
             deep_data_version: Some(1),
             chunk_count: compute_chunk_count(
@@ -269,3 +269,84 @@ fn compare_png_to_dwaa_f32() {
 fn compare_png_to_pxr24_f32() {
     expect_eq_png("f32_pxr24.exr");
 }
+
+#[test]
+fn decompress_into_reused_buffer_matches_blocks() {
+    use exr::block::reader::ChunksReader;
+
+    let open = |path: &Path| {
+        let file = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+        exr::block::read(file, true)
+            .unwrap()
+            .all_chunks(true)
+            .unwrap()
+            .sequential_decompressor(true)
+    };
+
+    for entry in std::fs::read_dir(dir().join("f32")).unwrap() {
+        let path = entry.unwrap().path();
+
+        let mut blocks = open(&path);
+        let mut decompressor = open(&path);
+        let mut pixels = Vec::new();
+
+        while let Some(block) = blocks.decompress_next_block() {
+            let block = block.unwrap();
+            let index = decompressor.decompress_next_block_into(&mut pixels).unwrap().unwrap();
+
+            assert_eq!(index, block.index, "{}", path.display());
+            assert_eq!(pixels, block.data, "{}", path.display());
+        }
+
+        assert!(decompressor.decompress_next_block_into(&mut pixels).is_none());
+    }
+}
+
+#[test]
+fn transcode_keeps_samples_and_attributes() {
+    use std::io::Cursor;
+
+    let read_image = |bytes: &[u8]| {
+        read()
+            .no_deep_data()
+            .all_resolution_levels()
+            .all_channels()
+            .all_layers()
+            .all_attributes()
+            .from_buffered(Cursor::new(bytes))
+            .unwrap()
+    };
+
+    let paths = [
+        Path::new("tests/images/valid/openexr/Beachball/multipart.0001.exr"),
+        Path::new("tests/images/valid/openexr/Tiles/GoldenGate.exr"),
+        Path::new("tests/images/valid/custom/compression_methods/f32/piz.exr"),
+    ];
+
+    let compressions =
+        [Compression::PIZ, Compression::ZIP16, Compression::RLE, Compression::Uncompressed];
+
+    for path in &paths {
+        let original = std::fs::read(path).unwrap();
+
+        for &compression in &compressions {
+            let mut transcoded = Vec::new();
+            exr::image::transcode::transcode(
+                Cursor::new(&original),
+                Cursor::new(&mut transcoded),
+                true,
+                |_| compression,
+            )
+            .unwrap();
+
+            let mut expected = read_image(&original);
+            let image = read_image(&transcoded);
+
+            for layer in &mut expected.layer_data {
+                layer.encoding.compression = compression;
+            }
+
+            expected.assert_equals_result(&image);
+        }
+    }
+}
@@ -0,0 +1,574 @@
+extern crate exr;
+extern crate smallvec;
+
+use std::{io::Cursor, path::Path};
+
+use exr::{
+    block::deep::tidy_deep_data,
+    math::RoundingMode,
+    meta::{attribute::DeepImageState, mip_map_levels, MetaData},
+    prelude::*,
+};
+use smallvec::smallvec;
+
+fn dir() -> &'static Path {
+    Path::new("tests/images/valid/openexr/v2/LowResLeftView")
+}
+
+fn deep_channels(image: &DeepAndFlatImage) -> Vec<(&Text, &DeepSamples)> {
+    image
+        .layer_data
+        .iter()
+        .flat_map(|layer| &layer.channel_data.list)
+        .map(|channel| match &channel.sample_data {
+            Levels::Singular(DeepAndFlatSamples::Deep(samples)) => (&channel.name, samples),
+            other => panic!("expected a single deep level, found {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn read_deep_scan_line_file() {
+    let image = read_all_deep_and_flat_data_from_file(dir().join("Balls.exr")).unwrap();
+    let layer = &image.layer_data[0];
+    let pixel_count = layer.size.area();
+
+    let channels = deep_channels(&image);
+    assert!(channels.iter().any(|(name, _)| *name == "Z"));
+
+    let (_, first) = channels[0];
+    assert_eq!(first.pixel_count(), pixel_count);
+    assert!(first.total_sample_count() > 0, "image should contain deep samples");
+
+    for (name, samples) in &channels {
+        assert_eq!(samples.samples.len(), samples.total_sample_count(), "channel {}", name);
+
+        assert_eq!(
+            samples.sample_offsets, first.sample_offsets,
+            "all channels should have the same number of samples per pixel"
+        );
+    }
+}
+
+#[test]
+fn read_deep_file_sequentially() {
+    let read = read().flat_and_deep_data().largest_resolution_level().all_channels().all_layers();
+
+    let image = read.clone().all_attributes().from_file(dir().join("Trunks.exr")).unwrap();
+    let sequential_image =
+        read.all_attributes().non_parallel().from_file(dir().join("Trunks.exr")).unwrap();
+
+    assert_eq!(image, sequential_image);
+    assert!(image.layer_data[0].channel_data.list[0].sample_data.is_deep());
+}
+
+#[test]
+fn read_flat_file_as_deep_and_flat() {
+    let path = dir().join("composited.exr");
+
+    let flat = read_all_data_from_file(&path).unwrap();
+    let deep_and_flat = read_all_deep_and_flat_data_from_file(&path).unwrap();
+
+    for (flat_layer, layer) in flat.layer_data.iter().zip(&deep_and_flat.layer_data) {
+        for (flat_channel, channel) in
+            flat_layer.channel_data.list.iter().zip(&layer.channel_data.list)
+        {
+            let flat_levels = flat_channel.sample_data.levels_as_slice();
+            let levels = channel.sample_data.levels_as_slice();
+            assert_eq!(flat_levels.len(), levels.len());
+
+            for (flat_samples, samples) in flat_levels.iter().zip(levels) {
+                assert_eq!(Some(flat_samples), samples.as_flat());
+            }
+        }
+    }
+}
+
+#[test]
+fn flat_reader_does_not_support_deep_data() {
+    match read_all_data_from_file(dir().join("Balls.exr")) {
+        Err(Error::NotSupported(_)) => {}
+        other => panic!("expected unsupported error, found {:?}", other.map(|_| ())),
+    }
+}
+
+fn generated_deep_samples(size: Vec2<usize>, seed: usize, sample_type: SampleType) -> DeepSamples {
+    let counts = (0..size.area()).map(|index| (index * 7 + seed) % 5);
+    let total = counts.clone().sum();
+
+    let samples = match sample_type {
+        SampleType::F16 => FlatSamples::F16((0..total).map(|i| f16::from_f32(i as f32)).collect()),
+        SampleType::F32 => FlatSamples::F32((0..total).map(|i| i as f32 * 0.25).collect()),
+        SampleType::U32 => FlatSamples::U32((0..total).map(|i| i as u32).collect()),
+    };
+
+    DeepSamples::from_sample_counts(counts, samples).unwrap()
+}
+
+fn generated_deep_image(
+    blocks: Blocks,
+    compression: Compression,
+    mip_maps: bool,
+) -> DeepAndFlatImage {
+    let size = Vec2(37, 23);
+
+    let channel = |name: &str, sample_type: SampleType| {
+        let level = |(index, level_size): (usize, Vec2<usize>)| {
+            DeepAndFlatSamples::Deep(generated_deep_samples(level_size, index, sample_type))
+        };
+
+        let levels = if mip_maps {
+            Levels::Mip {
+                rounding_mode: RoundingMode::Down,
+                level_data: mip_map_levels(RoundingMode::Down, size).map(level).collect(),
+            }
+        } else {
+            Levels::Singular(level((0, size)))
+        };
+
+        AnyChannel::new(name, levels)
+    };
+
+    let channels = AnyChannels::sort(smallvec![
+        channel("A", SampleType::F16),
+        channel("Z", SampleType::F32),
+        channel("id", SampleType::U32),
+    ]);
+
+    let encoding = Encoding {
+        compression,
+        blocks,
+        line_order: LineOrder::Increasing,
+    };
+
+    let layer = Layer::new(size, LayerAttributes::named("deep"), encoding, channels);
+    Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), vec![layer])
+}
+
+fn write_deep(image: &DeepAndFlatImage) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    image.write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+    bytes
+}
+
+fn read_deep(bytes: &[u8]) -> DeepAndFlatImage {
+    read()
+        .flat_and_deep_data()
+        .all_resolution_levels()
+        .all_channels()
+        .all_layers()
+        .all_attributes()
+        .from_buffered(Cursor::new(bytes))
+        .unwrap()
+}
+
+#[test]
+fn roundtrip_generated_deep_images() {
+    let compressions =
+        [Compression::Uncompressed, Compression::RLE, Compression::ZIP1, Compression::ZIP16];
+
+    let blocks = [
+        (Blocks::ScanLines, false),
+        (Blocks::Tiles(Vec2(8, 8)), false),
+        (Blocks::Tiles(Vec2(16, 8)), true),
+    ];
+
+    for &compression in &compressions {
+        for &(blocks, mip_maps) in &blocks {
+            let image = generated_deep_image(blocks, compression, mip_maps);
+            let bytes = write_deep(&image);
+
+            let header =
+                MetaData::read_from_buffered(Cursor::new(&bytes), true).unwrap().headers[0].clone();
+
+            assert!(header.deep);
+            assert_eq!(header.deep_data_version, Some(1));
+            assert_eq!(header.max_samples_per_pixel, Some(4));
+
+            let result = read_deep(&bytes);
+            assert_eq!(
+                image.layer_data[0].channel_data, result.layer_data[0].channel_data,
+                "{:?} {:?} (mip maps: {})",
+                compression, blocks, mip_maps
+            );
+        }
+    }
+}
+
+fn deep_and_flat_image() -> DeepAndFlatImage {
+    let mut image = generated_deep_image(Blocks::Tiles(Vec2(8, 8)), Compression::ZIP16, false);
+    let size = image.layer_data[0].size;
+
+    let flat_samples = FlatSamples::F32((0..size.area()).map(|index| index as f32).collect());
+    let flat_channels = AnyChannels::sort(smallvec![AnyChannel::new(
+        "Y",
+        Levels::Singular(DeepAndFlatSamples::Flat(flat_samples))
+    )]);
+
+    image.layer_data.push(Layer::new(
+        size,
+        LayerAttributes::named("flat"),
+        Encoding::SMALL_LOSSLESS,
+        flat_channels,
+    ));
+
+    image
+}
+
+#[test]
+fn read_deep_and_flat_layers_within_bounds() {
+    let image = deep_and_flat_image();
+    let bytes = write_deep(&image);
+    let size = image.layer_data[1].size;
+    let bounds = IntegerBounds::new(Vec2(3, 2), (5, 4));
+
+    let cropped = read()
+        .flat_and_deep_data()
+        .all_resolution_levels()
+        .all_channels()
+        .all_layers()
+        .all_attributes()
+        .within_bounds(bounds)
+        .from_buffered(Cursor::new(&bytes))
+        .unwrap();
+
+    // deep layers are read completely
+    assert_eq!(cropped.layer_data[0], read_deep(&bytes).layer_data[0]);
+
+    let flat = &cropped.layer_data[1];
+    assert_eq!(flat.size, Vec2(5, 4));
+
+    let expected: Vec<f32> =
+        (2..6).flat_map(|y| (3..8).map(move |x| (y * size.width() + x) as f32)).collect();
+
+    let samples = flat.channel_data.list[0].sample_data.levels_as_slice()[0].as_flat();
+    assert_eq!(samples, Some(&FlatSamples::F32(expected)));
+}
+
+#[test]
+fn analyze_compression_of_deep_and_flat_layers() {
+    use exr::image::analysis::{analyze_compression, WRITABLE_COMPRESSIONS};
+
+    let image = deep_and_flat_image();
+    let report = analyze_compression(&image, &WRITABLE_COMPRESSIONS).unwrap();
+    assert_eq!(report.layers.len(), 2);
+
+    // deep layers are not analyzed
+    let deep = &report.layers[0];
+    assert_eq!(deep.uncompressed_byte_size, None);
+    assert!(deep.compressions.is_empty());
+
+    let flat = &report.layers[1];
+    let size = image.layer_data[1].size;
+    assert_eq!(flat.uncompressed_byte_size, Some(size.area() * 4));
+    assert_eq!(flat.compressions.len(), WRITABLE_COMPRESSIONS.len());
+}
+
+#[test]
+fn extract_flat_block_from_deep_channels_is_an_error() {
+    use exr::{
+        block::BlockIndex,
+        image::write::{
+            channels::{ChannelsWriter, WritableChannels},
+            layers::WritableLayers,
+        },
+    };
+
+    let image = generated_deep_image(Blocks::ScanLines, Compression::ZIP16, false);
+    let headers = image.layer_data.infer_headers(&image.attributes);
+    let writer = image.layer_data[0].channel_data.create_writer(&headers[0]);
+
+    let block = BlockIndex {
+        layer: 0,
+        level: Vec2(0, 0),
+        pixel_position: Vec2(0, 0),
+        pixel_size: Vec2(4, 2),
+    };
+
+    assert!(writer.extract_uncompressed_block(&headers[0], block).is_err());
+    assert!(writer.extract_uncompressed_deep_block(&headers[0], block).is_ok());
+}
+
+#[test]
+fn read_deep_and_flat_layers_in_parallel() {
+    let image = deep_and_flat_image();
+    let bytes = write_deep(&image);
+    let read = read().flat_and_deep_data().all_resolution_levels().all_channels().all_layers();
+
+    let parallel = read.clone().all_attributes().from_buffered(Cursor::new(&bytes)).unwrap();
+    let sequential =
+        read.all_attributes().non_parallel().from_buffered(Cursor::new(&bytes)).unwrap();
+
+    assert_eq!(parallel, sequential);
+
+    for (layer, result_layer) in image.layer_data.iter().zip(&parallel.layer_data) {
+        assert_eq!(layer.channel_data, result_layer.channel_data);
+    }
+}
+
+#[test]
+fn read_deep_and_flat_layers_with_missing_flat_chunks() {
+    use std::convert::TryInto;
+
+    let original = write_deep(&deep_and_flat_image());
+
+    // the offset tables directly follow the headers, the flat layer is the second
+    let mut tracking = exr::io::Tracking::new(Cursor::new(&original));
+    let meta_data = MetaData::read_from_buffered(&mut tracking, false).unwrap();
+    let deep_table_start = tracking.byte_position();
+    let flat_table_start = deep_table_start + meta_data.headers[0].chunk_count * 8;
+    let tables_end = flat_table_start + meta_data.headers[1].chunk_count * 8;
+
+    let last_flat_chunk = original[flat_table_start..tables_end]
+        .chunks_exact(8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .max()
+        .unwrap();
+
+    // the last flat chunk cannot be located after zeroing the tables
+    let mut damaged = original[..last_flat_chunk as usize].to_vec();
+    damaged[deep_table_start..tables_end].fill(0);
+
+    let read = read().flat_and_deep_data().all_resolution_levels().all_channels().all_layers();
+    let parallel = read.clone().all_attributes().from_buffered(Cursor::new(&damaged));
+    let sequential = read.all_attributes().non_parallel().from_buffered(Cursor::new(&damaged));
+
+    // both readers report the missing chunk instead of returning an incomplete
+    // image
+    for result in [parallel, sequential] {
+        assert!(matches!(result, Err(Error::Invalid(_))));
+    }
+}
+
+#[test]
+fn reconstructing_offset_tables_limits_deep_chunks_to_the_file_size() {
+    use exr::io::{PeekRead, Tracking};
+
+    let image = generated_deep_image(Blocks::ScanLines, Compression::RLE, false);
+    let mut damaged = write_deep(&image);
+
+    let mut tracking = Tracking::new(Cursor::new(&damaged));
+    let meta_data = MetaData::read_from_buffered(&mut tracking, false).unwrap();
+    let tables_start = tracking.byte_position();
+    let tables_end = tables_start + meta_data.headers[0].chunk_count * 8;
+
+    // the first chunk claims to contain far more sample bytes than the file,
+    // after the y coordinate and the size of the pixel offset table
+    damaged[tables_start..tables_end].fill(0);
+    let sample_data_size = tables_end + 4 + 8;
+    damaged[sample_data_size..sample_data_size + 8].copy_from_slice(&(1_u64 << 40).to_le_bytes());
+
+    let mut read = PeekRead::new(Tracking::new(Cursor::new(&damaged)));
+    let mut meta_data = MetaData::read_from_buffered(&mut read, false).unwrap();
+    MetaData::read_offset_tables(&mut read, &meta_data.headers).unwrap();
+
+    // without the max samples attribute, the size of deep chunks is not limited by
+    // the header
+    meta_data.headers[0].max_samples_per_pixel = None;
+
+    let offset_tables = MetaData::reconstruct_offset_tables(&mut read, &meta_data);
+    assert!(offset_tables.iter().flatten().all(|&offset| offset == 0));
+
+    // the sample data was not read until the end of the file
+    assert!(read.byte_position() < damaged.len());
+}
+
+#[test]
+fn roundtrip_deep_files() {
+    for name in &["Balls.exr", "Trunks.exr", "Leaves.exr"] {
+        let mut image = read_all_deep_and_flat_data_from_file(dir().join(name)).unwrap();
+
+        // deep layers must be named in valid files
+        for layer in &mut image.layer_data {
+            if layer.attributes.layer_name.is_none() {
+                layer.attributes.layer_name = Some("deep".into());
+            }
+        }
+
+        let result = read_deep(&write_deep(&image));
+
+        for (layer, result_layer) in image.layer_data.iter().zip(&result.layer_data) {
+            assert_eq!(layer.channel_data, result_layer.channel_data, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn composite_combined_deep_files() {
+    let parts: Vec<DeepAndFlatImage> = ["Balls.exr", "Trunks.exr", "Leaves.exr"]
+        .iter()
+        .map(|name| read_all_deep_and_flat_data_from_file(dir().join(name)).unwrap())
+        .collect();
+
+    let reference = read_all_flat_layers_from_file(dir().join("composited.exr")).unwrap();
+    let reference = &reference.layer_data[0];
+    let bounds = IntegerBounds::new(reference.attributes.layer_position, reference.size);
+
+    // combine the samples of all three files into a single deep layer
+    let names = ["A", "B", "G", "R", "Z"];
+    let mut sample_counts = Vec::with_capacity(bounds.size.area());
+    let mut values: Vec<Vec<f32>> = names.iter().map(|_| Vec::new()).collect();
+
+    for y in 0..bounds.size.height() {
+        for x in 0..bounds.size.width() {
+            let position = bounds.position + Vec2(x, y).to_i32();
+            let previous_sample_count = values[0].len();
+
+            for part in &parts {
+                let layer = &part.layer_data[0];
+                let local = position - layer.attributes.layer_position;
+                if local.x() < 0 || local.y() < 0 {
+                    continue;
+                }
+
+                let local = local.to_usize("pixel position").unwrap();
+                if local.x() >= layer.size.width() || local.y() >= layer.size.height() {
+                    continue;
+                }
+
+                let pixel_index = local.flat_index_for_size(layer.size);
+                for ((channel, name), values) in
+                    layer.channel_data.list.iter().zip(&names).zip(&mut values)
+                {
+                    assert_eq!(channel.name, Text::from(*name));
+                    let samples = channel.sample_data.levels_as_slice()[0].as_deep().unwrap();
+                    values.extend(samples.pixel_samples(pixel_index).map(Sample::to_f32));
+                }
+            }
+
+            sample_counts.push(values[0].len() - previous_sample_count);
+        }
+    }
+
+    let channels = names
+        .iter()
+        .zip(values)
+        .map(|(name, values)| {
+            let samples = DeepSamples::from_sample_counts(
+                sample_counts.iter().copied(),
+                FlatSamples::F32(values),
+            )
+            .unwrap();
+
+            AnyChannel::new(*name, DeepAndFlatSamples::Deep(samples))
+        })
+        .collect();
+
+    let deep_layer = Layer::new(
+        bounds.size,
+        LayerAttributes::named("combined"),
+        Encoding::UNCOMPRESSED,
+        AnyChannels::sort(channels),
+    );
+
+    let flat_layer = deep_layer.composite_deep_samples().unwrap();
+    let names: Vec<&Text> =
+        flat_layer.channel_data.list.iter().map(|channel| &channel.name).collect();
+    assert_eq!(names, ["A", "B", "G", "R", "Z"]);
+
+    // the reference image contains further elements, which can only add to the
+    // opacity
+    let alpha = flat_layer.channel_data.list[0].sample_data.values_as_f32();
+    let reference_alpha = reference.channel_data.list[0].sample_data.values_as_f32();
+
+    for (alpha, reference_alpha) in alpha.zip(reference_alpha) {
+        assert!(alpha <= reference_alpha + 0.01, "expected {}, found {}", reference_alpha, alpha);
+
+        if alpha > 0.99 {
+            assert!((alpha - reference_alpha).abs() < 0.01);
+        }
+    }
+
+    // the composited layer is an ordinary flat layer
+    let mut bytes = Vec::new();
+    Image::from_layer(flat_layer).write().to_buffered(Cursor::new(&mut bytes)).unwrap();
+}
+
+#[test]
+fn tidy_deep_files() {
+    for name in &["Balls.exr", "Trunks.exr", "Leaves.exr"] {
+        let path = dir().join(name);
+
+        let mut bytes = Vec::new();
+        let file = std::io::BufReader::new(std::fs::File::open(&path).unwrap());
+        tidy_deep_data(file, Cursor::new(&mut bytes), false).unwrap();
+
+        let header =
+            MetaData::read_from_buffered(Cursor::new(&bytes), false).unwrap().headers[0].clone();
+        assert_eq!(header.own_attributes.deep_image_state, Some(DeepImageState::Tidy));
+
+        let image = read_all_deep_and_flat_data_from_file(&path).unwrap();
+        let tidy = read_deep(&bytes);
+        let channels = deep_channels(&tidy);
+
+        // without a `ZBack` channel, tidy samples are sorted and have distinct depths
+        let (_, depths) = channels.iter().find(|(name, _)| *name == "Z").unwrap();
+        for pixel in 0..depths.pixel_count() {
+            let pixel_depths: Vec<f32> = depths.pixel_samples(pixel).map(Sample::to_f32).collect();
+            assert!(pixel_depths.windows(2).all(|pair| pair[0] < pair[1]), "{}", name);
+
+            let max_samples = header.max_samples_per_pixel.unwrap();
+            assert!(pixel_depths.len() <= max_samples);
+        }
+
+        // tidying does not change the composited result
+        let composited = image.layer_data[0].composite_deep_samples().unwrap();
+        let tidy_composited = tidy.layer_data[0].composite_deep_samples().unwrap();
+
+        for (channel, tidy_channel) in
+            composited.channel_data.list.iter().zip(&tidy_composited.channel_data.list)
+        {
+            let values = channel.sample_data.values_as_f32();
+            let tidy_values = tidy_channel.sample_data.values_as_f32();
+
+            for (value, tidy_value) in values.zip(tidy_values) {
+                assert!(
+                    value == tidy_value || (value - tidy_value).abs() < 0.01,
+                    "{} {}: expected {}, found {}",
+                    name,
+                    channel.name,
+                    value,
+                    tidy_value
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn transcode_deep_file() {
+    let path = dir().join("Balls.exr");
+    let original = std::fs::read(&path).unwrap();
+
+    let mut transcoded = Vec::new();
+    exr::image::transcode::transcode(
+        Cursor::new(&original),
+        Cursor::new(&mut transcoded),
+        false,
+        |_| Compression::RLE,
+    )
+    .unwrap();
+
+    let image = read_all_deep_and_flat_data_from_file(&path).unwrap();
+    let transcoded_image = read()
+        .flat_and_deep_data()
+        .all_resolution_levels()
+        .all_channels()
+        .all_layers()
+        .all_attributes()
+        .from_buffered(Cursor::new(&transcoded))
+        .unwrap();
+
+    assert_eq!(transcoded_image.layer_data[0].encoding.compression, Compression::RLE);
+    assert_eq!(deep_channels(&image), deep_channels(&transcoded_image));
+
+    // deep blocks of different heights cannot be combined yet
+    let result = exr::image::transcode::transcode(
+        Cursor::new(&original),
+        Cursor::new(Vec::new()),
+        false,
+        |_| Compression::ZIP16,
+    );
+
+    assert!(matches!(result, Err(Error::NotSupported(_))));
+}
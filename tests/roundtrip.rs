@@ -382,6 +382,464 @@ fn roundtrip_uncompressed() {
     test_mixed_roundtrip_with_compression(Compression::Uncompressed)
 }
 
+#[test]
+fn writing_htj2k_is_not_supported() {
+    for &compression in &[Compression::HTJ2K32, Compression::HTJ2K256] {
+        let image = Image::from_encoded_channels(
+            (2, 2),
+            Encoding {
+                compression,
+                ..Encoding::default()
+            },
+            SpecificChannels::rgb(|_: Vec2<usize>| (0.5_f32, 0.25_f32, 1.0_f32)),
+        );
+
+        let result = image.write().to_buffered(Cursor::new(Vec::new()));
+        assert!(matches!(result, Err(Error::NotSupported(_))), "{:?}", compression);
+    }
+}
+
+#[test]
+fn roundtrip_zip_compression_levels() {
+    let size = Vec2(64, 48);
+    let pixels = PixelVec::new(
+        size,
+        (0..size.area())
+            .map(|index| {
+                let (x, y) = ((index % size.width()) as f32, (index / size.width()) as f32);
+                ((x * 0.1).sin().to_f16(), y * 0.5, (x * y).sqrt())
+            })
+            .collect(),
+    );
+
+    let write = |compression: Compression, deflate_level: u8| {
+        let image = Image::from_encoded_channels(
+            size,
+            Encoding {
+                compression,
+                ..Encoding::default()
+            },
+            SpecificChannels::rgb(pixels.clone()),
+        );
+
+        let options = CompressionOptions::default().with_deflate_level(deflate_level);
+        let write = image.write().compression_options(options);
+        let headers = write.infer_meta_data();
+
+        let mut bytes = Vec::new();
+        write.to_buffered(Cursor::new(&mut bytes)).map(|()| (headers, bytes))
+    };
+
+    for &compression in &[Compression::ZIP1, Compression::ZIP16, Compression::PXR24] {
+        let (fast_headers, fast_bytes) = write(compression, 1).unwrap();
+        let (_, small_bytes) = write(compression, 9).unwrap();
+        assert!(small_bytes.len() <= fast_bytes.len(), "{:?}", compression);
+
+        // the level is not stored in the file, and is ignored when comparing headers
+        let meta_data = MetaData::read_from_buffered(Cursor::new(&fast_bytes), true).unwrap();
+        assert_eq!(meta_data.headers, fast_headers);
+
+        for bytes in &[fast_bytes, small_bytes] {
+            let image = read()
+                .no_deep_data()
+                .largest_resolution_level()
+                .rgb_channels(PixelVec::<(f16, f32, f32)>::constructor, PixelVec::set_pixel)
+                .first_valid_layer()
+                .all_attributes()
+                .from_buffered(Cursor::new(bytes))
+                .unwrap();
+
+            assert_eq!(image.layer_data.encoding.compression, compression);
+
+            if !compression.may_loose_data() {
+                assert_eq!(image.layer_data.channel_data.pixels, pixels);
+            }
+        }
+    }
+
+    match write(Compression::ZIP16, 10) {
+        Err(Error::Invalid(_)) => {}
+        other => panic!("expected invalid level error, found {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn roundtrip_b44_uniform_area_option() {
+    let size = Vec2(64, 48);
+
+    // the left half is uniform, the right half is a gradient
+    let pixels = PixelVec::new(
+        size,
+        (0..size.area())
+            .map(|index| {
+                let x = index % size.width();
+                let value = if x < size.width() / 2 {
+                    0.5
+                } else {
+                    x as f32 * 0.01
+                };
+                (value.to_f16(), value.to_f16(), value.to_f16())
+            })
+            .collect(),
+    );
+
+    let write = |compression: Compression, optimize: Option<bool>| {
+        let compression_options = CompressionOptions {
+            b44_optimize_uniform_areas: optimize,
+            ..CompressionOptions::default()
+        };
+
+        let image = Image::from_encoded_channels(
+            size,
+            Encoding {
+                compression,
+                ..Encoding::default()
+            },
+            SpecificChannels::rgb(pixels.clone()),
+        );
+
+        let mut bytes = Vec::new();
+        image
+            .write()
+            .compression_options(compression_options)
+            .to_buffered(Cursor::new(&mut bytes))
+            .unwrap();
+        bytes
+    };
+
+    let b44 = write(Compression::B44, None);
+    let b44a = write(Compression::B44A, None);
+    assert!(b44a.len() < b44.len());
+
+    // the option overrides the default of both compression methods
+    assert_eq!(write(Compression::B44, Some(true)).len(), b44a.len());
+    assert_eq!(write(Compression::B44A, Some(false)).len(), b44.len());
+
+    for bytes in &[b44, b44a] {
+        let image = read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .rgb_channels(PixelVec::<(f16, f16, f16)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer()
+            .all_attributes()
+            .from_buffered(Cursor::new(bytes))
+            .unwrap();
+
+        let uniform_pixel = image.layer_data.channel_data.pixels.get_pixel(Vec2(0, 0));
+        assert_eq!(*uniform_pixel, (0.5.to_f16(), 0.5.to_f16(), 0.5.to_f16()));
+    }
+}
+
+#[test]
+fn write_dwa_with_custom_channel_rules() {
+    use exr::compression::dwa::{ChannelRule, CompressorScheme};
+
+    let size = Vec2(64, 48);
+    let channel = |name: &str, offset: f32| {
+        let samples = (0..size.area())
+            .map(|index| {
+                let (x, y) = ((index % size.width()) as f32, (index / size.width()) as f32);
+                (0.5 + 0.25 * (x * 0.3 + offset).sin() + 0.125 * (y * 0.2).cos()).to_f16()
+            })
+            .collect();
+
+        AnyChannel::new(name, FlatSamples::F16(samples))
+    };
+
+    let channels = AnyChannels::sort(smallvec::smallvec![
+        channel("diffuse.red", 0.0),
+        channel("diffuse.green", 1.0),
+        channel("diffuse.blue", 2.0),
+    ]);
+
+    let write = |compression_options: CompressionOptions| {
+        let encoding = Encoding {
+            compression: Compression::DWAA(None),
+            ..Encoding::SMALL_LOSSLESS
+        };
+
+        let image = Image::from_encoded_channels(size, encoding, channels.clone());
+        let mut bytes = Vec::new();
+        image
+            .write()
+            .compression_options(compression_options)
+            .to_buffered(Cursor::new(&mut bytes))
+            .unwrap();
+        bytes
+    };
+
+    let rules = vec![
+        ChannelRule::new("red", SampleType::F16, CompressorScheme::LossyDct, Some(0)),
+        ChannelRule::new("green", SampleType::F16, CompressorScheme::LossyDct, Some(1)),
+        ChannelRule::new("blue", SampleType::F16, CompressorScheme::LossyDct, Some(2)),
+    ];
+
+    let lossless = write(CompressionOptions::default());
+    let lossy = write(CompressionOptions::default().with_dwa_channel_rules(rules));
+    assert!(lossy.len() < lossless.len(), "custom rules must enable lossy compression");
+
+    let image = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .all_channels()
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(Cursor::new(&lossy))
+        .unwrap();
+
+    for (channel, result) in channels.list.iter().zip(&image.layer_data.channel_data.list) {
+        assert_eq!(channel.name, result.name);
+
+        let samples = channel.sample_data.values_as_f32();
+        for (original, decoded) in samples.zip(result.sample_data.values_as_f32()) {
+            assert!((original - decoded).abs() < 0.05, "{}", channel.name);
+        }
+    }
+}
+
+#[test]
+fn lossy_compression_within_max_error() {
+    let size = Vec2(64, 48);
+    let pixels = PixelVec::new(
+        size,
+        (0..size.area())
+            .map(|index| {
+                let (x, y) = ((index % size.width()) as f32, (index / size.width()) as f32);
+                let depth = 1000.0 + x * 0.123_456 + y * 7.654_321;
+                ((x * 0.1).sin().to_f16(), depth, (index as f32).sqrt())
+            })
+            .collect(),
+    );
+
+    for &compression in &[Compression::PXR24, Compression::B44, Compression::DWAA(None)] {
+        let image = Image::from_encoded_channels(
+            size,
+            Encoding {
+                compression,
+                ..Encoding::default()
+            },
+            SpecificChannels::rgb(pixels.clone()),
+        );
+
+        let write = |max_error: Option<f32>, parallel: bool| {
+            let mut bytes = Vec::new();
+            let mut writer = image.write();
+
+            if let Some(max_error) = max_error {
+                writer = writer.max_lossy_error(max_error);
+            }
+
+            if !parallel {
+                writer = writer.non_parallel();
+            }
+
+            writer.to_buffered(Cursor::new(&mut bytes)).map(|()| bytes)
+        };
+
+        let read_pixels = |bytes: &[u8]| {
+            read()
+                .no_deep_data()
+                .largest_resolution_level()
+                .rgb_channels(PixelVec::<(f16, f32, f32)>::constructor, PixelVec::set_pixel)
+                .first_valid_layer()
+                .all_attributes()
+                .from_buffered(Cursor::new(bytes))
+                .unwrap()
+                .layer_data
+                .channel_data
+                .pixels
+        };
+
+        let read_compression = |bytes: &[u8]| {
+            MetaData::read_from_buffered(Cursor::new(bytes), false).unwrap().headers[0].compression
+        };
+
+        let lossy = write(None, true).unwrap();
+        assert_ne!(read_pixels(&lossy), pixels, "{}", compression);
+
+        // tolerating any error keeps the lossy compression
+        assert_eq!(write(Some(f32::MAX), true).unwrap(), lossy, "{}", compression);
+
+        for &invalid in &[-1.0, f32::INFINITY, f32::NAN] {
+            match write(Some(invalid), true) {
+                Err(Error::Invalid(_)) => {}
+                other => panic!("expected invalid error, found {:?}", other.map(|_| ())),
+            }
+        }
+
+        for &parallel in &[true, false] {
+            let exact = write(Some(0.0), parallel).unwrap();
+            assert_eq!(read_compression(&exact), Compression::ZIP16, "{}", compression);
+            assert_eq!(read_pixels(&exact), pixels, "{}", compression);
+        }
+    }
+}
+
+#[test]
+fn lossy_compression_within_max_channel_error() {
+    let size = Vec2(64, 48);
+    let layer = |layer_name: &str, channel_name: &str| {
+        let samples = (0..size.area())
+            .map(|index| {
+                let (x, y) = ((index % size.width()) as f32, (index / size.width()) as f32);
+                1000.0 + x * 0.123_456 + y * 7.654_321
+            })
+            .collect();
+
+        let channels = AnyChannels::sort(smallvec::smallvec![AnyChannel::new(
+            channel_name,
+            FlatSamples::F32(samples)
+        )]);
+
+        let encoding = Encoding {
+            compression: Compression::PXR24,
+            ..Encoding::default()
+        };
+
+        Layer::new(size, LayerAttributes::named(layer_name), encoding, channels)
+    };
+
+    let image = Image::from_layers(
+        ImageAttributes::new(IntegerBounds::from_dimensions(size)),
+        smallvec::smallvec![layer("depth", "Z"), layer("color", "Y")],
+    );
+
+    let write = |parallel: bool, max_error: Option<f32>, channel_errors: &[(&str, f32)]| {
+        let mut writer = image.write();
+        if let Some(max_error) = max_error {
+            writer = writer.max_lossy_error(max_error);
+        }
+
+        for &(channel_name, max_error) in channel_errors {
+            writer = writer.max_lossy_channel_error(channel_name, max_error);
+        }
+
+        if !parallel {
+            writer = writer.non_parallel();
+        }
+
+        let mut bytes = Vec::new();
+        writer.to_buffered(Cursor::new(&mut bytes)).unwrap();
+        bytes
+    };
+
+    let read_layers = |bytes: &[u8]| {
+        read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .all_layers()
+            .all_attributes()
+            .from_buffered(Cursor::new(bytes))
+            .unwrap()
+            .layer_data
+    };
+
+    let compressions = |bytes: &[u8]| {
+        MetaData::read_from_buffered(Cursor::new(bytes), false)
+            .unwrap()
+            .headers
+            .iter()
+            .map(|header| header.compression)
+            .collect::<Vec<_>>()
+    };
+
+    let lossy = write(true, None, &[]);
+
+    for &parallel in &[true, false] {
+        // only the layer with the exact channel is compressed again
+        let exact_depth = write(parallel, None, &[("Z", 0.0)]);
+        assert_eq!(compressions(&exact_depth), vec![Compression::ZIP16, Compression::PXR24]);
+
+        let layers = read_layers(&exact_depth);
+        let lossy_layers = read_layers(&lossy);
+        assert_eq!(layers[0].channel_data, image.layer_data[0].channel_data);
+        assert_eq!(layers[1].channel_data, lossy_layers[1].channel_data);
+        assert_ne!(layers[1].channel_data, image.layer_data[1].channel_data);
+
+        // the last error of a channel replaces its previous errors and the error of all
+        // channels
+        let exact_color = write(parallel, Some(0.0), &[("Z", 0.0), ("Z", f32::MAX)]);
+
+        assert_eq!(compressions(&exact_color), vec![Compression::PXR24, Compression::ZIP16]);
+
+        let invalid = image.write().max_lossy_channel_error("Y", f32::NAN);
+        match invalid.to_buffered(Cursor::new(Vec::new())) {
+            Err(Error::Invalid(_)) => {}
+            other => panic!("expected invalid error, found {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn write_smallest_lossless_compression() {
+    let size = Vec2(64, 48);
+
+    // all rows are equal, so the sample blocks represent the whole image
+    let pixels = PixelVec::new(
+        size,
+        (0..size.area())
+            .map(|index| {
+                let x = (index % size.width()) as f32;
+                ((x * 0.1).sin().to_f16(), (x * 0.37).cos().to_f16(), (x * 13.0 % 7.0) as u32)
+            })
+            .collect(),
+    );
+
+    let write = |compression: Compression, smallest: bool| {
+        let image = Image::from_encoded_channels(
+            size,
+            Encoding {
+                compression,
+                ..Encoding::default()
+            },
+            SpecificChannels::rgb(pixels.clone()),
+        );
+
+        let mut bytes = Vec::new();
+        let mut writer = image.write();
+        if smallest {
+            writer = writer.smallest_lossless_compression();
+        }
+
+        writer.to_buffered(Cursor::new(&mut bytes)).unwrap();
+        bytes
+    };
+
+    let read_image = |bytes: &[u8]| {
+        read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .rgb_channels(PixelVec::<(f16, f16, u32)>::constructor, PixelVec::set_pixel)
+            .first_valid_layer()
+            .all_attributes()
+            .from_buffered(Cursor::new(bytes))
+            .unwrap()
+    };
+
+    let candidates = [
+        Compression::RLE,
+        Compression::ZIP1,
+        Compression::ZIP16,
+        Compression::PIZ,
+        Compression::PXR24,
+    ];
+
+    let smallest = write(Compression::Uncompressed, true);
+    let smallest_size =
+        candidates.iter().map(|&compression| write(compression, false).len()).min().unwrap();
+
+    assert_eq!(smallest.len(), smallest_size);
+
+    let image = read_image(&smallest);
+    assert_eq!(image.layer_data.channel_data.pixels, pixels);
+    assert!(candidates.contains(&image.layer_data.encoding.compression));
+
+    // lossy compression is not replaced
+    let lossy = read_image(&write(Compression::B44, true));
+    assert_eq!(lossy.layer_data.encoding.compression, Compression::B44);
+}
+
 fn test_mixed_roundtrip_with_compression(compression: Compression) {
     let original_pixels: [(f16, f32, f32); 4] = [
         ((0.0).to_f16(), -1.1, std::f32::consts::PI),
@@ -416,3 +874,342 @@ fn test_mixed_roundtrip_with_compression(compression: Compression) {
     lossy_image.assert_equals_result(&lossy_image);
     original_image.assert_equals_result(&lossy_image);
 }
+
+#[test]
+fn combine_and_split_multipart_files() {
+    use exr::image::multipart::{combine, extract_part};
+
+    let read_image = |bytes: &[u8]| {
+        read()
+            .no_deep_data()
+            .all_resolution_levels()
+            .all_channels()
+            .all_layers()
+            .all_attributes()
+            .from_buffered(Cursor::new(bytes))
+            .unwrap()
+    };
+
+    let single_part =
+        std::fs::read("tests/images/valid/custom/compression_methods/f32/zip.exr").unwrap();
+    let multi_part =
+        std::fs::read("tests/images/valid/openexr/Beachball/multipart.0001.exr").unwrap();
+    let tiled = std::fs::read("tests/images/valid/openexr/Tiles/GoldenGate.exr").unwrap();
+
+    let files = [&single_part, &single_part, &multi_part, &tiled];
+    let names = ["zip", "zip", "beachball", "tiles"];
+
+    let mut combined = Vec::new();
+    let sources =
+        names.iter().zip(&files).map(|(name, file)| (Text::from(*name), Cursor::new(file)));
+    combine(sources, Cursor::new(&mut combined), true).unwrap();
+
+    let combined_image = read_image(&combined);
+    let original_images: Vec<_> = files.iter().map(|file| read_image(file)).collect();
+    let original_layers: Vec<_> =
+        original_images.iter().flat_map(|image| image.layer_data.iter()).collect();
+
+    assert_eq!(combined_image.layer_data.len(), original_layers.len());
+    assert_eq!(combined_image.layer_data[0].attributes.layer_name, Some(Text::from("zip")));
+    assert_eq!(combined_image.layer_data[1].attributes.layer_name, Some(Text::from("zip_1")));
+
+    for (index, original_layer) in original_layers.iter().enumerate() {
+        let layer = &combined_image.layer_data[index];
+        layer.channel_data.assert_equals_result(&original_layer.channel_data);
+
+        let mut part = Vec::new();
+        extract_part(Cursor::new(&combined), index, Cursor::new(&mut part), true).unwrap();
+
+        let part_image = read_image(&part);
+        assert_eq!(part_image.layer_data.len(), 1);
+        assert_eq!(part_image.attributes, combined_image.attributes);
+
+        let part_layer = &part_image.layer_data[0];
+        assert_eq!(part_layer.attributes, layer.attributes);
+        part_layer.channel_data.assert_equals_result(&original_layer.channel_data);
+    }
+}
+
+#[test]
+fn combine_parts_with_empty_or_long_names() {
+    use exr::image::multipart::combine;
+
+    let file = std::fs::read("tests/images/valid/custom/compression_methods/f32/zip.exr").unwrap();
+    let combine_named = |name: &str| {
+        let sources =
+            vec![(Text::from(name), Cursor::new(&file)), (Text::from(name), Cursor::new(&file))];
+
+        let mut combined = Vec::new();
+        combine(sources, Cursor::new(&mut combined), true).map(|()| combined)
+    };
+
+    let combined = combine_named("").unwrap();
+    let headers = MetaData::read_from_buffered(Cursor::new(&combined), true).unwrap().headers;
+    let names: Vec<_> =
+        headers.iter().map(|header| header.own_attributes.layer_name.clone()).collect();
+    assert_eq!(names, vec![Some(Text::from("part")), Some(Text::from("part_1"))]);
+
+    // the second name would be longer than 255 bytes
+    match combine_named(&"a".repeat(254)) {
+        Err(Error::Invalid(_)) => {}
+        other => panic!("expected invalid error, found {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn read_files_with_zeroed_offset_tables() {
+    use std::convert::TryInto;
+
+    use exr::image::read::lenient::BlockStatus;
+
+    let read_image = |bytes: &[u8], pedantic: bool| {
+        let reader = read()
+            .no_deep_data()
+            .all_resolution_levels()
+            .all_channels()
+            .all_layers()
+            .all_attributes();
+
+        if pedantic {
+            reader.pedantic().from_buffered(Cursor::new(bytes))
+        } else {
+            reader.from_buffered(Cursor::new(bytes))
+        }
+    };
+
+    let paths = [
+        "tests/images/valid/custom/compression_methods/f32/zip.exr",
+        "tests/images/valid/openexr/Beachball/multipart.0001.exr",
+        "tests/images/valid/openexr/Tiles/GoldenGate.exr",
+    ];
+
+    for path in paths {
+        let original = std::fs::read(path).unwrap();
+
+        // the offset tables directly follow the headers
+        let mut tracking = exr::io::Tracking::new(Cursor::new(&original));
+        let meta_data = MetaData::read_from_buffered(&mut tracking, false).unwrap();
+        let tables_start = tracking.byte_position();
+        let chunk_count: usize = meta_data.headers.iter().map(|header| header.chunk_count).sum();
+        let tables_end = tables_start + chunk_count * 8;
+
+        let first_chunk = original[tables_start..tables_end]
+            .chunks_exact(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .min();
+
+        assert_eq!(first_chunk, Some(tables_end as u64), "{}", path);
+
+        let mut damaged = original.clone();
+        damaged[tables_start..tables_end].fill(0);
+
+        assert!(read_image(&damaged, true).is_err(), "{}", path);
+
+        let expected = read_image(&original, true).unwrap();
+        let reconstructed = read_image(&damaged, false).unwrap();
+        reconstructed.assert_equals_result(&expected);
+
+        // the chunks that were not completely written are missing
+        let truncated = &damaged[..damaged.len() - 100];
+        match read_image(truncated, false) {
+            Err(Error::Invalid(_)) => {}
+            other => panic!("expected invalid error, found {:?}", other.map(|_| ())),
+        }
+
+        // only the lenient reader skips the missing chunks
+        let read_lenient = |bytes: &[u8]| {
+            read()
+                .no_deep_data()
+                .all_resolution_levels()
+                .all_channels()
+                .all_layers()
+                .all_attributes()
+                .lenient(0.0_f32)
+                .from_buffered(Cursor::new(bytes))
+                .unwrap()
+        };
+
+        let recovered = read_lenient(&damaged);
+        assert!(recovered.is_complete(), "{}", path);
+        recovered.image.assert_equals_result(&expected);
+
+        let partial = read_lenient(truncated);
+        assert!(!partial.is_complete(), "{}", path);
+        assert!(partial.blocks.iter().any(|&(_, status)| status == BlockStatus::Complete));
+        assert_eq!(partial.blocks.len(), recovered.blocks.len(), "{}", path);
+    }
+}
+
+#[test]
+fn read_truncated_file_leniently() {
+    use exr::image::read::lenient::BlockStatus;
+
+    let reader = || {
+        read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .all_layers()
+            .all_attributes()
+    };
+
+    let original =
+        std::fs::read("tests/images/valid/custom/compression_methods/f32/zip.exr").unwrap();
+    let truncated = &original[..original.len() * 2 / 3];
+    assert!(reader().from_buffered(Cursor::new(truncated)).is_err());
+
+    let complete = reader().lenient(0.5_f32).from_buffered(Cursor::new(&original)).unwrap();
+    assert!(complete.is_complete());
+
+    let partial = reader().lenient(0.5_f32).from_buffered(Cursor::new(truncated)).unwrap();
+    assert!(!partial.is_complete());
+    assert_eq!(partial.blocks.len(), complete.blocks.len());
+    assert!(partial.blocks.iter().any(|&(_, status)| status == BlockStatus::Complete));
+    assert!(partial.damaged_blocks().all(|(_, status)| status == BlockStatus::Missing));
+
+    let expected_layer = &complete.image.layer_data[0];
+    let layer = &partial.image.layer_data[0];
+    let width = layer.size.width();
+
+    for (block, status) in partial.blocks {
+        for y in block.pixel_position.y()..block.pixel_position.y() + block.pixel_size.height() {
+            let flat_index = y * width + block.pixel_position.x();
+
+            for (channel, expected_channel) in
+                layer.channel_data.list.iter().zip(&expected_layer.channel_data.list)
+            {
+                let sample = channel.sample_data.value_by_flat_index(flat_index);
+
+                if status == BlockStatus::Complete {
+                    let expected = expected_channel.sample_data.value_by_flat_index(flat_index);
+                    assert_eq!(sample.to_f32().to_bits(), expected.to_f32().to_bits());
+                } else {
+                    assert_eq!(sample.to_f32(), 0.5);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn read_within_bounds() {
+    let paths = [
+        "tests/images/valid/custom/compression_methods/f32/zip.exr",
+        "tests/images/valid/openexr/Tiles/GoldenGate.exr",
+    ];
+
+    let reader = || {
+        read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+    };
+
+    for path in paths {
+        let full = reader().from_file(path).unwrap().layer_data;
+        let position = full.attributes.layer_position;
+
+        let bounds = IntegerBounds::new(position + Vec2(13, 7), (40, 30));
+        let cropped = reader().within_bounds(bounds).from_file(path).unwrap().layer_data;
+
+        assert_eq!(cropped.size, Vec2(40, 30), "{}", path);
+        assert_eq!(cropped.attributes.layer_position, bounds.position, "{}", path);
+
+        for (channel, full_channel) in cropped.channel_data.list.iter().zip(&full.channel_data.list)
+        {
+            for y in 0..30 {
+                for x in 0..40 {
+                    let sample = channel.sample_data.value_by_flat_index(y * 40 + x);
+                    let expected = full_channel
+                        .sample_data
+                        .value_by_flat_index((y + 7) * full.size.width() + x + 13);
+
+                    assert_eq!(sample.to_f32().to_bits(), expected.to_f32().to_bits(), "{}", path);
+                }
+            }
+        }
+
+        // bounds that exceed the layer are clipped
+        let bounds = IntegerBounds::new(position - Vec2(5, 5), (20, 10));
+        let clipped = reader().within_bounds(bounds).from_file(path).unwrap().layer_data;
+        assert_eq!(clipped.size, Vec2(15, 5), "{}", path);
+        assert_eq!(clipped.attributes.layer_position, position, "{}", path);
+
+        // cropped layers contain only the largest level
+        let levels = read()
+            .no_deep_data()
+            .all_resolution_levels()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+            .within_bounds(bounds)
+            .from_file(path)
+            .unwrap()
+            .layer_data;
+
+        let samples = &levels.channel_data.list[0].sample_data;
+        assert_eq!(samples.levels_as_slice().len(), 1, "{}", path);
+        assert_eq!(samples.levels_as_slice()[0].len(), 15 * 5, "{}", path);
+    }
+}
+
+#[test]
+fn read_specific_resolution_level() {
+    let path = "tests/images/valid/openexr/MultiResolution/Bonita.exr";
+
+    let all_levels = read()
+        .no_deep_data()
+        .all_resolution_levels()
+        .all_channels()
+        .first_valid_layer()
+        .all_attributes()
+        .from_file(path)
+        .unwrap()
+        .layer_data;
+
+    let reader = || {
+        read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+    };
+
+    let level_sizes = std::cell::RefCell::new(Vec::new());
+    let level = reader()
+        .specific_resolution_level(|sizes: &[Vec2<usize>]| {
+            level_sizes.replace(sizes.to_vec());
+            2
+        })
+        .from_file(path)
+        .unwrap()
+        .layer_data;
+
+    let level_sizes = level_sizes.into_inner();
+    assert!(level_sizes.len() > 3);
+    assert_eq!(level_sizes[0], all_levels.size);
+    assert_eq!(level.size, level_sizes[2]);
+
+    for (channel, all_levels_channel) in
+        level.channel_data.list.iter().zip(&all_levels.channel_data.list)
+    {
+        let expected = &all_levels_channel.sample_data.levels_as_slice()[2];
+        channel.sample_data.assert_equals_result(expected);
+    }
+
+    // the smallest level that is at least 100 pixels wide
+    let expected_size = level_sizes.iter().copied().rfind(|size| size.width() >= 100).unwrap();
+    let thumbnail = reader().smallest_resolution_level_at_least(100).from_file(path).unwrap();
+    assert_eq!(thumbnail.layer_data.size, expected_size);
+
+    // no level is wide enough, so the largest level is read
+    let largest = reader().smallest_resolution_level_at_least(100_000).from_file(path).unwrap();
+    assert_eq!(largest.layer_data.size, all_levels.size);
+
+    let invalid =
+        reader().specific_resolution_level(|sizes: &[Vec2<usize>]| sizes.len()).from_file(path);
+    assert!(invalid.is_err());
+}